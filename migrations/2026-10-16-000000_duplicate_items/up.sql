@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS duplicate_items (
+    tx_id CHAR(43) NOT NULL,
+    bundle_id CHAR(43) NOT NULL,
+    duplicate_bundle_id CHAR(43) NOT NULL,
+    PRIMARY KEY (tx_id, duplicate_bundle_id)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS validator_stake (
+    address CHAR(43) PRIMARY KEY,
+    registered BOOLEAN NOT NULL,
+    stake VARCHAR NOT NULL,
+    minimum_stake VARCHAR NOT NULL,
+    nominated BOOLEAN NOT NULL,
+    epoch BYTEA NOT NULL
+);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS transactions_updated_at;
+DROP INDEX IF EXISTS bundle_created_at;
+ALTER TABLE transactions DROP COLUMN IF EXISTS updated_at, DROP COLUMN IF EXISTS created_at;
+ALTER TABLE bundle DROP COLUMN IF EXISTS updated_at, DROP COLUMN IF EXISTS created_at;
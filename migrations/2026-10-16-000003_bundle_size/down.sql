@@ -0,0 +1 @@
+ALTER TABLE bundle DROP COLUMN IF EXISTS item_count, DROP COLUMN IF EXISTS size;
@@ -0,0 +1,3 @@
+ALTER TABLE bundle
+    ADD COLUMN IF NOT EXISTS size BIGINT,
+    ADD COLUMN IF NOT EXISTS item_count INTEGER;
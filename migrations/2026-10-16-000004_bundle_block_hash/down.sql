@@ -0,0 +1 @@
+ALTER TABLE bundle DROP COLUMN IF EXISTS block_hash;
@@ -0,0 +1 @@
+ALTER TABLE bundle ADD COLUMN IF NOT EXISTS block_hash VARCHAR(64);
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS nested_items;
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS nested_items (
+    tx_id CHAR(43) NOT NULL,
+    bundle_id CHAR(43) NOT NULL,
+    parent_id CHAR(43) NOT NULL,
+    PRIMARY KEY (tx_id, bundle_id)
+);
+
+CREATE INDEX IF NOT EXISTS nested_items_bundle_id ON nested_items (bundle_id);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS raw_receipts (
+    tx_id CHAR(43) NOT NULL,
+    source VARCHAR NOT NULL,
+    payload TEXT NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (tx_id, source)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS attestations (
+    bundle_id CHAR(43) NOT NULL,
+    validator CHAR(43) NOT NULL,
+    epoch BYTEA NOT NULL,
+    signature BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (bundle_id, validator)
+);
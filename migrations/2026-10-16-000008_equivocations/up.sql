@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS equivocations (
+    tx_id CHAR(43) NOT NULL,
+    kind VARCHAR NOT NULL,
+    first_evidence TEXT NOT NULL,
+    second_evidence TEXT NOT NULL,
+    detected_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    proposed BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (tx_id, kind)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS pruned_epochs (
+    epoch BYTEA NOT NULL,
+    validated BIGINT NOT NULL DEFAULT 0,
+    invalid BIGINT NOT NULL DEFAULT 0,
+    slashed BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (epoch)
+);
@@ -0,0 +1 @@
+ALTER TABLE bundle ADD COLUMN IF NOT EXISTS checksum VARCHAR(64);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS round_attempts (
+    bundle_id CHAR(43) NOT NULL PRIMARY KEY,
+    attempts INT NOT NULL,
+    retry_at TIMESTAMPTZ NOT NULL
+);
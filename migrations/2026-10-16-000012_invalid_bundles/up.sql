@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS invalid_bundles (
+    id CHAR(43) NOT NULL PRIMARY KEY,
+    reason TEXT NOT NULL,
+    detected_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
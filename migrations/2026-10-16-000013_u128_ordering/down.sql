@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS transactions_epoch_value_idx;
+DROP FUNCTION IF EXISTS le_u128(BYTEA);
@@ -0,0 +1,9 @@
+-- Epochs and blocks are stored as the native (little-endian) bytes of a
+-- u128, which don't sort by value. le_u128 reads them as a number so
+-- they can be compared and aggregated in SQL.
+CREATE OR REPLACE FUNCTION le_u128(value BYTEA) RETURNS NUMERIC(39, 0) AS $$
+    SELECT COALESCE(SUM(get_byte(value, i)::NUMERIC * 256::NUMERIC ^ i), 0)::NUMERIC(39, 0)
+    FROM generate_series(0, length(value) - 1) AS i
+$$ LANGUAGE SQL IMMUTABLE STRICT;
+
+CREATE INDEX IF NOT EXISTS transactions_epoch_value_idx ON transactions (le_u128(epoch));
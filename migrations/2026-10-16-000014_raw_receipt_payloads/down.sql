@@ -0,0 +1,7 @@
+DELETE FROM raw_receipts a USING raw_receipts b
+WHERE a.tx_id = b.tx_id
+  AND a.source = b.source
+  AND (a.received_at, a.payload_hash) > (b.received_at, b.payload_hash);
+ALTER TABLE raw_receipts DROP CONSTRAINT IF EXISTS raw_receipts_pkey;
+ALTER TABLE raw_receipts DROP COLUMN IF EXISTS payload_hash;
+ALTER TABLE raw_receipts ADD PRIMARY KEY (tx_id, source);
@@ -0,0 +1,8 @@
+-- Key raw receipts by their payload too, so a second receipt of the same
+-- transaction from the same source is kept as evidence instead of being
+-- dropped
+ALTER TABLE raw_receipts ADD COLUMN IF NOT EXISTS payload_hash BYTEA;
+UPDATE raw_receipts SET payload_hash = sha256(convert_to(payload, 'UTF8'));
+ALTER TABLE raw_receipts ALTER COLUMN payload_hash SET NOT NULL;
+ALTER TABLE raw_receipts DROP CONSTRAINT IF EXISTS raw_receipts_pkey;
+ALTER TABLE raw_receipts ADD PRIMARY KEY (tx_id, source, payload_hash);
@@ -0,0 +1,3 @@
+-- Address of the bundler key that signed the receipt of the transaction,
+-- kept so receipts accepted during a key rotation can be audited
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS bundler_key VARCHAR;
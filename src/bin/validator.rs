@@ -1,80 +1,528 @@
-use clap::Parser;
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
 use data_encoding::{DecodeError, BASE64URL_NOPAD};
 use diesel::{
     r2d2::{self, ConnectionManager},
     PgConnection,
 };
-use env_logger::Env;
 use jsonwebkey::{JsonWebKey, Key, PublicExponent, RsaPublic};
 use serde::Deserialize;
-use std::{fs, net::SocketAddr, process, str::FromStr};
+use std::{net::SocketAddr, path::PathBuf, process, str::FromStr};
 use sysinfo::{System, SystemExt};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, level_filters::LevelFilter, warn};
 use url::Url;
 
 use validator::{
+    broker::{run_publisher, BrokerConfig, Publisher},
+    build_info,
+    bundle::store::BundleStorage,
     bundler::BundlerConfig,
+    config::{
+        merge_option, require_option, ConfigError, FileConfig, RuntimeConfig, RuntimeConfigAccess,
+    },
+    epoch::EpochParams,
+    error::ValidatorError,
     hardware::HardwareCheck,
-    http::reqwest::ReqwestClient,
-    key_manager::{InMemoryKeyManager, InMemoryKeyManagerConfig},
+    http::HttpClientConfig,
+    key_manager::{
+        InMemoryKeyManager, InMemoryKeyManagerConfig, KeyManager, KeyManagerAccess, KeyType,
+    },
+    logging::{self, LogFormat},
+    network::{Network, NetworkPreset},
+    pid_file::PidFile,
+    telemetry,
+    watchdog::{notify_ready, run_watchdog, HeartbeatAccess},
+    webhooks::run_webhooks,
 };
 use validator::{context::AppContext, state::generate_state};
-use validator::{cron::run_crons, server::run_server};
+use validator::{
+    cron::run_crons,
+    server::{bind_listener, start_server},
+};
+
+mod commands;
+mod preflight;
 
 #[derive(Clone, Debug, Parser)]
-struct CliOpts {
-    /// Do not start cron jobs
-    #[clap(long)]
-    no_cron: bool,
+#[clap(version, long_version = build_info::LONG_VERSION)]
+struct Cli {
+    #[clap(flatten)]
+    config: ConfigOpts,
+
+    /// Command to run, runs both the server and cron jobs if omitted
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum Command {
+    /// Start HTTP server
+    Serve,
+    /// Run cron jobs
+    Cron,
+    /// Run pending database migrations
+    Migrate,
+    /// Generate new validator wallet
+    Keygen {
+        /// Write wallet to file instead of stdout
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Key type, rsa-2048 or rsa-4096
+        #[clap(long, default_value = "rsa-2048")]
+        key_type: KeyType,
+        /// Also print the public JWK, which holds no private key and can be
+        /// shared with the bundler
+        #[clap(long)]
+        print_public: bool,
+    },
+    /// Export transactions from database as JSON
+    Export {
+        /// Only export transactions from given epoch
+        #[clap(long)]
+        epoch: Option<u128>,
+    },
+    /// Database maintenance
+    Db {
+        #[clap(subcommand)]
+        command: DbCommand,
+    },
+    /// Export or import validator state as a compressed archive
+    Snapshot {
+        #[clap(subcommand)]
+        command: SnapshotCommand,
+    },
+    /// Peer connectivity
+    Peers {
+        #[clap(subcommand)]
+        command: PeersCommand,
+    },
+    /// Print role, epoch, backlog and cron task health of a running
+    /// validator
+    Status {
+        /// API URL of the validator [default: configured listen address]
+        #[clap(long)]
+        url: Option<Url>,
+    },
+    /// Verify configuration and validator key
+    Verify,
+    /// Verify a bundle and print a JSON report of its data items
+    VerifyBundle {
+        /// Path to bundle file or id of bundle transaction to download
+        bundle: String,
+    },
+    /// Check a receipt's signature offline and tell why it doesn't verify
+    VerifyReceipt {
+        /// Path to receipt JSON with block, tx_id and signature, `-` reads
+        /// it from stdin
+        receipt: String,
+        /// Base64url encoded public modulus of the bundler key, as returned
+        /// by the bundler's /public endpoint
+        #[clap(long)]
+        bundler_public_key: String,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum DbCommand {
+    /// Delete bundles validated before the retention period, together with
+    /// their transactions, and print what was deleted
+    Prune {
+        /// Retention period in days [default: retention_days from config]
+        #[clap(long)]
+        older_than_days: Option<u64>,
+        /// Only print what would be deleted
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
 
-    /// Do not start app in server mode
-    #[clap(long)]
-    no_server: bool,
+#[derive(Clone, Debug, Subcommand)]
+enum SnapshotCommand {
+    /// Write the database tables of this validator to a snapshot file
+    Create {
+        /// Snapshot file to write
+        output: PathBuf,
+    },
+    /// Bootstrap an empty database from a snapshot file, running pending
+    /// migrations first
+    Restore {
+        /// Snapshot file to read
+        input: PathBuf,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum PeersCommand {
+    /// Request /info and /tx/{id} of configured and registered peers and
+    /// print their reachability and latency
+    Ping {
+        /// Transaction looked up on peers [default: latest stored
+        /// transaction]
+        #[clap(long)]
+        tx: Option<String>,
+    },
+}
+
+/// Configuration options shared by all commands
+#[derive(Clone, Debug, Args)]
+struct ConfigOpts {
+    /// Path to TOML or YAML config file, command line options and
+    /// environment variables override values from the file
+    #[clap(short, long, env = "VALIDATOR_CONFIG", global = true)]
+    config: Option<PathBuf>,
+
+    /// Network to join, mainnet, testnet or devnet. Selects default bundler,
+    /// Arweave and contract gateway URLs and epoch parameters, each of them
+    /// can be overridden by its own option.
+    #[clap(long, env = "VALIDATOR_NETWORK", global = true)]
+    network: Option<Network>,
 
     /// Database connection URL
-    #[clap(long, env)]
-    database_url: String,
+    #[clap(long, env = "VALIDATOR_DATABASE_URL", global = true)]
+    database_url: Option<String>,
 
-    /// Listen address for the server
-    #[clap(short, long, env, default_value = "0.0.0.0:42069")]
-    listen: SocketAddr,
+    /// Listen address for the server [default: 0.0.0.0:42069]
+    #[clap(short, long, env = "VALIDATOR_LISTEN", global = true)]
+    listen: Option<SocketAddr>,
 
     /// URL for the bundler connection
-    #[clap(long, env = "BUNDLER_URL")]
-    bundler_url: Url,
+    #[clap(long, env = "VALIDATOR_BUNDLER_URL", global = true)]
+    bundler_url: Option<Url>,
 
     /// Path to JWK file holding validator private key
-    #[clap(long, env = "VALIDATOR_KEY")]
-    validator_key: String,
+    #[clap(long, env = "VALIDATOR_KEY", global = true)]
+    validator_key: Option<String>,
 
-    #[clap(long, env = "ARWEAVE_URL")]
+    #[clap(long, env = "VALIDATOR_ARWEAVE_URL", global = true)]
     arweave_url: Option<Url>,
 
-    #[clap(long)]
-    bundler_key: Option<Url>,
+    /// Arweave gateway is an ArLocal instance: blocks are mined before
+    /// listing bundles and bundles are validated without waiting for a
+    /// block. Enabled by default on devnet.
+    #[clap(long, env = "VALIDATOR_DEV_ARLOCAL", global = true)]
+    dev_arlocal: bool,
+
+    /// Base64url encoded public modulus of the bundler key, the validator
+    /// refuses to start if the bundler's /public endpoint serves another key
+    /// and refuses other keys it serves later
+    #[clap(long, env = "VALIDATOR_BUNDLER_KEY", global = true)]
+    bundler_key: Option<String>,
+
+    /// Base64url encoded public moduli of other keys the bundler may sign
+    /// receipts with, e.g. while it rotates its key
+    #[clap(
+        long = "additional-bundler-key",
+        env = "VALIDATOR_ADDITIONAL_BUNDLER_KEYS",
+        use_value_delimiter = true,
+        global = true
+    )]
+    additional_bundler_keys: Vec<String>,
+
+    /// URL for the contract gateway [default: http://localhost:3000]
+    #[clap(long, env = "VALIDATOR_CONTRACT_GATEWAY_URL", global = true)]
+    contract_gateway_url: Option<Url>,
+
+    /// Log level [default: info]
+    #[clap(long, env = "VALIDATOR_LOG_LEVEL", global = true)]
+    log_level: Option<LevelFilter>,
+
+    /// Log output format, text or json [default: text]
+    #[clap(long, env = "VALIDATOR_LOG_FORMAT", global = true)]
+    log_format: Option<LogFormat>,
 
+    /// OTLP collector endpoint, tracing spans are exported when set
+    #[clap(long, env = "VALIDATOR_OTLP_ENDPOINT", global = true)]
+    otlp_endpoint: Option<Url>,
+
+    /// Peer validator URLs used for looking up transaction receipts
     #[clap(
-        long,
-        env = "CONTRACT_GATEWAY",
-        default_value = "http://localhost:3000"
+        long = "peer",
+        env = "VALIDATOR_PEERS",
+        use_value_delimiter = true,
+        global = true
     )]
+    peers: Vec<Url>,
+
+    /// Downloaded bundles verified at the same time [default: number of
+    /// CPUs]
+    #[clap(long, env = "VALIDATOR_WORKERS", global = true)]
+    workers: Option<usize>,
+
+    /// Set SO_REUSEPORT on the listening socket, so a new validator process
+    /// can take over the listen address before the old one exits
+    #[clap(long, env = "VALIDATOR_REUSE_PORT", global = true)]
+    reuse_port: bool,
+
+    /// Proxy for all outgoing requests, HTTP_PROXY and HTTPS_PROXY are used
+    /// when not set
+    #[clap(long, env = "VALIDATOR_PROXY", global = true)]
+    proxy: Option<Url>,
+
+    /// Append responses to outgoing requests to this file, to be replayed
+    /// as test fixtures
+    #[clap(long, env = "VALIDATOR_RECORD_HTTP", global = true)]
+    record_http: Option<PathBuf>,
+
+    /// Write the process id to this file while the validator runs
+    #[clap(long, env = "VALIDATOR_PID_FILE", global = true)]
+    pid_file: Option<PathBuf>,
+}
+
+/// Environment variable names released before the `VALIDATOR_` prefix was
+/// introduced, mapped to their current name. `VALIDATOR_KEY` kept its name.
+/// Options added since never had an unprefixed name, aliasing generic names
+/// would pick up unrelated variables.
+const LEGACY_ENV_VARS: &[(&str, &str)] = &[
+    ("DATABASE_URL", "VALIDATOR_DATABASE_URL"),
+    ("LISTEN", "VALIDATOR_LISTEN"),
+    ("BUNDLER_URL", "VALIDATOR_BUNDLER_URL"),
+    ("ARWEAVE_URL", "VALIDATOR_ARWEAVE_URL"),
+    ("CONTRACT_GATEWAY", "VALIDATOR_CONTRACT_GATEWAY_URL"),
+];
+
+/// Copy values of legacy environment variables to their `VALIDATOR_`
+/// prefixed counterparts unless those are already set. Returns the legacy
+/// names that were used along with the names replacing them.
+fn apply_legacy_env_vars(
+    aliases: &[(&'static str, &'static str)],
+) -> Vec<(&'static str, &'static str)> {
+    let mut used = Vec::new();
+    for (legacy, current) in aliases {
+        if std::env::var_os(current).is_some() {
+            continue;
+        }
+        if let Some(value) = std::env::var_os(legacy) {
+            std::env::set_var(current, value);
+            used.push((*legacy, *current));
+        }
+    }
+    used
+}
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:42069";
+const DEFAULT_CONTRACT_GATEWAY_URL: &str = "http://localhost:3000";
+
+/// Configuration resolved from command line, environment and config file
+#[derive(Clone, Debug)]
+struct AppConfig {
+    database_url: String,
+    listen: SocketAddr,
+    reuse_port: bool,
+    bundler_url: Url,
+    validator_key: String,
+    arweave_url: Option<Url>,
+    /// Whether `arweave_url` is the gateway served by the bundler, to be
+    /// followed when the bundler moves
+    arweave_follows_bundler: bool,
+    dev_arlocal: bool,
     contract_gateway_url: Url,
+    otlp_endpoint: Option<Url>,
+    proxy: Option<Url>,
+    http_client: HttpClientConfig,
+    record_http: Option<PathBuf>,
+    pid_file: Option<PathBuf>,
+    bundle_store: BundleStorage,
+    event_broker: Option<BrokerConfig>,
+    epoch_params: Option<EpochParams>,
+    /// Fetched from the bundler's /info during preflight
+    bundler_config: Option<BundlerConfig>,
+    runtime: RuntimeConfig,
+}
+
+impl AppConfig {
+    fn http_client_builder(&self) -> anyhow::Result<reqwest::ClientBuilder> {
+        http_client_builder(&self.http_client, self.proxy.as_ref())
+    }
+}
+
+/// Builder for clients sending requests through `proxy`, with the
+/// `[http_client]` settings applied. Without a proxy, reqwest picks one up
+/// from environment.
+fn http_client_builder(
+    config: &HttpClientConfig,
+    proxy: Option<&Url>,
+) -> anyhow::Result<reqwest::ClientBuilder> {
+    let builder = config.apply(reqwest::Client::builder());
+    match proxy {
+        Some(proxy) => {
+            let proxy = reqwest::Proxy::all(proxy.clone()).context("Invalid proxy URL")?;
+            Ok(builder.proxy(proxy))
+        }
+        None => Ok(builder),
+    }
+}
+
+impl ConfigOpts {
+    fn file_config(&self) -> Result<FileConfig, ConfigError> {
+        match &self.config {
+            Some(path) => FileConfig::load(path),
+            None => Ok(FileConfig::default()),
+        }
+    }
+
+    /// Log format is needed before logger is initialized, so any error
+    /// reading the config file is reported later when loading the full config
+    fn log_format(&self) -> LogFormat {
+        let file = self.file_config().unwrap_or_default();
+        merge_option(self.log_format, file.log_format).unwrap_or_default()
+    }
+
+    /// Same as `log_format`, tracer is installed together with the logger
+    fn otlp_endpoint(&self) -> Option<Url> {
+        let file = self.file_config().unwrap_or_default();
+        merge_option(self.otlp_endpoint.clone(), file.otlp_endpoint)
+    }
+
+    /// Defaults of the network selected on command line or in the config
+    /// file
+    fn preset(&self, file: &FileConfig) -> Option<NetworkPreset> {
+        merge_option(self.network, file.network).map(|network| network.preset())
+    }
+
+    /// Same as `AppConfig::http_client_builder`, for commands that don't
+    /// need the full config
+    fn http_client_builder(&self) -> anyhow::Result<reqwest::ClientBuilder> {
+        let file = self.file_config()?;
+        http_client_builder(
+            &file.http_client.unwrap_or_default(),
+            merge_option(self.proxy.clone(), file.proxy).as_ref(),
+        )
+    }
+
+    fn database_url(&self) -> Result<String, ConfigError> {
+        let file = self.file_config()?;
+        require_option("database_url", self.database_url.clone(), file.database_url)
+    }
+
+    fn arweave_url(&self) -> Result<Option<Url>, ConfigError> {
+        let file = self.file_config()?;
+        let preset = self.preset(&file);
+        Ok(merge_option(self.arweave_url.clone(), file.arweave_url)
+            .or_else(|| preset.map(|preset| preset.arweave_url)))
+    }
+
+    fn resolve(self) -> Result<AppConfig, ConfigError> {
+        let file = self.file_config()?;
+        let preset = self.preset(&file);
+        let runtime =
+            RuntimeConfig::from_file_config(self.log_level, self.peers, self.workers, &file);
+        let runtime = RuntimeConfig {
+            bundler_key: merge_option(self.bundler_key, runtime.bundler_key),
+            additional_bundler_keys: if self.additional_bundler_keys.is_empty() {
+                runtime.additional_bundler_keys
+            } else {
+                self.additional_bundler_keys
+            },
+            ..runtime
+        };
+
+        Ok(AppConfig {
+            database_url: require_option("database_url", self.database_url, file.database_url)?,
+            listen: merge_option(self.listen, file.listen)
+                .unwrap_or_else(|| DEFAULT_LISTEN.parse().unwrap()),
+            reuse_port: self.reuse_port || file.reuse_port.unwrap_or(false),
+            bundler_url: require_option(
+                "bundler_url",
+                self.bundler_url,
+                file.bundler_url
+                    .or_else(|| preset.as_ref().map(|preset| preset.bundler_url.clone())),
+            )?,
+            validator_key: require_option("validator_key", self.validator_key, file.validator_key)?,
+            arweave_url: merge_option(self.arweave_url, file.arweave_url)
+                .or_else(|| preset.as_ref().map(|preset| preset.arweave_url.clone())),
+            arweave_follows_bundler: false,
+            dev_arlocal: self.dev_arlocal
+                || file.dev_arlocal.unwrap_or_else(|| {
+                    merge_option(self.network, file.network) == Some(Network::Devnet)
+                }),
+            contract_gateway_url: merge_option(
+                self.contract_gateway_url,
+                file.contract_gateway_url,
+            )
+            .or_else(|| {
+                preset
+                    .as_ref()
+                    .map(|preset| preset.contract_gateway_url.clone())
+            })
+            .unwrap_or_else(|| Url::from_str(DEFAULT_CONTRACT_GATEWAY_URL).unwrap()),
+            otlp_endpoint: merge_option(self.otlp_endpoint, file.otlp_endpoint),
+            proxy: merge_option(self.proxy, file.proxy),
+            http_client: file.http_client.clone().unwrap_or_default(),
+            record_http: self.record_http,
+            pid_file: merge_option(self.pid_file, file.pid_file),
+            bundle_store: file.bundle_store.clone().unwrap_or_default(),
+            event_broker: file.event_broker.clone(),
+            epoch_params: preset.map(|preset| preset.epoch_params),
+            bundler_config: None,
+            runtime,
+        })
+    }
+}
+
+/// Re-read configuration on SIGHUP and replace the runtime-tunable part of it
+async fn reload_on_sighup(opts: ConfigOpts, ctx: AppContext) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!("Failed to install SIGHUP handler: {}", err);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        match opts.clone().resolve() {
+            Ok(config) => {
+                let runtime = config.runtime;
+                if let Err(err) = ctx.get_key_manager().set_bundler_keys(
+                    runtime.bundler_key.as_deref(),
+                    &runtime.additional_bundler_keys,
+                ) {
+                    error!(
+                        "Failed to reload configuration, invalid bundler key: {}",
+                        err
+                    );
+                    continue;
+                }
+                info!("Reloading runtime configuration");
+                ctx.replace_runtime_config(runtime);
+            }
+            Err(err) => error!("Failed to reload configuration: {}", err),
+        }
+    }
+}
+
+async fn wait_for_shutdown() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = terminate.recv() => (),
+        res = tokio::signal::ctrl_c() => res?,
+    }
+    Ok(())
 }
 
 // TODO: merge config should return own type as returned arweave_url can never be None
-fn merge_configs(config: CliOpts, bundler_config: BundlerConfig) -> CliOpts {
-    let arweave_url = match config.arweave_url {
-        Some(u) => Some(u),
+fn merge_configs(config: AppConfig, bundler_config: BundlerConfig) -> anyhow::Result<AppConfig> {
+    let (arweave_url, arweave_follows_bundler) = match config.arweave_url {
+        Some(u) => (Some(u), false),
         None => {
             let url_string = format!("https://{}", bundler_config.gateway);
-            let url = url::Url::from_str(&url_string).unwrap();
-            Some(url)
+            let url = url::Url::from_str(&url_string).with_context(|| {
+                format!(
+                    "Bundler serves an invalid Arweave gateway {:?}",
+                    bundler_config.gateway
+                )
+            })?;
+            (Some(url), true)
         }
     };
 
-    CliOpts {
+    Ok(AppConfig {
         arweave_url,
+        arweave_follows_bundler,
+        bundler_config: Some(bundler_config),
         ..config
-    }
+    })
 }
 
 fn public_only_jwk_from_rsa_n(encoded_n: &str) -> Result<JsonWebKey, DecodeError> {
@@ -110,54 +558,94 @@ pub trait IntoAsync<T> {
 }
 
 #[async_trait::async_trait]
-impl IntoAsync<AppContext> for CliOpts {
-    async fn into_async(&self) -> AppContext {
-        let fmt_bundler_url: String = self.bundler_url.to_string().replace(&['\"', '\''][..], "");
-        dbg!(&fmt_bundler_url);
-        let n_response = reqwest::get(format!("{}public", fmt_bundler_url))
-            .await
-            .expect("Couldn't get public key from bundler")
-            .text()
-            .await
-            .expect("Couldn't parse public key response from bundler");
-
-        let bundler_jwk =
-            public_only_jwk_from_rsa_n(&n_response).expect("Failed to decode bundler key");
-
-        let validator_jwk: JsonWebKey = {
-            let file = fs::read_to_string(&self.validator_key).unwrap();
-            file.parse().unwrap()
-        };
+impl IntoAsync<anyhow::Result<AppContext>> for AppConfig {
+    async fn into_async(&self) -> anyhow::Result<AppContext> {
+        let http_client = self
+            .http_client_builder()?
+            .build()
+            .context("Failed to build HTTP client")?;
+        let bundler_jwk = preflight::fetch_bundler_jwk(&http_client, &self.bundler_url).await?;
+        preflight::check_bundler_key(self.runtime.bundler_key.as_deref(), &bundler_jwk)?;
+        let validator_jwk = preflight::read_validator_key(&self.validator_key)?;
 
         let key_manager = InMemoryKeyManager::new(&Keys(bundler_jwk, validator_jwk));
+        key_manager
+            .set_bundler_keys(
+                self.runtime.bundler_key.as_deref(),
+                &self.runtime.additional_bundler_keys,
+            )
+            .map_err(ValidatorError::from)
+            .context("Failed to decode bundler keys")?;
+        if let Some(bundler_config) = &self.bundler_config {
+            info!(
+                version = %bundler_config.version,
+                receipt_version = ?bundler_config.receipt_version,
+                "Bundler config fetched"
+            );
+            match bundler_config.address("arweave") {
+                Some(address) if address != key_manager.bundler_address() => warn!(
+                    address,
+                    key_address = key_manager.bundler_address(),
+                    "Bundler's Arweave address on /info doesn't match its public key"
+                ),
+                _ => (),
+            }
+        }
         let state = generate_state();
+        if let Some(params) = self.epoch_params {
+            state
+                .set_epoch_params(params)
+                .map_err(ValidatorError::from)
+                .context("Invalid epoch parameters")?;
+        }
 
         let connection_mgr = ConnectionManager::<PgConnection>::new(&self.database_url);
 
         let pool = r2d2::Pool::builder()
             .build(connection_mgr)
-            .expect("Failed to create database connection pool.");
+            .context("Failed to create database connection pool")?;
 
-        let arweave_url = match &self.arweave_url {
-            Some(url) => url,
-            None => unreachable!(),
-        };
+        let arweave_url = self
+            .arweave_url
+            .as_ref()
+            .context("Arweave URL is not configured")?;
 
-        AppContext::new(
+        let ctx = AppContext::new(
             key_manager,
             pool,
             self.listen,
             state,
-            reqwest::Client::new(),
+            http_client,
             arweave_url,
             &self.bundler_url,
+            self.bundler_config.clone(),
             &self.contract_gateway_url,
-        )
+            self.runtime.clone(),
+            self.bundle_store.clone(),
+        );
+
+        let ctx = if self.dev_arlocal {
+            ctx.dev_arlocal()
+        } else {
+            ctx
+        };
+        let ctx = if self.arweave_follows_bundler {
+            ctx.arweave_following_bundler()
+        } else {
+            ctx
+        };
+        let ctx = ctx.rate_limited();
+        Ok(match &self.record_http {
+            Some(path) => ctx
+                .record_http(path.clone())
+                .with_context(|| format!("Failed to open HTTP fixtures {}", path.display()))?,
+            None => ctx,
+        })
     }
 }
 
-fn main() -> () {
-    actix_rt::System::new().block_on(async {
+fn run(opts: ConfigOpts, with_server: bool, with_cron: bool) -> anyhow::Result<()> {
+    actix_rt::System::new().block_on(async move {
         let sys = System::new_all();
         System::print_hardware_info(&sys);
         // let enough_resources = System::has_enough_resources(&sys);
@@ -166,32 +654,240 @@ fn main() -> () {
         // process::exit(1);
         // }
 
-        dotenv::dotenv().ok();
-
-        env_logger::init_from_env(Env::default().default_filter_or("info"));
+        let config = opts.clone().resolve().map_err(ValidatorError::from)?;
+        // Taken before preflight, so a second validator started by mistake
+        // stops without touching anything
+        let _pid_file = match &config.pid_file {
+            Some(path) => Some(
+                PidFile::create(path)
+                    .with_context(|| format!("Failed to write PID file {}", path.display()))?,
+            ),
+            None => None,
+        };
+        let config = preflight::preflight(config).await?;
+        config.runtime.apply();
+        let publisher = match &config.event_broker {
+            Some(broker) => Some(
+                Publisher::connect(broker)
+                    .await
+                    .context("Failed to set up event broker")?,
+            ),
+            None => None,
+        };
+        // Bind before connecting anything else, so the address is taken
+        // over from a previous process as early as possible
+        let listener = if with_server {
+            Some(bind_listener(&config.listen, config.reuse_port).map_err(ValidatorError::from)?)
+        } else {
+            None
+        };
+        let ctx = config.into_async().await?;
 
-        let http_client = ReqwestClient::new(reqwest::Client::new());
-        let app_config = CliOpts::parse();
-        let bundler_config =
-            BundlerConfig::fetch_config(http_client, &app_config.bundler_url).await;
-        let config = merge_configs(app_config, bundler_config);
-        let ctx = config.into_async().await;
+        tokio::task::spawn_local(reload_on_sighup(opts, ctx.clone()));
+        tokio::task::spawn_local(run_webhooks(ctx.clone()));
+        tokio::task::spawn_local(run_watchdog(ctx.clone()));
+        if let Some(publisher) = publisher {
+            tokio::task::spawn_local(run_publisher(ctx.clone(), publisher));
+        }
 
-        if !config.no_cron {
-            paris::info!("Running with cron");
+        if with_cron {
+            info!("Running with cron");
             tokio::task::spawn_local(run_crons(ctx.clone()));
-        };
+        }
 
-        if !config.no_server {
-            paris::info!("Running with server");
-            run_server(ctx.clone()).await.unwrap()
-        };
-    });
+        if let Some(listener) = listener {
+            info!("Running with server");
+            let server = start_server(ctx.clone(), listener).map_err(ValidatorError::from)?;
+            notify_ready();
+            // Server stops accepting connections on SIGTERM or SIGINT and
+            // finishes once in-flight requests are handled
+            server.await.map_err(ValidatorError::from)?;
+        } else {
+            notify_ready();
+            wait_for_shutdown().await.map_err(ValidatorError::from)?;
+        }
+
+        if with_cron {
+            info!("Shutting down, waiting for running validations to finish");
+            ctx.heartbeats().drain().await;
+        }
+
+        Ok(())
+    })
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+
+    let legacy_env_vars = apply_legacy_env_vars(LEGACY_ENV_VARS);
+    let cli = Cli::parse();
+    let otlp_endpoint = cli.config.otlp_endpoint();
+    let exporter = match otlp_endpoint
+        .as_ref()
+        .map(telemetry::Exporter::install)
+        .transpose()
+    {
+        Ok(exporter) => exporter,
+        Err(err) => {
+            eprintln!("Failed to install OTLP exporter: {}", err);
+            process::exit(1);
+        }
+    };
+    logging::init(
+        cli.config.log_format(),
+        exporter.as_ref().map(telemetry::Exporter::tracer),
+    );
+    if let Some(endpoint) = otlp_endpoint {
+        info!(%endpoint, "Exporting traces");
+    }
+    for (legacy, current) in legacy_env_vars {
+        warn!(
+            variable = legacy,
+            replacement = current,
+            "Environment variable {} is deprecated, use {} instead",
+            legacy,
+            current
+        );
+    }
+    let res = match cli.command {
+        None => run(cli.config, true, true),
+        Some(Command::Serve) => run(cli.config, true, false),
+        Some(Command::Cron) => run(cli.config, false, true),
+        Some(Command::Migrate) => commands::migrate(&cli.config),
+        Some(Command::Keygen {
+            output,
+            key_type,
+            print_public,
+        }) => commands::keygen(output, key_type, print_public),
+        Some(Command::Export { epoch }) => commands::export(&cli.config, epoch),
+        Some(Command::Db {
+            command:
+                DbCommand::Prune {
+                    older_than_days,
+                    dry_run,
+                },
+        }) => commands::prune(&cli.config, older_than_days, dry_run),
+        Some(Command::Snapshot {
+            command: SnapshotCommand::Create { output },
+        }) => commands::snapshot_create(&cli.config, &output),
+        Some(Command::Snapshot {
+            command: SnapshotCommand::Restore { input },
+        }) => commands::snapshot_restore(&cli.config, &input),
+        Some(Command::Peers {
+            command: PeersCommand::Ping { tx },
+        }) => commands::ping(&cli.config, tx),
+        Some(Command::Status { url }) => commands::status(&cli.config, url),
+        Some(Command::Verify) => commands::verify(cli.config),
+        Some(Command::VerifyBundle { bundle }) => commands::verify_bundle(&cli.config, bundle),
+        Some(Command::VerifyReceipt {
+            receipt,
+            bundler_public_key,
+        }) => commands::verify_receipt(&receipt, &bundler_public_key),
+    };
+
+    if let Some(exporter) = exporter {
+        exporter.shutdown();
+    }
+    if let Err(err) = res {
+        error!("{:#}", err);
+        process::exit(1);
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::public_only_jwk_from_rsa_n;
+    use clap::Parser;
+    use validator::bundler::BundlerConfig;
+
+    use crate::{
+        apply_legacy_env_vars, merge_configs, preflight::check_bundler_key,
+        public_only_jwk_from_rsa_n, Cli,
+    };
+
+    #[test]
+    fn legacy_env_var_is_copied_to_prefixed_name() {
+        std::env::set_var("LEGACY_ALIAS_TEST", "legacy");
+        std::env::remove_var("VALIDATOR_LEGACY_ALIAS_TEST");
+
+        let used = apply_legacy_env_vars(&[("LEGACY_ALIAS_TEST", "VALIDATOR_LEGACY_ALIAS_TEST")]);
+
+        assert_eq!(
+            used,
+            vec![("LEGACY_ALIAS_TEST", "VALIDATOR_LEGACY_ALIAS_TEST")]
+        );
+        assert_eq!(
+            std::env::var("VALIDATOR_LEGACY_ALIAS_TEST").unwrap(),
+            "legacy"
+        );
+    }
+
+    #[test]
+    fn prefixed_env_var_takes_precedence_over_legacy_name() {
+        std::env::set_var("LEGACY_PRECEDENCE_TEST", "legacy");
+        std::env::set_var("VALIDATOR_LEGACY_PRECEDENCE_TEST", "current");
+
+        let used = apply_legacy_env_vars(&[(
+            "LEGACY_PRECEDENCE_TEST",
+            "VALIDATOR_LEGACY_PRECEDENCE_TEST",
+        )]);
+
+        assert!(used.is_empty());
+        assert_eq!(
+            std::env::var("VALIDATOR_LEGACY_PRECEDENCE_TEST").unwrap(),
+            "current"
+        );
+    }
+
+    #[test]
+    fn network_preset_is_overridden_by_options() {
+        let cli = Cli::parse_from([
+            "validator",
+            "--network",
+            "devnet",
+            "--database-url",
+            "postgres://localhost/validator",
+            "--validator-key",
+            "wallet.json",
+            "--arweave-url",
+            "http://arlocal:1984",
+        ]);
+
+        let config = cli.config.resolve().unwrap();
+        assert_eq!(config.arweave_url.unwrap().as_str(), "http://arlocal:1984/");
+        assert_eq!(config.bundler_url.as_str(), "http://localhost:10000/");
+        assert_eq!(
+            config.contract_gateway_url.as_str(),
+            "http://localhost:3000/"
+        );
+        assert_eq!(config.epoch_params.unwrap().epoch_length, 10);
+        assert!(config.dev_arlocal);
+    }
+
+    #[test]
+    fn invalid_gateway_served_by_bundler_is_an_error() {
+        let cli = Cli::parse_from([
+            "validator",
+            "--database-url",
+            "postgres://localhost/validator",
+            "--validator-key",
+            "wallet.json",
+            "--bundler-url",
+            "http://localhost:10000",
+        ]);
+        let config = cli.config.resolve().unwrap();
+        let bundler_config = |gateway: &str| BundlerConfig {
+            version: "0.2.0".to_string(),
+            gateway: gateway.to_string(),
+            addresses: Default::default(),
+            receipt_version: None,
+        };
+
+        let merged = merge_configs(config.clone(), bundler_config("arweave.net")).unwrap();
+        assert_eq!(merged.arweave_url.unwrap().as_str(), "https://arweave.net/");
+        assert!(merged.arweave_follows_bundler);
+
+        assert!(merge_configs(config, bundler_config("not a gateway")).is_err());
+    }
 
     #[test]
     fn when_building_jwk_from_encoded_public_key_then_serialized_n_matches() {
@@ -206,4 +902,14 @@ mod tests {
 
         assert_eq!(encoded_n, n);
     }
+
+    #[test]
+    fn pinned_bundler_key_must_match_served_key() {
+        let served = public_only_jwk_from_rsa_n("sq9JbppKLlAKtQwalfX5Dag").unwrap();
+
+        assert!(check_bundler_key(None, &served).is_ok());
+        assert!(check_bundler_key(Some("sq9JbppKLlAKtQwalfX5Dag"), &served).is_ok());
+        assert!(check_bundler_key(Some("AQAB"), &served).is_err());
+        assert!(check_bundler_key(Some("not base64!"), &served).is_err());
+    }
 }
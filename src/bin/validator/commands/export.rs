@@ -0,0 +1,21 @@
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use validator::database::{
+    models::{Epoch, Transaction},
+    schema::transactions::dsl,
+};
+
+use crate::ConfigOpts;
+
+pub fn export(opts: &ConfigOpts, epoch: Option<u128>) -> anyhow::Result<()> {
+    let conn = PgConnection::establish(&opts.database_url()?)?;
+
+    let mut query = dsl::transactions.order_by(dsl::id).into_boxed();
+    if let Some(epoch) = epoch {
+        query = query.filter(dsl::epoch.eq(Epoch(epoch)));
+    }
+
+    let txs = query.load::<Transaction>(&conn)?;
+    println!("{}", serde_json::to_string(&txs)?);
+
+    Ok(())
+}
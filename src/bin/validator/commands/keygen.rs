@@ -0,0 +1,38 @@
+use std::{fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt, path::PathBuf};
+
+use anyhow::Context;
+use validator::key_manager::{generate_jwk, public_jwk, split_jwk, KeyType};
+
+/// Generate a validator wallet, written to `output` or printed. With
+/// `print_public` the public JWK is printed as well, to be shared with the
+/// bundler.
+///
+/// The wallet file is only readable by its owner, and an existing file is
+/// never overwritten.
+pub fn keygen(
+    output: Option<PathBuf>,
+    key_type: KeyType,
+    print_public: bool,
+) -> anyhow::Result<()> {
+    let jwk = generate_jwk(key_type);
+
+    match output {
+        Some(path) => {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path)
+                .with_context(|| format!("Failed to create wallet file {}", path.display()))?;
+            file.write_all(jwk.to_string().as_bytes())?;
+            let (_, _, address) = split_jwk(&jwk);
+            tracing::info!("Wallet {} written to {}", address, path.display());
+        }
+        None => println!("{}", jwk),
+    }
+    if print_public {
+        println!("{}", public_jwk(&jwk));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,11 @@
+use diesel::{Connection, PgConnection};
+use validator::database::run_migrations;
+
+use crate::ConfigOpts;
+
+pub fn migrate(opts: &ConfigOpts) -> anyhow::Result<()> {
+    let conn = PgConnection::establish(&opts.database_url()?)?;
+    run_migrations(&conn)?;
+    tracing::info!("Database migrations complete");
+    Ok(())
+}
@@ -0,0 +1,21 @@
+mod export;
+mod keygen;
+mod migrate;
+mod peers;
+mod prune;
+mod snapshot;
+mod status;
+mod verify;
+mod verify_bundle;
+mod verify_receipt;
+
+pub use export::export;
+pub use keygen::keygen;
+pub use migrate::migrate;
+pub use peers::ping;
+pub use prune::prune;
+pub use snapshot::{snapshot_create, snapshot_restore};
+pub use status::status;
+pub use verify::verify;
+pub use verify_bundle::verify_bundle;
+pub use verify_receipt::verify_receipt;
@@ -0,0 +1,71 @@
+use std::{str::FromStr, time::Duration};
+
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use futures::future::join_all;
+use tracing::warn;
+use url::Url;
+use validator::{
+    database::{
+        models::Validator,
+        schema::{transactions, validators},
+    },
+    http::{reqwest::ReqwestClient, ClientAccess},
+    peers::ping_peer,
+};
+
+use crate::ConfigOpts;
+
+/// Requests to a peer taking longer than this are reported as failed
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct PingContext(ReqwestClient);
+
+impl ClientAccess<ReqwestClient> for PingContext {
+    fn get_http_client(&self) -> &ReqwestClient {
+        &self.0
+    }
+}
+
+/// Ping configured peers and validators stored from the contract, looking
+/// up `tx_id` or the most recently stored transaction on each
+pub fn ping(opts: &ConfigOpts, tx_id: Option<String>) -> anyhow::Result<()> {
+    let file = opts.file_config()?;
+    let mut peers = if opts.peers.is_empty() {
+        file.peers.unwrap_or_default()
+    } else {
+        opts.peers.clone()
+    };
+
+    let conn = PgConnection::establish(&opts.database_url()?)?;
+    for validator in validators::table.load::<Validator>(&conn)? {
+        match validator.url.as_deref().map(Url::from_str) {
+            Some(Ok(url)) if !peers.contains(&url) => peers.push(url),
+            Some(Err(err)) => {
+                warn!(validator = %validator.address, "Invalid validator url - {}", err)
+            }
+            _ => (),
+        }
+    }
+
+    let tx_id = match tx_id {
+        Some(tx_id) => Some(tx_id),
+        None => transactions::table
+            .select(transactions::id)
+            .order_by(transactions::updated_at.desc())
+            .first::<String>(&conn)
+            .ok(),
+    };
+
+    let client = opts.http_client_builder()?.timeout(PING_TIMEOUT).build()?;
+    let ctx = PingContext(ReqwestClient::new(client));
+    actix_rt::System::new().block_on(async move {
+        let pings = join_all(
+            peers
+                .iter()
+                .map(|peer| ping_peer(&ctx, peer, tx_id.as_deref())),
+        )
+        .await;
+        println!("{}", serde_json::to_string(&pings)?);
+        Ok(())
+    })
+}
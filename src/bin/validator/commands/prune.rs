@@ -0,0 +1,22 @@
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use diesel::{Connection, PgConnection};
+use validator::{config::merge_option, database::prune};
+
+use crate::ConfigOpts;
+
+/// Delete bundles validated more than `older_than_days` days ago, falling
+/// back to `retention_days` from the config file
+pub fn prune(opts: &ConfigOpts, older_than_days: Option<u64>, dry_run: bool) -> anyhow::Result<()> {
+    let file = opts.file_config()?;
+    let days = merge_option(older_than_days, file.retention_days)
+        .context("--older-than-days or retention_days in the config file is required")?;
+    let days = i64::try_from(days).context("Retention period too long")?;
+    let before = Utc::now() - Duration::days(days);
+
+    let conn = PgConnection::establish(&opts.database_url()?)?;
+    let report = prune::prune(&conn, before, dry_run)?;
+    println!("{}", serde_json::to_string(&report)?);
+
+    Ok(())
+}
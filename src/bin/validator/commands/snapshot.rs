@@ -0,0 +1,33 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use anyhow::Context;
+use diesel::{Connection, PgConnection};
+use validator::database::{run_migrations, snapshot};
+
+use crate::ConfigOpts;
+
+/// Write a snapshot of the database to `output` and print its header
+pub fn snapshot_create(opts: &ConfigOpts, output: &Path) -> anyhow::Result<()> {
+    let conn = PgConnection::establish(&opts.database_url()?)?;
+    let file =
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    let header = snapshot::create(&conn, BufWriter::new(file))?;
+    println!("{}", serde_json::to_string(&header)?);
+
+    Ok(())
+}
+
+/// Migrate the database and restore the snapshot at `input` into it
+pub fn snapshot_restore(opts: &ConfigOpts, input: &Path) -> anyhow::Result<()> {
+    let conn = PgConnection::establish(&opts.database_url()?)?;
+    run_migrations(&conn)?;
+    let file = File::open(input).with_context(|| format!("Failed to open {}", input.display()))?;
+    let header = snapshot::restore(&conn, BufReader::new(file))?;
+    println!("{}", serde_json::to_string(&header)?);
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use std::{fmt::Write, net::Ipv4Addr, time::Duration};
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize};
+use url::Url;
+use validator::{config::merge_option, state::ValidatorRole, watchdog::CronStatus};
+
+use crate::{ConfigOpts, DEFAULT_LISTEN};
+
+/// Requests to the validator API taking longer than this fail
+const STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+struct Info {
+    version: String,
+    git_commit: String,
+    latest_epoch: Option<u64>,
+    latest_block: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct State {
+    role: ValidatorRole,
+    epoch: u64,
+    block: u64,
+    bundler_underfunded: bool,
+    #[serde(default)]
+    bundler_key_mismatch: bool,
+    #[serde(default)]
+    validation_backlog: u64,
+    #[serde(default)]
+    pending_writes: u64,
+}
+
+/// Print a summary of `/info`, `/state` and `/crons` of the validator
+/// listening at `url`, or at the configured listen address
+pub fn status(opts: &ConfigOpts, url: Option<Url>) -> anyhow::Result<()> {
+    let url = match url {
+        Some(url) => url,
+        None => local_url(opts)?,
+    };
+
+    let client = reqwest::Client::builder().timeout(STATUS_TIMEOUT).build()?;
+    let (info, state, crons) = actix_rt::System::new().block_on(async {
+        let info: Info = get_json(&client, &url, "info").await?;
+        let state: State = get_json(&client, &url, "state").await?;
+        let crons: Vec<CronStatus> = get_json(&client, &url, "crons").await?;
+        Ok::<_, anyhow::Error>((info, state, crons))
+    })?;
+
+    print!("{}", format_status(&info, &state, &crons));
+    Ok(())
+}
+
+/// API address of a validator running with the same config, the unspecified
+/// address is reached through localhost
+fn local_url(opts: &ConfigOpts) -> anyhow::Result<Url> {
+    let file = opts.file_config()?;
+    let mut listen =
+        merge_option(opts.listen, file.listen).unwrap_or_else(|| DEFAULT_LISTEN.parse().unwrap());
+    if listen.ip().is_unspecified() {
+        listen.set_ip(Ipv4Addr::LOCALHOST.into());
+    }
+    Ok(Url::parse(&format!("http://{}/", listen))?)
+}
+
+async fn get_json<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &Url,
+    path: &str,
+) -> anyhow::Result<T> {
+    let url = url.join(path)?;
+    let res = client
+        .get(url.clone())
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach validator at {}", url))?
+        .error_for_status()?;
+    res.json()
+        .await
+        .with_context(|| format!("Unexpected response from {}", url))
+}
+
+fn format_status(info: &Info, state: &State, crons: &[CronStatus]) -> String {
+    let mut out = String::new();
+    let role = match state.role {
+        ValidatorRole::Cosigner => "cosigner",
+        ValidatorRole::Idle => "idle",
+        ValidatorRole::Leader => "leader",
+    };
+    writeln!(out, "Validator {} ({})", info.version, info.git_commit).unwrap();
+    writeln!(out, "Role:       {}", role).unwrap();
+    writeln!(out, "Epoch:      {}, block {}", state.epoch, state.block).unwrap();
+    match (info.latest_epoch, info.latest_block) {
+        (Some(epoch), Some(block)) => {
+            writeln!(out, "Validated:  up to epoch {}, block {}", epoch, block).unwrap()
+        }
+        _ => writeln!(out, "Validated:  nothing yet").unwrap(),
+    }
+    writeln!(
+        out,
+        "Backlog:    {} transactions awaiting validation, {} receipts being written",
+        state.validation_backlog, state.pending_writes
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "Bundler:    {}",
+        if state.bundler_key_mismatch {
+            "KEY MISMATCH, receipts not verified"
+        } else if state.bundler_underfunded {
+            "UNDERFUNDED"
+        } else {
+            "funded"
+        }
+    )
+    .unwrap();
+
+    if crons.is_empty() {
+        writeln!(out, "Cron tasks: none running in this process").unwrap();
+        return out;
+    }
+    writeln!(out, "Cron tasks:").unwrap();
+    let width = crons.iter().map(|cron| cron.task.len()).max().unwrap_or(0);
+    for cron in crons {
+        let health = if cron.stalled {
+            "STALLED"
+        } else if cron.consecutive_failures > 0 {
+            "FAILING"
+        } else if cron.running {
+            "running"
+        } else {
+            "ok"
+        };
+        let last_run = match cron.last_finished {
+            Some(at) => format!("last run {}", at.format("%Y-%m-%d %H:%M:%S UTC")),
+            None => "not run yet".to_string(),
+        };
+        writeln!(
+            out,
+            "  {:width$}  {:8} {}",
+            cron.task,
+            health,
+            last_run,
+            width = width
+        )
+        .unwrap();
+        if let (Some(error), Some(at)) = (&cron.last_error, cron.last_error_at) {
+            writeln!(
+                out,
+                "  {:width$}  last error at {}: {}",
+                "",
+                at.format("%Y-%m-%d %H:%M:%S UTC"),
+                error,
+                width = width
+            )
+            .unwrap();
+        }
+    }
+
+    out
+}
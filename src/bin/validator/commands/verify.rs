@@ -0,0 +1,23 @@
+use validator::key_manager::split_jwk;
+
+use crate::{preflight, ConfigOpts};
+
+pub fn verify(opts: ConfigOpts) -> anyhow::Result<()> {
+    let config = opts.resolve()?;
+
+    let jwk = preflight::read_validator_key(&config.validator_key)?;
+    let (_, _, address) = split_jwk(&jwk);
+
+    let config = actix_rt::System::new().block_on(preflight::preflight(config))?;
+
+    tracing::info!("Configuration OK");
+    println!("validator address: {}", address);
+    println!("bundler url: {}", config.bundler_url);
+    if let Some(arweave_url) = &config.arweave_url {
+        println!("arweave url: {}", arweave_url);
+    }
+    println!("contract gateway url: {}", config.contract_gateway_url);
+    println!("listen: {}", config.listen);
+
+    Ok(())
+}
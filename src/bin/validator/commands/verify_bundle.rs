@@ -0,0 +1,56 @@
+use std::path::Path;
+
+use anyhow::Context;
+use validator::{
+    bundle::{verify_bundle_file, BundleReport, DEFAULT_MAX_NESTING_DEPTH},
+    cron::arweave::{Arweave, ArweaveContext},
+    http::reqwest::ReqwestClient,
+    metrics::{Metrics, MetricsAccess},
+};
+
+use crate::ConfigOpts;
+
+struct DownloadContext(ReqwestClient, Metrics);
+
+impl ArweaveContext<ReqwestClient> for DownloadContext {
+    fn get_client(&self) -> &ReqwestClient {
+        &self.0
+    }
+}
+
+impl MetricsAccess for DownloadContext {
+    fn metrics(&self) -> &Metrics {
+        &self.1
+    }
+}
+
+/// Verify bundle stored at `bundle`, or download it first if `bundle` is
+/// not an existing file and treat it as a transaction id instead
+pub fn verify_bundle(opts: &ConfigOpts, bundle: String) -> anyhow::Result<()> {
+    let arweave_url = opts.arweave_url()?;
+
+    actix_rt::System::new().block_on(async move {
+        let path = if Path::new(&bundle).is_file() {
+            bundle.clone()
+        } else {
+            let arweave_url =
+                arweave_url.context("--arweave-url is required for downloading bundles")?;
+            let arweave = Arweave::new(arweave_url);
+            let ctx = DownloadContext(
+                ReqwestClient::new(reqwest::Client::new()),
+                Metrics::default(),
+            );
+
+            arweave
+                .get_tx_data(&ctx, &bundle)
+                .await
+                .with_context(|| format!("Failed to download bundle {}", bundle))?
+        };
+
+        let res = verify_bundle_file(&path, DEFAULT_MAX_NESTING_DEPTH).await;
+        let report = BundleReport::new(&bundle, &res);
+        println!("{}", serde_json::to_string(&report)?);
+
+        res.map(|_| ()).map_err(Into::into)
+    })
+}
@@ -0,0 +1,38 @@
+use std::{fs, io::Read, path::Path};
+
+use anyhow::Context;
+use data_encoding::BASE64URL_NOPAD;
+use validator::{
+    error::ValidatorError,
+    key_manager::owner_address,
+    receipt::{check_receipt, receipt_message, TxReceipt},
+};
+
+/// Verify receipt JSON read from `receipt`, `-` for stdin, against the
+/// bundler key with base64url encoded public modulus `bundler_public_key`
+pub fn verify_receipt(receipt: &str, bundler_public_key: &str) -> anyhow::Result<()> {
+    let json = if receipt == "-" {
+        let mut json = String::new();
+        std::io::stdin().read_to_string(&mut json)?;
+        json
+    } else {
+        fs::read_to_string(Path::new(receipt))
+            .with_context(|| format!("Failed to read receipt from {}", receipt))?
+    };
+    let receipt: TxReceipt = serde_json::from_str(&json).context("Invalid receipt")?;
+    let owner = BASE64URL_NOPAD
+        .decode(bundler_public_key.as_bytes())
+        .context("Bundler public key is not base64url encoded")?;
+
+    println!("tx_id: {}", receipt.tx_id);
+    println!("block: {}", receipt.block);
+    println!("bundler address: {}", owner_address(&owner));
+    let message = receipt_message(&receipt).map_err(ValidatorError::from)?;
+    println!("signed message: {}", BASE64URL_NOPAD.encode(&message));
+    check_receipt(&owner, &receipt)
+        .map_err(ValidatorError::from)
+        .context("Receipt verification failed")?;
+    println!("receipt OK");
+
+    Ok(())
+}
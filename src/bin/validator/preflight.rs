@@ -0,0 +1,132 @@
+use std::{fs, time::Duration};
+
+use anyhow::Context;
+use diesel::{Connection, PgConnection};
+use jsonwebkey::JsonWebKey;
+use url::Url;
+use validator::{bundler::BundlerConfig, http::reqwest::ReqwestClient};
+
+use crate::{merge_configs, public_only_jwk_from_rsa_n, AppConfig};
+
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read validator key from `path` and make sure it holds a private key
+pub fn read_validator_key(path: &str) -> anyhow::Result<JsonWebKey> {
+    let jwk: JsonWebKey = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read validator key {}", path))?
+        .parse()
+        .with_context(|| format!("Invalid validator key {}", path))?;
+    if !jwk.key.is_private() {
+        anyhow::bail!("Validator key {} has no private part", path);
+    }
+    Ok(jwk)
+}
+
+/// Fetch and decode the bundler's public key
+pub async fn fetch_bundler_jwk(
+    client: &reqwest::Client,
+    bundler_url: &Url,
+) -> anyhow::Result<JsonWebKey> {
+    let n_response = client
+        .get(format!("{}public", bundler_url))
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .with_context(|| format!("Couldn't get public key from bundler {}", bundler_url))?
+        .text()
+        .await
+        .context("Couldn't read public key response from bundler")?;
+
+    public_only_jwk_from_rsa_n(n_response.trim()).context("Failed to decode bundler public key")
+}
+
+/// Make sure the bundler serves the key pinned with `--bundler-key`, if any
+pub fn check_bundler_key(pinned: Option<&str>, bundler_jwk: &JsonWebKey) -> anyhow::Result<()> {
+    let pinned = match pinned {
+        Some(pinned) => public_only_jwk_from_rsa_n(pinned.trim())
+            .context("Failed to decode configured bundler key")?,
+        None => return Ok(()),
+    };
+    if pinned.key != bundler_jwk.key {
+        anyhow::bail!("Bundler serves a public key other than the configured bundler key");
+    }
+    Ok(())
+}
+
+fn check_database(database_url: &str) -> anyhow::Result<()> {
+    PgConnection::establish(database_url)
+        .map(|_| ())
+        .context("Couldn't connect to database, check database_url")
+}
+
+async fn check_reachable(client: &reqwest::Client, url: &Url) -> anyhow::Result<()> {
+    client
+        .get(url.clone())
+        .send()
+        .await
+        .with_context(|| format!("{} is not reachable", url))?;
+    Ok(())
+}
+
+/// Validate configuration before the validator starts.
+///
+/// All checks are run even if some of them fail so every problem is
+/// reported at once. On success, returns the configuration merged with
+/// the bundler's config.
+pub async fn preflight(config: AppConfig) -> anyhow::Result<AppConfig> {
+    let client = config
+        .http_client_builder()?
+        .timeout(PREFLIGHT_TIMEOUT)
+        .build()
+        .context("Failed to build HTTP client")?;
+    let mut failures: Vec<(&str, anyhow::Error)> = Vec::new();
+
+    if let Err(err) = read_validator_key(&config.validator_key) {
+        failures.push(("validator key", err));
+    }
+
+    if let Err(err) = check_database(&config.database_url) {
+        failures.push(("database", err));
+    }
+
+    match fetch_bundler_jwk(&client, &config.bundler_url).await {
+        Ok(jwk) => {
+            if let Err(err) = check_bundler_key(config.runtime.bundler_key.as_deref(), &jwk) {
+                failures.push(("bundler public key", err));
+            }
+        }
+        Err(err) => failures.push(("bundler public key", err)),
+    }
+
+    let merged =
+        BundlerConfig::fetch_config(&ReqwestClient::new(client.clone()), &config.bundler_url)
+            .await
+            .with_context(|| format!("Bundler {} did not answer /info", config.bundler_url))
+            .and_then(|bundler_config| merge_configs(config.clone(), bundler_config));
+    let config = match merged {
+        Ok(config) => config,
+        Err(err) => {
+            failures.push(("bundler info", err));
+            config
+        }
+    };
+
+    if let Some(arweave_url) = &config.arweave_url {
+        if let Err(err) = check_reachable(&client, arweave_url).await {
+            failures.push(("arweave gateway", err));
+        }
+    }
+
+    if let Err(err) = check_reachable(&client, &config.contract_gateway_url).await {
+        failures.push(("contract gateway", err));
+    }
+
+    if failures.is_empty() {
+        return Ok(config);
+    }
+
+    for (check, err) in &failures {
+        tracing::error!(check = *check, "Preflight check failed: {:#}", err);
+    }
+    anyhow::bail!("{} preflight check(s) failed", failures.len())
+}
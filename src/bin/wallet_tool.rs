@@ -1,8 +1,7 @@
 use std::fs;
 
 use clap::{Parser, Subcommand};
-use jsonwebkey::{JsonWebKey, Key, PublicExponent, RsaPrivate, RsaPublic};
-use openssl::rsa::Rsa;
+use jsonwebkey::JsonWebKey;
 
 use validator::key_manager;
 
@@ -29,22 +28,7 @@ fn main() {
 
     match args.command {
         Command::Create => {
-            let rsa = Rsa::generate(2048).unwrap();
-
-            let jwk = JsonWebKey::new(Key::RSA {
-                public: RsaPublic {
-                    e: PublicExponent,
-                    n: rsa.n().to_vec().into(),
-                },
-                private: Some(RsaPrivate {
-                    d: rsa.d().to_vec().into(),
-                    p: rsa.p().map(|v| v.to_vec().into()),
-                    q: rsa.q().map(|v| v.to_vec().into()),
-                    dp: rsa.dmp1().map(|v| v.to_vec().into()),
-                    dq: rsa.dmq1().map(|v| v.to_vec().into()),
-                    qi: rsa.iqmp().map(|v| v.to_vec().into()),
-                }),
-            });
+            let jwk = key_manager::generate_jwk(key_manager::KeyType::default());
 
             println!("{}", jwk);
         }
@@ -0,0 +1,166 @@
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+
+use crate::events::{Event, EventBusAccess};
+
+/// Message broker validation events are published to
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum BrokerConfig {
+    /// Events are published to `{subject}.{event name}`
+    Nats {
+        url: String,
+        #[serde(default = "default_subject")]
+        subject: String,
+    },
+    /// Events are published to `topic` keyed by event name
+    Kafka { brokers: String, topic: String },
+}
+
+fn default_subject() -> String {
+    "validator.events".to_string()
+}
+
+#[derive(Debug, Error)]
+pub enum BrokerError {
+    #[error("validator was built without {0} support")]
+    Unsupported(&'static str),
+    #[error("failed to connect to broker: {0}")]
+    Connect(String),
+    #[error("failed to publish event: {0}")]
+    Publish(String),
+}
+
+/// Connected event publisher
+pub enum Publisher {
+    #[cfg(feature = "nats")]
+    Nats {
+        client: async_nats::Client,
+        subject: String,
+    },
+    #[cfg(feature = "kafka")]
+    Kafka {
+        producer: rdkafka::producer::FutureProducer,
+        topic: String,
+    },
+}
+
+impl Publisher {
+    pub async fn connect(config: &BrokerConfig) -> Result<Publisher, BrokerError> {
+        match config {
+            #[cfg(feature = "nats")]
+            BrokerConfig::Nats { url, subject } => {
+                let client = async_nats::connect(url.as_str())
+                    .await
+                    .map_err(|err| BrokerError::Connect(err.to_string()))?;
+                Ok(Publisher::Nats {
+                    client,
+                    subject: subject.clone(),
+                })
+            }
+            #[cfg(not(feature = "nats"))]
+            BrokerConfig::Nats { .. } => Err(BrokerError::Unsupported("nats")),
+            #[cfg(feature = "kafka")]
+            BrokerConfig::Kafka { brokers, topic } => {
+                let producer = rdkafka::ClientConfig::new()
+                    .set("bootstrap.servers", brokers)
+                    .create()
+                    .map_err(|err| BrokerError::Connect(err.to_string()))?;
+                Ok(Publisher::Kafka {
+                    producer,
+                    topic: topic.clone(),
+                })
+            }
+            #[cfg(not(feature = "kafka"))]
+            BrokerConfig::Kafka { .. } => Err(BrokerError::Unsupported("kafka")),
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub async fn publish(&self, event: &Event) -> Result<(), BrokerError> {
+        let payload = serde_json::to_vec(event).expect("Event is serializable");
+        match self {
+            #[cfg(feature = "nats")]
+            Publisher::Nats { client, subject } => client
+                .publish(format!("{}.{}", subject, event.name()), payload.into())
+                .await
+                .map_err(|err| BrokerError::Publish(err.to_string())),
+            #[cfg(feature = "kafka")]
+            Publisher::Kafka { producer, topic } => {
+                let record = rdkafka::producer::FutureRecord::to(topic)
+                    .key(event.name())
+                    .payload(&payload);
+                producer
+                    .send(record, std::time::Duration::from_secs(5))
+                    .await
+                    .map(|_| ())
+                    .map_err(|(err, _)| BrokerError::Publish(err.to_string()))
+            }
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("Publisher can't be constructed without a broker feature"),
+        }
+    }
+}
+
+/// Publish all events from the event bus to the message broker
+pub async fn run_publisher<Context>(ctx: Context, publisher: Publisher)
+where
+    Context: EventBusAccess,
+{
+    info!("Publishing events to message broker");
+    let mut events = ctx.event_bus().subscribe();
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                if let Err(err) = publisher.publish(&event).await {
+                    error!(event = event.name(), "Failed to publish event: {}", err);
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(skipped, "Event publisher fell behind, events were dropped")
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BrokerConfig;
+
+    #[test]
+    fn parse_broker_config() {
+        let nats: BrokerConfig = toml::from_str(
+            r#"
+            type = "nats"
+            url = "nats://localhost:4222"
+            "#,
+        )
+        .unwrap();
+        let kafka: BrokerConfig = toml::from_str(
+            r#"
+            type = "kafka"
+            brokers = "localhost:9092"
+            topic = "validator"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            nats,
+            BrokerConfig::Nats {
+                url: "nats://localhost:4222".to_string(),
+                subject: "validator.events".to_string()
+            }
+        );
+        assert_eq!(
+            kafka,
+            BrokerConfig::Kafka {
+                brokers: "localhost:9092".to_string(),
+                topic: "validator".to_string()
+            }
+        );
+    }
+}
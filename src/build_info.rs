@@ -0,0 +1,37 @@
+use serde::Serialize;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_COMMIT: &str = env!("VALIDATOR_GIT_COMMIT");
+pub const BUILD_DATE: &str = env!("VALIDATOR_BUILD_DATE");
+pub const FEATURES: &str = env!("VALIDATOR_FEATURES");
+
+/// Version string used for `--version`
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ncommit: ",
+    env!("VALIDATOR_GIT_COMMIT"),
+    "\nbuild date: ",
+    env!("VALIDATOR_BUILD_DATE"),
+    "\nfeatures: ",
+    env!("VALIDATOR_FEATURES"),
+);
+
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub build_date: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        git_commit: GIT_COMMIT,
+        build_date: BUILD_DATE,
+        features: FEATURES
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+    }
+}
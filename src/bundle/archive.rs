@@ -0,0 +1,159 @@
+use std::{io, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    read_header,
+    store::{BundleStorage, BundleStore, StoreError},
+    HeaderEntry,
+};
+use crate::{
+    database::{
+        models::{Bundle, Transaction},
+        queries::{self, QueryContext},
+    },
+    http,
+};
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("failed to read bundle record: {0}")]
+    Database(#[from] diesel::result::Error),
+    #[error("failed to write report: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to serialize report: {0}")]
+    Report(#[from] serde_json::Error),
+    #[error(transparent)]
+    Store(#[from] StoreError),
+}
+
+/// What is uploaded to the archive for each bundle
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveContents {
+    /// Bundle file together with its report
+    #[default]
+    Bundle,
+    /// Report only, which includes the bundle header
+    Report,
+}
+
+/// Storage bundle files are copied to before they are deleted locally
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BundleArchive {
+    #[serde(default)]
+    pub contents: ArchiveContents,
+    pub storage: BundleStorage,
+}
+
+/// Evidence of a past validation, stored as `{bundle}.json` in the archive
+#[derive(Debug, Serialize)]
+pub struct ArchiveReport {
+    pub bundle: String,
+    pub archived_at: DateTime<Utc>,
+    /// Bundle as stored when it was validated, `None` if it never was
+    pub validation: Option<Bundle>,
+    /// Transactions validated as part of the bundle, with their receipt
+    /// signatures
+    pub transactions: Vec<Transaction>,
+    /// Data items listed in the bundle file, `None` if the header can't be
+    /// parsed
+    pub header: Option<Vec<HeaderEntry>>,
+}
+
+impl ArchiveReport {
+    pub fn new<Context>(ctx: &Context, bundle_id: &str, path: &Path) -> Result<Self, ArchiveError>
+    where
+        Context: QueryContext,
+    {
+        let validation = match queries::get_bundle(ctx, bundle_id) {
+            Ok(bundle) => Some(bundle),
+            Err(diesel::result::Error::NotFound) => None,
+            Err(err) => return Err(err.into()),
+        };
+        let transactions = match validation {
+            Some(_) => queries::get_bundle_txs(ctx, bundle_id)?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            bundle: bundle_id.to_string(),
+            archived_at: Utc::now(),
+            validation,
+            transactions,
+            header: read_header(path).ok(),
+        })
+    }
+}
+
+/// Upload the report of bundle `bundle_id`, and the bundle file at `path`
+/// unless only reports are archived
+pub async fn archive_bundle<Context, HttpClient>(
+    ctx: &Context,
+    archive: &BundleArchive,
+    bundle_id: &str,
+    path: &Path,
+) -> Result<(), ArchiveError>
+where
+    Context: http::ClientAccess<HttpClient> + QueryContext,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    if archive.contents == ArchiveContents::Bundle {
+        archive.storage.put(ctx, bundle_id, path).await?;
+    }
+
+    // Report goes last, so its presence tells the bundle was archived
+    let report = ArchiveReport::new(ctx, bundle_id, path)?;
+    let report_path = std::env::temp_dir().join(format!("validator_report_{}.json", bundle_id));
+    tokio::fs::write(&report_path, serde_json::to_vec_pretty(&report)?).await?;
+    let res = archive
+        .storage
+        .put(ctx, &format!("{}.json", bundle_id), &report_path)
+        .await;
+    tokio::fs::remove_file(&report_path).await?;
+    Ok(res?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        bundle::store::{BundleStorage, FilesystemStore},
+        context::test_utils::test_context,
+        key_manager::test_utils::test_keys,
+    };
+
+    use super::{archive_bundle, ArchiveContents, BundleArchive};
+
+    #[actix_rt::test]
+    async fn report_only_archive_keeps_header() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        let dir = std::env::temp_dir().join("validator_report_archive");
+        let archive = BundleArchive {
+            contents: ArchiveContents::Report,
+            storage: BundleStorage::Filesystem(FilesystemStore { path: dir.clone() }),
+        };
+
+        archive_bundle(
+            &ctx,
+            &archive,
+            "unvalidated_bundle",
+            "./bundles/test_bundle".as_ref(),
+        )
+        .await
+        .unwrap();
+
+        assert!(!dir.join("unvalidated_bundle").exists());
+        let report: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.join("unvalidated_bundle.json")).unwrap())
+                .unwrap();
+        assert_eq!(report["validation"], serde_json::Value::Null);
+        assert_eq!(report["header"].as_array().unwrap().len(), 10);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
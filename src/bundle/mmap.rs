@@ -0,0 +1,557 @@
+use std::{fs::File, path::Path};
+
+use data_encoding::BASE64URL_NOPAD;
+use memmap2::Mmap;
+use openssl::{
+    bn::BigNum,
+    hash::MessageDigest,
+    pkey::{Id, PKey, Public},
+    rsa::{Padding, Rsa},
+    sha::{sha256, Sha384},
+    sign::Verifier,
+};
+use tracing::warn;
+
+use super::{
+    BundleError, BundleItem, HeaderEntry, ItemFault, BUNDLE_FORMAT_TAG, BUNDLE_VERSION_TAG,
+    SUPPORTED_BUNDLE_FORMATS,
+};
+
+const ARWEAVE_SIGNATURE: u16 = 1;
+const ED25519_SIGNATURE: u16 = 2;
+const SOLANA_SIGNATURE: u16 = 4;
+
+const HEADER_ENTRY_LENGTH: usize = 64;
+const ITEM_COUNT_LENGTH: usize = 32;
+const RSA_PUBLIC_EXPONENT: u32 = 65537;
+
+/// Verify all data items of the bundle stored at `path`.
+///
+/// The file is memory mapped and items are verified in place, so memory
+/// usage does not grow with the bundle size. Returns
+/// `BundleError::UnsupportedSignatureType` for bundles containing items
+/// signed with a scheme that can't be verified here.
+///
+/// Data items that are bundles themselves are verified as well, down to
+/// `max_depth` levels of nesting, and their items returned with the id of
+/// the data item containing them. A nested bundle failing verification
+/// doesn't make the outer bundle invalid, its items are left out instead.
+pub fn verify_mapped_bundle<P: AsRef<Path>>(
+    path: P,
+    max_depth: usize,
+) -> Result<Vec<BundleItem>, BundleError> {
+    let file = File::open(path)?;
+    // SAFETY: bundle files are written once when downloaded and not
+    // modified while being verified
+    let bundle = unsafe { Mmap::map(&file)? };
+
+    let mut items = Vec::new();
+    verify_items(&bundle, None, max_depth, &mut items)?;
+    Ok(items)
+}
+
+/// Read the header of the bundle stored at `path`, without verifying the
+/// data items it lists
+pub fn read_header<P: AsRef<Path>>(path: P) -> Result<Vec<HeaderEntry>, BundleError> {
+    let file = File::open(path)?;
+    // SAFETY: see `verify_mapped_bundle`
+    let bundle = unsafe { Mmap::map(&file)? };
+
+    let item_count = read_le_usize(&bundle, 0, ITEM_COUNT_LENGTH)?;
+    let mut entries = Vec::with_capacity(item_count.min(bundle.len() / HEADER_ENTRY_LENGTH));
+    for index in 0..item_count {
+        let entry = ITEM_COUNT_LENGTH + index * HEADER_ENTRY_LENGTH;
+        entries.push(HeaderEntry {
+            id: BASE64URL_NOPAD.encode(slice(&bundle, entry + 32, 32)?),
+            size: read_le_usize(&bundle, entry, 32)?,
+        });
+    }
+    Ok(entries)
+}
+
+/// Verify items of `bundle` and append them to `items`, `parent` being the
+/// id of the data item `bundle` is the data of
+fn verify_items(
+    bundle: &[u8],
+    parent: Option<&str>,
+    max_depth: usize,
+    items: &mut Vec<BundleItem>,
+) -> Result<(), BundleError> {
+    let item_count = read_le_usize(&bundle, 0, ITEM_COUNT_LENGTH)?;
+    let header_length = item_count
+        .checked_mul(HEADER_ENTRY_LENGTH)
+        .and_then(|len| len.checked_add(ITEM_COUNT_LENGTH))
+        .ok_or_else(|| invalid("item count too large"))?;
+
+    items.reserve(item_count.min(bundle.len() / HEADER_ENTRY_LENGTH));
+    let mut offset = header_length;
+    for index in 0..item_count {
+        let entry = ITEM_COUNT_LENGTH + index * HEADER_ENTRY_LENGTH;
+        let size = read_le_usize(&bundle, entry, 32)?;
+        let id = slice(&bundle, entry + 32, 32)?;
+        let item = slice(&bundle, offset, size)?;
+        offset += size;
+
+        let tx_id = BASE64URL_NOPAD.encode(id);
+        let verified = verify_item(&tx_id, item)?;
+        if sha256(verified.signature)[..] != *id {
+            return Err(item_fault(&tx_id, ItemFault::Id));
+        }
+        items.push(BundleItem {
+            tx_id: tx_id.clone(),
+            parent: parent.map(ToString::to_string),
+        });
+
+        if max_depth > 0 && is_bundle(verified.tags) {
+            let mut nested = Vec::new();
+            match verify_items(verified.data, Some(&tx_id), max_depth - 1, &mut nested) {
+                Ok(()) => items.append(&mut nested),
+                Err(err) => warn!(tx = %tx_id, "Invalid nested bundle: {}", err),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parts of a data item with a valid signature
+struct VerifiedItem<'a> {
+    signature: &'a [u8],
+    tags: &'a [u8],
+    data: &'a [u8],
+}
+
+/// Verify that the owner of data item `tx_id` is a valid key and signed it
+fn verify_item<'a>(tx_id: &str, item: &'a [u8]) -> Result<VerifiedItem<'a>, BundleError> {
+    let signature_type = read_le_usize(item, 0, 2)? as u16;
+    let (signature_length, owner_length) = match signature_type {
+        ARWEAVE_SIGNATURE => (512, 512),
+        ED25519_SIGNATURE | SOLANA_SIGNATURE => (64, 32),
+        other => return Err(BundleError::UnsupportedSignatureType(other)),
+    };
+
+    let signature = slice(item, 2, signature_length)?;
+    let owner = slice(item, 2 + signature_length, owner_length)?;
+    let mut offset = 2 + signature_length + owner_length;
+    let target =
+        read_optional(item, &mut offset)?.ok_or_else(|| invalid("invalid target presence flag"))?;
+    let anchor =
+        read_optional(item, &mut offset)?.ok_or_else(|| item_fault(tx_id, ItemFault::Anchor))?;
+    let tags_length = read_le_usize(item, offset + 8, 8)?;
+    let tags = slice(item, offset + 16, tags_length)?;
+    let data = &item[offset + 16 + tags_length..];
+
+    let type_tag = signature_type.to_string();
+    let message = deep_hash(&[
+        b"dataitem",
+        b"1",
+        type_tag.as_bytes(),
+        owner,
+        target,
+        anchor,
+        tags,
+        data,
+    ]);
+
+    let key =
+        public_key(signature_type, owner).ok_or_else(|| item_fault(tx_id, ItemFault::Owner))?;
+    let verified = match key.id() {
+        Id::RSA => {
+            let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+            verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+            verifier.verify_oneshot(signature, &message)
+        }
+        _ => Verifier::new_without_digest(&key)?.verify_oneshot(signature, &message),
+    };
+    // Malformed signatures fail with an error rather than `false`
+    if !verified.unwrap_or(false) {
+        return Err(item_fault(tx_id, ItemFault::Signature));
+    }
+
+    Ok(VerifiedItem {
+        signature,
+        tags,
+        data,
+    })
+}
+
+/// Whether Avro encoded `tags` declare a bundle in a supported format
+fn is_bundle(tags: &[u8]) -> bool {
+    let tags = match parse_tags(tags) {
+        Ok(tags) => tags,
+        Err(_) => return false,
+    };
+    let value = |name: &str| {
+        tags.iter()
+            .find(|(tag, _)| *tag == name.as_bytes())
+            .map(|(_, value)| *value)
+    };
+    match (value(BUNDLE_FORMAT_TAG), value(BUNDLE_VERSION_TAG)) {
+        (Some(format), Some(version)) => SUPPORTED_BUNDLE_FORMATS
+            .iter()
+            .any(|(f, v)| f.as_bytes() == format && v.as_bytes() == version),
+        _ => false,
+    }
+}
+
+/// Decode Avro array of name and value pairs
+fn parse_tags(tags: &[u8]) -> Result<Vec<(&[u8], &[u8])>, BundleError> {
+    let mut parsed = Vec::new();
+    if tags.is_empty() {
+        return Ok(parsed);
+    }
+
+    let mut offset = 0;
+    loop {
+        let count = read_zigzag(tags, &mut offset)?;
+        if count == 0 {
+            return Ok(parsed);
+        }
+        // Negative block counts are followed by the block size in bytes
+        if count < 0 {
+            read_zigzag(tags, &mut offset)?;
+        }
+        for _ in 0..count.unsigned_abs() {
+            let name = read_avro_bytes(tags, &mut offset)?;
+            let value = read_avro_bytes(tags, &mut offset)?;
+            parsed.push((name, value));
+        }
+    }
+}
+
+/// Read zigzag encoded variable length number
+fn read_zigzag(bytes: &[u8], offset: &mut usize) -> Result<i64, BundleError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes
+            .get(*offset)
+            .ok_or_else(|| invalid("tags truncated"))?;
+        *offset += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+    }
+    Err(invalid("tag number too large"))
+}
+
+fn read_avro_bytes<'a>(bytes: &'a [u8], offset: &mut usize) -> Result<&'a [u8], BundleError> {
+    let length =
+        usize::try_from(read_zigzag(bytes, offset)?).map_err(|_| invalid("negative tag length"))?;
+    let value = slice(bytes, *offset, length)?;
+    *offset += length;
+    Ok(value)
+}
+
+/// Public key `owner` stands for, `None` if it isn't a valid key. Arweave
+/// owners are 4096 bit RSA moduli, which use all of their 512 bytes.
+fn public_key(signature_type: u16, owner: &[u8]) -> Option<PKey<Public>> {
+    if signature_type == ARWEAVE_SIGNATURE {
+        if owner.first().map_or(true, |byte| byte & 0x80 == 0) {
+            return None;
+        }
+        let rsa = Rsa::from_public_components(
+            BigNum::from_slice(owner).ok()?,
+            BigNum::from_u32(RSA_PUBLIC_EXPONENT).ok()?,
+        )
+        .ok()?;
+        PKey::from_rsa(rsa).ok()
+    } else {
+        PKey::public_key_from_raw_bytes(owner, Id::ED25519).ok()
+    }
+}
+
+/// Deep hash of a list of blobs, computed without copying them
+fn deep_hash(chunks: &[&[u8]]) -> [u8; 48] {
+    let mut acc = sha384(&[format!("list{}", chunks.len()).as_bytes()]);
+    for chunk in chunks {
+        let tag = sha384(&[format!("blob{}", chunk.len()).as_bytes()]);
+        let blob = sha384(&[&tag[..], &sha384(&[*chunk])[..]]);
+        acc = sha384(&[&acc[..], &blob[..]]);
+    }
+    acc
+}
+
+fn sha384(parts: &[&[u8]]) -> [u8; 48] {
+    let mut hasher = Sha384::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finish()
+}
+
+/// Read presence flag followed by a 32 byte value, the value is empty if
+/// absent and `None` if the flag is neither 0 nor 1
+fn read_optional<'a>(item: &'a [u8], offset: &mut usize) -> Result<Option<&'a [u8]>, BundleError> {
+    let present = *item
+        .get(*offset)
+        .ok_or_else(|| invalid("data item truncated"))?;
+    *offset += 1;
+    match present {
+        0 => Ok(Some(&[])),
+        1 => {
+            let value = slice(item, *offset, 32)?;
+            *offset += 32;
+            Ok(Some(value))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn slice(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8], BundleError> {
+    offset
+        .checked_add(length)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| invalid("bundle truncated"))
+}
+
+/// Read little endian number stored in `length` bytes
+fn read_le_usize(bytes: &[u8], offset: usize, length: usize) -> Result<usize, BundleError> {
+    let value = slice(bytes, offset, length)?;
+    let size = std::mem::size_of::<usize>();
+    if value.iter().skip(size).any(|byte| *byte != 0) {
+        return Err(invalid("number too large"));
+    }
+    Ok(value
+        .iter()
+        .take(size)
+        .rev()
+        .fold(0, |acc, byte| (acc << 8) | *byte as usize))
+}
+
+fn invalid(reason: &str) -> BundleError {
+    BundleError::Invalid(reason.to_string())
+}
+
+fn item_fault(tx_id: &str, fault: ItemFault) -> BundleError {
+    BundleError::InvalidItem {
+        item: tx_id.to_string(),
+        fault,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use data_encoding::BASE64URL_NOPAD;
+    use openssl::{
+        pkey::{PKey, Private},
+        sha::sha256,
+        sign::Signer,
+    };
+
+    use super::{deep_hash, read_header, verify_mapped_bundle, ED25519_SIGNATURE};
+    use crate::bundle::{BundleError, ItemFault};
+
+    /// Avro encoded tags, names and values shorter than 64 bytes
+    fn encode_tags(tags: &[(&str, &str)]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        if tags.is_empty() {
+            return encoded;
+        }
+        encoded.push(tags.len() as u8 * 2);
+        for (name, value) in tags {
+            for bytes in [name.as_bytes(), value.as_bytes()] {
+                encoded.push(bytes.len() as u8 * 2);
+                encoded.extend_from_slice(bytes);
+            }
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    /// Ed25519 signed data item and its id
+    fn data_item(key: &PKey<Private>, tags: &[(&str, &str)], data: &[u8]) -> (Vec<u8>, String) {
+        anchored_data_item(key, &[], tags, data)
+    }
+
+    /// Ed25519 signed data item with `anchor`, empty for none, and its id
+    fn anchored_data_item(
+        key: &PKey<Private>,
+        anchor: &[u8],
+        tags: &[(&str, &str)],
+        data: &[u8],
+    ) -> (Vec<u8>, String) {
+        let owner = key.raw_public_key().unwrap();
+        let tag_count = tags.len() as u64;
+        let tags = encode_tags(tags);
+        let type_tag = ED25519_SIGNATURE.to_string();
+        let message = deep_hash(&[
+            b"dataitem",
+            b"1",
+            type_tag.as_bytes(),
+            &owner[..],
+            &[],
+            anchor,
+            &tags[..],
+            data,
+        ]);
+        let signature = Signer::new_without_digest(key)
+            .unwrap()
+            .sign_oneshot_to_vec(&message)
+            .unwrap();
+
+        let mut item = ED25519_SIGNATURE.to_le_bytes().to_vec();
+        item.extend_from_slice(&signature);
+        item.extend_from_slice(&owner);
+        // No target
+        item.push(0);
+        if anchor.is_empty() {
+            item.push(0);
+        } else {
+            item.push(1);
+            item.extend_from_slice(anchor);
+        }
+        item.extend_from_slice(&tag_count.to_le_bytes());
+        item.extend_from_slice(&(tags.len() as u64).to_le_bytes());
+        item.extend_from_slice(&tags);
+        item.extend_from_slice(data);
+        (item, BASE64URL_NOPAD.encode(&sha256(&signature)))
+    }
+
+    fn le_bytes_32(value: usize) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&(value as u64).to_le_bytes());
+        bytes
+    }
+
+    fn bundle(items: &[(Vec<u8>, String)]) -> Vec<u8> {
+        let mut bundle = le_bytes_32(items.len()).to_vec();
+        for (item, id) in items {
+            bundle.extend_from_slice(&le_bytes_32(item.len()));
+            bundle.extend_from_slice(&BASE64URL_NOPAD.decode(id.as_bytes()).unwrap());
+        }
+        for (item, _) in items {
+            bundle.extend_from_slice(item);
+        }
+        bundle
+    }
+
+    const BUNDLE_TAGS: [(&str, &str); 2] =
+        [("Bundle-Format", "binary"), ("Bundle-Version", "2.0.0")];
+
+    fn verify_bytes(name: &str, bundle: &[u8], max_depth: usize) -> Vec<(String, Option<String>)> {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, bundle).unwrap();
+        let res = verify_mapped_bundle(&path, max_depth);
+        fs::remove_file(&path).unwrap();
+
+        res.unwrap()
+            .into_iter()
+            .map(|item| (item.tx_id, item.parent))
+            .collect()
+    }
+
+    #[test]
+    fn verify_test_bundle() {
+        let items = verify_mapped_bundle("./bundles/test_bundle", 1).unwrap();
+
+        assert_eq!(items.len(), 10);
+    }
+
+    #[test]
+    fn header_lists_bundle_items() {
+        let header = read_header("./bundles/test_bundle").unwrap();
+        let items = verify_mapped_bundle("./bundles/test_bundle", 0).unwrap();
+
+        assert_eq!(
+            header.iter().map(|entry| &entry.id).collect::<Vec<_>>(),
+            items.iter().map(|item| &item.tx_id).collect::<Vec<_>>()
+        );
+        assert!(header.iter().all(|entry| entry.size > 0));
+    }
+
+    #[test]
+    fn tampered_data_fails_verification() {
+        let mut bundle = fs::read("./bundles/test_bundle").unwrap();
+        let last = bundle.len() - 1;
+        bundle[last] ^= 0xff;
+        let path = std::env::temp_dir().join("validator_tampered_bundle");
+        fs::write(&path, bundle).unwrap();
+
+        let res = verify_mapped_bundle(&path, 1);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            res,
+            Err(BundleError::InvalidItem {
+                fault: ItemFault::Signature,
+                ..
+            })
+        ));
+    }
+
+    fn verify_error(name: &str, bundle: &[u8]) -> BundleError {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, bundle).unwrap();
+        let res = verify_mapped_bundle(&path, 0);
+        fs::remove_file(&path).unwrap();
+        res.unwrap_err()
+    }
+
+    #[test]
+    fn anchor_may_be_shared_by_items() {
+        // ANS-104 doesn't restrict anchors, an owner may sign several
+        // items with the same one
+        let key = PKey::generate_ed25519().unwrap();
+        let anchor = [7u8; 32];
+        let first = anchored_data_item(&key, &anchor, &[], b"first");
+        let second = anchored_data_item(&key, &anchor, &[], b"second");
+
+        let shared = bundle(&[first, second]);
+        assert_eq!(verify_bytes("validator_shared_anchor", &shared, 0).len(), 2);
+    }
+
+    #[test]
+    fn malformed_anchor_flag_is_reported() {
+        let key = PKey::generate_ed25519().unwrap();
+        let (mut item, id) = data_item(&key, &[], b"data");
+        // Anchor flag follows the signature type, signature, owner and
+        // target flag
+        item[2 + 64 + 32 + 1] = 2;
+
+        assert!(matches!(
+            verify_error("validator_malformed_anchor", &bundle(&[(item, id)])),
+            BundleError::InvalidItem {
+                fault: ItemFault::Anchor,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn nested_bundle_items_are_attributed_to_their_parent() {
+        let key = PKey::generate_ed25519().unwrap();
+        let (inner, inner_id) = data_item(&key, &[("Content-Type", "text/plain")], b"inner");
+        let inner_bundle = bundle(&[(inner, inner_id.clone())]);
+        let (outer, outer_id) = data_item(&key, &BUNDLE_TAGS, &inner_bundle);
+        let (plain, plain_id) = data_item(&key, &[], b"plain");
+        let bundle = bundle(&[(outer, outer_id.clone()), (plain, plain_id.clone())]);
+
+        assert_eq!(
+            verify_bytes("validator_nested_bundle", &bundle, 1),
+            vec![
+                (outer_id.clone(), None),
+                (inner_id, Some(outer_id.clone())),
+                (plain_id.clone(), None),
+            ]
+        );
+        assert_eq!(
+            verify_bytes("validator_nested_bundle_no_depth", &bundle, 0),
+            vec![(outer_id, None), (plain_id, None)]
+        );
+    }
+
+    #[test]
+    fn invalid_nested_bundle_keeps_outer_bundle_valid() {
+        let key = PKey::generate_ed25519().unwrap();
+        let (outer, outer_id) = data_item(&key, &BUNDLE_TAGS, b"not a bundle");
+        let bundle = bundle(&[(outer, outer_id.clone())]);
+
+        assert_eq!(
+            verify_bytes("validator_invalid_nested_bundle", &bundle, 1),
+            vec![(outer_id, None)]
+        );
+    }
+}
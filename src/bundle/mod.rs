@@ -1 +1,213 @@
+pub mod archive;
+mod mmap;
+pub mod store;
 
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use bundlr_sdk::verify::file::verify_file_bundle;
+use data_encoding::HEXLOWER;
+use openssl::{error::ErrorStack, sha::Sha256};
+use serde::Serialize;
+use thiserror::Error;
+
+pub use mmap::{read_header, verify_mapped_bundle};
+
+#[derive(Debug, Error)]
+pub enum BundleError {
+    #[error("invalid bundle: {0}")]
+    Invalid(String),
+    #[error("data item {item} failed the {fault} check")]
+    InvalidItem { item: String, fault: ItemFault },
+    #[error("failed to read bundle: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to verify signature: {0}")]
+    Crypto(#[from] ErrorStack),
+    #[error("unsupported signature type {0}")]
+    UnsupportedSignatureType(u16),
+}
+
+impl BundleError {
+    /// Whether the error comes from the bundle data, so verifying the same
+    /// data again fails the same way. Failures to read the file or to run
+    /// the crypto may not happen again.
+    pub fn is_verdict(&self) -> bool {
+        matches!(
+            self,
+            BundleError::Invalid(_)
+                | BundleError::InvalidItem { .. }
+                | BundleError::UnsupportedSignatureType(_)
+        )
+    }
+}
+
+/// Rule of ANS-104 a data item broke
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ItemFault {
+    /// Signature doesn't verify against the owner
+    Signature,
+    /// Id listed in the bundle header isn't the hash of the signature
+    Id,
+    /// Owner isn't a well formed public key for the signature type
+    Owner,
+    /// Anchor presence flag is neither 0 nor 1
+    Anchor,
+}
+
+impl ItemFault {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ItemFault::Signature => "signature",
+            ItemFault::Id => "id",
+            ItemFault::Owner => "owner",
+            ItemFault::Anchor => "anchor",
+        }
+    }
+}
+
+impl fmt::Display for ItemFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Tags every ANS-104 bundle declares
+pub const BUNDLE_FORMAT_TAG: &str = "Bundle-Format";
+pub const BUNDLE_VERSION_TAG: &str = "Bundle-Version";
+/// ANS-104 format and version pairs the validator can verify
+pub const SUPPORTED_BUNDLE_FORMATS: [(&str, &str); 1] = [("binary", "2.0.0")];
+
+/// Levels of bundles nested in data items verified by default
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 1;
+
+/// Data item found in a verified bundle
+#[derive(Clone, Debug, PartialEq)]
+pub struct BundleItem {
+    pub tx_id: String,
+    /// Data item this item is nested in, `None` for items of the bundle
+    /// itself
+    pub parent: Option<String>,
+}
+
+/// Entry of the bundle header, listing a data item of the bundle
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HeaderEntry {
+    pub id: String,
+    /// Size of the data item in bytes
+    pub size: usize,
+}
+
+/// Parse bundle stored at `path` and verify all data items in it,
+/// including items of bundles nested up to `max_depth` levels deep.
+///
+/// Bundles are verified in place through a memory map, bundles containing
+/// items with signature types not supported there are verified by the SDK,
+/// without looking into nested bundles.
+pub async fn verify_bundle_file(
+    path: &str,
+    max_depth: usize,
+) -> Result<Vec<BundleItem>, BundleError> {
+    let mapped_path = path.to_string();
+    let res = tokio::task::spawn_blocking(move || verify_mapped_bundle(mapped_path, max_depth))
+        .await
+        .map_err(|err| BundleError::Invalid(err.to_string()))?;
+
+    match res {
+        Err(BundleError::UnsupportedSignatureType(_)) => verify_file_bundle(path.to_string())
+            .await
+            .map(|items| {
+                items
+                    .into_iter()
+                    .map(|item| BundleItem {
+                        tx_id: item.tx_id,
+                        parent: None,
+                    })
+                    .collect()
+            })
+            .map_err(|err| BundleError::Invalid(err.to_string())),
+        res => res,
+    }
+}
+
+/// Hex encoded SHA-256 of the file at `path`, recorded with a validated
+/// bundle to detect later changes to its file
+pub async fn file_checksum(path: &Path) -> io::Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+        Ok(HEXLOWER.encode(&hasher.finish()))
+    })
+    .await
+    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ItemReport {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+}
+
+/// Machine-readable result of verifying a single bundle
+#[derive(Debug, PartialEq, Serialize)]
+pub struct BundleReport {
+    pub bundle: String,
+    pub valid: bool,
+    pub error: Option<String>,
+    pub item_count: usize,
+    pub items: Vec<ItemReport>,
+}
+
+impl BundleReport {
+    pub fn new(bundle: &str, result: &Result<Vec<BundleItem>, BundleError>) -> Self {
+        match result {
+            Ok(items) => Self {
+                bundle: bundle.to_string(),
+                valid: true,
+                error: None,
+                item_count: items.len(),
+                items: items
+                    .iter()
+                    .map(|item| ItemReport {
+                        id: item.tx_id.clone(),
+                        parent: item.parent.clone(),
+                    })
+                    .collect(),
+            },
+            Err(err) => Self {
+                bundle: bundle.to_string(),
+                valid: false,
+                error: Some(err.to_string()),
+                item_count: 0,
+                items: Vec::new(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_bundle_file, BundleReport, DEFAULT_MAX_NESTING_DEPTH};
+
+    #[actix_rt::test]
+    async fn verify_test_bundle_yields_valid_report() {
+        let res = verify_bundle_file("./bundles/test_bundle", DEFAULT_MAX_NESTING_DEPTH).await;
+        let report = BundleReport::new("test_bundle", &res);
+
+        assert!(report.valid, "Failed: {:?}", report.error);
+        assert_eq!(report.item_count, report.items.len());
+    }
+}
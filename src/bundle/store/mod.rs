@@ -0,0 +1,196 @@
+mod s3;
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::http;
+
+pub use s3::S3Store;
+
+/// Key prefix for bundles that failed verification
+pub const QUARANTINE_PREFIX: &str = "quarantine/";
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("failed to access bundle file: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to build storage request: {0}")]
+    InvalidRequest(String),
+    #[error("storage request failed: {0}")]
+    RequestFailed(String),
+}
+
+pub trait BundleStoreAccess {
+    fn bundle_store(&self) -> &BundleStorage;
+}
+
+/// Durable storage for downloaded bundles.
+///
+/// Bundles are always downloaded and verified from a local file, the store
+/// keeps a copy of it under `key` once verification is done.
+#[async_trait(?Send)]
+pub trait BundleStore {
+    /// Store local file at `path` under `key`
+    async fn put<Context, HttpClient>(
+        &self,
+        ctx: &Context,
+        key: &str,
+        path: &Path,
+    ) -> Result<(), StoreError>
+    where
+        Context: http::ClientAccess<HttpClient>,
+        HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>;
+
+    /// Remove bundle stored under `key`, removing a missing bundle succeeds
+    async fn delete<Context, HttpClient>(&self, ctx: &Context, key: &str) -> Result<(), StoreError>
+    where
+        Context: http::ClientAccess<HttpClient>,
+        HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>;
+}
+
+/// Store keeping bundles in a local directory
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct FilesystemStore {
+    pub path: PathBuf,
+}
+
+impl Default for FilesystemStore {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("./bundles"),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl BundleStore for FilesystemStore {
+    async fn put<Context, HttpClient>(
+        &self,
+        _ctx: &Context,
+        key: &str,
+        path: &Path,
+    ) -> Result<(), StoreError>
+    where
+        Context: http::ClientAccess<HttpClient>,
+        HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        let target = self.path.join(key);
+        if target == path {
+            return Ok(());
+        }
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::copy(path, target).await?;
+        Ok(())
+    }
+
+    async fn delete<Context, HttpClient>(&self, _ctx: &Context, key: &str) -> Result<(), StoreError>
+    where
+        Context: http::ClientAccess<HttpClient>,
+        HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        match tokio::fs::remove_file(self.path.join(key)).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Bundle store selected in the config file
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BundleStorage {
+    Filesystem(FilesystemStore),
+    S3(S3Store),
+}
+
+impl Default for BundleStorage {
+    fn default() -> Self {
+        BundleStorage::Filesystem(FilesystemStore::default())
+    }
+}
+
+#[async_trait(?Send)]
+impl BundleStore for BundleStorage {
+    async fn put<Context, HttpClient>(
+        &self,
+        ctx: &Context,
+        key: &str,
+        path: &Path,
+    ) -> Result<(), StoreError>
+    where
+        Context: http::ClientAccess<HttpClient>,
+        HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        match self {
+            BundleStorage::Filesystem(store) => store.put(ctx, key, path).await,
+            BundleStorage::S3(store) => store.put(ctx, key, path).await,
+        }
+    }
+
+    async fn delete<Context, HttpClient>(&self, ctx: &Context, key: &str) -> Result<(), StoreError>
+    where
+        Context: http::ClientAccess<HttpClient>,
+        HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        match self {
+            BundleStorage::Filesystem(store) => store.delete(ctx, key).await,
+            BundleStorage::S3(store) => store.delete(ctx, key).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{context::test_utils::test_context, key_manager::test_utils::test_keys};
+
+    use super::{BundleStorage, BundleStore, FilesystemStore, QUARANTINE_PREFIX};
+
+    #[actix_rt::test]
+    async fn filesystem_store_copies_bundle() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        let dir = std::env::temp_dir().join("validator_filesystem_store");
+        let store = FilesystemStore { path: dir.clone() };
+        let key = format!("{}test_bundle", QUARANTINE_PREFIX);
+
+        store
+            .put(&ctx, &key, "./bundles/test_bundle".as_ref())
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read(dir.join(&key)).unwrap(),
+            fs::read("./bundles/test_bundle").unwrap()
+        );
+
+        store.delete(&ctx, &key).await.unwrap();
+        assert!(!dir.join(&key).exists());
+    }
+
+    #[test]
+    fn parse_storage_config() {
+        let storage: BundleStorage = toml::from_str(
+            r#"
+            type = "filesystem"
+            path = "/var/lib/validator/bundles"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            storage,
+            BundleStorage::Filesystem(FilesystemStore {
+                path: "/var/lib/validator/bundles".into()
+            })
+        );
+    }
+}
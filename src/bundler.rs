@@ -1,36 +1,141 @@
 use crate::http::Client;
+use data_encoding::BASE64URL_NOPAD;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use thiserror::Error;
 use url::Url;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Debug, Error)]
+pub enum BundlerError {
+    #[error("request to bundler failed: {0}")]
+    RequestFailed(String),
+    #[error("invalid response from bundler: {0}")]
+    InvalidResponse(String),
+}
+
+/// Configuration the bundler serves on `/info`
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
 pub struct BundlerConfig {
+    /// Version of the bundler node
     pub version: String,
+    /// Arweave gateway the bundler posts bundles to, without scheme
     pub gateway: String,
+    /// Address of the bundler's wallet for each currency it accepts
+    /// payments in, e.g. `arweave`
     pub addresses: HashMap<String, String>,
+    /// Version of the receipts the bundler signs, not served by older
+    /// bundlers
+    #[serde(default, alias = "receiptVersion")]
+    pub receipt_version: Option<String>,
+}
+
+impl BundlerConfig {
+    /// Address of the bundler's wallet for `currency`
+    pub fn address(&self, currency: &str) -> Option<&str> {
+        self.addresses.get(currency).map(String::as_str)
+    }
 }
 
+/// Bundler configuration shared between the tasks of the validator, `None`
+/// until fetched
+pub type SharedBundlerConfig = Arc<RwLock<Option<BundlerConfig>>>;
+
 #[derive(Clone, Default)]
 pub struct Bundler {
     pub address: String,
     pub url: String, // FIXME: type of this field should be Url
+    pub config: SharedBundlerConfig,
+}
+
+impl Bundler {
+    /// Configuration last fetched from the bundler's `/info`
+    pub fn config(&self) -> Option<BundlerConfig> {
+        self.config
+            .read()
+            .expect("Bundler config lock poisoned")
+            .clone()
+    }
+
+    /// Replace the bundler configuration, returns whether it changed
+    pub fn set_config(&self, config: BundlerConfig) -> bool {
+        let mut current = self.config.write().expect("Bundler config lock poisoned");
+        let changed = current.as_ref() != Some(&config);
+        *current = Some(config);
+        changed
+    }
 }
 
 impl BundlerConfig {
-    pub async fn fetch_config<HttpClient>(client: HttpClient, url: &Url) -> BundlerConfig
+    /// Fetch bundler configuration from its `/info` endpoint
+    pub async fn fetch_config<HttpClient>(
+        client: &HttpClient,
+        url: &Url,
+    ) -> Result<BundlerConfig, BundlerError>
     where
         HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
     {
         let reqwest_client = reqwest::Client::new();
-        let req = reqwest_client.get(url.to_string()).build().unwrap();
+        let req = reqwest_client
+            .get(format!("{}info", url))
+            .build()
+            .map_err(|err| BundlerError::RequestFailed(err.to_string()))?;
+
+        let res = client
+            .execute(req)
+            .await
+            .map_err(|err| BundlerError::RequestFailed(format!("{:?}", err)))?;
+        if !res.status().is_success() {
+            return Err(BundlerError::RequestFailed(format!(
+                "unexpected status {}",
+                res.status()
+            )));
+        }
+
+        let data = res
+            .text()
+            .await
+            .map_err(|err| BundlerError::InvalidResponse(err.to_string()))?;
+        serde_json::from_str::<BundlerConfig>(data.as_str())
+            .map_err(|err| BundlerError::InvalidResponse(err.to_string()))
+    }
+}
 
-        let res = client.execute(req).await.expect("request failed");
-        let data = res.text().await.unwrap();
-        let body = serde_json::from_str::<BundlerConfig>(data.as_str());
+/// Fetch the public modulus of the bundler key from its `/public` endpoint
+pub async fn fetch_public_key<HttpClient>(
+    client: &HttpClient,
+    url: &Url,
+) -> Result<Vec<u8>, BundlerError>
+where
+    HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let req = reqwest::Client::new()
+        .get(format!("{}public", url))
+        .build()
+        .map_err(|err| BundlerError::RequestFailed(err.to_string()))?;
 
-        body.unwrap()
+    let res = client
+        .execute(req)
+        .await
+        .map_err(|err| BundlerError::RequestFailed(format!("{:?}", err)))?;
+    if !res.status().is_success() {
+        return Err(BundlerError::RequestFailed(format!(
+            "unexpected status {}",
+            res.status()
+        )));
     }
+
+    let data = res
+        .text()
+        .await
+        .map_err(|err| BundlerError::InvalidResponse(err.to_string()))?;
+    BASE64URL_NOPAD
+        .decode(data.trim().as_bytes())
+        .map_err(|err| BundlerError::InvalidResponse(err.to_string()))
 }
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -39,14 +144,14 @@ mod tests {
     use http::Method;
     use reqwest::{Request, Response};
 
-    use super::BundlerConfig;
+    use super::{BundlerConfig, BundlerError};
 
     #[actix_rt::test]
     async fn fetch_config_should_return_ok() {
         let url = url::Url::from_str("https://example.com/").unwrap();
         let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
         .when(|req: &Request| {
-            let url = "https://example.com/";
+            let url = "https://example.com/info";
             req.method() == Method::GET && &req.url().to_string() == url
         })
         .then(|_: &Request| {
@@ -58,6 +163,47 @@ mod tests {
             Response::from(response)
         });
 
-        BundlerConfig::fetch_config(client, &url).await;
+        let config = BundlerConfig::fetch_config(&client, &url).await.unwrap();
+        assert_eq!(config.gateway, "example.com");
+        assert_eq!(config.version, "0.2.0");
+        assert_eq!(config.address("arweave"), Some("arweave"));
+        assert_eq!(config.address("solana"), None);
+        assert_eq!(config.receipt_version, None);
+    }
+
+    #[actix_rt::test]
+    async fn fetch_config_should_parse_receipt_version() {
+        let url = url::Url::from_str("https://example.com/").unwrap();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.method() == Method::GET)
+            .then(|_: &Request| {
+                let data = r#"{"version":"0.3.0","addresses":{"arweave":"arweave","ethereum":"0x1"},"gateway":"example.com","receiptVersion":"1.0.0"}"#;
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data)
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let config = BundlerConfig::fetch_config(&client, &url).await.unwrap();
+        assert_eq!(config.address("ethereum"), Some("0x1"));
+        assert_eq!(config.receipt_version.as_deref(), Some("1.0.0"));
+    }
+
+    #[actix_rt::test]
+    async fn fetch_config_should_fail_on_invalid_response() {
+        let url = url::Url::from_str("https://example.com/").unwrap();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.method() == Method::GET)
+            .then(|_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body("<html></html>")
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let res = BundlerConfig::fetch_config(&client, &url).await;
+        assert!(matches!(res, Err(BundlerError::InvalidResponse(_))));
     }
 }
@@ -1,15 +1,49 @@
-use crate::http::{self, method::Method};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::RuntimeConfigAccess,
+    database::models::Equivocation,
+    http::{
+        self,
+        method::Method,
+        middleware::{retry_after, IDEMPOTENCY_KEY, MAX_RETRY_AFTER},
+    },
+    key_manager,
+    server::routes::cosign::{Attestation, AttestationRequest},
+};
 use bundlr_contracts_validators::{
     slashing::Proposal as SlashProposal, slashing::Vote, State as ContractState,
 };
-use derive_more::{Display, Error};
-use paris::error;
+use data_encoding::BASE64URL_NOPAD;
+use reqwest::{
+    header::{
+        HeaderMap, HeaderName, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    },
+    StatusCode,
+};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use url::Url;
 
-#[derive(Debug, Display, Error, Clone, PartialEq)]
+#[derive(Debug, Error)]
 pub enum ContractGatewayError {
-    RequestFailed,
+    #[error("failed to build contract gateway request: {0}")]
+    InvalidRequest(String),
+    #[error("request to contract gateway failed: {0}")]
+    RequestFailed(String),
+    #[error("invalid response from contract gateway: {0}")]
+    InvalidResponse(String),
+    #[error("contract gateway rejected {0} with status {1}")]
+    InteractionRejected(&'static str, String),
+    #[error("contract gateway is throttling requests, retry after {0:?}")]
+    Throttled(Duration),
 }
 
 pub trait ContractGatewayAccess {
@@ -19,6 +53,36 @@ pub trait ContractGatewayAccess {
 #[derive(Clone, Debug)]
 pub struct ContractGateway {
     pub url: Url,
+    cache: Arc<Mutex<Option<CachedState>>>,
+    /// Nonce of the last interaction submitted
+    last_nonce: Arc<AtomicU64>,
+}
+
+/// Contract state last received from the gateway, with the `ETag` and
+/// `Last-Modified` values needed to ask for it conditionally
+#[derive(Clone)]
+struct CachedState {
+    state: Arc<ContractState>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fetched_at: Instant,
+}
+
+impl fmt::Debug for CachedState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedState")
+            .field("etag", &self.etag)
+            .field("last_modified", &self.last_modified)
+            .field("fetched_at", &self.fetched_at)
+            .finish_non_exhaustive()
+    }
+}
+
+fn header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string)
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -27,92 +91,543 @@ struct VoteRequest<'a> {
     vote: Vote,
 }
 
+#[derive(Clone, Debug, Serialize)]
+struct SlashRequest<'a> {
+    tx: &'a str,
+    kind: &'a str,
+    /// Both contradicting pieces of evidence, oldest first
+    evidence: [&'a str; 2],
+}
+
+/// Cosignatures of a bundle collected by the epoch leader
+#[derive(Clone, Debug, Serialize)]
+pub struct AttestationSubmission<'a> {
+    #[serde(flatten)]
+    pub request: &'a AttestationRequest,
+    pub attestations: &'a [Attestation],
+}
+
 #[derive(Clone, Debug, Deserialize)]
-struct VoteResponse {
+struct StatusResponse {
     status: String,
 }
 
 impl ContractGateway {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            cache: Arc::default(),
+            last_nonce: Arc::default(),
+        }
+    }
+
+    /// Current contract state. A state fetched less than
+    /// `contract_state_ttl` seconds ago is returned as is, otherwise the
+    /// gateway is asked for it with the `ETag` and `Last-Modified` of the
+    /// cached state, and the response is only parsed if the state changed.
     pub async fn get_current_state<Context, HttpClient>(
         &self,
         ctx: &Context,
-    ) -> Result<ContractState, ContractGatewayError>
+    ) -> Result<Arc<ContractState>, ContractGatewayError>
     where
-        Context: http::ClientAccess<HttpClient>,
+        Context: http::ClientAccess<HttpClient> + RuntimeConfigAccess,
         HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
     {
-        let url = format!("{}validators/state", self.url);
+        let cached = self
+            .cache
+            .lock()
+            .expect("Contract state cache lock poisoned")
+            .clone();
+        let ttl = Duration::from_secs(ctx.contract_state_ttl());
+        if let Some(cached) = &cached {
+            if cached.fetched_at.elapsed() < ttl {
+                return Ok(cached.state.clone());
+            }
+        }
 
-        let req = http::request::Builder::new()
-            .method(Method::GET)
-            .uri(url)
+        let url = format!("{}validators/state", self.url);
+        let mut builder = http::request::Builder::new().method(Method::GET).uri(url);
+        if let Some(cached) = &cached {
+            if let Some(etag) = &cached.etag {
+                builder = builder.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                builder = builder.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let req = builder
             .body("".to_string())
-            .map(|req| {
-                reqwest::Request::try_from(req)
-                    .expect("Failed to convert http::request::Request into reqwest::Request")
-            })
-            .expect("Failed to parse URL for fetching contract state");
+            .map_err(|err| ContractGatewayError::InvalidRequest(err.to_string()))?;
+        let req = reqwest::Request::try_from(req)
+            .map_err(|err| ContractGatewayError::InvalidRequest(err.to_string()))?;
 
         let res = ctx
             .get_http_client()
             .execute(req)
             .await
-            .map_err(|_| ContractGatewayError::RequestFailed)?; // TODO: needs better error
+            .map_err(|err| ContractGatewayError::RequestFailed(format!("{:?}", err)))?;
+        if let Some(delay) = retry_after(res.status(), res.headers()) {
+            return Err(ContractGatewayError::Throttled(delay));
+        }
 
-        res.json()
-            .await
-            .map_err(|_| ContractGatewayError::RequestFailed) // TODO: needs better error
+        let cached = match (res.status(), cached) {
+            (StatusCode::NOT_MODIFIED, Some(cached)) => CachedState {
+                fetched_at: Instant::now(),
+                ..cached
+            },
+            (StatusCode::NOT_MODIFIED, None) => {
+                return Err(ContractGatewayError::InvalidResponse(
+                    "state not modified but none cached".to_string(),
+                ))
+            }
+            _ => {
+                let etag = header_string(res.headers(), ETAG);
+                let last_modified = header_string(res.headers(), LAST_MODIFIED);
+                let state: ContractState = res
+                    .json()
+                    .await
+                    .map_err(|err| ContractGatewayError::InvalidResponse(err.to_string()))?;
+                CachedState {
+                    state: Arc::new(state),
+                    etag,
+                    last_modified,
+                    fetched_at: Instant::now(),
+                }
+            }
+        };
+
+        let state = cached.state.clone();
+        *self
+            .cache
+            .lock()
+            .expect("Contract state cache lock poisoned") = Some(cached);
+        Ok(state)
     }
 
-    pub async fn vote_for_proposal<Context, HttpClient>(
+    /// Nonce for the next interaction, greater than the previous one. Nonces
+    /// start from the current time in milliseconds so they keep increasing
+    /// across restarts.
+    fn next_nonce(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time before unix epoch")
+            .as_millis() as u64;
+        let next = |last: u64| last.max(now.saturating_sub(1)) + 1;
+        let last = self
+            .last_nonce
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(next(last)))
+            .expect("Nonce update never fails");
+        next(last)
+    }
+
+    pub async fn vote_for_proposal<Context, HttpClient, KeyManager>(
         &self,
         ctx: &Context,
+        key_manager: &KeyManager,
         proposal: &SlashProposal,
         vote: Vote,
     ) -> Result<(), ContractGatewayError>
     where
         Context: http::ClientAccess<HttpClient>,
         HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+        KeyManager: key_manager::KeyManager,
+    {
+        let input = VoteRequest {
+            tx: &proposal.id,
+            vote,
+        };
+        SignedInteraction::new("vote", &input)
+            .submit(self, ctx, key_manager)
+            .await
+    }
+
+    pub async fn submit_attestations<Context, HttpClient, KeyManager>(
+        &self,
+        ctx: &Context,
+        key_manager: &KeyManager,
+        submission: &AttestationSubmission<'_>,
+    ) -> Result<(), ContractGatewayError>
+    where
+        Context: http::ClientAccess<HttpClient>,
+        HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+        KeyManager: key_manager::KeyManager,
+    {
+        SignedInteraction::new("attest", submission)
+            .submit(self, ctx, key_manager)
+            .await
+    }
+
+    /// Propose slashing the bundler for `equivocation`
+    pub async fn propose_slash<Context, HttpClient, KeyManager>(
+        &self,
+        ctx: &Context,
+        key_manager: &KeyManager,
+        equivocation: &Equivocation,
+    ) -> Result<(), ContractGatewayError>
+    where
+        Context: http::ClientAccess<HttpClient>,
+        HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+        KeyManager: key_manager::KeyManager,
     {
-        let url = format!("{}validators/vote", self.url);
+        let input = SlashRequest {
+            tx: &equivocation.tx_id,
+            kind: &equivocation.kind,
+            evidence: [&equivocation.first_evidence, &equivocation.second_evidence],
+        };
+        SignedInteraction::new("propose_slash", &input)
+            .submit(self, ctx, key_manager)
+            .await
+    }
+}
+
+/// Message signed for an interaction, the gateway checks the signature
+/// against the `owner` sent along with it
+pub fn interaction_message(function: &str, nonce: u64, input: &[u8]) -> Vec<u8> {
+    let mut message = format!("{}\n{}\n", function, nonce).into_bytes();
+    message.extend_from_slice(input);
+    message
+}
+
+/// Body of an interaction sent to the contract gateway
+#[derive(Serialize)]
+struct InteractionBody<'a, Input> {
+    #[serde(flatten)]
+    input: &'a Input,
+    nonce: u64,
+    /// Public modulus of the validator key, base64url encoded
+    owner: String,
+    /// Signature of [`interaction_message`], base64url encoded
+    signature: String,
+}
+
+/// Call of a contract function, signed with the validator key and posted to
+/// `validators/{function}` on the contract gateway.
+///
+/// The interaction is signed once with a new nonce, which is also sent as
+/// [`IDEMPOTENCY_KEY`]: the gateway applies an interaction only once per
+/// nonce, so the client's `Retry` middleware may submit the same signed body
+/// again when the gateway can't be reached or answers with a transient
+/// status. A gateway asking to wait longer than [`MAX_RETRY_AFTER`] isn't
+/// waited for, the interaction fails with [`ContractGatewayError::Throttled`]
+/// instead.
+pub struct SignedInteraction<'a, Input> {
+    function: &'static str,
+    input: &'a Input,
+}
+
+impl<'a, Input> SignedInteraction<'a, Input>
+where
+    Input: Serialize,
+{
+    pub fn new(function: &'static str, input: &'a Input) -> Self {
+        Self { function, input }
+    }
+
+    pub async fn submit<Context, HttpClient, KeyManager>(
+        self,
+        gateway: &ContractGateway,
+        ctx: &Context,
+        key_manager: &KeyManager,
+    ) -> Result<(), ContractGatewayError>
+    where
+        Context: http::ClientAccess<HttpClient>,
+        HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+        KeyManager: key_manager::KeyManager,
+    {
+        let input = serde_json::to_vec(self.input)
+            .map_err(|err| ContractGatewayError::InvalidRequest(err.to_string()))?;
+        let nonce = gateway.next_nonce();
+        let signature =
+            key_manager.validator_sign(&interaction_message(self.function, nonce, &input));
+        let body = serde_json::to_string(&InteractionBody {
+            input: self.input,
+            nonce,
+            owner: BASE64URL_NOPAD.encode(&key_manager.validator_owner()),
+            signature: BASE64URL_NOPAD.encode(&signature),
+        })
+        .map_err(|err| ContractGatewayError::InvalidRequest(err.to_string()))?;
+        let url = format!("{}validators/{}", gateway.url, self.function);
 
         let req = http::request::Builder::new()
             .method(Method::POST)
-            .uri(url)
-            .body(
-                serde_json::to_string(&VoteRequest {
-                    tx: &proposal.id,
-                    vote,
-                })
-                .map_err(|err| {
-                    error!("Building request failed: {:?}", err);
-                    // TODO: needs better error
-                    ContractGatewayError::RequestFailed
-                })?,
-            )
-            .map(|req| {
-                reqwest::Request::try_from(req)
-                    .expect("Failed to convert http::request::Request into reqwest::Request")
-            })
-            .expect("Failed to parse URL for fetching contract state");
+            .uri(url.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .header(IDEMPOTENCY_KEY, nonce)
+            .body(body)
+            .map_err(|err| ContractGatewayError::InvalidRequest(err.to_string()))?;
+        let req = reqwest::Request::try_from(req)
+            .map_err(|err| ContractGatewayError::InvalidRequest(err.to_string()))?;
 
-        let res = ctx.get_http_client().execute(req).await.map_err(|err| {
-            error!("Request failed: {:?}", err);
-            // TODO: needs better error
-            ContractGatewayError::RequestFailed
-        })?; // TODO: needs better error
+        let res = ctx
+            .get_http_client()
+            .execute(req)
+            .await
+            .map_err(|err| ContractGatewayError::RequestFailed(format!("{:?}", err)))?;
+        if let Some(delay) = retry_after(res.status(), res.headers()) {
+            return Err(ContractGatewayError::Throttled(delay));
+        }
 
-        let res: VoteResponse = res.json().await.map_err(|err| {
-            error!("Failed to deserialize the response: {:?}", err);
-            // TODO: needs better error
-            ContractGatewayError::RequestFailed
-        })?;
+        let res: StatusResponse = res
+            .json()
+            .await
+            .map_err(|err| ContractGatewayError::InvalidResponse(err.to_string()))?;
 
         if res.status != "OK" {
-            error!("Request failed: {:?}", res);
-            return Err(ContractGatewayError::RequestFailed);
+            return Err(ContractGatewayError::InteractionRejected(
+                self.function,
+                res.status,
+            ));
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use bundlr_contracts_validators::{Epoch as ContractEpoch, State};
+    use data_encoding::BASE64URL_NOPAD;
+    use http::Method;
+    use reqwest::{
+        header::{ETAG, IF_NONE_MATCH},
+        Request, Response,
+    };
+    use serde_json::{json, Value};
+
+    use super::{
+        interaction_message, ContractGateway, ContractGatewayAccess, ContractGatewayError,
+        SignedInteraction,
+    };
+    use crate::{
+        config::RuntimeConfig,
+        context::test_utils::TestContext,
+        http::{middleware::Retry, reqwest::mock::MockHttpClient, Client},
+        key_manager::{test_utils::test_keys, verify_owner_signature},
+    };
+
+    fn contract_state() -> State {
+        State {
+            bundler: "bundler_address".try_into().unwrap(),
+            bundlers_contract: "bundlers_contract_address".try_into().unwrap(),
+            epoch: ContractEpoch {
+                seq: 1,
+                tx: "tx1".try_into().unwrap(),
+                height: 1,
+            },
+            epoch_duration: 2,
+            minimum_stake: 1.into(),
+            token: "token_contract_address".try_into().unwrap(),
+            max_num_nominated_validators: 10,
+            validators: HashMap::new(),
+            nominated_validators: Vec::new(),
+            slash_proposal_lifetime: 10,
+            slash_proposals: HashMap::new(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn unmodified_state_is_served_from_cache() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.headers()
+                    .get(IF_NONE_MATCH)
+                    .map_or(false, |etag| etag == "\"v1\"")
+            })
+            .times(1)
+            .then(|_: &Request| {
+                let response = http::response::Builder::new().status(304).body("");
+                Response::from(response.unwrap())
+            })
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && &req.url().to_string() == "http://localhost:3000/validators/state"
+            })
+            .times(1)
+            .then(|_: &Request| {
+                let body = serde_json::to_string(&contract_state()).unwrap();
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .header(ETAG, "\"v1\"")
+                    .body(body);
+                Response::from(response.unwrap())
+            });
+        let ctx = TestContext::builder()
+            .with_http_client(client.clone())
+            .with_runtime_config(RuntimeConfig {
+                contract_state_ttl: 0,
+                ..RuntimeConfig::default()
+            })
+            .build();
+
+        let first = ctx
+            .contract_gateway()
+            .get_current_state(&ctx)
+            .await
+            .unwrap();
+        let second = ctx
+            .contract_gateway()
+            .get_current_state(&ctx)
+            .await
+            .unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+        client.assert_expectations();
+    }
+
+    #[actix_rt::test]
+    async fn state_is_not_requested_again_within_ttl() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && &req.url().to_string() == "http://localhost:3000/validators/state"
+            })
+            .times(1)
+            .then(|_: &Request| {
+                let body = serde_json::to_string(&contract_state()).unwrap();
+                let response = http::response::Builder::new().status(200).body(body);
+                Response::from(response.unwrap())
+            });
+        let ctx = TestContext::builder()
+            .with_http_client(client.clone())
+            .build();
+
+        ctx.contract_gateway()
+            .get_current_state(&ctx)
+            .await
+            .unwrap();
+        ctx.contract_gateway()
+            .get_current_state(&ctx)
+            .await
+            .unwrap();
+
+        client.assert_expectations();
+    }
+
+    #[actix_rt::test]
+    async fn throttled_state_request_carries_retry_after() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.method() == Method::GET)
+            .then(|_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(429)
+                    .header("Retry-After", "90")
+                    .body("".to_string());
+                Response::from(response.unwrap())
+            });
+        let ctx = TestContext::builder().with_http_client(client).build();
+
+        let err = ctx
+            .contract_gateway()
+            .get_current_state(&ctx)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            ContractGatewayError::Throttled(delay) if delay == Duration::from_secs(90)
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn interaction_is_signed_and_resubmitted_with_same_nonce() {
+        let bodies: Arc<Mutex<Vec<Vec<u8>>>> = Arc::default();
+        let received = bodies.clone();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::POST
+                    && &req.url().to_string() == "http://localhost:3000/validators/join"
+            })
+            .times(2)
+            .then(move |req: &Request| {
+                let mut bodies = received.lock().unwrap();
+                bodies.push(req.body().unwrap().as_bytes().unwrap().to_vec());
+                let response = if bodies.len() == 1 {
+                    http::response::Builder::new()
+                        .status(503)
+                        .body("".to_string())
+                } else {
+                    http::response::Builder::new()
+                        .status(200)
+                        .body("{\"status\": \"OK\"}".to_string())
+                };
+                Response::from(response.unwrap())
+            });
+        let ctx = TestContext::builder()
+            .with_http_client(client.clone().with(Retry {
+                backoff: Duration::ZERO,
+                ..Retry::default()
+            }))
+            .build();
+        let (key_manager, _) = test_keys();
+        let input = json!({ "url": "http://validator.example.com/" });
+
+        SignedInteraction::new("join", &input)
+            .submit(ctx.contract_gateway(), &ctx, &key_manager)
+            .await
+            .unwrap();
+
+        client.assert_expectations();
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies[0], bodies[1]);
+        let body: Value = serde_json::from_slice(&bodies[0]).unwrap();
+        assert_eq!(body["url"], input["url"]);
+        let owner = BASE64URL_NOPAD
+            .decode(body["owner"].as_str().unwrap().as_bytes())
+            .unwrap();
+        let signature = BASE64URL_NOPAD
+            .decode(body["signature"].as_str().unwrap().as_bytes())
+            .unwrap();
+        let message = interaction_message(
+            "join",
+            body["nonce"].as_u64().unwrap(),
+            &serde_json::to_vec(&input).unwrap(),
+        );
+        assert!(verify_owner_signature(&owner, &message, &signature));
+    }
+
+    #[actix_rt::test]
+    async fn long_retry_after_is_returned_instead_of_waited_for() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::POST
+                    && &req.url().to_string() == "http://localhost:3000/validators/join"
+            })
+            .times(1)
+            .then(|_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(429)
+                    .header("Retry-After", "3600")
+                    .body("".to_string());
+                Response::from(response.unwrap())
+            });
+        let ctx = TestContext::builder()
+            .with_http_client(client.clone().with(Retry::default()))
+            .build();
+        let (key_manager, _) = test_keys();
+        let input = json!({ "url": "http://validator.example.com/" });
+
+        let err = SignedInteraction::new("join", &input)
+            .submit(ctx.contract_gateway(), &ctx, &key_manager)
+            .await
+            .unwrap_err();
+
+        client.assert_expectations();
+        assert!(matches!(
+            err,
+            ContractGatewayError::Throttled(delay) if delay == Duration::from_secs(3600)
+        ));
+    }
+
+    #[test]
+    fn nonces_increase() {
+        let gateway = ContractGateway::new("http://localhost:3000".parse().unwrap());
+        let first = gateway.next_nonce();
+        assert!(gateway.next_nonce() > first);
+    }
+}
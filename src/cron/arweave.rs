@@ -1,19 +1,50 @@
-use paris::error;
-use paris::info;
+use data_encoding::BASE64URL_NOPAD;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::fmt::Debug;
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::Path;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 use url::Url;
 
 use crate::context::ArweaveAccess;
-use crate::http::Client;
+use crate::http::{middleware::retry_after, Client};
+use crate::metrics::MetricsAccess;
 use crate::state::ValidatorStateAccess;
 
+use super::indexer::Indexer;
+
+/// Directory bundle data is downloaded to, kept apart from the bundle
+/// store so cleaning up downloads never touches stored bundles
+pub const DOWNLOADS_DIR: &str = "./downloads";
+
+/// Location of transaction data in the weave, as returned by `/tx/{id}/offset`
+#[derive(Deserialize)]
+struct TxOffset {
+    size: String,
+    offset: String,
+}
+
+/// Transaction as returned by `/tx/{id}`, small transactions carry their
+/// data inline
+#[derive(Deserialize)]
+struct TxHeader {
+    #[serde(default)]
+    data: String,
+    data_size: String,
+}
+
+/// Piece of transaction data, as returned by `/chunk/{offset}`
+#[derive(Deserialize)]
+struct Chunk {
+    chunk: String,
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct NetworkInfo {
     pub network: String,
@@ -22,8 +53,12 @@ pub struct NetworkInfo {
     pub height: u128,
     pub current: String,
     pub blocks: usize,
+    // Not returned by every ArLocal version
+    #[serde(default)]
     pub peers: usize,
+    #[serde(default)]
     pub queue_length: usize,
+    #[serde(default)]
     pub node_state_latency: usize,
 }
 
@@ -45,8 +80,8 @@ pub struct Fee {
 
 #[derive(Deserialize, Serialize, Default, Clone, Debug)]
 pub struct TransactionData {
-    size: String,
-    r#type: Option<String>,
+    pub size: String,
+    pub r#type: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Default, Clone, Debug)]
@@ -63,13 +98,23 @@ pub struct Transaction {
     pub signature: String,
     pub recipient: Option<String>,
     pub tags: Vec<Tag>,
+    pub data: Option<TransactionData>,
     pub block: Option<BlockInfo>,
 }
 
+impl Transaction {
+    /// Size of the transaction data in bytes, if returned by the gateway
+    pub fn data_size(&self) -> Option<u64> {
+        self.data.as_ref()?.size.parse().ok()
+    }
+}
+
 #[derive(Deserialize, Serialize, Default, Clone, Debug)]
 pub struct GraphqlNodes {
     pub node: Transaction,
-    pub cursor: String,
+    /// Not returned by indexers giving the end cursor in `pageInfo`
+    #[serde(default)]
+    pub cursor: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Default, Clone, Debug)]
@@ -83,6 +128,8 @@ pub struct GraphqlEdges {
 #[serde(rename_all = "camelCase")]
 pub struct PageInfo {
     pub has_next_page: bool,
+    #[serde(default)]
+    pub end_cursor: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Default, Clone, Debug)]
@@ -100,23 +147,68 @@ pub struct TransactionStatus {
     pub block_indep_hash: String,
 }
 
-use derive_more::{Display, Error};
-use std::convert::From;
+use std::io;
+use thiserror::Error;
 
 use super::CronJobError;
 
-#[derive(Debug, Display, Error, Clone, PartialEq)]
+#[derive(Debug, Error)]
 pub enum ArweaveError {
+    #[error("transaction not found")]
     TxsNotFound,
+    #[error("malformed query")]
     MalformedQuery,
+    #[error("gateway internal server error")]
     InternalServerError,
+    #[error("gateway timed out")]
     GatewayTimeout,
-    UnknownErr,
+    #[error("unexpected response status {0}")]
+    UnexpectedStatus(reqwest::StatusCode),
+    #[error("gateway answered {status}, retry after {retry_after:?}")]
+    Throttled {
+        status: reqwest::StatusCode,
+        retry_after: Duration,
+    },
+    #[error("request to {endpoint} failed: {reason}")]
+    RequestFailed { endpoint: String, reason: String },
+    #[error("invalid response from {endpoint}: {reason}")]
+    InvalidResponse { endpoint: String, reason: String },
+    #[error("failed to write {path}: {source}")]
+    Io { path: String, source: io::Error },
 }
 
-impl From<anyhow::Error> for ArweaveError {
-    fn from(_err: anyhow::Error) -> ArweaveError {
-        ArweaveError::UnknownErr
+impl ArweaveError {
+    fn request_failed(endpoint: &str, err: impl Debug) -> Self {
+        ArweaveError::RequestFailed {
+            endpoint: endpoint.to_string(),
+            reason: format!("{:?}", err),
+        }
+    }
+
+    fn invalid_response(endpoint: &str, reason: impl ToString) -> Self {
+        ArweaveError::InvalidResponse {
+            endpoint: endpoint.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Error for a response with an unexpected status, with the delay a
+    /// throttling gateway asked to wait
+    fn status(res: &reqwest::Response) -> Self {
+        match retry_after(res.status(), res.headers()) {
+            Some(retry_after) => ArweaveError::Throttled {
+                status: res.status(),
+                retry_after,
+            },
+            None => ArweaveError::UnexpectedStatus(res.status()),
+        }
+    }
+
+    fn io(path: &str, source: io::Error) -> Self {
+        ArweaveError::Io {
+            path: path.to_string(),
+            source,
+        }
     }
 }
 
@@ -126,9 +218,14 @@ pub enum ArweaveProtocol {
     Https,
 }
 
+/// Arweave gateway client. Clones share the gateway URL, so a gateway
+/// change is seen by every task.
 #[derive(Clone)]
 pub struct Arweave {
-    pub url: Url,
+    url: Arc<RwLock<Url>>,
+    /// Whether the gateway is the one the bundler serves on `/info`, as
+    /// opposed to `arweave_url` configured explicitly
+    follows_bundler: bool,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -144,41 +241,233 @@ pub struct ReqBody {
     pub variables: GqlVariables,
 }
 
-pub trait ArweaveContext<HttpClient>
+pub trait ArweaveContext<HttpClient>: MetricsAccess
 where
     HttpClient: crate::http::Client<Request = reqwest::Request, Response = reqwest::Response>,
 {
     fn get_client(&self) -> &HttpClient;
+
+    /// Throttle shared by bundle downloads and the bytes per second they
+    /// may use together, `None` when bandwidth isn't limited
+    fn download_throttle(&self) -> Option<(&DownloadThrottle, u64)> {
+        None
+    }
+
+    /// Disk quota reservations shared by bundle downloads, `None` when
+    /// downloads don't run concurrently
+    fn download_quota(&self) -> Option<&DownloadQuota> {
+        None
+    }
+
+    /// GraphQL service transactions are listed from
+    fn indexer(&self) -> Indexer {
+        Indexer::default()
+    }
+
+    /// Whether the gateway is an ArLocal instance, which only includes
+    /// transactions in a block when asked to mine one
+    fn arlocal(&self) -> bool {
+        false
+    }
+}
+
+/// Paces concurrent downloads so together they stay under a byte rate
+#[derive(Clone)]
+pub struct DownloadThrottle {
+    next_slot: Arc<Mutex<Instant>>,
+}
+
+impl DownloadThrottle {
+    pub fn new() -> Self {
+        Self {
+            next_slot: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Account for `bytes` received, returns how long to pause before
+    /// receiving more to stay under `rate` bytes per second
+    fn reserve(&self, bytes: u64, rate: u64) -> Duration {
+        let mut next_slot = self.next_slot.lock().expect("Throttle lock poisoned");
+        let now = Instant::now();
+        let slot = (*next_slot).max(now);
+        *next_slot = slot + Duration::from_secs_f64(bytes as f64 / rate.max(1) as f64);
+        slot - now
+    }
+}
+
+impl Default for DownloadThrottle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Space of the downloads directory reserved by downloads in progress, so
+/// concurrent downloads can't all fit in the same free space of the quota
+#[derive(Clone, Default)]
+pub struct DownloadQuota {
+    /// Bytes reserved per bundle id
+    reserved: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl DownloadQuota {
+    /// Reserve `size` bytes for downloading `bundle_id` if they fit in
+    /// `quota` next to the files in the downloads directory and the other
+    /// reservations, returns the free bytes otherwise. Partial files of
+    /// downloads holding a reservation only count through it.
+    pub fn reserve(&self, bundle_id: &str, size: u64, quota: u64) -> Result<QuotaReservation, u64> {
+        let mut reserved = self.reserved.lock().expect("Download quota lock poisoned");
+        let used = downloads_dir_usage(|name| name == bundle_id || reserved.contains_key(name))
+            + reserved.values().sum::<u64>();
+        let free = quota.saturating_sub(used);
+        if size > free {
+            return Err(free);
+        }
+        reserved.insert(bundle_id.to_string(), size);
+        Ok(QuotaReservation {
+            reserved: self.reserved.clone(),
+            bundle_id: bundle_id.to_string(),
+        })
+    }
+}
+
+/// Reservation of [`DownloadQuota`], released when dropped once the
+/// downloaded file takes its place
+pub struct QuotaReservation {
+    reserved: Arc<Mutex<HashMap<String, u64>>>,
+    bundle_id: String,
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        self.reserved
+            .lock()
+            .expect("Download quota lock poisoned")
+            .remove(&self.bundle_id);
+    }
+}
+
+/// Bytes taken by files of the downloads directory, other than those
+/// `skip` matches the name of
+fn downloads_dir_usage(skip: impl Fn(&str) -> bool) -> u64 {
+    let entries = match fs::read_dir(DOWNLOADS_DIR) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("Failed to read downloads directory: {}", err);
+            return 0;
+        }
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.file_name().to_str().map(&skip).unwrap_or(false))
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Pause after receiving `bytes` of bundle data if downloads are throttled
+async fn throttle<Context, HttpClient>(ctx: &Context, bytes: usize)
+where
+    Context: ArweaveContext<HttpClient>,
+    HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    if let Some((throttle, rate)) = ctx.download_throttle() {
+        let wait = throttle.reserve(bytes as u64, rate);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+fn record_request<Context, HttpClient>(ctx: &Context, endpoint: &str, status: reqwest::StatusCode)
+where
+    Context: ArweaveContext<HttpClient>,
+    HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let result = if status.is_success() { "ok" } else { "error" };
+    ctx.metrics()
+        .arweave_requests
+        .with_label_values(&[endpoint, result])
+        .inc();
 }
 
 #[warn(dead_code)]
 impl Arweave {
     pub fn new(url: Url) -> Arweave {
-        Arweave { url }
+        Arweave {
+            url: Arc::new(RwLock::new(url)),
+            follows_bundler: false,
+        }
+    }
+
+    /// Move to the gateway the bundler moves to
+    pub fn following_bundler(mut self) -> Arweave {
+        self.follows_bundler = true;
+        self
+    }
+
+    pub fn follows_bundler(&self) -> bool {
+        self.follows_bundler
+    }
+
+    /// Replace the gateway URL, returns whether it changed
+    pub fn set_url(&self, url: Url) -> bool {
+        let mut current = self.url.write().expect("Arweave URL lock poisoned");
+        let changed = *current != url;
+        *current = url;
+        changed
     }
 
     pub async fn get_network_info<Context, HttpClient>(
         &self,
         ctx: &Context,
-    ) -> reqwest::Result<NetworkInfo>
+    ) -> Result<NetworkInfo, ArweaveError>
     where
         Context: ArweaveContext<HttpClient>,
         HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
     {
         info!("Fetch network info");
-        let uri = http::uri::Uri::from_str(&format!("{}info", self.get_host())).unwrap();
-        let req: http::Request<String> = http::request::Builder::new()
-            .method(http::Method::GET)
-            .uri(uri)
-            .body("".to_string())
-            .unwrap();
+        let url = self
+            .get_host()
+            .join("info")
+            .map_err(|_| ArweaveError::MalformedQuery)?;
+        let req = reqwest::Request::new(reqwest::Method::GET, url);
+        let res = ctx
+            .get_client()
+            .execute(req)
+            .await
+            .map_err(|err| ArweaveError::request_failed("info", err))?;
+        record_request(ctx, "info", res.status());
+        if res.status().is_success() {
+            res.json()
+                .await
+                .map_err(|err| ArweaveError::invalid_response("info", err))
+        } else {
+            Err(ArweaveError::status(&res))
+        }
+    }
 
-        let req: reqwest::Request = reqwest::Request::try_from(req).unwrap();
-        let res: reqwest::Response = ctx.get_client().execute(req).await.expect("request failed"); // FIXME: should not panic, handle failure
+    /// Ask an ArLocal gateway to mine a block including pending transactions
+    pub async fn mine<Context, HttpClient>(&self, ctx: &Context) -> Result<(), ArweaveError>
+    where
+        Context: ArweaveContext<HttpClient>,
+        HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        let url = self
+            .get_host()
+            .join("mine")
+            .map_err(|_| ArweaveError::MalformedQuery)?;
+        let req = reqwest::Request::new(reqwest::Method::GET, url);
+        let res = ctx
+            .get_client()
+            .execute(req)
+            .await
+            .map_err(|err| ArweaveError::request_failed("mine", err))?;
+        record_request(ctx, "mine", res.status());
         if res.status().is_success() {
-            return res.json().await;
+            Ok(())
         } else {
-            Err(res.error_for_status().err().unwrap()) // FIXME: do not unwrap
+            Err(ArweaveError::status(&res))
         }
     }
 
@@ -186,40 +475,256 @@ impl Arweave {
         &self,
         ctx: &Context,
         transaction_id: &str,
-    ) -> reqwest::Result<String>
+    ) -> Result<String, ArweaveError>
     where
         Context: ArweaveContext<HttpClient>,
         HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
     {
-        info!("Downloading bundle {} content ...", &transaction_id);
-        let raw_path = format!("./bundles/{}", transaction_id);
-        let file_path = Path::new(&raw_path);
-        let mut buffer = File::create(&file_path).unwrap(); // FIXME: change to expect
-
-        let uri =
-            http::uri::Uri::from_str(&format!("{}{}", self.get_host(), transaction_id)).unwrap();
-        let req: http::Request<String> = http::request::Builder::new()
-            .method(http::Method::GET)
-            .uri(uri)
-            .body("".to_string())
-            .unwrap();
-
-        let req: reqwest::Request = reqwest::Request::try_from(req).unwrap();
-        let mut res: reqwest::Response =
-            ctx.get_client().execute(req).await.expect("request failed"); // FIXME: should not panic, handle failure
-        if res.status().is_success() {
-            while let Some(chunk) = res.chunk().await? {
-                match buffer.write(&chunk) {
-                    Ok(_) => {}
-                    Err(err) => {
-                        error!("Error writing on file {:?}: {:?}", file_path.to_str(), err)
+        let url = format!("{}{}", self.get_host(), transaction_id);
+        match self
+            .download_tx_data(ctx, transaction_id, &url, "tx_data")
+            .await
+        {
+            // Data of transactions the gateway didn't index yet may still
+            // be inline in the transaction
+            Err(err @ ArweaveError::UnexpectedStatus(reqwest::StatusCode::NOT_FOUND)) => {
+                match self.get_inline_tx_data(ctx, transaction_id).await {
+                    Ok(Some(path)) => Ok(path),
+                    Ok(None) => Err(err),
+                    Err(inline_err) => {
+                        info!(
+                            bundle = transaction_id,
+                            "No inline data for bundle: {}", inline_err
+                        );
+                        Err(err)
                     }
                 }
             }
-            info!("Downloaded {} content!", &transaction_id);
-            return Ok(String::from(file_path.to_string_lossy()));
-        } else {
-            Err(res.error_for_status().err().unwrap()) // FIXME: do not unwrap
+            res => res,
+        }
+    }
+
+    /// Write the data inlined in the `/tx/{id}` response of
+    /// `transaction_id` to the downloads directory, `None` if its data is
+    /// only stored in chunks
+    async fn get_inline_tx_data<Context, HttpClient>(
+        &self,
+        ctx: &Context,
+        transaction_id: &str,
+    ) -> Result<Option<String>, ArweaveError>
+    where
+        Context: ArweaveContext<HttpClient>,
+        HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        let tx: TxHeader = self
+            .get_node_json(
+                ctx,
+                &self.get_host(),
+                &format!("tx/{}", transaction_id),
+                "tx",
+            )
+            .await?;
+        if tx.data.is_empty() {
+            return Ok(None);
+        }
+
+        let data = BASE64URL_NOPAD
+            .decode(tx.data.as_bytes())
+            .map_err(|err| ArweaveError::invalid_response("tx", err))?;
+        if tx.data_size.parse::<usize>().ok() != Some(data.len()) {
+            return Err(ArweaveError::invalid_response(
+                "tx",
+                "inline data doesn't match data_size",
+            ));
+        }
+
+        let (raw_path, mut file) = Self::create_tx_data_file(transaction_id)?;
+        file.write_all(&data)
+            .map_err(|err| ArweaveError::io(&raw_path, err))?;
+        info!(bundle = transaction_id, "Read bundle content inline");
+        Ok(Some(raw_path))
+    }
+
+    /// Path data of `transaction_id` is downloaded to
+    pub fn tx_data_path(transaction_id: &str) -> String {
+        format!("{}/{}", DOWNLOADS_DIR, transaction_id)
+    }
+
+    /// Create, or truncate, the file data of `transaction_id` is downloaded
+    /// to, along with the downloads directory
+    fn create_tx_data_file(transaction_id: &str) -> Result<(String, File), ArweaveError> {
+        fs::create_dir_all(DOWNLOADS_DIR).map_err(|err| ArweaveError::io(DOWNLOADS_DIR, err))?;
+        let raw_path = Self::tx_data_path(transaction_id);
+        let file = File::create(&raw_path).map_err(|err| ArweaveError::io(&raw_path, err))?;
+        Ok((raw_path, file))
+    }
+
+    /// Size of the data of `transaction_id` as announced by the gateway,
+    /// learned without downloading the data
+    pub async fn get_tx_data_size<Context, HttpClient>(
+        &self,
+        ctx: &Context,
+        transaction_id: &str,
+    ) -> Result<Option<u64>, ArweaveError>
+    where
+        Context: ArweaveContext<HttpClient>,
+        HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        let url = self
+            .get_host()
+            .join(transaction_id)
+            .map_err(|_| ArweaveError::MalformedQuery)?;
+        let req = reqwest::Request::new(reqwest::Method::HEAD, url);
+        let res = ctx
+            .get_client()
+            .execute(req)
+            .await
+            .map_err(|err| ArweaveError::request_failed("tx_data_size", err))?;
+        record_request(ctx, "tx_data_size", res.status());
+
+        match res.status() {
+            reqwest::StatusCode::OK => Ok(res
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())),
+            reqwest::StatusCode::NOT_FOUND => Err(ArweaveError::TxsNotFound),
+            _ => Err(ArweaveError::status(&res)),
+        }
+    }
+
+    /// Download data of `transaction_id` from `url` into the bundles
+    /// directory, `endpoint` labels the request in metrics
+    pub async fn download_tx_data<Context, HttpClient>(
+        &self,
+        ctx: &Context,
+        transaction_id: &str,
+        url: &str,
+        endpoint: &str,
+    ) -> Result<String, ArweaveError>
+    where
+        Context: ArweaveContext<HttpClient>,
+        HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        info!(
+            bundle = transaction_id,
+            url, "Downloading bundle content ..."
+        );
+        let url = Url::from_str(url).map_err(|_| ArweaveError::MalformedQuery)?;
+        let req = reqwest::Request::new(reqwest::Method::GET, url);
+        let mut res = ctx
+            .get_client()
+            .execute(req)
+            .await
+            .map_err(|err| ArweaveError::request_failed(endpoint, err))?;
+        record_request(ctx, endpoint, res.status());
+        if !res.status().is_success() {
+            return Err(ArweaveError::status(&res));
+        }
+
+        let (raw_path, mut file) = Self::create_tx_data_file(transaction_id)?;
+        while let Some(chunk) = res
+            .chunk()
+            .await
+            .map_err(|err| ArweaveError::invalid_response(endpoint, err))?
+        {
+            file.write_all(&chunk)
+                .map_err(|err| ArweaveError::io(&raw_path, err))?;
+            throttle(ctx, chunk.len()).await;
+        }
+        info!(bundle = transaction_id, "Downloaded bundle content");
+        Ok(raw_path)
+    }
+
+    /// Download data of `transaction_id` chunk by chunk from the Arweave
+    /// `node`, for data gateways won't serve in a single response
+    pub async fn download_tx_chunks<Context, HttpClient>(
+        &self,
+        ctx: &Context,
+        transaction_id: &str,
+        node: &Url,
+    ) -> Result<String, ArweaveError>
+    where
+        Context: ArweaveContext<HttpClient>,
+        HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        info!(
+            bundle = transaction_id,
+            %node, "Downloading bundle content in chunks ..."
+        );
+        let tx_offset: TxOffset = self
+            .get_node_json(
+                ctx,
+                node,
+                &format!("tx/{}/offset", transaction_id),
+                "tx_offset",
+            )
+            .await?;
+        let size: u64 = tx_offset
+            .size
+            .parse()
+            .map_err(|err| ArweaveError::invalid_response("tx_offset", err))?;
+        let end: u64 = tx_offset
+            .offset
+            .parse()
+            .map_err(|err| ArweaveError::invalid_response("tx_offset", err))?;
+        // `offset` is the absolute offset of the last byte of the data
+        let start = (end + 1)
+            .checked_sub(size)
+            .ok_or_else(|| ArweaveError::invalid_response("tx_offset", "size exceeds offset"))?;
+
+        let (raw_path, mut file) = Self::create_tx_data_file(transaction_id)?;
+        let mut written = 0;
+        while written < size {
+            let chunk: Chunk = self
+                .get_node_json(ctx, node, &format!("chunk/{}", start + written), "chunk")
+                .await?;
+            let data = BASE64URL_NOPAD
+                .decode(chunk.chunk.as_bytes())
+                .map_err(|err| ArweaveError::invalid_response("chunk", err))?;
+            if data.is_empty() {
+                return Err(ArweaveError::invalid_response("chunk", "empty chunk"));
+            }
+            file.write_all(&data)
+                .map_err(|err| ArweaveError::io(&raw_path, err))?;
+            written += data.len() as u64;
+            throttle(ctx, data.len()).await;
+        }
+
+        info!(bundle = transaction_id, "Downloaded bundle content");
+        Ok(raw_path)
+    }
+
+    /// JSON returned by `path` of an Arweave node
+    async fn get_node_json<Context, HttpClient, T>(
+        &self,
+        ctx: &Context,
+        node: &Url,
+        path: &str,
+        endpoint: &str,
+    ) -> Result<T, ArweaveError>
+    where
+        Context: ArweaveContext<HttpClient>,
+        HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+        T: serde::de::DeserializeOwned,
+    {
+        let url = node.join(path).map_err(|_| ArweaveError::MalformedQuery)?;
+        let req = reqwest::Request::new(reqwest::Method::GET, url);
+        let res = ctx
+            .get_client()
+            .execute(req)
+            .await
+            .map_err(|err| ArweaveError::request_failed(endpoint, err))?;
+        record_request(ctx, endpoint, res.status());
+
+        match res.status() {
+            reqwest::StatusCode::OK => res
+                .json()
+                .await
+                .map_err(|err| ArweaveError::invalid_response(endpoint, err)),
+            reqwest::StatusCode::NOT_FOUND => Err(ArweaveError::TxsNotFound),
+            reqwest::StatusCode::GATEWAY_TIMEOUT => Err(ArweaveError::GatewayTimeout),
+            _ => Err(ArweaveError::status(&res)),
         }
     }
 
@@ -234,44 +739,51 @@ impl Arweave {
         Context: ArweaveContext<HttpClient>,
         HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
     {
-        let raw_query = "query($owners: [String!], $first: Int) { transactions(owners: $owners, first: $first) { pageInfo { hasNextPage } edges { cursor node { id owner { address } signature recipient tags { name value } block { height id timestamp } } } } }";
-        let raw_variables = format!(
-            "{{\"owners\": [\"{}\"], \"first\": {}, \"after\": {}}}",
-            owner,
-            first.unwrap_or(10),
-            match after {
-                None => r"null".to_string(),
-                Some(a) => a,
-            }
-        );
-
-        let url = format!("{}graphql?query={}", self.get_host(), raw_query);
-
-        // TODO: why to build object by parsing from string and then turn it later back to string
-        let data = format!(
-            "{{\"query\":\"{}\",\"variables\":{}}}",
-            raw_query, raw_variables
-        );
+        let indexer = ctx.indexer();
+        let raw_query = indexer.query();
+        let endpoint = indexer
+            .endpoint(&self.get_host())
+            .map_err(|_| ArweaveError::MalformedQuery)?;
+        let url = format!("{}?query={}", endpoint, raw_query);
+        let body = ReqBody {
+            query: raw_query.to_string(),
+            variables: GqlVariables {
+                owners: vec![owner.to_string()],
+                first: u128::try_from(first.unwrap_or(10).min(indexer.max_page_size()))
+                    .map_err(|_| ArweaveError::MalformedQuery)?,
+                after,
+            },
+        };
 
-        let reqwest_client = reqwest::Client::new();
-        let body = serde_json::from_str::<ReqBody>(&data);
-        let req = reqwest_client
+        let req = reqwest::Client::new()
             .post(&url)
-            .json(&body.unwrap()) // FIXME: do not unwrap
+            .json(&body)
             .build()
-            .unwrap();
-        let res = ctx.get_client().execute(req).await.unwrap(); // FIXME: do not unwrap
+            .map_err(|_| ArweaveError::MalformedQuery)?;
+        let res = ctx
+            .get_client()
+            .execute(req)
+            .await
+            .map_err(|err| ArweaveError::request_failed("graphql", err))?;
+        record_request(ctx, "graphql", res.status());
 
         match res.status() {
             reqwest::StatusCode::OK => {
-                let res: GraphqlQueryResponse = res.json().await.unwrap(); // FIXME: do not unwrap
+                let res: GraphqlQueryResponse = res
+                    .json()
+                    .await
+                    .map_err(|err| ArweaveError::invalid_response("graphql", err))?;
                 let mut txs: Vec<Transaction> = Vec::<Transaction>::new();
                 let mut end_cursor: Option<String> = None;
                 for tx in &res.data.transactions.edges {
                     txs.push(tx.node.clone());
-                    end_cursor = Some(tx.cursor.clone());
+                    end_cursor = tx.cursor.clone();
+                }
+                let page_info = res.data.transactions.page_info;
+                if page_info.end_cursor.is_some() {
+                    end_cursor = page_info.end_cursor;
                 }
-                let has_next_page = res.data.transactions.page_info.has_next_page;
+                let has_next_page = page_info.has_next_page;
 
                 Ok((txs, has_next_page, end_cursor))
             }
@@ -279,12 +791,81 @@ impl Arweave {
             reqwest::StatusCode::NOT_FOUND => Err(ArweaveError::TxsNotFound),
             reqwest::StatusCode::INTERNAL_SERVER_ERROR => Err(ArweaveError::InternalServerError),
             reqwest::StatusCode::GATEWAY_TIMEOUT => Err(ArweaveError::GatewayTimeout),
-            _ => Err(ArweaveError::UnknownErr),
+            _ => Err(ArweaveError::status(&res)),
+        }
+    }
+
+    /// Balance of the wallet with `address` in winston
+    pub async fn get_wallet_balance<Context, HttpClient>(
+        &self,
+        ctx: &Context,
+        address: &str,
+    ) -> Result<u128, ArweaveError>
+    where
+        Context: ArweaveContext<HttpClient>,
+        HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        let path = format!("wallet/{}/balance", address);
+        self.get_winston(ctx, &path, "wallet_balance").await
+    }
+
+    /// Fee in winston for uploading `bytes` of data
+    pub async fn get_price<Context, HttpClient>(
+        &self,
+        ctx: &Context,
+        bytes: u64,
+    ) -> Result<u128, ArweaveError>
+    where
+        Context: ArweaveContext<HttpClient>,
+        HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        let path = format!("price/{}", bytes);
+        self.get_winston(ctx, &path, "price").await
+    }
+
+    /// Amount of winston returned as plain text by `path`
+    async fn get_winston<Context, HttpClient>(
+        &self,
+        ctx: &Context,
+        path: &str,
+        endpoint: &str,
+    ) -> Result<u128, ArweaveError>
+    where
+        Context: ArweaveContext<HttpClient>,
+        HttpClient: Client<Request = reqwest::Request, Response = reqwest::Response>,
+    {
+        let url = self
+            .get_host()
+            .join(path)
+            .map_err(|_| ArweaveError::MalformedQuery)?;
+        let req = reqwest::Request::new(reqwest::Method::GET, url);
+        let res = ctx
+            .get_client()
+            .execute(req)
+            .await
+            .map_err(|err| ArweaveError::request_failed(endpoint, err))?;
+        record_request(ctx, endpoint, res.status());
+
+        match res.status() {
+            reqwest::StatusCode::OK => {
+                let text = res
+                    .text()
+                    .await
+                    .map_err(|err| ArweaveError::invalid_response(endpoint, err))?;
+                text.trim()
+                    .parse()
+                    .map_err(|err| ArweaveError::invalid_response(endpoint, err))
+            }
+            reqwest::StatusCode::BAD_REQUEST => Err(ArweaveError::MalformedQuery),
+            reqwest::StatusCode::NOT_FOUND => Err(ArweaveError::TxsNotFound),
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR => Err(ArweaveError::InternalServerError),
+            reqwest::StatusCode::GATEWAY_TIMEOUT => Err(ArweaveError::GatewayTimeout),
+            _ => Err(ArweaveError::status(&res)),
         }
     }
 
-    fn get_host(&self) -> Url {
-        self.url.clone()
+    pub fn get_host(&self) -> Url {
+        self.url.read().expect("Arweave URL lock poisoned").clone()
     }
 }
 
@@ -293,14 +874,15 @@ where
     Context: ArweaveContext<HttpClient> + ArweaveAccess + ValidatorStateAccess,
     HttpClient: crate::http::Client<Request = reqwest::Request, Response = reqwest::Response>,
 {
-    let network_info = ctx.arweave().get_network_info(ctx).await.map_err(|err| {
-        paris::error!("Request for network info failed: {:?}", err);
-        CronJobError::ArweaveError(ArweaveError::UnknownErr)
-    })?;
+    let network_info = ctx
+        .arweave()
+        .get_network_info(ctx)
+        .await
+        .map_err(CronJobError::ArweaveError)?;
 
     let state = ctx.get_validator_state();
 
-    paris::info!("Update state: current_block={}", network_info.height);
+    info!(current_block = network_info.height, "Update state");
     state.set_current_block(network_info.height);
 
     Ok(())
@@ -308,16 +890,48 @@ where
 
 #[cfg(test)]
 mod tests {
-    use std::{fs, path::Path, str::FromStr};
+    use std::{fs, path::Path, str::FromStr, time::Duration};
+
+    use data_encoding::BASE64URL_NOPAD;
 
     use crate::{
-        context::test_utils::test_context_with_http_client, cron::arweave::Arweave,
-        http::reqwest::mock::MockHttpClient, key_manager::test_utils::test_keys,
+        config::{RuntimeConfig, RuntimeConfigAccess},
+        context::test_utils::test_context_with_http_client,
+        cron::{
+            arweave::{Arweave, ArweaveError, DownloadQuota, DownloadThrottle},
+            indexer::{Indexer, IndexerKind},
+        },
+        http::reqwest::mock::MockHttpClient,
+        key_manager::test_utils::test_keys,
     };
     use http::Method;
     use reqwest::{Request, Response};
     use url::Url;
 
+    #[test]
+    fn throttle_paces_downloads_to_rate() {
+        let throttle = DownloadThrottle::new();
+
+        assert!(throttle.reserve(1000, 1000).is_zero());
+        let wait = throttle.reserve(1000, 1000);
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+        // Clones pace the same downloads
+        assert!(throttle.clone().reserve(1000, 1000) > Duration::from_millis(1900));
+    }
+
+    #[test]
+    fn quota_is_reserved_until_the_download_completes() {
+        let quota = DownloadQuota::default();
+        let usage = super::downloads_dir_usage(|_| false);
+        let limit = usage + 1000;
+
+        let first = quota.reserve("quota-first", 600, limit).unwrap();
+        // Both fit in the free space alone, not together
+        assert_eq!(quota.reserve("quota-second", 600, limit).err(), Some(400));
+        drop(first);
+        assert!(quota.reserve("quota-second", 600, limit).is_ok());
+    }
+
     #[actix_rt::test]
     async fn get_network_info() {
         let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
@@ -336,14 +950,32 @@ mod tests {
 
         let (key_manager, _bundle_pvk) = test_keys();
         let ctx = test_context_with_http_client(key_manager, client);
-        let arweave = Arweave {
-            url: Url::from_str("http://example.com").unwrap(),
-        };
+        let arweave = Arweave::new(Url::from_str("http://example.com").unwrap());
         let network_info = arweave.get_network_info(&ctx).await.unwrap();
 
         assert_eq!(network_info.height, 551511);
     }
 
+    #[actix_rt::test]
+    async fn failed_download_returns_error() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.url().as_str() == "http://example.com/missing_tx")
+            .then(|_: &Request| {
+                let response = http::response::Builder::new().status(404).body("").unwrap();
+                Response::from(response)
+            });
+
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = test_context_with_http_client(key_manager, client);
+        let arweave = Arweave::new(Url::from_str("http://example.com").unwrap());
+
+        let err = arweave.get_tx_data(&ctx, "missing_tx").await.unwrap_err();
+        assert!(matches!(err, ArweaveError::UnexpectedStatus(status) if status == 404));
+        // No response is set for the info endpoint
+        let err = arweave.get_network_info(&ctx).await.unwrap_err();
+        assert!(matches!(err, ArweaveError::RequestFailed { .. }));
+    }
+
     #[actix_rt::test]
     async fn get_tx_data_should_return_ok() {
         let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
@@ -363,12 +995,10 @@ mod tests {
 
         let (key_manager, _bundle_pvk) = test_keys();
         let ctx = test_context_with_http_client(key_manager, client);
-        let arweave = Arweave {
-            url: Url::from_str(&"http://example.com".to_string()).unwrap(),
-        };
+        let arweave = Arweave::new(Url::from_str(&"http://example.com".to_string()).unwrap());
         arweave.get_tx_data(&ctx, "tx_id").await.unwrap();
 
-        let raw_path = "./bundles/tx_id";
+        let raw_path = "./downloads/tx_id";
         let file_path = Path::new(raw_path).is_file();
         assert!(file_path); // FIXME: remove/replace use of assert
         match fs::remove_file(raw_path) {
@@ -380,11 +1010,81 @@ mod tests {
         }
     }
 
+    #[actix_rt::test]
+    async fn small_tx_data_is_read_inline_when_data_endpoint_404s() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.url().as_str() == "http://example.com/inline_tx_id")
+            .then(|_: &Request| {
+                let response = http::response::Builder::new().status(404).body("").unwrap();
+                Response::from(response)
+            })
+            .when(|req: &Request| req.url().as_str() == "http://example.com/tx/inline_tx_id")
+            .then(|_: &Request| {
+                let body = format!(
+                    r#"{{"data":"{}","data_size":"5"}}"#,
+                    BASE64URL_NOPAD.encode(b"hello")
+                );
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(body)
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = test_context_with_http_client(key_manager, client);
+        let arweave = Arweave::new(Url::from_str("http://example.com").unwrap());
+        let path = arweave.get_tx_data(&ctx, "inline_tx_id").await.unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"hello");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn tx_data_is_assembled_from_chunks() {
+        let json = |body: String| {
+            Response::from(
+                http::response::Builder::new()
+                    .status(200)
+                    .body(body)
+                    .unwrap(),
+            )
+        };
+        let chunk = |data: &str| {
+            format!(
+                r#"{{"chunk":"{}"}}"#,
+                BASE64URL_NOPAD.encode(data.as_bytes())
+            )
+        };
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.url().as_str() == "http://node.example.com/tx/chunked_tx_id/offset"
+            })
+            .then(move |_: &Request| json(r#"{"size":"11","offset":"1010"}"#.to_string()))
+            .when(|req: &Request| req.url().as_str() == "http://node.example.com/chunk/1000")
+            .then(move |_: &Request| json(chunk("hello ")))
+            .when(|req: &Request| req.url().as_str() == "http://node.example.com/chunk/1006")
+            .then(move |_: &Request| json(chunk("world")));
+
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = test_context_with_http_client(key_manager, client);
+        let arweave = Arweave::new(Url::from_str("http://example.com").unwrap());
+        let node = Url::from_str("http://node.example.com/").unwrap();
+        let path = arweave
+            .download_tx_chunks(&ctx, "chunked_tx_id", &node)
+            .await
+            .unwrap();
+        let data = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(data, "hello world");
+    }
+
     #[actix_rt::test]
     async fn get_latest_transactions_should_return_ok() {
         let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
             .when(|req: &Request| {
-                let url = "http://example.com/graphql?query=query($owners:%20[String!],%20$first:%20Int)%20{%20transactions(owners:%20$owners,%20first:%20$first)%20{%20pageInfo%20{%20hasNextPage%20}%20edges%20{%20cursor%20node%20{%20id%20owner%20{%20address%20}%20signature%20recipient%20tags%20{%20name%20value%20}%20block%20{%20height%20id%20timestamp%20}%20}%20}%20}%20}";
+                let url = "http://example.com/graphql?query=query($owners:%20[String!],%20$first:%20Int,%20$after:%20String)%20{%20transactions(owners:%20$owners,%20first:%20$first,%20after:%20$after)%20{%20pageInfo%20{%20hasNextPage%20}%20edges%20{%20cursor%20node%20{%20id%20owner%20{%20address%20}%20signature%20recipient%20tags%20{%20name%20value%20}%20data%20{%20size%20}%20block%20{%20height%20id%20timestamp%20}%20}%20}%20}%20}";
                 req.method() == Method::POST && &req.url().to_string() == url
             })
             .then(|_: &Request| {
@@ -398,12 +1098,90 @@ mod tests {
 
         let (key_manager, _bundle_pvk) = test_keys();
         let ctx = test_context_with_http_client(key_manager, client);
-        let arweave = Arweave {
-            url: Url::from_str(&"http://example.com".to_string()).unwrap(),
-        };
+        let arweave = Arweave::new(Url::from_str(&"http://example.com".to_string()).unwrap());
         arweave
             .get_latest_transactions(&ctx, "owner", None, None)
             .await
             .unwrap();
     }
+
+    #[actix_rt::test]
+    async fn arlocal_is_asked_to_mine() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::GET && req.url().as_str() == "http://localhost:1984/mine"
+            })
+            .then(|_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body("{\"blocks\": 1}")
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = test_context_with_http_client(key_manager, client);
+        let arweave = Arweave::new(Url::from_str("http://localhost:1984").unwrap());
+        arweave.mine(&ctx).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn goldsky_indexer_returns_end_cursor_of_page_info() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::POST
+                    && req.url().host_str() == Some("search.example.com")
+                    && req.url().query().unwrap().contains("HEIGHT_DESC")
+            })
+            .then(|_: &Request| {
+                let data = "{\"data\": {\"transactions\": {\"pageInfo\": {\"hasNextPage\": true, \"endCursor\": \"end_cursor\" },\"edges\": [{\"node\": { \"id\": \"tx_id\",\"owner\": {\"address\": \"address\"}, \"signature\": \"signature\",\"recipient\": \"\", \"tags\": [], \"block\": null } } ] } } }";
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data)
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = test_context_with_http_client(key_manager, client);
+        ctx.replace_runtime_config(RuntimeConfig {
+            indexer: Indexer::new(
+                IndexerKind::Goldsky,
+                Some(Url::from_str("http://search.example.com/graphql").unwrap()),
+            ),
+            ..RuntimeConfig::default()
+        });
+        let arweave = Arweave::new(Url::from_str("http://example.com").unwrap());
+
+        let (txs, has_next_page, end_cursor) = arweave
+            .get_latest_transactions(&ctx, "owner", None, None)
+            .await
+            .unwrap();
+        assert_eq!(txs.len(), 1);
+        assert!(has_next_page);
+        assert_eq!(end_cursor.as_deref(), Some("end_cursor"));
+    }
+
+    #[actix_rt::test]
+    async fn malformed_graphql_response_returns_error() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.method() == Method::POST)
+            .then(|_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body("{\"data\": null}")
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = test_context_with_http_client(key_manager, client);
+        let arweave = Arweave::new(Url::from_str("http://example.com").unwrap());
+
+        let err = arweave
+            .get_latest_transactions(&ctx, "owner", None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ArweaveError::InvalidResponse { .. }));
+    }
 }
@@ -0,0 +1,121 @@
+use tracing::{info, warn};
+
+use crate::{
+    context,
+    database::queries::{self, QueryContext},
+    events::{Event, EventBusAccess},
+    http,
+};
+
+use super::{arweave::ArweaveContext, error::ValidatorCronError, CronJobError};
+
+/// Receipts don't carry the size of the data item, pending data volume is
+/// estimated with this average item size
+const ESTIMATED_TX_SIZE: u64 = 256 * 1024;
+
+/// Compare the bundler's wallet balance with the estimated fee for
+/// uploading the transactions it promised but hasn't bundled yet, so a
+/// bundler that is about to miss its promises is noticed early
+pub async fn check_bundler_balance<Context, HttpClient>(ctx: &Context) -> Result<(), CronJobError>
+where
+    Context: ArweaveContext<HttpClient>
+        + context::ArweaveAccess
+        + context::BundlerAccess
+        + EventBusAccess
+        + QueryContext,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let pending = queries::count_pending_txs(ctx)
+        .map_err(|err| CronJobError::ValidatorError(ValidatorCronError::DatabaseError(err)))?;
+    ctx.get_validator_state()
+        .set_validation_backlog(pending as u64);
+
+    let arweave = ctx.arweave();
+    let balance = arweave
+        .get_wallet_balance(ctx, &ctx.bundler().address)
+        .await
+        .map_err(CronJobError::ArweaveError)?;
+    let required = arweave
+        .get_price(ctx, pending as u64 * ESTIMATED_TX_SIZE)
+        .await
+        .map_err(CronJobError::ArweaveError)?;
+
+    let underfunded = balance < required;
+    ctx.metrics().bundler_balance.set(balance as f64);
+    ctx.metrics().bundler_underfunded.set(underfunded as i64);
+    let was_underfunded = ctx
+        .get_validator_state()
+        .set_bundler_underfunded(underfunded);
+
+    if underfunded {
+        warn!(balance, required, pending, "Bundler is underfunded");
+        if !was_underfunded {
+            ctx.event_bus().publish(Event::BundlerUnderfunded {
+                balance: balance.to_string(),
+                required: required.to_string(),
+            });
+        }
+    } else {
+        info!(
+            balance,
+            required, pending, "Bundler balance covers pending transactions"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+    use reqwest::{Request, Response};
+
+    use super::check_bundler_balance;
+    use crate::{
+        context::test_utils::test_context_with_http_client,
+        events::{Event, EventBusAccess},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::test_utils::test_keys,
+        metrics::MetricsAccess,
+        state::ValidatorStateAccess,
+    };
+
+    fn text(body: &'static str) -> Response {
+        Response::from(
+            http::response::Builder::new()
+                .status(200)
+                .body(body)
+                .unwrap(),
+        )
+    }
+
+    #[actix_rt::test]
+    async fn low_balance_flags_bundler_as_underfunded() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::GET && req.url().path().ends_with("/balance")
+            })
+            .then(|_: &Request| text("100"))
+            .when(|req: &Request| {
+                req.method() == Method::GET && req.url().path().starts_with("/price/")
+            })
+            .then(|_: &Request| text("5000"));
+
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = test_context_with_http_client(key_manager, client);
+        let mut events = ctx.event_bus().subscribe();
+
+        check_bundler_balance(&ctx).await.unwrap();
+
+        assert!(ctx.get_validator_state().bundler_underfunded());
+        assert_eq!(ctx.metrics().bundler_underfunded.get(), 1);
+        assert_eq!(ctx.metrics().bundler_balance.get(), 100.0);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            Event::BundlerUnderfunded {
+                balance: "100".to_string(),
+                required: "5000".to_string(),
+            }
+        );
+    }
+}
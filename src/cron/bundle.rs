@@ -1,131 +1,614 @@
 extern crate diesel;
 
-use super::arweave::{self, ArweaveContext};
+use std::{fs, path::Path};
+
+use super::arweave::{self, ArweaveContext, ArweaveError, QuotaReservation};
 use super::error::ValidatorCronError;
 use super::slasher::vote_slash;
 use super::transactions::get_transactions;
+use crate::bundle::store::{BundleStore, BundleStoreAccess, QUARANTINE_PREFIX};
+use crate::bundle::{
+    file_checksum, verify_bundle_file, BundleError, BundleItem, BUNDLE_FORMAT_TAG,
+    BUNDLE_VERSION_TAG, SUPPORTED_BUNDLE_FORMATS,
+};
 use crate::bundler::Bundler;
-use crate::context::{ArweaveAccess, BundlerAccess};
+use crate::config::RuntimeConfigAccess;
+use crate::context::{ArweaveAccess, BundlerAccess, ValidatorAddressAccess};
 use crate::cron::arweave::{Arweave, Transaction as ArweaveTx};
-use crate::database::models::{Block, Epoch, NewBundle, NewTransaction};
-use crate::database::queries::{self, *};
+use crate::database::models::{
+    Block, DuplicateItem, Epoch, NestedItem, NewBundle, NewInvalidBundle, NewTransaction,
+};
+use crate::database::queries::BundleQueries;
+use crate::equivocation::{record_equivocation, EquivocationKind};
+use crate::events::{Event, EventBusAccess};
 use crate::key_manager::KeyManagerAccess;
-use crate::types::Validator;
+use crate::peers::{peer_urls, PeerOutcome, PeerScoresAccess};
+use crate::receipt::{verify_and_record_receipt, RawTxReceipt, ReceiptQueueAccess, SOURCE_PEER};
 use crate::{http, key_manager};
-use awc::Client;
-use bundlr_sdk::deep_hash_sync::{deep_hash_sync, ONE_AS_BUFFER};
-use bundlr_sdk::verify::types::Item;
-use bundlr_sdk::{deep_hash::DeepHashChunk, verify::file::verify_file_bundle};
 use data_encoding::BASE64URL_NOPAD;
-use paris::{error, info};
-use serde::{Deserialize, Serialize};
+use derive_more::Display;
+use futures::{channel::mpsc, join, stream, SinkExt, StreamExt};
+use tracing::{error, info, info_span, warn, Instrument};
+use url::Url;
 
-#[derive(Serialize, Deserialize, Default, Debug)]
-pub struct TxReceipt {
-    block: u128,
-    tx_id: String,
-    signature: String,
-}
+/// Bundler transactions requested per GraphQL page
+const BUNDLES_PER_PAGE: i64 = 50;
+/// Pages of bundler transactions validated in a single run, the rest of
+/// the backlog is left to the next runs
+const MAX_PAGES_PER_RUN: usize = 20;
 
+#[tracing::instrument(skip_all, fields(bundler = %ctx.bundler().address))]
 pub async fn validate_bundler<Context, HttpClient, KeyManager>(
     ctx: &Context,
 ) -> Result<(), ValidatorCronError>
 where
-    Context: queries::QueryContext
+    Context: BundleQueries
         + arweave::ArweaveContext<HttpClient>
         + ArweaveAccess
         + BundlerAccess
-        + KeyManagerAccess<KeyManager>,
+        + BundleStoreAccess
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + ValidatorAddressAccess,
     HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
     KeyManager: key_manager::KeyManager,
 {
+    // Receipts checked against the wrong key would make valid transactions
+    // look unreceipted
+    if ctx.get_validator_state().bundler_key_mismatch() {
+        return Err(ValidatorCronError::BundlerKeyMismatch);
+    }
+
     let arweave = ctx.arweave();
     let bundler = ctx.bundler();
-    let txs_req = arweave
-        .get_latest_transactions(ctx, &bundler.address, Some(50), None)
-        .await;
-
-    if let Err(r) = txs_req {
-        error!(
-            "Error occurred while getting txs from bundler address: \n {}. Error: {}",
-            bundler.address, r
-        );
-        return Err(ValidatorCronError::TxsFromAddressNotFound);
-    }
-
-    let txs_req = &txs_req.unwrap().0;
-    for bundle in txs_req {
-        let res = validate_bundle(ctx, arweave, bundle).await;
-        if let Err(err) = res {
-            match err {
-                ValidatorCronError::TxNotFound => todo!(),
-                ValidatorCronError::AddressNotFound => todo!(),
-                ValidatorCronError::TxsFromAddressNotFound => todo!(),
-                ValidatorCronError::BundleNotInsertedInDB => todo!(),
-                ValidatorCronError::TxInvalid => todo!(),
-                ValidatorCronError::FileError => (),
+    let fetch_page = |after: Option<String>| async move {
+        arweave
+            .get_latest_transactions(ctx, &bundler.address, Some(BUNDLES_PER_PAGE), after)
+            .instrument(info_span!("fetch_bundler_txs"))
+            .await
+            .map_err(|source| ValidatorCronError::TxsFromAddressNotFound {
+                address: bundler.address.clone(),
+                source,
+            })
+    };
+
+    if ctx.arlocal() {
+        // ArLocal leaves transactions pending until a block is mined
+        if let Err(err) = arweave.mine(ctx).await {
+            warn!("Failed to mine an ArLocal block: {}", err);
+        }
+    }
+
+    let (mut txs, mut has_next_page, mut end_cursor) = fetch_page(None).await?;
+    let mut pages = 1;
+    loop {
+        // Pages are sorted newest first, older pages are only worth
+        // fetching while this one still has bundles to validate
+        let backlog = txs
+            .iter()
+            .any(|tx| (tx.block.is_some() || ctx.arlocal()) && !has_verdict(ctx, tx));
+        let fetch_next = has_next_page && backlog && pages < MAX_PAGES_PER_RUN;
+
+        // The next page is fetched while bundles of this one are validated
+        let next_page = async {
+            match &end_cursor {
+                Some(cursor) if fetch_next => Some(fetch_page(Some(cursor.clone())).await),
+                _ => None,
             }
+        };
+        let (res, next_page) = join!(validate_bundles(ctx, &txs), next_page);
+        res?;
+
+        match next_page {
+            // A repeated cursor means the gateway isn't paginating
+            Some(Ok((next_txs, next_has_next_page, next_cursor))) if next_cursor != end_cursor => {
+                txs = next_txs;
+                has_next_page = next_has_next_page;
+                end_cursor = next_cursor;
+                pages += 1;
+            }
+            Some(Err(err)) => {
+                warn!(pages, "Failed to fetch next page of bundles: {}", err);
+                break;
+            }
+            _ => break,
         }
     }
 
     Ok(())
 }
 
-async fn validate_bundle<Context, HttpClient, KeyManager>(
+/// Download bundles in `txs` and verify them as they complete, up to
+/// `workers` of them at the same time. Downloading stops when the gateway
+/// throttles a download, which is returned once the downloaded bundles are
+/// verified.
+async fn validate_bundles<Context, HttpClient, KeyManager>(
     ctx: &Context,
-    arweave: &Arweave,
-    bundle: &ArweaveTx,
+    txs: &[ArweaveTx],
 ) -> Result<(), ValidatorCronError>
 where
-    Context: queries::QueryContext
-        + ArweaveContext<HttpClient>
+    Context: BundleQueries
+        + arweave::ArweaveContext<HttpClient>
+        + ArweaveAccess
         + BundlerAccess
-        + KeyManagerAccess<KeyManager>,
+        + BundleStoreAccess
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + ValidatorAddressAccess,
     HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
     KeyManager: key_manager::KeyManager,
 {
-    let block_ok = check_bundle_block(bundle);
-    if let Err(err) = block_ok {
-        return Err(err);
+    let arweave = ctx.arweave();
+    let max_downloads = ctx.max_concurrent_downloads().max(1);
+    let workers = ctx.workers().max(1);
+
+    // Downloaded bundles are queued for verification, downloading pauses
+    // while the queue is full
+    let (mut queue, downloaded_bundles) = mpsc::channel::<DownloadedBundle>(max_downloads);
+    let downloads = async move {
+        let mut downloads = stream::iter(txs)
+            .map(|bundle| fetch_bundle(ctx, arweave, bundle))
+            .buffer_unordered(max_downloads);
+        while let Some(res) = downloads.next().await {
+            match res {
+                Ok(Some(downloaded)) => {
+                    if queue.send(downloaded).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => (),
+                Err(err @ ValidatorCronError::BundleDownloadThrottled { .. }) => return Err(err),
+                Err(err) => handle_bundle_error(err),
+            }
+        }
+        Ok(())
+    };
+    let verification = downloaded_bundles.for_each_concurrent(workers, |downloaded| async move {
+        if let Err(err) = validate_bundle(ctx, &downloaded).await {
+            handle_bundle_error(err);
+        }
+    });
+    let (res, _) = join!(downloads, verification);
+    res
+}
+
+/// A failing bundle doesn't stop validation of the others
+fn handle_bundle_error(err: ValidatorCronError) {
+    error!("Bundle validation failed: {}", err);
+}
+
+/// Bundle stored on disk and waiting for verification
+struct DownloadedBundle<'a> {
+    bundle: &'a ArweaveTx,
+    block: u128,
+    path: String,
+}
+
+/// Why a transaction returned by the GraphQL query isn't downloaded
+#[derive(Debug, Display, PartialEq)]
+enum SkipReason {
+    #[display(fmt = "owned by {}, not by the bundler", _0)]
+    ForeignOwner(String),
+    #[display(fmt = "missing {} tag", _0)]
+    MissingTag(&'static str),
+    #[display(fmt = "unsupported format {} {}", format, version)]
+    UnsupportedFormat { format: String, version: String },
+    #[display(fmt = "size {} exceeds the maximum of {} bytes", size, limit)]
+    TooLarge { size: u64, limit: u64 },
+    #[display(
+        fmt = "size {} doesn't fit in the remaining disk quota of {} bytes",
+        size,
+        free
+    )]
+    OverQuota { size: u64, free: u64 },
+}
+
+fn tag_value<'a>(tx: &'a ArweaveTx, name: &str) -> Option<&'a str> {
+    tx.tags
+        .iter()
+        .find(|tag| tag.name == name)
+        .map(|tag| tag.value.as_str())
+}
+
+/// Check the transaction is a bundle posted by the bundler, and not a
+/// transaction that merely mentions the bundler's address
+fn check_bundle_tx(bundler_address: &str, tx: &ArweaveTx) -> Result<(), SkipReason> {
+    if tx.owner.address != bundler_address {
+        return Err(SkipReason::ForeignOwner(tx.owner.address.clone()));
+    }
+    let format =
+        tag_value(tx, BUNDLE_FORMAT_TAG).ok_or(SkipReason::MissingTag(BUNDLE_FORMAT_TAG))?;
+    let version =
+        tag_value(tx, BUNDLE_VERSION_TAG).ok_or(SkipReason::MissingTag(BUNDLE_VERSION_TAG))?;
+    if !SUPPORTED_BUNDLE_FORMATS.contains(&(format, version)) {
+        return Err(SkipReason::UnsupportedFormat {
+            format: format.to_string(),
+            version: version.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Check the bundle data fits the size limits before downloading it. The
+/// size is taken from the GraphQL response, or asked from the gateway
+/// when it wasn't included; bundles of unknown size are let through.
+/// The space the bundle takes in the disk quota is reserved until the
+/// returned reservation is dropped, once the bundle is downloaded.
+async fn check_download_size<Context, HttpClient>(
+    ctx: &Context,
+    arweave: &Arweave,
+    bundle: &ArweaveTx,
+) -> Result<Option<QuotaReservation>, SkipReason>
+where
+    Context: ArweaveContext<HttpClient> + RuntimeConfigAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let (max_bundle_size, disk_quota) = ctx.download_limits();
+    if max_bundle_size.is_none() && disk_quota.is_none() {
+        return Ok(None);
     }
 
-    let current_block = block_ok.unwrap();
-    if current_block.is_none() {
-        return Ok(());
-    } else {
-        let current_block = current_block.unwrap();
-        let _store = store_bundle(ctx, bundle, current_block);
+    let size = match bundle.data_size() {
+        Some(size) => size,
+        None => match arweave.get_tx_data_size(ctx, &bundle.id).await {
+            Ok(Some(size)) => size,
+            Ok(None) => {
+                warn!(bundle = %bundle.id, "Gateway didn't announce the bundle size");
+                return Ok(None);
+            }
+            Err(err) => {
+                warn!(bundle = %bundle.id, "Failed to get bundle size: {:?}", err);
+                return Ok(None);
+            }
+        },
+    };
+
+    if let Some(limit) = max_bundle_size {
+        if size > limit {
+            return Err(SkipReason::TooLarge { size, limit });
+        }
+    }
+    match disk_quota {
+        Some(quota) => ctx
+            .download_quota()
+            .cloned()
+            .unwrap_or_default()
+            .reserve(&bundle.id, size, quota)
+            .map(Some)
+            .map_err(|free| SkipReason::OverQuota { size, free }),
+        None => Ok(None),
     }
+}
 
-    let path = match arweave.get_tx_data(ctx, &bundle.id).await {
-        Ok(path) => path,
+/// Whether an earlier validation run settled the bundle: stored bundles
+/// passed verification, invalid ones failed it, and neither needs to be
+/// checked again
+fn has_verdict<Context>(ctx: &Context, bundle: &ArweaveTx) -> bool
+where
+    Context: BundleQueries,
+{
+    match ctx.get_bundle(&bundle.id) {
+        Ok(_) => return true,
+        Err(diesel::result::Error::NotFound) => (),
         Err(err) => {
-            error!("File path error {:?}", err);
-            return Err(ValidatorCronError::FileError);
+            error!(bundle = %bundle.id, "Failed to look up bundle: {}", err);
+            return false;
+        }
+    }
+    match ctx.is_invalid_bundle(&bundle.id) {
+        Ok(invalid) => invalid,
+        Err(err) => {
+            error!(bundle = %bundle.id, "Failed to look up invalid bundle: {}", err);
+            false
+        }
+    }
+}
+
+/// Download data of a bundle included in a block, returns `None` for
+/// bundles that are not included in any block yet or already validated,
+/// and for transactions that aren't bundles of the bundler
+#[tracing::instrument(skip_all, fields(bundle = %bundle.id))]
+async fn fetch_bundle<'a, Context, HttpClient>(
+    ctx: &Context,
+    arweave: &Arweave,
+    bundle: &'a ArweaveTx,
+) -> Result<Option<DownloadedBundle<'a>>, ValidatorCronError>
+where
+    Context: BundleQueries + ArweaveContext<HttpClient> + BundlerAccess + RuntimeConfigAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    if let Err(reason) = check_bundle_tx(&ctx.bundler().address, bundle) {
+        if let SkipReason::UnsupportedFormat { .. } = reason {
+            // Bundler's own bundles we can't verify, count them so they
+            // don't go unnoticed
+            ctx.metrics()
+                .bundle_validations
+                .with_label_values(&["skipped"])
+                .inc();
+        }
+        info!(bundle = %bundle.id, "Skipped: {}", reason);
+        return Ok(None);
+    }
+
+    if has_verdict(ctx, bundle) {
+        info!(bundle = %bundle.id, "Bundle already verified");
+        return Ok(None);
+    }
+
+    // ArLocal bundles are validated without waiting for a block
+    let unconfirmed_at = ctx
+        .arlocal()
+        .then(|| ctx.get_validator_state().current_block());
+    let block = match check_bundle_block(bundle, unconfirmed_at) {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+
+    let reservation = match check_download_size(ctx, arweave, bundle).await {
+        Ok(reservation) => reservation,
+        Err(reason) => {
+            ctx.metrics()
+                .bundle_validations
+                .with_label_values(&["skipped"])
+                .inc();
+            warn!(bundle = %bundle.id, "Skipped: {}", reason);
+            return Ok(None);
         }
     };
 
-    let bundle_txs = match verify_file_bundle(path.clone()).await {
+    let path = download_bundle(ctx, arweave, bundle)
+        .instrument(info_span!("download_bundle"))
+        .await?;
+    // The downloaded file now counts towards the quota itself
+    drop(reservation);
+
+    Ok(Some(DownloadedBundle {
+        bundle,
+        block,
+        path,
+    }))
+}
+
+/// Download bundle data from the Arweave gateway, falling back to the
+/// alternate sources in runtime config. When none of them serves the data
+/// after the gateway throttled the download, the throttling is returned so
+/// the task pauses as the gateway asked.
+async fn download_bundle<Context, HttpClient>(
+    ctx: &Context,
+    arweave: &Arweave,
+    bundle: &ArweaveTx,
+) -> Result<String, ValidatorCronError>
+where
+    Context: ArweaveContext<HttpClient> + BundlerAccess + RuntimeConfigAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    if let Some(path) = downloaded_file(bundle) {
+        info!(bundle = %bundle.id, "Reusing bundle downloaded earlier");
+        return Ok(path);
+    }
+
+    let mut throttled = None;
+    match arweave.get_tx_data(ctx, &bundle.id).await {
+        Ok(path) => return Ok(path),
+        Err(err @ ArweaveError::Throttled { .. }) => {
+            warn!(bundle = %bundle.id, "Download throttled: {}", err);
+            throttled = Some(err);
+        }
+        Err(err) => error!(bundle = %bundle.id, "Download failed: {}", err),
+    }
+
+    for node in ctx.data_sources().chunk_nodes {
+        match arweave.download_tx_chunks(ctx, &bundle.id, &node).await {
+            Ok(path) => return Ok(path),
+            Err(err) => error!(bundle = %bundle.id, %node, "Chunk download failed: {}", err),
+        }
+    }
+
+    for url in fallback_urls(ctx, bundle) {
+        match arweave
+            .download_tx_data(ctx, &bundle.id, url.as_str(), "tx_data_fallback")
+            .await
+        {
+            Ok(path) => return Ok(path),
+            Err(err) => error!(bundle = %bundle.id, %url, "Fallback download failed: {}", err),
+        }
+    }
+
+    Err(match throttled {
+        Some(source) => ValidatorCronError::BundleDownloadThrottled {
+            bundle_id: bundle.id.clone(),
+            source,
+        },
+        None => ValidatorCronError::BundleNotRetrievable {
+            bundle_id: bundle.id.clone(),
+        },
+    })
+}
+
+/// Path of bundle data left on disk by an earlier run, files with a size
+/// other than the expected one are partial downloads and aren't reused
+fn downloaded_file(bundle: &ArweaveTx) -> Option<String> {
+    let size = bundle.data_size()?;
+    let path = Arweave::tx_data_path(&bundle.id);
+    match fs::metadata(&path) {
+        Ok(metadata) if metadata.is_file() && metadata.len() == size => Some(path),
+        _ => None,
+    }
+}
+
+/// Tag holding the IPFS CID of bundle data
+const IPFS_CID_TAGS: [&str; 2] = ["IPFS-CID", "IPFS-Hash"];
+
+fn fallback_urls<Context>(ctx: &Context, bundle: &ArweaveTx) -> Vec<Url>
+where
+    Context: BundlerAccess + RuntimeConfigAccess,
+{
+    let sources = ctx.data_sources();
+    let mut urls: Vec<Url> = sources
+        .gateways
+        .iter()
+        .filter_map(|gateway| gateway.join(&bundle.id).ok())
+        .collect();
+
+    if sources.bundler {
+        if let Ok(url) = Url::parse(&ctx.bundler().url)
+            .and_then(|bundler| bundler.join(&format!("tx/{}/data", bundle.id)))
+        {
+            urls.push(url);
+        }
+    }
+
+    if let Some(ipfs_gateway) = sources.ipfs_gateway {
+        let cid = bundle
+            .tags
+            .iter()
+            .find(|tag| IPFS_CID_TAGS.contains(&tag.name.as_str()));
+        if let Some(url) =
+            cid.and_then(|tag| ipfs_gateway.join(&format!("ipfs/{}", tag.value)).ok())
+        {
+            urls.push(url);
+        }
+    }
+
+    urls
+}
+
+#[tracing::instrument(skip_all, fields(bundle = %downloaded.bundle.id))]
+async fn validate_bundle<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+    downloaded: &DownloadedBundle<'_>,
+) -> Result<(), ValidatorCronError>
+where
+    Context: BundleQueries
+        + BundlerAccess
+        + BundleStoreAccess
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + ValidatorAddressAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let bundle = downloaded.bundle;
+    // Hashed before the items are verified, so the recorded checksum is
+    // that of the data that was verified and a file changing later, even
+    // while it is verified, is caught by the integrity check
+    let checksum = match file_checksum(Path::new(&downloaded.path)).await {
+        Ok(checksum) => Some(checksum),
+        Err(err) => {
+            warn!(bundle = %bundle.id, "Failed to hash bundle file: {}", err);
+            None
+        }
+    };
+    let items = match verify_bundle_file(&downloaded.path, ctx.max_nesting_depth())
+        .instrument(info_span!("verify_items"))
+        .await
+    {
         Err(r) => {
-            error!("Error verifying bundle {}:", r);
-            Vec::new()
+            error!(bundle = %bundle.id, "Error verifying bundle: {}", r);
+            ctx.metrics()
+                .bundle_validations
+                .with_label_values(&["invalid"])
+                .inc();
+            if let BundleError::InvalidItem { fault, .. } = &r {
+                ctx.metrics()
+                    .invalid_items
+                    .with_label_values(&[fault.as_str()])
+                    .inc();
+            }
+            if r.is_verdict() {
+                let invalid = NewInvalidBundle {
+                    id: bundle.id.clone(),
+                    reason: r.to_string(),
+                };
+                if let Err(err) = ctx.insert_invalid_bundle(&invalid) {
+                    error!(bundle = %bundle.id, "Failed to record invalid bundle: {}", err);
+                }
+            }
+            ctx.event_bus().publish(Event::BundleInvalid {
+                bundle: bundle.id.clone(),
+                reason: r.to_string(),
+            });
+            keep_bundle(
+                ctx,
+                &format!("{}{}", QUARANTINE_PREFIX, bundle.id),
+                &downloaded.path,
+            )
+            .await;
+            return Ok(());
         }
         Ok(v) => v,
     };
 
+    // Receipts are issued for the bundle's own data items only, items of
+    // nested bundles are stored with the data item containing them
+    let (bundle_txs, nested_items): (Vec<BundleItem>, Vec<BundleItem>) =
+        items.into_iter().partition(|item| item.parent.is_none());
+    let item_count = bundle_txs.len();
     info!(
-        "{} transactions found in bundle {}",
-        &bundle_txs.len(),
-        &bundle.id
+        bundle = %bundle.id,
+        nested = nested_items.len(),
+        "{} transactions found in bundle",
+        item_count
     );
+    let mut validated_txs = Vec::new();
     for bundle_tx in bundle_txs {
-        let tx_receipt = verify_bundle_tx(ctx, &bundle_tx, current_block).await;
-        if let Err(err) = tx_receipt {
-            info!("Error found in transaction {} : {}", &bundle_tx.tx_id, err);
-            return Err(ValidatorCronError::TxInvalid);
+        match verify_bundle_tx(ctx, bundle, &bundle_tx, downloaded.block).await {
+            Ok(Some(tx)) => validated_txs.push(tx),
+            Ok(None) => (),
+            Err(err) => {
+                ctx.metrics()
+                    .bundle_validations
+                    .with_label_values(&["invalid"])
+                    .inc();
+                ctx.event_bus().publish(Event::TxInvalid {
+                    bundle: bundle.id.clone(),
+                    tx: bundle_tx.tx_id.clone(),
+                    reason: err.to_string(),
+                });
+                keep_bundle(
+                    ctx,
+                    &format!("{}{}", QUARANTINE_PREFIX, bundle.id),
+                    &downloaded.path,
+                )
+                .await;
+                return Err(err);
+            }
         }
     }
-    info!("All transactions ok in bundle {}", &bundle.id);
+    info!(bundle = %bundle.id, "All transactions ok in bundle");
+    info_span!("write_db").in_scope(|| {
+        store_bundle(
+            ctx,
+            downloaded,
+            item_count,
+            checksum,
+            &validated_txs,
+            &nested_items,
+        )
+    })?;
+    ctx.metrics()
+        .bundle_validations
+        .with_label_values(&["valid"])
+        .inc();
+    ctx.metrics().record_validation(downloaded.block);
+    ctx.event_bus().publish(Event::BundleValidated {
+        bundle: bundle.id.clone(),
+        txs: validated_txs.len(),
+    });
+    keep_bundle(ctx, &bundle.id, &downloaded.path).await;
 
     /*
     match std::fs::remove_file(path.clone()) {
@@ -137,98 +620,160 @@ where
     Ok(())
 }
 
-fn check_bundle_block(bundle: &ArweaveTx) -> Result<Option<u128>, ValidatorCronError> {
-    let current_block = match bundle.block {
-        Some(ref block) => block.height,
-        None => {
-            info!("Bundle {} not included in any block", &bundle.id);
-            return Ok(None);
+/// Copy bundle to the configured bundle store, failing to do so doesn't
+/// affect the validation result
+async fn keep_bundle<Context, HttpClient>(ctx: &Context, key: &str, path: &str)
+where
+    Context: BundleStoreAccess + http::ClientAccess<HttpClient>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    if let Err(err) = ctx.bundle_store().put(ctx, key, Path::new(path)).await {
+        error!(key, "Failed to store bundle: {}", err);
+    }
+}
+
+/// Block the bundle was included in, bundles not included yet are taken as
+/// included at `unconfirmed_at` when set
+fn check_bundle_block(bundle: &ArweaveTx, unconfirmed_at: Option<u128>) -> Option<u128> {
+    let current_block = match (&bundle.block, unconfirmed_at) {
+        (Some(block), _) => block.height,
+        (None, Some(height)) => {
+            info!(bundle = %bundle.id, block = height, "Bundle not included in any block yet, taking current block");
+            return Some(height);
+        }
+        (None, None) => {
+            info!(bundle = %bundle.id, "Bundle not included in any block");
+            return None;
         }
     };
 
-    info!("Bundle {} included in block {}", &bundle.id, current_block);
-    Ok(Some(current_block))
+    info!(bundle = %bundle.id, block = current_block, "Bundle included in block");
+    Some(current_block)
 }
 
 fn store_bundle<Context>(
     ctx: &Context,
-    bundle: &ArweaveTx,
-    current_block: u128,
+    downloaded: &DownloadedBundle<'_>,
+    item_count: usize,
+    checksum: Option<String>,
+    txs: &[NewTransaction],
+    nested: &[BundleItem],
 ) -> Result<(), ValidatorCronError>
 where
-    Context: queries::QueryContext + BundlerAccess,
+    Context: BundleQueries + BundlerAccess,
 {
-    let is_bundle_present = get_bundle(ctx, &bundle.id).is_ok();
-    if !is_bundle_present {
-        return match insert_bundle_in_db(
-            ctx,
-            NewBundle {
-                id: bundle.id.clone(),
-                owner_address: ctx.bundler().address.clone(),
-                block_height: Block(current_block),
-            },
-        ) {
-            Ok(()) => {
-                info!("Bundle {} successfully stored", &bundle.id);
-                Ok(())
-            }
-            Err(err) => {
-                error!("Error when storing bundle {} : {}", &bundle.id, err);
-                Err(ValidatorCronError::BundleNotInsertedInDB)
-            }
-        };
+    let bundle = downloaded.bundle;
+    let size = match fs::metadata(&downloaded.path) {
+        Ok(metadata) => i64::try_from(metadata.len()).ok(),
+        Err(err) => {
+            warn!(bundle = %bundle.id, "Failed to read bundle size: {}", err);
+            None
+        }
+    };
+    let new_bundle = NewBundle {
+        id: bundle.id.clone(),
+        owner_address: ctx.bundler().address.clone(),
+        block_height: Block(downloaded.block),
+        size,
+        item_count: i32::try_from(item_count).ok(),
+        block_hash: bundle.block.as_ref().map(|block| block.id.clone()),
+        checksum,
+    };
+    let nested: Vec<NestedItem> = nested
+        .iter()
+        .filter_map(|item| {
+            item.parent.as_ref().map(|parent| NestedItem {
+                tx_id: item.tx_id.clone(),
+                bundle_id: bundle.id.clone(),
+                parent_id: parent.clone(),
+            })
+        })
+        .collect();
+    match ctx.insert_bundle_with_items(&new_bundle, txs, &nested) {
+        Ok(()) => {
+            info!(
+                bundle = %bundle.id,
+                txs = txs.len(),
+                nested = nested.len(),
+                "Bundle successfully stored"
+            );
+            Ok(())
+        }
+        Err(source) => Err(ValidatorCronError::BundleNotInsertedInDB {
+            bundle_id: bundle.id.clone(),
+            source,
+        }),
     }
-
-    Ok(())
 }
 
-async fn verify_bundle_tx<Context, KeyManager>(
+#[tracing::instrument(skip_all, fields(tx = %bundle_tx.tx_id))]
+/// Check receipt of a bundled transaction, returns the transaction to store
+/// if its receipt is valid
+async fn verify_bundle_tx<Context, HttpClient, KeyManager>(
     ctx: &Context,
-    bundle_tx: &Item,
-    current_block: Option<u128>,
-) -> Result<(), ValidatorCronError>
+    bundle: &ArweaveTx,
+    bundle_tx: &BundleItem,
+    current_block: u128,
+) -> Result<Option<NewTransaction>, ValidatorCronError>
 where
-    Context: queries::QueryContext + KeyManagerAccess<KeyManager>,
+    Context: BundleQueries
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + ValidatorAddressAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
     KeyManager: key_manager::KeyManager,
 {
-    let tx = get_tx(ctx, &bundle_tx.tx_id).await;
-    let mut tx_receipt: Option<TxReceipt> = None;
-    if tx.is_ok() {
-        let tx = tx.unwrap();
-        tx_receipt = Some(TxReceipt {
-            block: tx.block_promised.into(),
-            tx_id: tx.id,
-            signature: match std::str::from_utf8(&tx.signature.to_vec()) {
-                Ok(v) => v.to_string(),
-                Err(e) => panic!("Invalid UTF-8 seq: {}", e),
-            },
-        });
-    } else {
-        let peer_tx = tx_exists_on_peers(&bundle_tx.tx_id).await;
-        if peer_tx.is_ok() {
-            tx_receipt = Some(peer_tx.unwrap());
+    let tx = ctx.get_cached_tx(&bundle_tx.tx_id);
+    // Receipt signed by the bundler and the key it is signed with
+    let mut tx_receipt: Option<(RawTxReceipt, String)> = None;
+    if let Ok(tx) = tx {
+        if let Some(first_bundle) = tx.bundle_id.as_ref().filter(|id| **id != bundle.id) {
+            record_duplicate(ctx, &tx.id, first_bundle, &bundle.id);
+            return Ok(None);
         }
+        // The stored signature is this validator's own, the receipt is the
+        // payload the bundler or a peer signed
+        for stored in ctx.get_raw_receipts(&tx.id)? {
+            let raw = match RawTxReceipt::from_stored(&stored) {
+                Ok(raw) => raw,
+                Err(err) => {
+                    warn!(tx = %tx.id, source = %stored.source, "Invalid stored receipt: {}", err);
+                    continue;
+                }
+            };
+            if let Some(key) = verify_and_record_receipt(ctx, ctx.get_key_manager(), &raw) {
+                tx_receipt = Some((raw, key));
+                break;
+            }
+        }
+    } else if let Ok((raw, key)) = tx_exists_on_peers(ctx, &bundle_tx.tx_id).await {
+        if let Err(err) = ctx.insert_raw_receipts(&[raw.to_new_raw_receipt(SOURCE_PEER)]) {
+            warn!(tx = %raw.receipt.tx_id, "Failed to store raw receipt: {}", err);
+        }
+        tx_receipt = Some((raw, key));
     }
 
     match tx_receipt {
-        Some(receipt) => {
-            let tx_is_ok = verify_tx_receipt(ctx.get_key_manager(), &receipt).unwrap();
-            // FIXME: don't use unwrap
-            if tx_is_ok && receipt.block <= current_block.unwrap() {
-                if let Err(_err) = insert_tx_in_db(
-                    ctx,
-                    &NewTransaction {
-                        id: receipt.tx_id,
-                        epoch: Epoch(0),
-                        block_promised: receipt.block.into(),
-                        block_actual: current_block.map(Block),
-                        signature: receipt.signature.as_bytes().to_vec(),
-                        validated: true,
-                        bundle_id: Some(bundle_tx.tx_id.clone()),
-                    },
-                ) {
-                    // FIXME: missing error handling
-                }
+        Some((raw, bundler_key)) => {
+            // Shared whether it was learned from the bundler or a peer, so
+            // peers the bundler didn't reach learn it too
+            ctx.receipt_queue().push(raw.clone());
+            let receipt = raw.receipt;
+            if receipt.block <= current_block {
+                return Ok(Some(NewTransaction {
+                    id: receipt.tx_id,
+                    epoch: Epoch(ctx.get_validator_state().current_epoch()),
+                    block_promised: receipt.block.into(),
+                    block_actual: Some(Block(current_block)),
+                    signature: BASE64URL_NOPAD.encode(&receipt.signature).into_bytes(),
+                    validated: true,
+                    bundle_id: Some(bundle.id.clone()),
+                    bundler_key: Some(bundler_key),
+                }));
             } else {
                 // TODO: vote slash
             }
@@ -238,63 +783,118 @@ where
         }
     }
 
-    Ok(())
+    Ok(None)
 }
 
-async fn tx_exists_on_peers(tx_id: &str) -> Result<TxReceipt, ValidatorCronError> {
-    let client = Client::default();
-    let validator_peers = Vec::<Validator>::new();
-    for peer in validator_peers {
-        let response = client
-            .get(format!("{}/tx/{}", peer.url, tx_id))
-            .send()
-            .await;
-
-        if let Err(r) = response {
-            error!("Error occurred while getting tx from peer - {}", r);
-            continue;
-        }
-
-        let mut response = response.unwrap();
-
-        if response.status().is_success() {
-            return Ok(response.json().await.unwrap());
-        }
+/// Record a data item stored with `first_bundle` that is also in `bundle`,
+/// the item stays attributed to the bundle it was first seen in. Including
+/// an item twice is an equivocation of the bundler.
+fn record_duplicate<Context>(ctx: &Context, tx_id: &str, first_bundle: &str, bundle: &str)
+where
+    Context: BundleQueries + EventBusAccess,
+{
+    warn!(
+        tx = tx_id,
+        bundle = first_bundle,
+        duplicate_bundle = bundle,
+        "Data item found in more than one bundle"
+    );
+    let item = DuplicateItem {
+        tx_id: tx_id.to_string(),
+        bundle_id: first_bundle.to_string(),
+        duplicate_bundle_id: bundle.to_string(),
+    };
+    match ctx.insert_duplicate_item(&item) {
+        Ok(true) => ctx.event_bus().publish(Event::DuplicateItem {
+            tx: item.tx_id,
+            bundle: item.bundle_id,
+            duplicate_bundle: item.duplicate_bundle_id,
+        }),
+        Ok(false) => (),
+        Err(err) => error!(tx = tx_id, "Failed to record duplicate data item: {}", err),
     }
-
-    Err(ValidatorCronError::TxNotFound)
+    record_equivocation(ctx, tx_id, EquivocationKind::Bundles, first_bundle, bundle);
 }
 
-fn verify_tx_receipt<KeyManager>(
-    key_manager: &KeyManager,
-    tx_receipt: &TxReceipt,
-) -> std::io::Result<bool>
+/// Look up receipt of `tx_id` on peers, trying better scored peers first.
+/// Receipts not signed by the bundler are skipped, the one found is
+/// returned with its payload as received and the bundler key it is signed
+/// with.
+async fn tx_exists_on_peers<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+    tx_id: &str,
+) -> Result<(RawTxReceipt, String), ValidatorCronError>
 where
+    Context: BundleQueries
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + RuntimeConfigAccess
+        + ValidatorAddressAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
     KeyManager: key_manager::KeyManager,
 {
-    pub const BUNDLR_AS_BUFFER: &[u8] = "Bundlr".as_bytes();
-
-    let block = tx_receipt.block.to_string().as_bytes().to_vec();
-
-    let tx_id = tx_receipt.tx_id.as_bytes().to_vec();
+    for peer in peer_urls(ctx).await {
+        let req = match peer.join(&format!("tx/{}/receipt", tx_id)) {
+            Ok(url) => reqwest::Request::new(reqwest::Method::GET, url),
+            Err(err) => {
+                error!(peer = %peer, tx = tx_id, "Invalid peer tx url - {}", err);
+                continue;
+            }
+        };
 
-    let message = deep_hash_sync(DeepHashChunk::Chunks(vec![
-        DeepHashChunk::Chunk(BUNDLR_AS_BUFFER.into()),
-        DeepHashChunk::Chunk(ONE_AS_BUFFER.into()),
-        DeepHashChunk::Chunk(tx_id.into()),
-        DeepHashChunk::Chunk(block.into()),
-    ]))
-    .unwrap();
+        let response = match ctx.get_http_client().execute(req).await {
+            Ok(response) => response,
+            Err(err) => {
+                error!(peer = %peer, tx = tx_id, "Error occurred while getting tx from peer - {:?}", err);
+                ctx.peer_scores().record(&peer, PeerOutcome::Unreachable);
+                continue;
+            }
+        };
 
-    let sig = BASE64URL_NOPAD
-        .decode(tx_receipt.signature.as_bytes())
-        .unwrap();
+        if response.status().is_success() {
+            // Not scored, the peer may run a version serving another format
+            let raw: RawTxReceipt = match response.json().await {
+                Ok(raw) => raw,
+                Err(err) => {
+                    warn!(peer = %peer, tx = tx_id, "Unreadable tx receipt from peer - {}", err);
+                    continue;
+                }
+            };
+            let receipt = &raw.receipt;
+            if receipt.tx_id != tx_id {
+                error!(peer = %peer, tx = tx_id, "Peer returned receipt of another tx");
+                ctx.peer_scores().record(&peer, PeerOutcome::InvalidReceipt);
+                continue;
+            }
+            let key = match verify_and_record_receipt(ctx, ctx.get_key_manager(), &raw) {
+                Some(key) => key,
+                None => {
+                    error!(peer = %peer, tx = tx_id, "Tx receipt from peer has invalid signature");
+                    ctx.peer_scores().record(&peer, PeerOutcome::BadSignature);
+                    continue;
+                }
+            };
+            ctx.peer_scores().record(&peer, PeerOutcome::Success);
+            return Ok((raw, key));
+        } else if response.status().is_server_error() {
+            ctx.peer_scores().record(&peer, PeerOutcome::Unreachable);
+        }
+    }
 
-    Ok(key_manager.verify_bundler_signature(&message, &sig))
+    Err(ValidatorCronError::TxNotFound {
+        tx_id: tx_id.to_string(),
+    })
 }
 
-pub async fn validate_transactions(bundler: &Bundler) -> Result<(), ValidatorCronError> {
-    let res = get_transactions(bundler, Some(100), None).await;
+pub async fn validate_transactions<HttpClient>(
+    client: &HttpClient,
+    bundler: &Bundler,
+) -> Result<(), ValidatorCronError>
+where
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let res = get_transactions(client, bundler, Some(100), None).await;
     let txs = match res {
         Ok(r) => r.0,
         Err(_) => Vec::new(),
@@ -314,21 +914,555 @@ pub async fn validate_transactions(bundler: &Bundler) -> Result<(), ValidatorCro
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+
+    use chrono::{Duration, Utc};
+
+    use crate::config::{RuntimeConfig, RuntimeConfigAccess};
     use crate::utils::get_file_as_byte_vector;
     use crate::{
-        context::test_utils::test_context_with_http_client, http::reqwest::mock::MockHttpClient,
+        context::test_utils::test_context_with_http_client,
+        http::reqwest::mock::MockHttpClient,
         key_manager::test_utils::test_keys,
+        peers::PeerScoresAccess,
+        receipt::test_utils::{raw_receipt, signed_receipt, signed_sign_request},
     };
     use http::Method;
     use reqwest::{Request, Response};
+    use url::Url;
 
-    use super::validate_bundler;
+    use super::{
+        check_bundle_block, check_bundle_tx, download_bundle, fallback_urls, fetch_bundle,
+        record_duplicate, store_bundle, tx_exists_on_peers, validate_bundler, verify_bundle_tx,
+        DownloadedBundle, SkipReason,
+    };
+    use crate::bundle::BundleItem;
+    use crate::config::DataSources;
+    use crate::context::{ArweaveAccess, BundlerAccess, ValidatorAddressAccess};
+    use crate::cron::arweave::{
+        Arweave, BlockInfo, Owner, Tag, Transaction as ArweaveTx, TransactionData,
+    };
+    use crate::cron::CronJobError;
+    use crate::database::memory::MemoryContext;
+    use crate::database::models::{
+        Block, Epoch, NestedItem, NewBundle, NewInvalidBundle, NewTransaction,
+    };
+    use crate::database::queries::{
+        get_bundles_between, get_validated_txs_between, insert_bundle_with_txs, BundleQueries,
+    };
+    use crate::database::test_utils::unique_id;
+    use crate::events::{Event, EventBusAccess};
+    use crate::http::ClientAccess;
+    use crate::key_manager::{KeyManager, KeyManagerAccess};
+    use crate::receipt::{ReceiptQueueAccess, SOURCE_BUNDLER};
+
+    fn bundle_tags() -> Vec<Tag> {
+        vec![
+            Tag {
+                name: "Bundle-Format".to_string(),
+                value: "binary".to_string(),
+            },
+            Tag {
+                name: "Bundle-Version".to_string(),
+                value: "2.0.0".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn only_bundles_owned_by_bundler_are_fetched() {
+        let bundle = ArweaveTx {
+            id: "bundle_id".to_string(),
+            owner: Owner {
+                address: "bundler".to_string(),
+            },
+            tags: bundle_tags(),
+            ..ArweaveTx::default()
+        };
+        assert_eq!(check_bundle_tx("bundler", &bundle), Ok(()));
+        assert_eq!(
+            check_bundle_tx("other", &bundle),
+            Err(SkipReason::ForeignOwner("bundler".to_string()))
+        );
+
+        let untagged = ArweaveTx {
+            tags: bundle_tags().into_iter().take(1).collect(),
+            ..bundle
+        };
+        assert_eq!(
+            check_bundle_tx("bundler", &untagged),
+            Err(SkipReason::MissingTag("Bundle-Version"))
+        );
+
+        let mut unsupported = bundle_tags();
+        unsupported[1].value = "3.0.0".to_string();
+        let unsupported = ArweaveTx {
+            tags: unsupported,
+            ..untagged
+        };
+        let reason = check_bundle_tx("bundler", &unsupported).unwrap_err();
+        assert_eq!(reason.to_string(), "unsupported format binary 3.0.0");
+    }
+
+    #[actix_rt::test]
+    async fn validated_bundle_is_not_downloaded_again() {
+        let ctx = MemoryContext::new("bundler");
+        let bundle = ArweaveTx {
+            id: "bundle_id".to_string(),
+            owner: Owner {
+                address: ctx.bundler().address.clone(),
+            },
+            tags: bundle_tags(),
+            block: Some(BlockInfo {
+                height: 10,
+                ..BlockInfo::default()
+            }),
+            ..ArweaveTx::default()
+        };
+        ctx.db.insert_bundle_with_items(
+            &NewBundle {
+                id: bundle.id.clone(),
+                owner_address: ctx.bundler().address.clone(),
+                block_height: Block(10),
+                size: None,
+                item_count: None,
+                block_hash: None,
+                checksum: None,
+            },
+            &[],
+            &[],
+        );
+
+        let res = fetch_bundle(&ctx, ctx.arweave(), &bundle).await;
+
+        assert!(matches!(res, Ok(None)));
+        assert!(ctx.get_http_client().take_unmatched().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn invalid_bundle_is_not_downloaded_again() {
+        let ctx = MemoryContext::new("bundler");
+        let bundle = ArweaveTx {
+            id: "bundle_id".to_string(),
+            owner: Owner {
+                address: ctx.bundler().address.clone(),
+            },
+            tags: bundle_tags(),
+            block: Some(BlockInfo {
+                height: 10,
+                ..BlockInfo::default()
+            }),
+            ..ArweaveTx::default()
+        };
+        ctx.insert_invalid_bundle(&NewInvalidBundle {
+            id: bundle.id.clone(),
+            reason: "invalid bundle: truncated header".to_string(),
+        })
+        .unwrap();
+
+        let res = fetch_bundle(&ctx, ctx.arweave(), &bundle).await;
+
+        assert!(matches!(res, Ok(None)));
+        assert!(ctx.get_http_client().take_unmatched().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn bundle_over_size_limit_is_not_downloaded() {
+        let (key_manager, _bundle_pvk) = test_keys();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::HEAD && req.url().as_str() == "http://example.com/bundle_id"
+            })
+            .then(|_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .header("Content-Length", "1000")
+                    .body("")
+                    .unwrap();
+                Response::from(response)
+            });
+        let ctx = MemoryContext::with_http_client(key_manager, client);
+        ctx.replace_runtime_config(RuntimeConfig {
+            max_bundle_size: Some(500),
+            ..RuntimeConfig::default()
+        });
+        let bundle = ArweaveTx {
+            id: "bundle_id".to_string(),
+            owner: Owner {
+                address: ctx.bundler().address.clone(),
+            },
+            tags: bundle_tags(),
+            block: Some(BlockInfo {
+                height: 10,
+                ..BlockInfo::default()
+            }),
+            ..ArweaveTx::default()
+        };
+
+        let res = fetch_bundle(&ctx, ctx.arweave(), &bundle).await;
+
+        assert!(matches!(res, Ok(None)));
+        assert!(ctx.get_http_client().take_unmatched().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn complete_download_is_reused() {
+        let ctx = MemoryContext::new("bundler");
+        let bundle = ArweaveTx {
+            id: ctx.get_validator_address().to_string(),
+            data: Some(TransactionData {
+                size: "6".to_string(),
+                r#type: None,
+            }),
+            ..ArweaveTx::default()
+        };
+        let path = Arweave::tx_data_path(&bundle.id);
+        std::fs::create_dir_all(crate::cron::arweave::DOWNLOADS_DIR).unwrap();
+        std::fs::write(&path, "bundle").unwrap();
+
+        let reused = download_bundle(&ctx, ctx.arweave(), &bundle).await;
+        let unmatched = ctx.get_http_client().take_unmatched();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(reused.unwrap(), path);
+        assert!(unmatched.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn item_in_second_bundle_is_recorded_as_duplicate() {
+        let (tx_id, first_bundle) = ("tx_id", "first_bundle");
+        let ctx = MemoryContext::new("bundler");
+        ctx.db.insert_bundle_with_items(
+            &NewBundle {
+                id: first_bundle.to_string(),
+                owner_address: ctx.bundler().address.clone(),
+                block_height: Block(10),
+                size: None,
+                item_count: None,
+                block_hash: None,
+                checksum: None,
+            },
+            &[NewTransaction {
+                id: tx_id.to_string(),
+                epoch: Epoch(1),
+                block_promised: Block(10),
+                block_actual: Some(Block(10)),
+                signature: "c2lnbmF0dXJl".as_bytes().to_vec(),
+                validated: true,
+                bundle_id: Some(first_bundle.to_string()),
+                bundler_key: None,
+            }],
+            &[],
+        );
+        let mut events = ctx.event_bus().subscribe();
+        let second_bundle = ArweaveTx {
+            id: "second_bundle".to_string(),
+            ..ArweaveTx::default()
+        };
+        let item = BundleItem {
+            tx_id: tx_id.to_string(),
+            parent: None,
+        };
+
+        let res = verify_bundle_tx(&ctx, &second_bundle, &item, 20).await;
+
+        assert!(matches!(res, Ok(None)));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            Event::DuplicateItem {
+                tx: tx_id.to_string(),
+                bundle: first_bundle.to_string(),
+                duplicate_bundle: second_bundle.id.clone(),
+            }
+        );
+    }
+
+    #[actix_rt::test]
+    async fn sign_request_of_bundler_is_queued_for_gossip() {
+        let (tx_id, bundle_id) = ("tx_id".to_string(), "bundle_id".to_string());
+        let (key_manager, bundle_pvk) = test_keys();
+        let ctx = MemoryContext::with_http_client(key_manager, MockHttpClient::new(|_, _| false));
+        // Stored as by the sign route: this validator's signature with the
+        // bundler's sign request as received
+        let validator = ctx.get_key_manager().validator_address().to_string();
+        let request = signed_sign_request(&bundle_pvk, &tx_id, 10, &validator);
+        let raw = raw_receipt(&request);
+        ctx.db.insert_tx(&NewTransaction {
+            id: tx_id.clone(),
+            epoch: Epoch(1),
+            block_promised: Block(10),
+            block_actual: None,
+            signature: b"validator-signature".to_vec(),
+            validated: false,
+            bundle_id: None,
+            bundler_key: None,
+        });
+        ctx.insert_raw_receipts(&[raw.to_new_raw_receipt(SOURCE_BUNDLER)])
+            .unwrap();
+        let bundle = ArweaveTx {
+            id: bundle_id,
+            ..ArweaveTx::default()
+        };
+        let item = BundleItem {
+            tx_id: tx_id.clone(),
+            parent: None,
+        };
+
+        let res = verify_bundle_tx(&ctx, &bundle, &item, 20).await.unwrap();
+
+        assert_eq!(
+            res.unwrap().bundler_key.as_deref(),
+            Some(ctx.get_key_manager().bundler_address())
+        );
+        assert_eq!(ctx.receipt_queue().take(), vec![raw]);
+    }
+
+    #[actix_rt::test]
+    async fn stored_bundle_is_found_by_time_range() {
+        let (tx_id, bundle_id) = (unique_id(), unique_id());
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = test_context_with_http_client(key_manager, MockHttpClient::new(|_, _| false));
+        let before = Utc::now() - Duration::minutes(1);
+        insert_bundle_with_txs(
+            &ctx,
+            &NewBundle {
+                id: bundle_id.to_string(),
+                owner_address: ctx.bundler().address.clone(),
+                block_height: Block(10),
+                size: None,
+                item_count: None,
+                block_hash: None,
+                checksum: None,
+            },
+            &[NewTransaction {
+                id: tx_id.to_string(),
+                epoch: Epoch(1),
+                block_promised: Block(10),
+                block_actual: Some(Block(10)),
+                signature: "c2lnbmF0dXJl".as_bytes().to_vec(),
+                validated: true,
+                bundle_id: Some(bundle_id.to_string()),
+                bundler_key: None,
+            }],
+        )
+        .unwrap();
+        let after = Utc::now() + Duration::minutes(1);
+
+        let bundles = get_bundles_between(&ctx, before, after, 1000).unwrap();
+        let txs = get_validated_txs_between(&ctx, before, after, 1000).unwrap();
+        let earlier = get_validated_txs_between(&ctx, before - Duration::hours(1), before, 100);
+
+        assert!(bundles.iter().any(|bundle| bundle.id == bundle_id));
+        assert!(txs.iter().any(|tx| tx.id == tx_id));
+        assert!(!earlier.unwrap().iter().any(|tx| tx.id == tx_id));
+    }
+
+    #[test]
+    fn nested_items_are_stored_with_outer_bundle() {
+        let (parent_id, bundle_id, tx_id) = ("parent_id", "bundle_id", "tx_id".to_string());
+        let ctx = MemoryContext::new("bundler");
+        let bundle = ArweaveTx {
+            id: bundle_id.to_string(),
+            ..ArweaveTx::default()
+        };
+        let downloaded = DownloadedBundle {
+            bundle: &bundle,
+            block: 10,
+            path: "./bundles/test_bundle".to_string(),
+        };
+        let nested = BundleItem {
+            tx_id: tx_id.clone(),
+            parent: Some(parent_id.to_string()),
+        };
+
+        store_bundle(&ctx, &downloaded, 1, None, &[], &[nested]).unwrap();
+
+        assert_eq!(
+            ctx.db.get_nested_items(bundle_id),
+            vec![NestedItem {
+                tx_id,
+                bundle_id: bundle_id.to_string(),
+                parent_id: parent_id.to_string(),
+            }]
+        );
+        assert_eq!(ctx.db.get_bundle(bundle_id).unwrap().item_count, Some(1));
+    }
+
+    #[test]
+    fn duplicate_item_is_published_once_as_equivocation() {
+        let ctx = MemoryContext::new("bundler");
+        let mut events = ctx.event_bus().subscribe();
+
+        record_duplicate(&ctx, "tx_id", "first_bundle", "second_bundle");
+        record_duplicate(&ctx, "tx_id", "first_bundle", "second_bundle");
+
+        assert_eq!(ctx.db.get_duplicate_items("tx_id").len(), 1);
+        assert_eq!(ctx.db.get_equivocations("tx_id").len(), 1);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            Event::DuplicateItem {
+                tx: "tx_id".to_string(),
+                bundle: "first_bundle".to_string(),
+                duplicate_bundle: "second_bundle".to_string(),
+            }
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            Event::Equivocation {
+                tx: "tx_id".to_string(),
+                kind: "bundles".to_string(),
+                first_evidence: "first_bundle".to_string(),
+                second_evidence: "second_bundle".to_string(),
+            }
+        );
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn fallback_urls_follow_configured_sources() {
+        let ctx = MemoryContext::new("bundler");
+        ctx.replace_runtime_config(RuntimeConfig {
+            data_sources: DataSources {
+                gateways: vec![Url::from_str("https://gateway.example.com/").unwrap()],
+                chunk_nodes: Vec::new(),
+                bundler: false,
+                ipfs_gateway: Some(Url::from_str("https://ipfs.example.com/").unwrap()),
+            },
+            ..RuntimeConfig::default()
+        });
+        let bundle = ArweaveTx {
+            id: "bundle_id".to_string(),
+            tags: vec![Tag {
+                name: "IPFS-CID".to_string(),
+                value: "cid".to_string(),
+            }],
+            ..ArweaveTx::default()
+        };
+
+        assert_eq!(
+            fallback_urls(&ctx, &bundle),
+            vec![
+                Url::from_str("https://gateway.example.com/bundle_id").unwrap(),
+                Url::from_str("https://ipfs.example.com/ipfs/cid").unwrap(),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn tx_exists_on_peers_uses_context_client() {
+        let (key_manager, bundle_pvk) = test_keys();
+        let receipt = signed_receipt(&bundle_pvk, "tx_id", 10);
+        let data = serde_json::to_string(&receipt).unwrap();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && &req.url().to_string() == "http://peer.example.com/tx/tx_id/receipt"
+            })
+            .then(move |_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data.clone())
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let ctx = MemoryContext::with_http_client(key_manager, client);
+        ctx.replace_runtime_config(RuntimeConfig {
+            peers: vec![Url::from_str("http://peer.example.com/").unwrap()],
+            ..RuntimeConfig::default()
+        });
+
+        let (raw, key) = tx_exists_on_peers(&ctx, "tx_id").await.unwrap();
+        assert_eq!(key, ctx.get_key_manager().bundler_address());
+        assert_eq!(raw.receipt, receipt);
+        assert_eq!(raw.payload, serde_json::to_string(&receipt).unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn receipt_with_bad_signature_penalizes_peer() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && &req.url().to_string() == "http://peer.example.com/tx/tx_id/receipt"
+            })
+            .then(|_: &Request| {
+                let data = "{\"block\": 10, \"tx_id\": \"tx_id\", \"signature\": \"c2lnbmF0dXJl\"}";
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data)
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = MemoryContext::with_http_client(key_manager, client);
+        let peer = Url::from_str("http://peer.example.com/").unwrap();
+        ctx.replace_runtime_config(RuntimeConfig {
+            peers: vec![peer.clone()],
+            ..RuntimeConfig::default()
+        });
+
+        assert!(tx_exists_on_peers(&ctx, "tx_id").await.is_err());
+        let scores = ctx.peer_scores().snapshot();
+        let (_, score, _) = scores.iter().find(|(url, _, _)| *url == peer).unwrap();
+        assert_eq!(score.bad_signatures, 1);
+        assert!(score.score < 0);
+    }
+
+    #[actix_rt::test]
+    async fn unreadable_receipt_does_not_penalize_peer() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && &req.url().to_string() == "http://peer.example.com/tx/tx_id/receipt"
+            })
+            .then(|_: &Request| {
+                let data = "{\"id\": \"tx_id\", \"block_promised\": 10}";
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data)
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = MemoryContext::with_http_client(key_manager, client);
+        let peer = Url::from_str("http://peer.example.com/").unwrap();
+        ctx.replace_runtime_config(RuntimeConfig {
+            peers: vec![peer.clone()],
+            ..RuntimeConfig::default()
+        });
+
+        assert!(tx_exists_on_peers(&ctx, "tx_id").await.is_err());
+        let scores = ctx.peer_scores().snapshot();
+        assert!(scores
+            .iter()
+            .filter(|(url, _, _)| *url == peer)
+            .all(|(_, score, _)| score.score >= 0));
+    }
+
+    #[test]
+    fn unconfirmed_bundle_takes_current_block_on_arlocal() {
+        let mut bundle = ArweaveTx {
+            id: "bundle_id".to_string(),
+            ..ArweaveTx::default()
+        };
+        assert_eq!(check_bundle_block(&bundle, None), None);
+        assert_eq!(check_bundle_block(&bundle, Some(7)), Some(7));
+
+        bundle.block = Some(BlockInfo {
+            id: "block_id".to_string(),
+            timestamp: 10,
+            height: 5,
+        });
+        assert_eq!(check_bundle_block(&bundle, Some(7)), Some(5));
+    }
 
     #[actix_rt::test]
     async fn validate_bundler_should_abort_due_no_block() {
         let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
             .when(|req: &Request| {
-                let url = "http://example.com/graphql?query=query($owners:%20[String!],%20$first:%20Int)%20{%20transactions(owners:%20$owners,%20first:%20$first)%20{%20pageInfo%20{%20hasNextPage%20}%20edges%20{%20cursor%20node%20{%20id%20owner%20{%20address%20}%20signature%20recipient%20tags%20{%20name%20value%20}%20block%20{%20height%20id%20timestamp%20}%20}%20}%20}%20}";
+                let url = "http://example.com/graphql?query=query($owners:%20[String!],%20$first:%20Int,%20$after:%20String)%20{%20transactions(owners:%20$owners,%20first:%20$first,%20after:%20$after)%20{%20pageInfo%20{%20hasNextPage%20}%20edges%20{%20cursor%20node%20{%20id%20owner%20{%20address%20}%20signature%20recipient%20tags%20{%20name%20value%20}%20data%20{%20size%20}%20block%20{%20height%20id%20timestamp%20}%20}%20}%20}%20}";
                 req.method() == Method::POST && &req.url().to_string() == url
             })
             .then(|_: &Request| {
@@ -353,20 +1487,23 @@ mod tests {
             });
 
         let (key_manager, _bundle_pvk) = test_keys();
-        let ctx = test_context_with_http_client(key_manager, client);
+        let ctx = MemoryContext::with_http_client(key_manager, client);
         let res = validate_bundler(&ctx).await;
         assert!(res.is_ok())
     }
 
     #[actix_rt::test]
     async fn validate_bundler_should_return_ok() {
+        let (key_manager, _bundle_pvk) = test_keys();
+        let bundler_address = key_manager.bundler_address().to_string();
+        let tags = serde_json::to_string(&bundle_tags()).unwrap();
         let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
             .when(|req: &Request| {
-                let url = "http://example.com/graphql?query=query($owners:%20[String!],%20$first:%20Int)%20{%20transactions(owners:%20$owners,%20first:%20$first)%20{%20pageInfo%20{%20hasNextPage%20}%20edges%20{%20cursor%20node%20{%20id%20owner%20{%20address%20}%20signature%20recipient%20tags%20{%20name%20value%20}%20block%20{%20height%20id%20timestamp%20}%20}%20}%20}%20}";
+                let url = "http://example.com/graphql?query=query($owners:%20[String!],%20$first:%20Int,%20$after:%20String)%20{%20transactions(owners:%20$owners,%20first:%20$first,%20after:%20$after)%20{%20pageInfo%20{%20hasNextPage%20}%20edges%20{%20cursor%20node%20{%20id%20owner%20{%20address%20}%20signature%20recipient%20tags%20{%20name%20value%20}%20data%20{%20size%20}%20block%20{%20height%20id%20timestamp%20}%20}%20}%20}%20}";
                 req.method() == Method::POST && &req.url().to_string() == url
             })
-            .then(|_: &Request| {
-                let data = "{\"data\": {\"transactions\": {\"pageInfo\": {\"hasNextPage\": true },\"edges\": [{\"cursor\": \"cursor\", \"node\": { \"id\": \"tx_id\",\"owner\": {\"address\": \"address\"}, \"signature\": \"signature\", \"recipient\": \"\", \"tags\": [], \"block\": { \"id\": \"id\", \"timestamp\": 10, \"height\": 10 } } } ] } } }";
+            .then(move |_: &Request| {
+                let data = format!("{{\"data\": {{\"transactions\": {{\"pageInfo\": {{\"hasNextPage\": true }},\"edges\": [{{\"cursor\": \"cursor\", \"node\": {{ \"id\": \"tx_id\",\"owner\": {{\"address\": \"{}\"}}, \"signature\": \"signature\", \"recipient\": \"\", \"tags\": {}, \"block\": {{ \"id\": \"id\", \"timestamp\": 10, \"height\": 10 }} }} }} ] }} }} }}", bundler_address, tags);
                 let response = http::response::Builder::new()
                     .status(200)
                     .body(data)
@@ -386,9 +1523,82 @@ mod tests {
                 Response::from(response)
             });
 
-        let (key_manager, _bundle_pvk) = test_keys();
-        let ctx = test_context_with_http_client(key_manager, client);
+        let ctx = MemoryContext::with_http_client(key_manager, client);
         let res = validate_bundler(&ctx).await;
         assert!(res.is_ok())
     }
+
+    #[actix_rt::test]
+    async fn throttled_download_is_returned() {
+        let (key_manager, _bundle_pvk) = test_keys();
+        let bundler_address = key_manager.bundler_address().to_string();
+        let tags = serde_json::to_string(&bundle_tags()).unwrap();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.method() == Method::POST && req.url().path() == "/graphql")
+            .then(move |_: &Request| {
+                let data = format!("{{\"data\": {{\"transactions\": {{\"pageInfo\": {{\"hasNextPage\": false }},\"edges\": [{{\"cursor\": \"cursor\", \"node\": {{ \"id\": \"tx_id\",\"owner\": {{\"address\": \"{}\"}}, \"signature\": \"signature\", \"recipient\": \"\", \"tags\": {}, \"block\": {{ \"id\": \"id\", \"timestamp\": 10, \"height\": 10 }} }} }} ] }} }} }}", bundler_address, tags);
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data)
+                    .unwrap();
+                Response::from(response)
+            })
+            .when(|req: &Request| {
+                req.method() == Method::GET && req.url().as_str() == "http://example.com/tx_id"
+            })
+            .then(|_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(429)
+                    .header("Retry-After", "120")
+                    .body("")
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let ctx = MemoryContext::with_http_client(key_manager, client);
+        let err = validate_bundler(&ctx).await.unwrap_err();
+
+        assert!(matches!(
+            CronJobError::ValidatorError(err).retry_after(),
+            Some(delay) if delay == std::time::Duration::from_secs(120)
+        ));
+    }
+
+    fn graphql_after(req: &Request) -> Option<String> {
+        let body: serde_json::Value = serde_json::from_slice(req.body()?.as_bytes()?).ok()?;
+        body["variables"]["after"].as_str().map(ToString::to_string)
+    }
+
+    #[actix_rt::test]
+    async fn next_page_is_fetched_while_backlog_remains() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.url().path() == "/graphql" && graphql_after(req).is_none())
+            .times(1)
+            .then(|_: &Request| {
+                let data = "{\"data\": {\"transactions\": {\"pageInfo\": {\"hasNextPage\": true },\"edges\": [{\"cursor\": \"cursor_1\", \"node\": { \"id\": \"unvalidated_tx\",\"owner\": {\"address\": \"address\"}, \"signature\": \"signature\",\"recipient\": \"\", \"tags\": [], \"block\": { \"id\": \"id\", \"timestamp\": 10, \"height\": 10 } } } ] } } }";
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data)
+                    .unwrap();
+                Response::from(response)
+            })
+            .when(|req: &Request| {
+                req.url().path() == "/graphql" && graphql_after(req).as_deref() == Some("cursor_1")
+            })
+            .times(1)
+            .then(|_: &Request| {
+                let data = "{\"data\": {\"transactions\": {\"pageInfo\": {\"hasNextPage\": false },\"edges\": [] } } }";
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data)
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = MemoryContext::with_http_client(key_manager, client.clone());
+        validate_bundler(&ctx).await.unwrap();
+
+        client.assert_expectations();
+    }
 }
@@ -0,0 +1,262 @@
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{
+    bundler::{self, BundlerConfig, BundlerError},
+    config::RuntimeConfigAccess,
+    context,
+    contract_gateway::ContractGatewayAccess,
+    http,
+    key_manager::{self, owner_address, KeyManagerAccess, ServedBundlerKey},
+    metrics::MetricsAccess,
+};
+
+use super::{error::ValidatorCronError, CronJobError};
+
+/// Fetch the bundler's `/info` and `/public` again and replace what was read
+/// at startup, so a bundler upgrading, adding currencies, moving to another
+/// gateway or rotating its key is followed without a restart. A new key is
+/// only accepted when it belongs to the bundler registered in the contract.
+pub async fn refresh_bundler_config<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+) -> Result<(), CronJobError>
+where
+    Context: context::ArweaveAccess
+        + context::BundlerAccess
+        + ContractGatewayAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + MetricsAccess
+        + RuntimeConfigAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let bundler = ctx.bundler();
+    let url = Url::parse(&bundler.url).map_err(|err| {
+        ValidatorCronError::BundlerConfig(BundlerError::RequestFailed(err.to_string()))
+    })?;
+    let config = BundlerConfig::fetch_config(ctx.get_http_client(), &url)
+        .await
+        .map_err(ValidatorCronError::BundlerConfig)?;
+    let owner = bundler::fetch_public_key(ctx.get_http_client(), &url)
+        .await
+        .map_err(ValidatorCronError::BundlerConfig)?;
+
+    let address = owner_address(&owner);
+    let key_manager = ctx.get_key_manager();
+    let registered = key_manager.bundler_addresses().contains(&address)
+        || ctx
+            .contract_gateway()
+            .get_current_state(ctx)
+            .await
+            .map_err(CronJobError::ContractGatewayError)?
+            .bundler
+            .to_string()
+            == address;
+    if !registered {
+        warn!(
+            %address,
+            "Bundler serves a key of another address than the bundler registered in the contract, not accepting it"
+        );
+    } else {
+        match key_manager.use_served_bundler_key(&owner) {
+            ServedBundlerKey::Known => (),
+            ServedBundlerKey::Adopted(address) => {
+                info!(%address, "Bundler serves a new key, accepting receipts signed with it")
+            }
+            ServedBundlerKey::Refused(address) => warn!(
+                %address,
+                "Bundler serves a key other than the pinned bundler key, not accepting it"
+            ),
+        }
+    }
+
+    let previous = bundler.config();
+    if !bundler.set_config(config.clone()) {
+        return Ok(());
+    }
+    let arweave = ctx.arweave();
+    if previous.map(|previous| previous.gateway) != Some(config.gateway.clone())
+        && arweave.follows_bundler()
+    {
+        match Url::parse(&format!("https://{}", config.gateway)) {
+            Ok(gateway) => {
+                ctx.metrics().register_dependency(&gateway, "arweave");
+                if arweave.set_url(gateway.clone()) {
+                    info!(%gateway, "Bundler moved to another Arweave gateway, following it");
+                }
+            }
+            Err(err) => warn!(
+                gateway = %config.gateway,
+                "Bundler serves an invalid Arweave gateway, keeping the current one: {}",
+                err
+            ),
+        }
+    }
+    info!(
+        version = %config.version,
+        receipt_version = ?config.receipt_version,
+        currencies = config.addresses.len(),
+        "Bundler config updated"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        str::FromStr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use bundlr_contracts_validators::{Epoch, State};
+    use data_encoding::BASE64URL_NOPAD;
+    use http::Method;
+    use openssl::{
+        hash::MessageDigest,
+        pkey::{PKey, Private},
+        rsa::Padding,
+        sign::Signer,
+    };
+    use reqwest::{Request, Response};
+    use url::Url;
+
+    use super::refresh_bundler_config;
+    use crate::{
+        context::{test_utils::TestContext, ArweaveAccess, BundlerAccess},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::{
+            owner_address,
+            test_utils::{bundler_key, test_keys, to_public_key},
+            KeyManager, KeyManagerAccess,
+        },
+    };
+
+    /// Contract state registering the bundler with `address`
+    fn contract_state(address: &str) -> String {
+        serde_json::to_string(&State {
+            bundler: address.try_into().unwrap(),
+            bundlers_contract: "bundlers_contract_address".try_into().unwrap(),
+            epoch: Epoch {
+                seq: 0,
+                tx: "tx0".try_into().unwrap(),
+                height: 1,
+            },
+            epoch_duration: 2,
+            minimum_stake: 1.into(),
+            token: "token_contract_address".try_into().unwrap(),
+            max_num_nominated_validators: 10,
+            validators: HashMap::new(),
+            nominated_validators: Vec::new(),
+            slash_proposal_lifetime: 10,
+            slash_proposals: HashMap::new(),
+        })
+        .unwrap()
+    }
+
+    fn sign(key: &PKey<Private>) -> Vec<u8> {
+        let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+        signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+        signer.update(b"receipt").unwrap();
+        signer.sign_to_vec().unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn bundler_config_is_replaced_when_bundler_reconfigures() {
+        let (key_manager, bundler_private) = test_keys();
+        let (rotated_jwk, rotated_private) = bundler_key();
+        let (unregistered_jwk, unregistered_private) = bundler_key();
+        let current = BASE64URL_NOPAD.encode(&bundler_private.rsa().unwrap().n().to_vec());
+        let modulus = |jwk| to_public_key(jwk).unwrap().rsa().unwrap().n().to_vec();
+        let rotated = BASE64URL_NOPAD.encode(&modulus(&rotated_jwk));
+        let unregistered = BASE64URL_NOPAD.encode(&modulus(&unregistered_jwk));
+        let state = contract_state(&owner_address(&modulus(&rotated_jwk)));
+        let upgraded = Arc::new(AtomicBool::new(false));
+        let served_config = upgraded.clone();
+        let served_key = upgraded.clone();
+        // The bundler then serves a key the contract doesn't register
+        let compromised = Arc::new(AtomicBool::new(false));
+        let served_unregistered = compromised.clone();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::GET && req.url().as_str() == "https://bundler.example.com/info"
+            })
+            .then(move |_: &Request| {
+                let data = if served_config.load(Ordering::SeqCst) {
+                    r#"{"version":"0.3.0","addresses":{"arweave":"arweave","solana":"solana"},"gateway":"gateway.example.com","receiptVersion":"1.0.0"}"#
+                } else {
+                    r#"{"version":"0.2.0","addresses":{"arweave":"arweave"},"gateway":"arweave.net"}"#
+                };
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data)
+                    .unwrap();
+                Response::from(response)
+            })
+            .when(|req: &Request| {
+                req.method() == Method::GET && req.url().as_str() == "https://bundler.example.com/public"
+            })
+            .then(move |_: &Request| {
+                let data = if served_unregistered.load(Ordering::SeqCst) {
+                    unregistered.clone()
+                } else if served_key.load(Ordering::SeqCst) {
+                    rotated.clone()
+                } else {
+                    current.clone()
+                };
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data)
+                    .unwrap();
+                Response::from(response)
+            })
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && req.url().as_str() == "http://localhost:3000/validators/state"
+            })
+            .then(move |_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(state.clone())
+                    .unwrap();
+                Response::from(response)
+            });
+        let ctx = TestContext::builder()
+            .with_key_manager(key_manager)
+            .with_http_client(client)
+            .with_bundler_url(Url::from_str("https://bundler.example.com/").unwrap())
+            .build()
+            .arweave_following_bundler();
+
+        refresh_bundler_config(&ctx).await.unwrap();
+        let config = ctx.bundler().config().unwrap();
+        assert_eq!(config.version, "0.2.0");
+        assert_eq!(config.address("solana"), None);
+        assert_eq!(ctx.arweave().get_host().as_str(), "https://arweave.net/");
+
+        upgraded.store(true, Ordering::SeqCst);
+        refresh_bundler_config(&ctx).await.unwrap();
+        let config = ctx.bundler().config().unwrap();
+        assert_eq!(config.version, "0.3.0");
+        assert_eq!(config.address("solana"), Some("solana"));
+        assert_eq!(config.receipt_version.as_deref(), Some("1.0.0"));
+        assert_eq!(
+            ctx.arweave().get_host().as_str(),
+            "https://gateway.example.com/"
+        );
+        let key_manager = ctx.get_key_manager();
+        assert!(key_manager.verify_bundler_signature(b"receipt", &sign(&rotated_private)));
+        assert!(key_manager.verify_bundler_signature(b"receipt", &sign(&bundler_private)));
+
+        compromised.store(true, Ordering::SeqCst);
+        refresh_bundler_config(&ctx).await.unwrap();
+        let key_manager = ctx.get_key_manager();
+        assert!(!key_manager.verify_bundler_signature(b"receipt", &sign(&unregistered_private)));
+        assert!(key_manager.verify_bundler_signature(b"receipt", &sign(&rotated_private)));
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    io,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use tracing::{error, info};
+
+use crate::{
+    bundle::archive::{archive_bundle, BundleArchive},
+    config::RuntimeConfigAccess,
+    database::queries::QueryContext,
+    http,
+};
+
+use super::{arweave::DOWNLOADS_DIR, error::ValidatorCronError, CronJobError};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Delete bundle files older than `bundle_retention_days` from the downloads
+/// directory, archiving them first if a bundle archive is configured. The
+/// bundle store is left alone.
+pub async fn cleanup_bundles<Context, HttpClient>(ctx: &Context) -> Result<(), CronJobError>
+where
+    Context: http::ClientAccess<HttpClient> + QueryContext + RuntimeConfigAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let (retention_days, archive) = ctx.bundle_cleanup();
+    let retention_days = match retention_days {
+        Some(days) => days,
+        None => return Ok(()),
+    };
+
+    let cutoff = SystemTime::now() - Duration::from_secs(retention_days * SECONDS_PER_DAY);
+    let deleted = cleanup_dir(ctx, Path::new(DOWNLOADS_DIR), cutoff, archive.as_ref())
+        .await
+        .map_err(|err| CronJobError::ValidatorError(ValidatorCronError::BundleCleanup(err)))?;
+    info!(
+        deleted,
+        "Bundle files older than {} days deleted", retention_days
+    );
+
+    Ok(())
+}
+
+/// Delete files in `dir` last modified before `cutoff`. Files that fail to
+/// be archived are kept for the next run.
+async fn cleanup_dir<Context, HttpClient>(
+    ctx: &Context,
+    dir: &Path,
+    cutoff: SystemTime,
+    archive: Option<&BundleArchive>,
+) -> io::Result<usize>
+where
+    Context: http::ClientAccess<HttpClient> + QueryContext,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let mut deleted = 0;
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() || metadata.modified()? >= cutoff {
+            continue;
+        }
+        let bundle_id = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+
+        if let Some(archive) = archive {
+            if let Err(err) = archive_bundle(ctx, archive, &bundle_id, &path).await {
+                error!(bundle = %bundle_id, "Failed to archive bundle, keeping it: {}", err);
+                continue;
+            }
+        }
+        tokio::fs::remove_file(&path).await?;
+        info!(bundle = %bundle_id, "Bundle file deleted");
+        deleted += 1;
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::SystemTime};
+
+    use crate::{
+        bundle::{
+            archive::{ArchiveContents, BundleArchive},
+            store::{BundleStorage, FilesystemStore},
+        },
+        context::test_utils::test_context,
+        key_manager::test_utils::test_keys,
+    };
+
+    use super::cleanup_dir;
+
+    #[actix_rt::test]
+    async fn old_bundles_are_archived_before_deletion() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        let dir = std::env::temp_dir().join("validator_cleanup_bundles");
+        let archive_dir = std::env::temp_dir().join("validator_cleanup_archive");
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy("./bundles/test_bundle", dir.join("old_bundle")).unwrap();
+        let archive = BundleArchive {
+            contents: ArchiveContents::Bundle,
+            storage: BundleStorage::Filesystem(FilesystemStore {
+                path: archive_dir.clone(),
+            }),
+        };
+
+        // Nothing is older than a cutoff in the past
+        let deleted = cleanup_dir(&ctx, &dir, SystemTime::UNIX_EPOCH, Some(&archive))
+            .await
+            .unwrap();
+        assert_eq!(deleted, 0);
+        assert!(dir.join("old_bundle").exists());
+
+        let deleted = cleanup_dir(&ctx, &dir, SystemTime::now(), Some(&archive))
+            .await
+            .unwrap();
+        assert_eq!(deleted, 1);
+        assert!(!dir.join("old_bundle").exists());
+        assert_eq!(
+            fs::read(archive_dir.join("old_bundle")).unwrap(),
+            fs::read("./bundles/test_bundle").unwrap()
+        );
+        assert!(archive_dir.join("old_bundle.json").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(archive_dir).unwrap();
+    }
+}
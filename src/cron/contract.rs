@@ -1,26 +1,49 @@
 use std::str::FromStr;
 
 use crate::{
+    config::RuntimeConfigAccess,
     context, contract_gateway,
+    database::{
+        models::{Epoch, NewValidator, Stake},
+        queries,
+    },
+    epoch::{EpochInfo, EpochParams},
+    events::{Event, EventBusAccess},
+    key_manager::{self, KeyManagerAccess},
+    peers::PeerScoresAccess,
+    slash_votes::{forward_slash_vote, Ballot, SlashVote, SlashVotesAccess},
     state::{self, ValidatorRole},
 };
 
-use super::{arweave::ArweaveError, http, CronJobError};
+use super::{
+    arweave::ArweaveError, error::ValidatorCronError, http, slasher::propose_slashes, CronJobError,
+};
+
+use tracing::{error, info, warn};
 
 use bundlr_contracts_validators::{
     slashing::Proposal as SlashProposal,
     slashing::{Vote, Voting},
-    Address, Epoch, State as ContractState,
+    Address, State as ContractState,
 };
 
-pub async fn check_contract_updates<Context, HttpClient>(ctx: &Context) -> Result<(), CronJobError>
+pub async fn check_contract_updates<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+) -> Result<(), CronJobError>
 where
     Context: context::ArweaveAccess
         + context::ValidatorAddressAccess
         + contract_gateway::ContractGatewayAccess
+        + EventBusAccess
         + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + queries::QueryContext
+        + RuntimeConfigAccess
+        + SlashVotesAccess
         + state::ValidatorStateAccess,
     HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
 {
     let contract_gateway = ctx.contract_gateway();
 
@@ -29,33 +52,230 @@ where
         .await
         .map_err(CronJobError::ContractGatewayError)?;
 
+    sync_validators(ctx, &state);
+    sync_stake(ctx, &state);
+
+    if let Some(params) = EpochParams::from_contract_state(&state) {
+        ctx.get_validator_state()
+            .set_epoch_params(params)
+            .map_err(ValidatorCronError::EpochParams)?;
+    }
+
     if let Some((new_epoch, new_role)) = check_for_epoch_update(ctx, &state).await {
         let state = ctx.get_validator_state();
+        let previous_epoch = state.current_epoch();
         state.set_current_epoch(new_epoch.seq);
-        state.set_role(new_role);
+        let previous = state.transition(new_role);
+        if previous != new_role {
+            info!(epoch = new_epoch.seq, from = ?previous, to = ?new_role, "Validator role changed");
+        }
+        // Epoch hooks run off this event, see `cron::create_epoch_hook`
+        ctx.event_bus().publish(Event::EpochStarted {
+            epoch: new_epoch.seq as u64,
+            previous: previous_epoch as u64,
+            role: new_role,
+        });
     }
 
-    if let Some(new_slash_proposals) = check_for_slash_proposals(ctx, &state).await {
+    check_bundler_key(ctx, &state);
+
+    vote_on_slash_proposals(ctx, &state).await?;
+
+    propose_slashes(ctx).await;
+
+    Ok(())
+}
+
+/// Vote on open slash proposals right away rather than at the next contract
+/// sync, run when a peer's vote shows a proposal this validator may not
+/// have voted on yet
+pub async fn evaluate_slash_proposals<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+) -> Result<(), CronJobError>
+where
+    Context: context::ArweaveAccess
+        + context::ValidatorAddressAccess
+        + contract_gateway::ContractGatewayAccess
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + queries::QueryContext
+        + RuntimeConfigAccess
+        + SlashVotesAccess
+        + state::ValidatorStateAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let state = ctx
+        .contract_gateway()
+        .get_current_state(ctx)
+        .await
+        .map_err(CronJobError::ContractGatewayError)?;
+
+    vote_on_slash_proposals(ctx, &state).await
+}
+
+async fn vote_on_slash_proposals<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+    state: &ContractState,
+) -> Result<(), CronJobError>
+where
+    Context: context::ArweaveAccess
+        + context::ValidatorAddressAccess
+        + contract_gateway::ContractGatewayAccess
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + queries::QueryContext
+        + RuntimeConfigAccess
+        + SlashVotesAccess
+        + state::ValidatorStateAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    // Contract syncs and peer votes both lead here, the state either of
+    // them fetched may not show a vote the other one just cast
+    let _voting = ctx.slash_votes().lock_voting().await;
+    let contract_gateway = ctx.contract_gateway();
+    let own_address = ctx.get_validator_address();
+
+    if let Some(new_slash_proposals) = check_for_slash_proposals(ctx, state).await {
         for proposal in new_slash_proposals {
+            if ctx.slash_votes().has_voted(&proposal.id, own_address) {
+                continue;
+            }
             let is_valid = is_valid_proposal(ctx, proposal)
                 .await
                 .map_err(CronJobError::ArweaveError)?;
 
             let vote = if is_valid { Vote::For } else { Vote::Against };
             contract_gateway
-                .vote_for_proposal(ctx, proposal, vote)
+                .vote_for_proposal(ctx, ctx.get_key_manager(), proposal, vote)
                 .await
                 .map_err(CronJobError::ContractGatewayError)?;
+            let vote = SlashVote {
+                proposal: proposal.id.clone(),
+                validator: own_address.to_string(),
+                vote: if is_valid {
+                    Ballot::For
+                } else {
+                    Ballot::Against
+                },
+            };
+            ctx.event_bus().publish(Event::SlashVoteCast {
+                proposal: vote.proposal.clone(),
+                vote: vote.vote.as_str().to_string(),
+            });
+
+            // Peers learn about the proposal before their next contract sync
+            ctx.slash_votes().record(&vote);
+            forward_slash_vote(ctx, ctx.get_key_manager(), &vote).await;
         }
     }
 
     Ok(())
 }
 
-async fn check_for_epoch_update<'a, Context>(
+/// Compare the bundler keys receipts are verified with to the bundler
+/// registered in the contract. While none of them matches, e.g. after the
+/// bundler rotated its key, receipts aren't verified rather than judged
+/// against the wrong key.
+fn check_bundler_key<Context, KeyManager>(ctx: &Context, state: &ContractState)
+where
+    Context: EventBusAccess + KeyManagerAccess<KeyManager> + state::ValidatorStateAccess,
+    KeyManager: key_manager::KeyManager,
+{
+    let registered = state.bundler.to_string();
+    let key_manager = ctx.get_key_manager();
+    let configured = key_manager.bundler_address().trim();
+    let mismatch = !key_manager
+        .bundler_addresses()
+        .iter()
+        .any(|address| address.trim() == registered);
+    let was_mismatched = ctx.get_validator_state().set_bundler_key_mismatch(mismatch);
+
+    if mismatch {
+        error!(
+            configured = ?key_manager.bundler_addresses(),
+            registered = %registered,
+            "Bundler key doesn't match the bundler registered in the contract, not verifying receipts"
+        );
+        if !was_mismatched {
+            ctx.event_bus().publish(Event::BundlerKeyMismatch {
+                configured: configured.to_string(),
+                registered,
+            });
+        }
+    } else if was_mismatched {
+        info!(bundler = %registered, "Bundler key matches the contract again");
+    }
+}
+
+/// Length of the `validators.url` column
+const MAX_VALIDATOR_URL_LENGTH: usize = 100;
+
+/// Store validators registered in the contract, so they can be used as
+/// peers without configuring them
+fn sync_validators<Context>(ctx: &Context, state: &ContractState)
+where
+    Context: queries::QueryContext,
+{
+    let validators: Vec<NewValidator> = state
+        .validators
+        .values()
+        .filter_map(|validator| {
+            let url = validator.url.to_string();
+            if url.len() > MAX_VALIDATOR_URL_LENGTH {
+                warn!(validator = %validator.address, "Validator url too long, skipping");
+                return None;
+            }
+            Some(NewValidator {
+                address: validator.address.to_string(),
+                url: Some(url),
+            })
+        })
+        .collect();
+
+    if let Err(err) = queries::upsert_validators(ctx, &validators) {
+        error!("Failed to store contract validators: {}", err);
+    }
+}
+
+/// Store this validator's stake and nomination, so operators can follow
+/// them through `GET /stake`
+fn sync_stake<Context>(ctx: &Context, state: &ContractState)
+where
+    Context: context::ValidatorAddressAccess + queries::QueryContext,
+{
+    let address = ctx.get_validator_address();
+    let contract_address = Address::from_str(address).ok();
+    let validator = contract_address
+        .as_ref()
+        .and_then(|address| state.validators.get(address));
+    let stake = Stake {
+        address: address.to_string(),
+        registered: validator.is_some(),
+        stake: validator
+            .map(|validator| validator.stake.to_string())
+            .unwrap_or_else(|| "0".to_string()),
+        minimum_stake: state.minimum_stake.to_string(),
+        nominated: contract_address
+            .map(|address| state.nominated_validators.contains(&address))
+            .unwrap_or(false),
+        epoch: Epoch(state.epoch.seq),
+    };
+
+    if let Err(err) = queries::upsert_stake(ctx, &stake) {
+        error!("Failed to store validator stake: {}", err);
+    }
+}
+
+async fn check_for_epoch_update<Context>(
     ctx: &Context,
-    state: &'a ContractState,
-) -> Option<(&'a Epoch, ValidatorRole)>
+    state: &ContractState,
+) -> Option<(EpochInfo, ValidatorRole)>
 where
     Context: state::ValidatorStateAccess + context::ValidatorAddressAccess,
 {
@@ -64,14 +284,10 @@ where
         (state.current_block(), state.current_epoch())
     };
 
-    if state.epoch.seq > current_epoch && state.epoch.height <= current_block_height {
+    let epoch = EpochInfo::from_contract_epoch(&state.epoch, state.epoch_duration);
+    if epoch.seq > current_epoch && epoch.has_started(current_block_height) {
         let validator_address = Address::from_str(ctx.get_validator_address()).unwrap();
-        let role = if state.nominated_validators.contains(&validator_address) {
-            ValidatorRole::Cosigner
-        } else {
-            ValidatorRole::Idle
-        };
-        Some((&state.epoch, role))
+        Some((epoch, ValidatorRole::for_epoch(state, &validator_address)))
     } else {
         None
     }
@@ -125,12 +341,15 @@ mod test {
 
     use crate::{
         context::test_utils::test_context_with_http_client,
+        context::ValidatorAddressAccess,
+        database::queries::{get_stake, get_validators},
+        events::{Event, EventBusAccess},
         http::reqwest::mock::MockHttpClient,
         key_manager::{
-            test_utils::{test_keys, to_address, validator_key},
+            test_utils::{bundler_key, test_keys, to_address, validator_key},
             KeyManager,
         },
-        state::ValidatorStateAccess,
+        state::{ValidatorRole, ValidatorStateAccess},
     };
     use bundlr_contracts_validators::{
         slashing::{Proposal, Vote, Voting},
@@ -140,7 +359,7 @@ mod test {
     use http::Method;
     use reqwest;
 
-    use super::check_contract_updates;
+    use super::{check_bundler_key, check_contract_updates};
 
     fn create_contract_state(
         validators: HashMap<Address, Validator>,
@@ -165,6 +384,44 @@ mod test {
         }
     }
 
+    #[test]
+    fn bundler_key_not_registered_in_contract_is_flagged() {
+        let (key_manager, _bundle_pvk) = test_keys();
+        let bundler: Address = key_manager.bundler_address().try_into().unwrap();
+        let ctx = test_context_with_http_client(key_manager, MockHttpClient::new(|_, _| false));
+        let mut events = ctx.event_bus().subscribe();
+        let mut state = create_contract_state(HashMap::new(), Vec::new());
+
+        check_bundler_key(&ctx, &state);
+        assert!(ctx.get_validator_state().bundler_key_mismatch());
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            Event::BundlerKeyMismatch { registered, .. } if registered == "bundler_address"
+        ));
+
+        state.bundler = bundler;
+        check_bundler_key(&ctx, &state);
+        assert!(!ctx.get_validator_state().bundler_key_mismatch());
+    }
+
+    #[test]
+    fn additional_bundler_key_registered_in_contract_is_accepted() {
+        let (key_manager, _bundle_pvk) = test_keys();
+        let (rotated_jwk, _) = bundler_key();
+        let key_manager = key_manager.with_bundler_keys(&[rotated_jwk.clone()]);
+        let ctx = test_context_with_http_client(key_manager, MockHttpClient::new(|_, _| false));
+        let mut state = create_contract_state(HashMap::new(), Vec::new());
+        state.bundler = to_address(&rotated_jwk)
+            .unwrap()
+            .as_str()
+            .try_into()
+            .unwrap();
+
+        check_bundler_key(&ctx, &state);
+
+        assert!(!ctx.get_validator_state().bundler_key_mismatch());
+    }
+
     #[test]
     fn epoch_update_before_activation_yields_no_change() {
         let (key_manager, _bundle_pvk) = test_keys();
@@ -202,6 +459,15 @@ mod test {
         rt.run_until(check_contract_updates(&ctx)).unwrap();
 
         assert_eq!(ctx.get_validator_state().current_epoch(), 0);
+        let stake = get_stake(&ctx, ctx.get_validator_address())
+            .unwrap()
+            .unwrap();
+        assert!(stake.registered && stake.nominated);
+        assert_eq!(stake.stake, "1");
+        assert!(get_validators(&ctx)
+            .unwrap()
+            .iter()
+            .any(|validator| validator.url.as_deref() == Some("https://validator1.example.com/")));
     }
 
     #[test]
@@ -229,11 +495,46 @@ mod test {
 
         let ctx = test_context_with_http_client(key_manager, client);
         ctx.get_validator_state().set_current_block(1);
+        let mut events = ctx.event_bus().subscribe();
 
         let mut rt = LocalPool::new();
         rt.run_until(check_contract_updates(&ctx)).unwrap();
 
         assert_eq!(ctx.get_validator_state().current_epoch(), 1);
+        assert_eq!(ctx.get_validator_state().role(), ValidatorRole::Idle);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            Event::EpochStarted {
+                epoch: 1,
+                previous: 0,
+                role: ValidatorRole::Idle
+            }
+        );
+    }
+
+    #[test]
+    fn only_nominated_validator_becomes_leader() {
+        let (key_manager, _bundle_pvk) = test_keys();
+        let validator_address: Address = key_manager.validator_address().try_into().unwrap();
+        let validators: HashMap<Address, Validator> = HashMap::from([(
+            validator_address.clone(),
+            Validator {
+                address: validator_address.clone(),
+                url: "https://validator1.example.com".parse().unwrap(),
+                stake: 1.into(),
+            },
+        )]);
+        let contract_state = create_contract_state(validators, vec![validator_address.clone()]);
+
+        assert_eq!(
+            ValidatorRole::for_epoch(&contract_state, &validator_address),
+            ValidatorRole::Leader
+        );
+        let other: Address = to_address(&validator_key()).try_into().unwrap();
+        assert_eq!(
+            ValidatorRole::for_epoch(&contract_state, &other),
+            ValidatorRole::Idle
+        );
     }
 
     #[test]
@@ -1,23 +1,62 @@
-use derive_more::{Display, Error};
-use std::convert::From;
+use thiserror::Error;
 
-#[derive(Debug, Display, Error, Clone, PartialEq)]
+use crate::{bundler::BundlerError, epoch::EpochParamsError};
+
+use super::arweave::ArweaveError;
+
+/// Failure validating the bundler's bundles, naming the bundle or
+/// transaction it concerns
+#[derive(Debug, Error)]
 pub enum ValidatorCronError {
-    TxNotFound,
-    AddressNotFound,
-    TxsFromAddressNotFound,
-    BundleNotInsertedInDB,
-    TxInvalid,
-    FileError,
+    #[error("failed to fetch transactions of {address}: {source}")]
+    TxsFromAddressNotFound {
+        address: String,
+        source: ArweaveError,
+    },
+    #[error("data of bundle {bundle_id} is not retrievable from any source")]
+    BundleNotRetrievable { bundle_id: String },
+    /// Gateway throttled the download and no other source served the data,
+    /// downloads are paused for the delay the gateway asked
+    #[error("download of bundle {bundle_id} was throttled: {source}")]
+    BundleDownloadThrottled {
+        bundle_id: String,
+        source: ArweaveError,
+    },
+    #[error("failed to store bundle {bundle_id}: {source}")]
+    BundleNotInsertedInDB {
+        bundle_id: String,
+        source: diesel::result::Error,
+    },
+    #[error("invalid tx {tx_id} in bundle {bundle_id}: {reason}")]
+    TxInvalid {
+        bundle_id: String,
+        tx_id: String,
+        reason: String,
+    },
+    #[error("receipt of tx {tx_id} not found on any peer")]
+    TxNotFound { tx_id: String },
+    #[error("database error: {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    #[error("bundler key doesn't match the bundler registered in the contract")]
+    BundlerKeyMismatch,
+    #[error("failed to clean up bundle files: {0}")]
+    BundleCleanup(std::io::Error),
+    #[error("failed to check bundle files: {0}")]
+    IntegrityCheck(std::io::Error),
+    #[error("database pruning stopped: {0}")]
+    PruneTask(tokio::task::JoinError),
+    #[error("contract epoch parameters rejected: {0}")]
+    EpochParams(#[from] EpochParamsError),
+    #[error("failed to fetch bundler config: {0}")]
+    BundlerConfig(#[from] BundlerError),
 }
 
-#[derive(Debug, Display, Error, Clone)]
+#[derive(Debug, Error)]
 pub enum TxsError {
-    TxNotFound,
-}
-
-impl From<anyhow::Error> for ValidatorCronError {
-    fn from(_err: anyhow::Error) -> ValidatorCronError {
-        ValidatorCronError::AddressNotFound
-    }
+    #[error("request to bundler failed: {0}")]
+    RequestFailed(#[from] reqwest::Error),
+    #[error("bundler unreachable: {0}")]
+    Unreachable(String),
+    #[error("invalid transactions query: {0}")]
+    InvalidQuery(#[from] serde_json::Error),
 }
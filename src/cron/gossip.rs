@@ -0,0 +1,141 @@
+use tracing::{info, warn};
+use url::Url;
+
+use super::CronJobError;
+use crate::{
+    config::RuntimeConfigAccess,
+    context::ValidatorAddressAccess,
+    database::queries::QueryContext,
+    http,
+    key_manager::{self, KeyManagerAccess},
+    peer_auth::sign_request,
+    peers::{peer_urls, PeerOutcome, PeerScoresAccess},
+    receipt::{RawTxReceipt, ReceiptQueueAccess},
+};
+
+/// Receipts sent to a peer in a single request, keeps request bodies under
+/// the default JSON payload limit of the receiving server
+const GOSSIP_BATCH_SIZE: usize = 25;
+
+/// Share receipts learned since the last run with all peers
+pub async fn gossip_receipts<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+) -> Result<(), CronJobError>
+where
+    Context: QueryContext
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + ValidatorAddressAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let receipts = ctx.receipt_queue().take();
+    if receipts.is_empty() {
+        return Ok(());
+    }
+
+    info!(receipts = receipts.len(), "Gossiping receipts");
+    for peer in peer_urls(ctx).await {
+        for batch in receipts.chunks(GOSSIP_BATCH_SIZE) {
+            if let Err(err) = send_receipts(ctx, &peer, batch).await {
+                warn!(peer = %peer, "Failed to gossip receipts - {}", err);
+                ctx.peer_scores().record(&peer, PeerOutcome::Unreachable);
+                break;
+            }
+            ctx.peer_scores().record(&peer, PeerOutcome::Success);
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_receipts<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+    peer: &Url,
+    receipts: &[RawTxReceipt],
+) -> Result<(), String>
+where
+    Context: http::ClientAccess<HttpClient> + KeyManagerAccess<KeyManager>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let url = peer.join("receipts").map_err(|err| err.to_string())?;
+    let mut req = reqwest::Client::new()
+        .post(url)
+        .json(receipts)
+        .build()
+        .map_err(|err| err.to_string())?;
+    sign_request(ctx.get_key_manager(), &mut req);
+
+    let res = ctx
+        .get_http_client()
+        .execute(req)
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+    if !res.status().is_success() {
+        return Err(format!("unexpected status {}", res.status()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use http::Method;
+    use reqwest::{Request, Response};
+    use url::Url;
+
+    use super::gossip_receipts;
+    use crate::{
+        config::{RuntimeConfig, RuntimeConfigAccess},
+        context::test_utils::test_context_with_http_client,
+        http::reqwest::mock::MockHttpClient,
+        key_manager::{test_utils::test_keys, KeyManager},
+        peer_auth::SIGNATURE_HEADER,
+        receipt::{
+            test_utils::{raw_receipt, signed_sign_request},
+            ReceiptQueueAccess,
+        },
+    };
+
+    #[actix_rt::test]
+    async fn queued_receipts_are_posted_to_peers() {
+        let (key_manager, bundler_key) = test_keys();
+        // Sign requests of the bundler are shared exactly as received
+        let request =
+            signed_sign_request(&bundler_key, "tx_id", 10, key_manager.validator_address());
+        let raw = raw_receipt(&request);
+        let expected = format!("[{}]", raw.payload);
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when_matches(move |req: &Request| {
+                req.method() == Method::POST
+                    && &req.url().to_string() == "http://peer.example.com/receipts"
+                    && req.headers().contains_key(SIGNATURE_HEADER)
+                    && req.body().and_then(|body| body.as_bytes()) == Some(expected.as_bytes())
+            })
+            .then(|_: &Request| {
+                let response = http::response::Builder::new().status(200).body("").unwrap();
+                Response::from(response)
+            });
+
+        let ctx = test_context_with_http_client(key_manager, client.clone());
+        ctx.replace_runtime_config(RuntimeConfig {
+            peers: vec![Url::from_str("http://peer.example.com/").unwrap()],
+            ..RuntimeConfig::default()
+        });
+        ctx.receipt_queue().push(raw);
+
+        gossip_receipts(&ctx).await.unwrap();
+
+        assert!(ctx.receipt_queue().take().is_empty());
+        drop(ctx);
+        client.verify(|interactions| {
+            assert!(!interactions.is_empty());
+        });
+    }
+}
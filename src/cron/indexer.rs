@@ -0,0 +1,88 @@
+use serde::Deserialize;
+use url::Url;
+
+/// GraphQL service bundles are listed from. They all follow the schema of
+/// arweave.net but differ in arguments, pagination and page sizes.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IndexerKind {
+    /// arweave.net and gateways running the same GraphQL service
+    #[default]
+    Arweave,
+    /// Goldsky search, which needs an explicit sort order and returns the
+    /// end cursor in `pageInfo` instead of on every edge
+    Goldsky,
+    /// Gateways of the ar.io network, which accept larger pages
+    ArIo,
+}
+
+const ARWEAVE_QUERY: &str = "query($owners: [String!], $first: Int, $after: String) { transactions(owners: $owners, first: $first, after: $after) { pageInfo { hasNextPage } edges { cursor node { id owner { address } signature recipient tags { name value } data { size } block { height id timestamp } } } } }";
+
+const GOLDSKY_QUERY: &str = "query($owners: [String!], $first: Int, $after: String) { transactions(owners: $owners, first: $first, after: $after, sort: HEIGHT_DESC) { pageInfo { hasNextPage endCursor } edges { node { id owner { address } signature recipient tags { name value } data { size } block { height id timestamp } } } } }";
+
+/// Where and how transactions of a bundler are queried
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Indexer {
+    pub kind: IndexerKind,
+    /// GraphQL endpoint, `graphql` of the Arweave gateway when unset
+    pub url: Option<Url>,
+}
+
+impl Indexer {
+    pub fn new(kind: IndexerKind, url: Option<Url>) -> Self {
+        Self { kind, url }
+    }
+
+    /// GraphQL endpoint of this indexer for the Arweave gateway at `gateway`
+    pub fn endpoint(&self, gateway: &Url) -> Result<Url, url::ParseError> {
+        match &self.url {
+            Some(url) => Ok(url.clone()),
+            None => gateway.join("graphql"),
+        }
+    }
+
+    /// Query listing transactions of `$owners`, taking `$first` and
+    /// `$after` for pagination
+    pub fn query(&self) -> &'static str {
+        match self.kind {
+            IndexerKind::Arweave | IndexerKind::ArIo => ARWEAVE_QUERY,
+            IndexerKind::Goldsky => GOLDSKY_QUERY,
+        }
+    }
+
+    /// Largest page the indexer returns, asking for more is an error on
+    /// some of them
+    pub fn max_page_size(&self) -> i64 {
+        match self.kind {
+            IndexerKind::Arweave | IndexerKind::Goldsky => 100,
+            IndexerKind::ArIo => 1000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use url::Url;
+
+    use super::{Indexer, IndexerKind};
+
+    #[test]
+    fn endpoint_defaults_to_gateway_graphql() {
+        let gateway = Url::from_str("http://example.com").unwrap();
+        assert_eq!(
+            Indexer::default().endpoint(&gateway).unwrap().as_str(),
+            "http://example.com/graphql"
+        );
+
+        let goldsky = Url::from_str("https://arweave-search.goldsky.com/graphql").unwrap();
+        let indexer = Indexer::new(IndexerKind::Goldsky, Some(goldsky.clone()));
+        assert_eq!(indexer.endpoint(&gateway).unwrap(), goldsky);
+        assert!(indexer.query().contains("endCursor"));
+        assert_eq!(
+            serde_json::from_value::<IndexerKind>(serde_json::json!("ar-io")).unwrap(),
+            IndexerKind::ArIo
+        );
+    }
+}
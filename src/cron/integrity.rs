@@ -0,0 +1,188 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+use tracing::{error, info};
+
+use crate::{
+    bundle::{
+        file_checksum,
+        store::{BundleStorage, BundleStoreAccess},
+    },
+    config::RuntimeConfigAccess,
+    database::queries::{self, QueryContext},
+    events::{Event, EventBusAccess},
+};
+
+use super::{arweave::DOWNLOADS_DIR, error::ValidatorCronError, CronJobError};
+
+/// Files whose recorded checksums are looked up in a single query
+const LOOKUP_BATCH: usize = 500;
+
+/// Re-hash a random sample of stored bundle files and flag those no longer
+/// matching the checksum recorded when they were validated
+pub async fn check_bundle_integrity<Context>(ctx: &Context) -> Result<(), CronJobError>
+where
+    Context: BundleStoreAccess + EventBusAccess + QueryContext + RuntimeConfigAccess,
+{
+    let sample = ctx.integrity_check_sample();
+    if sample == 0 {
+        return Ok(());
+    }
+
+    // S3 stores can't be read back, their bundles are checked while still
+    // kept in the downloads directory
+    let dir = match ctx.bundle_store() {
+        BundleStorage::Filesystem(store) => store.path.clone(),
+        BundleStorage::S3(_) => PathBuf::from(DOWNLOADS_DIR),
+    };
+    let (checked, corrupt) = check_dir(ctx, &dir, sample)
+        .await
+        .map_err(|err| CronJobError::ValidatorError(ValidatorCronError::IntegrityCheck(err)))?;
+    info!(checked, corrupt, "Bundle files checked for corruption");
+
+    Ok(())
+}
+
+/// Check up to `sample` files of `dir` that belong to a validated bundle,
+/// returns how many were checked and how many of them are corrupt
+async fn check_dir<Context>(ctx: &Context, dir: &Path, sample: usize) -> io::Result<(usize, usize)>
+where
+    Context: EventBusAccess + QueryContext,
+{
+    let mut files = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.metadata().await?.is_file() {
+            files.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    // Ordering by a randomly keyed hash picks a different sample every run
+    let state = RandomState::new();
+    files.sort_by_cached_key(|name| {
+        let mut hasher = state.build_hasher();
+        name.hash(&mut hasher);
+        hasher.finish()
+    });
+
+    let (mut checked, mut corrupt) = (0, 0);
+    for batch in files.chunks(LOOKUP_BATCH) {
+        if checked == sample {
+            break;
+        }
+        // Reports, downloads not validated yet and bundles validated before
+        // checksums were recorded have no checksum and are skipped
+        let checksums = queries::get_bundle_checksums(ctx, batch)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        for bundle_id in batch {
+            if checked == sample {
+                break;
+            }
+            let expected = match checksums.get(bundle_id) {
+                Some(expected) => expected,
+                None => continue,
+            };
+            checked += 1;
+            if !check_file(ctx, dir, bundle_id, expected).await {
+                corrupt += 1;
+            }
+        }
+    }
+
+    Ok((checked, corrupt))
+}
+
+/// Re-hash the file of `bundle_id` and flag it when it doesn't match
+/// `expected`, returns whether it matches
+async fn check_file<Context>(ctx: &Context, dir: &Path, bundle_id: &str, expected: &str) -> bool
+where
+    Context: EventBusAccess + QueryContext,
+{
+    let reason = match file_checksum(&dir.join(bundle_id)).await {
+        Ok(actual) if actual == expected => None,
+        Ok(actual) => Some(format!(
+            "checksum {} doesn't match {} recorded at validation",
+            actual, expected
+        )),
+        Err(err) => Some(format!("failed to read file: {}", err)),
+    };
+    match reason {
+        None => {
+            ctx.metrics()
+                .bundle_integrity_checks
+                .with_label_values(&["ok"])
+                .inc();
+            true
+        }
+        Some(reason) => {
+            error!(bundle = %bundle_id, "Stored bundle file is corrupt: {}", reason);
+            ctx.metrics()
+                .bundle_integrity_checks
+                .with_label_values(&["corrupt"])
+                .inc();
+            ctx.event_bus().publish(Event::BundleCorrupt {
+                bundle: bundle_id.to_string(),
+                reason,
+            });
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        bundle::file_checksum,
+        context::{test_utils::test_context, ValidatorAddressAccess},
+        database::{
+            models::{Block, NewBundle},
+            queries::insert_bundle_in_db,
+        },
+        events::{Event, EventBusAccess},
+        key_manager::test_utils::test_keys,
+    };
+
+    use super::check_dir;
+
+    #[actix_rt::test]
+    async fn modified_bundle_file_is_flagged() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        let mut events = ctx.event_bus().subscribe();
+        let dir = std::env::temp_dir().join("validator_integrity_check");
+        fs::create_dir_all(&dir).unwrap();
+        // Unique per run, the database is shared between test runs
+        let bundle_id = ctx.get_validator_address().to_string();
+        fs::copy("./bundles/test_bundle", dir.join(&bundle_id)).unwrap();
+        fs::write(dir.join("unvalidated_bundle"), b"not validated").unwrap();
+        insert_bundle_in_db(
+            &ctx,
+            NewBundle {
+                id: bundle_id.clone(),
+                owner_address: "owner".to_string(),
+                block_height: Block(1),
+                size: None,
+                item_count: None,
+                block_hash: None,
+                checksum: Some(file_checksum(&dir.join(&bundle_id)).await.unwrap()),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(check_dir(&ctx, &dir, 10).await.unwrap(), (1, 0));
+
+        fs::write(dir.join(&bundle_id), b"rotten").unwrap();
+        assert_eq!(check_dir(&ctx, &dir, 10).await.unwrap(), (1, 1));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            Event::BundleCorrupt { bundle, .. } if bundle == bundle_id
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,31 +1,83 @@
 pub mod arweave;
+mod balance;
 mod bundle;
+mod bundler_config;
+mod cleanup;
 mod contract;
-mod error;
+pub mod error;
+mod gossip;
+pub mod indexer;
+mod integrity;
+mod prune;
+mod rounds;
 mod slasher;
+mod sync;
 mod transactions;
 mod validate;
 
 use crate::{
+    bundle::store::BundleStoreAccess,
+    config::{CronIntervals, RuntimeConfigAccess},
     context,
     contract_gateway::{self, ContractGatewayError},
     database::queries,
+    error::ValidatorError,
+    events::{Event, EventBusAccess},
     http, key_manager,
+    metrics::{result_label, MetricsAccess},
+    peers::PeerScoresAccess,
+    receipt::ReceiptQueueAccess,
+    slash_votes::SlashVotesAccess,
+    watchdog::HeartbeatAccess,
 };
-use derive_more::{Display, Error};
 use futures::{join, Future};
-use paris::{error, info};
 use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{
+    broadcast::error::{RecvError, TryRecvError},
+    Mutex,
+};
+use tracing::{error, info, warn};
 
 use self::{arweave::ArweaveError, error::ValidatorCronError};
 
-#[derive(Debug, Display, Error, Clone, PartialEq)]
+#[derive(Debug, Error)]
 pub enum CronJobError {
-    ArweaveError(ArweaveError),
-    ContractGatewayError(ContractGatewayError),
-    ValidatorError(ValidatorCronError),
+    #[error("arweave request failed: {0}")]
+    ArweaveError(#[from] ArweaveError),
+    #[error(transparent)]
+    ContractGatewayError(#[from] ContractGatewayError),
+    #[error(transparent)]
+    ValidatorError(#[from] ValidatorCronError),
+}
+
+impl CronJobError {
+    /// Delay a throttling gateway asked to wait before contacting it again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            CronJobError::ArweaveError(ArweaveError::Throttled { retry_after, .. })
+            | CronJobError::ValidatorError(ValidatorCronError::TxsFromAddressNotFound {
+                source: ArweaveError::Throttled { retry_after, .. },
+                ..
+            })
+            | CronJobError::ValidatorError(ValidatorCronError::BundleDownloadThrottled {
+                source: ArweaveError::Throttled { retry_after, .. },
+                ..
+            }) => Some(*retry_after),
+            CronJobError::ContractGatewayError(ContractGatewayError::Throttled(retry_after)) => {
+                Some(*retry_after)
+            }
+            _ => None,
+        }
+    }
 }
 
+/// Consecutive failures after which a task is reported as broken
+const CIRCUIT_FAILURE_THRESHOLD: u32 = 5;
+/// Longest pause a throttling gateway can impose on a task, the watchdog
+/// only reports a task stalled after twice its pause
+const MAX_THROTTLE_PAUSE: u64 = 15 * 60;
+
 // Update contract state
 pub async fn run_crons<Context, HttpClient, KeyManager>(ctx: Context)
 where
@@ -33,50 +85,266 @@ where
         + context::ArweaveAccess
         + context::BundlerAccess
         + context::ValidatorAddressAccess
+        + BundleStoreAccess
         + contract_gateway::ContractGatewayAccess
+        + EventBusAccess
+        + HeartbeatAccess
         + http::ClientAccess<HttpClient>
         + key_manager::KeyManagerAccess<KeyManager>
-        + queries::QueryContext,
+        + PeerScoresAccess
+        + queries::QueryContext
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + SlashVotesAccess,
     HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
     KeyManager: key_manager::KeyManager,
 {
     info!("Validator starting ...");
+    sync::sync_missing_receipts(&ctx).await;
+    // Tasks run both on an interval and when an epoch starts
+    let reconciling = Mutex::new(());
+    let proposing = Mutex::new(());
     join!(
         create_cron(
             &ctx,
             "check contract updates",
             contract::check_contract_updates,
-            30
+            |intervals| intervals.contract_updates
+        ),
+        create_cron(
+            &ctx,
+            "sync network info",
+            arweave::sync_network_info,
+            |intervals| intervals.network_info
         ),
-        create_cron(&ctx, "sync network info", arweave::sync_network_info, 30),
         // create_cron(&ctx, "validate bundler", validate::validate, 2 * 60),
         create_cron(
             &ctx,
             "validate transactions",
             validate::validate_transactions,
-            30
+            |intervals| intervals.validate_transactions
+        ),
+        create_cron(
+            &ctx,
+            "gossip receipts",
+            gossip::gossip_receipts,
+            |intervals| intervals.gossip_receipts
+        ),
+        create_cron(
+            &ctx,
+            "check bundler balance",
+            balance::check_bundler_balance,
+            |intervals| intervals.bundler_balance
+        ),
+        create_cron(
+            &ctx,
+            "propose validation rounds",
+            |ctx| exclusive(&proposing, rounds::propose_rounds(ctx)),
+            |intervals| intervals.validation_rounds
+        ),
+        create_cron(
+            &ctx,
+            "clean up bundles",
+            cleanup::cleanup_bundles,
+            |intervals| intervals.cleanup_bundles
+        ),
+        create_cron(
+            &ctx,
+            "reconcile epochs",
+            |ctx| exclusive(&reconciling, sync::reconcile_epochs(ctx)),
+            |intervals| intervals.reconcile_epochs
+        ),
+        create_cron(
+            &ctx,
+            "check bundle integrity",
+            integrity::check_bundle_integrity,
+            |intervals| intervals.integrity_check
+        ),
+        create_cron(
+            &ctx,
+            "refresh bundler config",
+            bundler_config::refresh_bundler_config,
+            |intervals| intervals.bundler_config
+        ),
+        // The previous epoch is complete once the next one starts
+        create_epoch_hook(&ctx, "reconcile finished epoch", |ctx| exclusive(
+            &reconciling,
+            sync::reconcile_epochs(ctx)
+        )),
+        create_epoch_hook(&ctx, "report finished epoch", sync::report_finished_epoch),
+        create_epoch_hook(&ctx, "prune database", prune::prune_database),
+        // A new leader starts its duties without waiting for the interval
+        create_epoch_hook(&ctx, "start validation rounds", |ctx| exclusive(
+            &proposing,
+            rounds::propose_rounds(ctx)
+        )),
+        create_event_hook(
+            &ctx,
+            "evaluate slash proposals",
+            |event| matches!(event, Event::SlashVoteReceived { .. }),
+            contract::evaluate_slash_proposals
         ),
     );
 }
 
+/// Run `f` once no other run holding `lock` is in progress, for tasks
+/// started both by a cron and a hook that mustn't overlap
+async fn exclusive<F>(lock: &Mutex<()>, f: F) -> Result<(), CronJobError>
+where
+    F: Future<Output = Result<(), CronJobError>>,
+{
+    let _running = lock.lock().await;
+    f.await
+}
+
+/// Run `f` every time the contract moves to a new epoch, for tasks tied to
+/// epoch boundaries rather than to an interval
+async fn create_epoch_hook<'a, Context, F>(
+    ctx: &'a Context,
+    description: &str,
+    f: impl Fn(&'a Context) -> F,
+) where
+    F: Future<Output = Result<(), CronJobError>> + 'a,
+    Context: EventBusAccess + MetricsAccess,
+{
+    create_event_hook(
+        ctx,
+        description,
+        |event| matches!(event, Event::EpochStarted { .. }),
+        f,
+    )
+    .await
+}
+
+/// Run `f` every time an event `trigger` matches is published. Events
+/// published while `f` runs trigger a single further run.
+async fn create_event_hook<'a, Context, F>(
+    ctx: &'a Context,
+    description: &str,
+    trigger: fn(&Event) -> bool,
+    f: impl Fn(&'a Context) -> F,
+) where
+    F: Future<Output = Result<(), CronJobError>> + 'a,
+    Context: EventBusAccess + MetricsAccess,
+{
+    let mut events = ctx.event_bus().subscribe();
+    let mut pending = false;
+    loop {
+        if !pending {
+            match events.recv().await {
+                Ok(event) if trigger(&event) => (),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        task = description,
+                        skipped, "Event hook fell behind, events were dropped"
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+
+        info!(task = description, "Event hook running");
+        let res = f(ctx).await;
+        ctx.metrics()
+            .cron_runs
+            .with_label_values(&[description, result_label(&res)])
+            .inc();
+        match res {
+            Ok(_) => info!(task = description, "Event hook finished"),
+            Err(e) => error!(
+                task = description,
+                "Event hook error: {}",
+                ValidatorError::from(e)
+            ),
+        }
+
+        // Events published during the run are covered by a single next run
+        pending = false;
+        loop {
+            match events.try_recv() {
+                Ok(event) => pending |= trigger(&event),
+                Err(TryRecvError::Lagged(_)) => pending = true,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Closed) => return,
+            }
+        }
+    }
+}
+
 async fn create_cron<'a, Context, HttpClient, F>(
     ctx: &'a Context,
     description: &str,
     f: impl Fn(&'a Context) -> F,
-    sleep: u64,
+    interval: fn(&CronIntervals) -> u64,
 ) where
     F: Future<Output = Result<(), CronJobError>> + 'a,
     HttpClient: http::Client,
-    Context: http::ClientAccess<HttpClient>,
+    Context: EventBusAccess
+        + HeartbeatAccess
+        + http::ClientAccess<HttpClient>
+        + MetricsAccess
+        + RuntimeConfigAccess,
 {
+    let mut consecutive_failures = 0;
     loop {
-        info!("Task running - {}", description);
-        match f(ctx).await {
-            Ok(_) => info!("Task finished - {}", description),
-            Err(e) => error!("Task error - {} with {}", description, e),
+        let sleep = interval(&ctx.cron_intervals());
+        ctx.heartbeats()
+            .beat(description, Duration::from_secs(sleep));
+        if !ctx.heartbeats().start_run(description) {
+            info!(task = description, "Task stopped");
+            return;
+        }
+        info!(task = description, "Task running");
+        let res = ctx
+            .heartbeats()
+            .beat_while(description, Duration::from_secs(sleep), f(ctx))
+            .await;
+        ctx.heartbeats().finish_run(description);
+        ctx.metrics()
+            .cron_runs
+            .with_label_values(&[description, result_label(&res)])
+            .inc();
+        let retry_after = res.as_ref().err().and_then(CronJobError::retry_after);
+        match res {
+            Ok(_) => {
+                consecutive_failures = 0;
+                ctx.heartbeats().record_result(description, None);
+                info!(task = description, "Task finished")
+            }
+            Err(e) => {
+                let err = ValidatorError::from(e);
+                consecutive_failures += 1;
+                ctx.heartbeats()
+                    .record_result(description, Some(err.to_string()));
+                error!(task = description, "Task error: {}", err);
+                if consecutive_failures == CIRCUIT_FAILURE_THRESHOLD {
+                    ctx.event_bus().publish(Event::CronCircuitOpened {
+                        task: description.to_string(),
+                        failures: consecutive_failures,
+                    });
+                }
+            }
         };
 
-        info!("Task sleeping for {} seconds - {}", sleep, description);
+        // Read interval on every iteration so config reloads are picked up
+        let mut sleep = interval(&ctx.cron_intervals());
+        if let Some(retry_after) = retry_after {
+            // Rounded up, so the gateway isn't contacted before it asked
+            let pause = (retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0))
+                .min(MAX_THROTTLE_PAUSE);
+            if pause > sleep {
+                warn!(
+                    task = description,
+                    pause, "Gateway asked to retry later, task paused"
+                );
+                sleep = pause;
+            }
+        }
+        ctx.heartbeats()
+            .beat(description, Duration::from_secs(sleep));
+        info!(task = description, "Task sleeping for {} seconds", sleep);
         tokio::time::sleep(Duration::from_secs(sleep)).await;
     }
 }
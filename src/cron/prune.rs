@@ -0,0 +1,41 @@
+use chrono::{Duration, Utc};
+use tracing::info;
+
+use crate::{
+    config::RuntimeConfigAccess,
+    database::{prune::prune, queries::QueryContext},
+};
+
+use super::{error::ValidatorCronError, CronJobError};
+
+/// Delete validated bundles older than the configured retention from the
+/// database, same as `validator db prune`, when `prune_on_epoch` is set.
+/// Run when an epoch starts, so the previous epochs' statistics are
+/// complete when their transactions are counted.
+pub async fn prune_database<Context>(ctx: &Context) -> Result<(), CronJobError>
+where
+    Context: QueryContext + RuntimeConfigAccess,
+{
+    let retention_days = match ctx.epoch_pruning() {
+        Some(days) => days,
+        None => return Ok(()),
+    };
+
+    let before = Utc::now() - Duration::days(retention_days as i64);
+    let conn = ctx.get_db_connection();
+    let report = tokio::task::spawn_blocking(move || prune(&conn, before, false))
+        .await
+        .map_err(ValidatorCronError::PruneTask)?
+        .map_err(ValidatorCronError::DatabaseError)?;
+    // Pruned transactions are deleted in bulk, without invalidating them
+    ctx.tx_cache().clear();
+    info!(
+        bundles = report.bundles,
+        transactions = report.transactions,
+        epochs = report.epochs,
+        "Bundles validated more than {} days ago pruned",
+        retention_days
+    );
+
+    Ok(())
+}
@@ -0,0 +1,425 @@
+//! Validation rounds proposed by the epoch leader.
+//!
+//! The leader picks bundles it validated in the current epoch that weren't
+//! attested yet, asks the other nominated validators to cosign each of them
+//! on `POST /cosign`, and submits the cosignatures to the contract once
+//! more than two thirds of the nominated validators, the leader included,
+//! signed.
+
+use std::collections::BTreeMap;
+
+use bundlr_contracts_validators::State as ContractState;
+use data_encoding::BASE64URL_NOPAD;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use super::{error::ValidatorCronError, CronJobError};
+use crate::{
+    config::RuntimeConfigAccess,
+    context::ValidatorAddressAccess,
+    contract_gateway::{AttestationSubmission, ContractGatewayAccess, ContractGatewayError},
+    database::{
+        models::{Epoch, NewAttestation, Transaction},
+        queries::{self, QueryContext},
+    },
+    http,
+    key_manager::{self, KeyManagerAccess},
+    peer_auth::sign_request,
+    peers::{PeerOutcome, PeerScoresAccess},
+    server::routes::cosign::{Attestation, AttestationRequest},
+    state::ValidatorStateAccess,
+};
+
+/// Bundles attested in a single run
+const ROUND_BUNDLES: i64 = 10;
+
+/// Signatures needed out of `nominated` validators
+fn quorum(nominated: usize) -> usize {
+    nominated * 2 / 3 + 1
+}
+
+/// Attestation requests for `txs`, one per bundle
+fn attestation_requests(epoch: u64, txs: Vec<Transaction>) -> Vec<AttestationRequest> {
+    let mut bundles: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for tx in txs {
+        if let Some(bundle_id) = tx.bundle_id {
+            bundles.entry(bundle_id).or_default().push(tx.id);
+        }
+    }
+
+    bundles
+        .into_iter()
+        .map(|(bundle, txs)| AttestationRequest { bundle, epoch, txs })
+        .collect()
+}
+
+/// Propose validation rounds for bundles validated in the current epoch,
+/// does nothing unless this validator leads the epoch. A bundle whose round
+/// fails is retried later, with a growing delay.
+pub async fn propose_rounds<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+) -> Result<(), CronJobError>
+where
+    Context: ContractGatewayAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + QueryContext
+        + RuntimeConfigAccess
+        + ValidatorAddressAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let state = ctx.get_validator_state();
+    if !state.role().proposes() {
+        return Ok(());
+    }
+
+    let epoch = state.current_epoch();
+    let txs =
+        queries::get_unattested_txs(ctx, epoch, ROUND_BUNDLES).map_err(ValidatorCronError::from)?;
+    if txs.is_empty() {
+        return Ok(());
+    }
+
+    let contract_state = ctx.contract_gateway().get_current_state(ctx).await?;
+    for request in attestation_requests(epoch as u64, txs) {
+        let attestations = collect_attestations(ctx, &contract_state, &request).await;
+        let needed = quorum(contract_state.nominated_validators.len());
+        if attestations.len() < needed {
+            warn!(
+                bundle = %request.bundle,
+                signatures = attestations.len(),
+                needed,
+                "Not enough cosignatures for bundle"
+            );
+            queries::record_round_failure(ctx, &request.bundle)
+                .map_err(ValidatorCronError::from)?;
+            continue;
+        }
+
+        let submission = AttestationSubmission {
+            request: &request,
+            attestations: &attestations,
+        };
+        match ctx
+            .contract_gateway()
+            .submit_attestations(ctx, ctx.get_key_manager(), &submission)
+            .await
+        {
+            Ok(()) => (),
+            Err(err @ ContractGatewayError::InteractionRejected(..)) => {
+                warn!(bundle = %request.bundle, "Attestations not accepted: {}", err);
+                queries::record_round_failure(ctx, &request.bundle)
+                    .map_err(ValidatorCronError::from)?;
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        let new_attestations: Vec<NewAttestation> = attestations
+            .iter()
+            .filter_map(|attestation| {
+                Some(NewAttestation {
+                    bundle_id: request.bundle.clone(),
+                    validator: attestation.validator.clone(),
+                    epoch: Epoch(epoch),
+                    signature: BASE64URL_NOPAD
+                        .decode(attestation.signature.as_bytes())
+                        .ok()?,
+                })
+            })
+            .collect();
+        queries::insert_attestations(ctx, &new_attestations).map_err(ValidatorCronError::from)?;
+        info!(
+            bundle = %request.bundle,
+            signatures = attestations.len(),
+            "Submitted attestations"
+        );
+    }
+
+    Ok(())
+}
+
+/// This validator's attestation of `request` followed by those of the
+/// other nominated validators that cosigned it
+async fn collect_attestations<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+    contract_state: &ContractState,
+    request: &AttestationRequest,
+) -> Vec<Attestation>
+where
+    Context: http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + ValidatorAddressAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let own_address = ctx.get_validator_address();
+    let mut attestations = Vec::new();
+    match request.signature_data(own_address) {
+        Ok(data) => attestations.push(Attestation {
+            validator: own_address.to_string(),
+            owner: BASE64URL_NOPAD.encode(&ctx.get_key_manager().validator_owner()),
+            signature: BASE64URL_NOPAD.encode(&ctx.get_key_manager().validator_sign(&data)),
+        }),
+        // Already logged, no peer can sign it either
+        Err(_) => return attestations,
+    }
+
+    let cosigners = contract_state
+        .nominated_validators
+        .iter()
+        .filter(|address| address.to_string() != own_address)
+        .filter_map(|address| contract_state.validators.get(address));
+    for validator in cosigners {
+        let address = validator.address.to_string();
+        let peer = match Url::parse(&validator.url.to_string()) {
+            Ok(peer) => peer,
+            Err(err) => {
+                warn!(validator = %address, "Invalid validator url: {}", err);
+                continue;
+            }
+        };
+
+        match request_attestation(ctx, &peer, request).await {
+            Ok(Some(attestation)) => {
+                ctx.peer_scores().record(&peer, PeerOutcome::Success);
+                if attestation.validator == address && attestation.verify(request) {
+                    attestations.push(attestation);
+                } else {
+                    warn!(peer = %peer, "Peer sent an invalid attestation");
+                }
+            }
+            Ok(None) => {
+                ctx.peer_scores().record(&peer, PeerOutcome::Success);
+                debug!(peer = %peer, bundle = %request.bundle, "Peer declined to cosign");
+            }
+            Err(err) => {
+                ctx.peer_scores().record(&peer, PeerOutcome::Unreachable);
+                warn!(peer = %peer, "Failed to request attestation - {}", err);
+            }
+        }
+    }
+
+    attestations
+}
+
+/// Ask `peer` to cosign `request`, `None` if it refused, e.g. because it
+/// didn't validate the bundle
+async fn request_attestation<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+    peer: &Url,
+    request: &AttestationRequest,
+) -> Result<Option<Attestation>, String>
+where
+    Context: http::ClientAccess<HttpClient> + KeyManagerAccess<KeyManager>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let url = peer.join("cosign").map_err(|err| err.to_string())?;
+    let mut req = reqwest::Client::new()
+        .post(url)
+        .json(request)
+        .build()
+        .map_err(|err| err.to_string())?;
+    sign_request(ctx.get_key_manager(), &mut req);
+
+    let res = ctx
+        .get_http_client()
+        .execute(req)
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+    if res.status().is_client_error() {
+        return Ok(None);
+    }
+    if !res.status().is_success() {
+        return Err(format!("unexpected status {}", res.status()));
+    }
+
+    res.json().await.map(Some).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use bundlr_contracts_validators::{Address, Epoch as ContractEpoch, State, Validator};
+    use data_encoding::BASE64URL_NOPAD;
+    use diesel::RunQueryDsl;
+    use http::Method;
+    use reqwest::{Request, Response};
+
+    use super::{propose_rounds, quorum};
+    use crate::{
+        context::{test_utils::test_context_with_http_client, BundlerAccess},
+        database::{
+            models::{Block, Epoch, NewBundle, NewTransaction},
+            queries::{get_unattested_txs, insert_bundle_with_txs, QueryContext},
+            test_utils::unique_id,
+        },
+        http::reqwest::mock::MockHttpClient,
+        key_manager::{test_utils::test_keys, KeyManager},
+        server::routes::cosign::{Attestation, AttestationRequest},
+        state::{ValidatorRole, ValidatorStateAccess},
+    };
+
+    /// Epoch no other test stores transactions in
+    const EPOCH: u128 = 1426;
+
+    #[test]
+    fn quorum_is_more_than_two_thirds() {
+        assert_eq!(quorum(1), 1);
+        assert_eq!(quorum(2), 2);
+        assert_eq!(quorum(3), 3);
+        assert_eq!(quorum(4), 3);
+        assert_eq!(quorum(10), 7);
+    }
+
+    #[actix_rt::test]
+    async fn leader_submits_cosigned_bundles() {
+        let (bundle_id, tx_id) = (unique_id(), unique_id());
+        let (peer_keys, _) = test_keys();
+        let peer_address: Address = peer_keys.validator_address().try_into().unwrap();
+        let (key_manager, _) = test_keys();
+        let own_address: Address = key_manager.validator_address().try_into().unwrap();
+
+        let validator = |address: &Address, url: &str| Validator {
+            address: address.clone(),
+            url: url.parse().unwrap(),
+            stake: 1.into(),
+        };
+        let contract_state = State {
+            bundler: "bundler_address".try_into().unwrap(),
+            bundlers_contract: "bundlers_contract_address".try_into().unwrap(),
+            epoch: ContractEpoch {
+                seq: 1,
+                tx: "tx1".try_into().unwrap(),
+                height: 1,
+            },
+            epoch_duration: 2,
+            minimum_stake: 1.into(),
+            token: "token_contract_address".try_into().unwrap(),
+            max_num_nominated_validators: 10,
+            validators: HashMap::from([
+                (
+                    own_address.clone(),
+                    validator(&own_address, "http://leader.example.com"),
+                ),
+                (
+                    peer_address.clone(),
+                    validator(&peer_address, "http://peer.example.com"),
+                ),
+            ]),
+            nominated_validators: vec![own_address, peer_address.clone()],
+            slash_proposal_lifetime: 10,
+            slash_proposals: HashMap::new(),
+        };
+        // The peer first answers with a signature made by another key
+        let honest = Arc::new(AtomicBool::new(false));
+        let peer_honest = honest.clone();
+        let (forger_keys, _) = test_keys();
+
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && &req.url().to_string() == "http://localhost:3000/validators/state"
+            })
+            .then(move |_: &Request| {
+                let body = serde_json::to_string(&contract_state).unwrap();
+                let response = http::response::Builder::new().status(200).body(body);
+                Response::from(response.unwrap())
+            })
+            .when(|req: &Request| {
+                req.method() == Method::POST
+                    && &req.url().to_string() == "http://peer.example.com/cosign"
+            })
+            .then(move |req: &Request| {
+                let request: AttestationRequest =
+                    serde_json::from_slice(req.body().unwrap().as_bytes().unwrap()).unwrap();
+                let validator = peer_keys.validator_address();
+                let data = request.signature_data(validator).unwrap();
+                let signer = if peer_honest.load(Ordering::SeqCst) {
+                    &peer_keys
+                } else {
+                    &forger_keys
+                };
+                let attestation = Attestation {
+                    validator: validator.to_string(),
+                    owner: BASE64URL_NOPAD.encode(&peer_keys.validator_owner()),
+                    signature: BASE64URL_NOPAD.encode(&signer.validator_sign(&data)),
+                };
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(serde_json::to_string(&attestation).unwrap());
+                Response::from(response.unwrap())
+            })
+            .when(|req: &Request| {
+                req.method() == Method::POST
+                    && &req.url().to_string() == "http://localhost:3000/validators/attest"
+            })
+            .then(|_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body("{\"status\": \"OK\"}");
+                Response::from(response.unwrap())
+            });
+
+        let ctx = test_context_with_http_client(key_manager, client);
+        insert_bundle_with_txs(
+            &ctx,
+            &NewBundle {
+                id: bundle_id.to_string(),
+                owner_address: ctx.bundler().address.clone(),
+                block_height: Block(10),
+                size: None,
+                item_count: None,
+                block_hash: None,
+                checksum: None,
+            },
+            &[NewTransaction {
+                id: tx_id.to_string(),
+                epoch: Epoch(EPOCH),
+                block_promised: Block(10),
+                block_actual: Some(Block(10)),
+                signature: Vec::new(),
+                validated: true,
+                bundle_id: Some(bundle_id.to_string()),
+                bundler_key: None,
+            }],
+        )
+        .unwrap();
+        ctx.get_validator_state().set_current_epoch(EPOCH);
+
+        // Cosigners don't propose
+        propose_rounds(&ctx).await.unwrap();
+        let pending = get_unattested_txs(&ctx, EPOCH, i64::MAX).unwrap();
+        assert!(pending.iter().any(|tx| tx.id == tx_id));
+
+        // Without the peer's valid signature there's no quorum, and the
+        // bundle waits before its next round
+        ctx.get_validator_state().set_role(ValidatorRole::Leader);
+        propose_rounds(&ctx).await.unwrap();
+        let pending = get_unattested_txs(&ctx, EPOCH, i64::MAX).unwrap();
+        assert!(!pending.iter().any(|tx| tx.id == tx_id));
+
+        diesel::sql_query("UPDATE round_attempts SET retry_at = NOW() WHERE bundle_id = $1")
+            .bind::<diesel::sql_types::Text, _>(bundle_id.as_str())
+            .execute(&ctx.get_db_connection())
+            .unwrap();
+        let pending = get_unattested_txs(&ctx, EPOCH, i64::MAX).unwrap();
+        assert!(pending.iter().any(|tx| tx.id == tx_id));
+
+        honest.store(true, Ordering::SeqCst);
+        propose_rounds(&ctx).await.unwrap();
+        let pending = get_unattested_txs(&ctx, EPOCH, i64::MAX).unwrap();
+        assert!(!pending.iter().any(|tx| tx.id == tx_id));
+    }
+}
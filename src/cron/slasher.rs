@@ -1,6 +1,58 @@
+use tracing::{error, info, warn};
+
 use crate::bundler::Bundler;
+use crate::contract_gateway::{ContractGatewayAccess, ContractGatewayError};
+use crate::database::queries;
+use crate::http;
+use crate::key_manager::{self, KeyManagerAccess};
+
+/// Equivocations a slash is proposed for in a single run
+const MAX_PROPOSALS_PER_RUN: i64 = 10;
 
 // TODO: implement slash voting
 pub fn vote_slash(_bundler: &Bundler) -> Result<(), ()> {
     Ok(())
 }
+
+/// Propose slashing the bundler for equivocations detected since the last
+/// run. Equivocations the contract rejects are not proposed again, the ones
+/// that failed to be submitted are retried on the next run.
+pub async fn propose_slashes<Context, HttpClient, KeyManager>(ctx: &Context)
+where
+    Context: ContractGatewayAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + queries::QueryContext,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let equivocations = match queries::get_unproposed_equivocations(ctx, MAX_PROPOSALS_PER_RUN) {
+        Ok(equivocations) => equivocations,
+        Err(err) => {
+            error!("Failed to read equivocations: {}", err);
+            return;
+        }
+    };
+
+    for equivocation in equivocations {
+        let res = ctx
+            .contract_gateway()
+            .propose_slash(ctx, ctx.get_key_manager(), &equivocation)
+            .await;
+        match res {
+            Ok(()) => info!(tx = %equivocation.tx_id, kind = %equivocation.kind, "Slash proposed"),
+            Err(ContractGatewayError::InteractionRejected(_, reason)) => {
+                warn!(tx = %equivocation.tx_id, "Slash proposal rejected with status {}", reason)
+            }
+            Err(err) => {
+                error!(tx = %equivocation.tx_id, "Failed to propose slash: {}", err);
+                continue;
+            }
+        }
+        if let Err(err) =
+            queries::set_equivocation_proposed(ctx, &equivocation.tx_id, &equivocation.kind)
+        {
+            error!(tx = %equivocation.tx_id, "Failed to mark equivocation proposed: {}", err);
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use std::{collections::HashSet, ops::RangeInclusive};
+
+use serde::Deserialize;
+use tracing::{error, info, warn};
+use url::Url;
+
+use crate::{
+    config::RuntimeConfigAccess,
+    context::ValidatorAddressAccess,
+    database::queries::{self, QueryContext},
+    epoch::{EpochSummary, EpochTx},
+    events::{Event, EventBusAccess},
+    http,
+    key_manager::{self, KeyManagerAccess},
+    peers::{peer_urls, PeerOutcome, PeerScoresAccess},
+    receipt::{store_receipts, RawTxReceipt},
+};
+
+use super::{error::ValidatorCronError, CronJobError};
+
+/// Most recent epochs fetched from a peer when catching up
+const SYNC_EPOCHS: u128 = 5;
+/// Receipts requested from a peer at once
+const SYNC_PAGE_SIZE: usize = 100;
+/// Transaction ids requested from a peer at once when reconciling epochs
+const EPOCH_TXS_PAGE_SIZE: usize = 1000;
+
+#[derive(Deserialize)]
+struct PeerInfo {
+    latest_epoch: Option<u64>,
+    latest_block: Option<u64>,
+}
+
+/// Fetch receipts of recent epochs from a peer that is ahead of this
+/// validator, so a validator that was offline doesn't only see new traffic
+pub async fn sync_missing_receipts<Context, HttpClient, KeyManager>(ctx: &Context)
+where
+    Context: QueryContext
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + RuntimeConfigAccess
+        + ValidatorAddressAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let ours = match queries::get_latest_validated(ctx) {
+        Ok(latest) => latest,
+        Err(err) => {
+            error!("Failed to read latest validated epoch: {}", err);
+            return;
+        }
+    };
+
+    let mut ahead = Vec::new();
+    for peer in peer_urls(ctx).await {
+        match peer_latest(ctx, &peer).await {
+            Ok(Some(latest)) if Some(latest) > ours => ahead.push((peer, latest)),
+            Ok(_) => (),
+            Err(err) => {
+                warn!(peer = %peer, "Failed to get peer info - {}", err);
+                ctx.peer_scores().record(&peer, PeerOutcome::Unreachable);
+            }
+        }
+    }
+    ahead.sort_by(|a, b| b.1.cmp(&a.1));
+
+    for (peer, (peer_epoch, _)) in ahead {
+        let first_epoch = ours
+            .map(|(epoch, _)| epoch)
+            .unwrap_or_default()
+            .max(peer_epoch.saturating_sub(SYNC_EPOCHS - 1));
+        info!(peer = %peer, from = first_epoch, to = peer_epoch, "Syncing missing receipts");
+        match sync_from_peer(ctx, &peer, first_epoch..=peer_epoch).await {
+            Ok(stored) => {
+                info!(peer = %peer, stored, "Synced missing receipts");
+                return;
+            }
+            Err(err) => {
+                warn!(peer = %peer, "Failed to sync receipts - {}", err);
+                ctx.peer_scores().record(&peer, PeerOutcome::Unreachable);
+            }
+        }
+    }
+}
+
+async fn peer_latest<Context, HttpClient>(
+    ctx: &Context,
+    peer: &Url,
+) -> Result<Option<(u128, u128)>, String>
+where
+    Context: http::ClientAccess<HttpClient>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let url = peer.join("info").map_err(|err| err.to_string())?;
+    let info: PeerInfo = get_json(ctx, url).await?;
+    Ok(info
+        .latest_epoch
+        .map(|epoch| (epoch.into(), info.latest_block.unwrap_or_default().into())))
+}
+
+/// Store receipts of `epochs` from `peer`, returns number of receipts
+/// that weren't known before
+async fn sync_from_peer<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+    peer: &Url,
+    epochs: RangeInclusive<u128>,
+) -> Result<usize, String>
+where
+    Context: QueryContext
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let mut stored = 0;
+    for epoch in epochs {
+        let mut after: Option<String> = None;
+        loop {
+            let mut url = peer.join("receipts").map_err(|err| err.to_string())?;
+            url.query_pairs_mut()
+                .append_pair("epoch", &epoch.to_string())
+                .append_pair("limit", &SYNC_PAGE_SIZE.to_string());
+            if let Some(after) = &after {
+                url.query_pairs_mut().append_pair("after", after);
+            }
+
+            let page: Vec<RawTxReceipt> = get_json(ctx, url).await?;
+            let page_len = page.len();
+            after = page.last().map(|raw| raw.receipt.tx_id.clone());
+
+            let (new, _) = store_receipts(ctx, ctx.get_key_manager(), epoch, page)
+                .map_err(|err| err.to_string())?;
+            stored += new.len();
+
+            if page_len < SYNC_PAGE_SIZE {
+                break;
+            }
+        }
+    }
+
+    Ok(stored)
+}
+
+/// Compare the summaries of the current and previous epoch with every peer.
+/// Receipts of transactions a peer has and this validator doesn't are
+/// fetched from it, transactions only the peer validated are logged.
+pub async fn reconcile_epochs<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+) -> Result<(), CronJobError>
+where
+    Context: QueryContext
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + RuntimeConfigAccess
+        + ValidatorAddressAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let current = ctx.current_epoch();
+    for epoch in current.saturating_sub(1)..=current {
+        let ours = queries::get_epoch_tx_ids(ctx, epoch)
+            .map_err(|err| CronJobError::ValidatorError(ValidatorCronError::DatabaseError(err)))?;
+        let summary = EpochSummary::new(epoch as u64, &ours);
+
+        for peer in peer_urls(ctx).await {
+            match reconcile_with_peer(ctx, &peer, &summary, &ours).await {
+                Ok(0) => (),
+                Ok(stored) => info!(peer = %peer, epoch, stored, "Repaired divergent epoch"),
+                Err(err) => {
+                    warn!(peer = %peer, epoch, "Failed to reconcile epoch - {}", err);
+                    ctx.peer_scores().record(&peer, PeerOutcome::Unreachable);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Report the epoch that just finished, logged and published as an
+/// `epoch_finished` event for webhooks and the event broker
+pub async fn report_finished_epoch<Context>(ctx: &Context) -> Result<(), CronJobError>
+where
+    Context: QueryContext + EventBusAccess,
+{
+    let epoch = match ctx.current_epoch().checked_sub(1) {
+        Some(epoch) => epoch,
+        None => return Ok(()),
+    };
+    let txs = queries::get_epoch_tx_ids(ctx, epoch)
+        .map_err(|err| CronJobError::ValidatorError(ValidatorCronError::DatabaseError(err)))?;
+    let summary = EpochSummary::new(epoch as u64, &txs);
+    info!(
+        epoch,
+        txs = summary.tx_count,
+        validated = summary.validated_count,
+        "Epoch finished"
+    );
+    ctx.event_bus().publish(Event::EpochFinished {
+        epoch: summary.epoch,
+        txs: summary.tx_count,
+        validated: summary.validated_count,
+        validated_root: summary.validated_root,
+    });
+
+    Ok(())
+}
+
+/// Compare `summary` of `ours` with the summary of `peer`, returns number of
+/// receipts fetched from `peer`
+async fn reconcile_with_peer<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+    peer: &Url,
+    summary: &EpochSummary,
+    ours: &[(String, bool)],
+) -> Result<usize, String>
+where
+    Context: QueryContext
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + KeyManagerAccess<KeyManager>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let url = peer
+        .join(&format!("epochs/{}/summary", summary.epoch))
+        .map_err(|err| err.to_string())?;
+    let theirs: EpochSummary = get_json(ctx, url).await?;
+    if theirs == *summary {
+        return Ok(0);
+    }
+
+    let known: HashSet<&str> = ours.iter().map(|(id, _)| id.as_str()).collect();
+    let validated: HashSet<&str> = ours
+        .iter()
+        .filter(|(_, validated)| *validated)
+        .map(|(id, _)| id.as_str())
+        .collect();
+    let (mut missing, mut unvalidated) = (0, 0);
+    let mut after: Option<String> = None;
+    loop {
+        let mut url = peer
+            .join(&format!("epochs/{}/txs", summary.epoch))
+            .map_err(|err| err.to_string())?;
+        url.query_pairs_mut()
+            .append_pair("limit", &EPOCH_TXS_PAGE_SIZE.to_string());
+        if let Some(after) = &after {
+            url.query_pairs_mut().append_pair("after", after);
+        }
+
+        let page: Vec<EpochTx> = get_json(ctx, url).await?;
+        let page_len = page.len();
+        after = page.last().map(|tx| tx.id.clone());
+        for tx in page {
+            if !known.contains(tx.id.as_str()) {
+                missing += 1;
+            } else if tx.validated && !validated.contains(tx.id.as_str()) {
+                unvalidated += 1;
+            }
+        }
+
+        if page_len < EPOCH_TXS_PAGE_SIZE {
+            break;
+        }
+    }
+
+    if unvalidated > 0 {
+        warn!(
+            peer = %peer,
+            epoch = summary.epoch,
+            "{} transactions validated by peer but not by this validator", unvalidated
+        );
+    }
+    if missing == 0 {
+        return Ok(0);
+    }
+    info!(peer = %peer, epoch = summary.epoch, missing, "Fetching missing receipts");
+    let epoch = summary.epoch as u128;
+    sync_from_peer(ctx, peer, epoch..=epoch).await
+}
+
+async fn get_json<Context, HttpClient, T>(ctx: &Context, url: Url) -> Result<T, String>
+where
+    Context: http::ClientAccess<HttpClient>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    T: serde::de::DeserializeOwned,
+{
+    let req = reqwest::Request::new(reqwest::Method::GET, url);
+    let res = ctx
+        .get_http_client()
+        .execute(req)
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+    if !res.status().is_success() {
+        return Err(format!("unexpected status {}", res.status()));
+    }
+    res.json().await.map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::Utc;
+    use http::Method;
+    use reqwest::{Request, Response};
+    use url::Url;
+
+    use super::{reconcile_epochs, sync_missing_receipts};
+    use crate::{
+        config::{RuntimeConfig, RuntimeConfigAccess},
+        context::test_utils::test_context_with_http_client,
+        database::{models::Epoch, queries::get_tx},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::{test_utils::test_keys, KeyManager},
+        receipt::test_utils::{signed_receipt, signed_sign_request},
+        state::ValidatorStateAccess,
+    };
+
+    const TX_ID: &str = "synced-receipt-0000000000000000000000000000";
+
+    #[actix_rt::test]
+    async fn receipts_are_fetched_from_peer_ahead() {
+        let (key_manager, bundle_pvk) = test_keys();
+        // Peers serve the sign requests the bundler sent them
+        let receipts = serde_json::to_string(&vec![signed_sign_request(
+            &bundle_pvk,
+            TX_ID,
+            10,
+            "peer-validator",
+        )])
+        .unwrap();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && &req.url().to_string() == "http://ahead.example.com/info"
+            })
+            .then(|_: &Request| {
+                let data = r#"{"latest_epoch": 4000000000, "latest_block": 10}"#;
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(data)
+                    .unwrap();
+                Response::from(response)
+            })
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && &req.url().to_string()
+                        == "http://ahead.example.com/receipts?epoch=4000000000&limit=100"
+            })
+            .then(move |_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body(receipts.clone())
+                    .unwrap();
+                Response::from(response)
+            })
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && req
+                        .url()
+                        .as_str()
+                        .starts_with("http://ahead.example.com/receipts?")
+            })
+            .then(|_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body("[]")
+                    .unwrap();
+                Response::from(response)
+            });
+
+        let ctx = test_context_with_http_client(key_manager, client);
+        ctx.replace_runtime_config(RuntimeConfig {
+            peers: vec![Url::from_str("http://ahead.example.com/").unwrap()],
+            ..RuntimeConfig::default()
+        });
+
+        sync_missing_receipts(&ctx).await;
+
+        assert_eq!(get_tx(&ctx, TX_ID).await.unwrap().block_promised.0, 10);
+    }
+
+    #[actix_rt::test]
+    async fn receipts_missing_from_diverged_epoch_are_fetched() {
+        let (key_manager, bundle_pvk) = test_keys();
+        let tx_id = key_manager.validator_address().to_string();
+        let epoch = 2_000_000_000 + Utc::now().timestamp_subsec_nanos() as u128;
+        let summary = format!(
+            r#"{{"epoch": {}, "tx_count": 1, "validated_count": 0, "validated_root": null}}"#,
+            epoch
+        );
+        let txs_url = format!(
+            "http://diverged.example.com/epochs/{}/txs?limit=1000",
+            epoch
+        );
+        let txs = format!(r#"[{{"id": "{}", "validated": false}}]"#, tx_id);
+        let receipts_url = format!(
+            "http://diverged.example.com/receipts?epoch={}&limit=100",
+            epoch
+        );
+        let receipts =
+            serde_json::to_string(&vec![signed_receipt(&bundle_pvk, &tx_id, 10)]).unwrap();
+        let ok = |body: String| {
+            Response::from(
+                http::response::Builder::new()
+                    .status(200)
+                    .body(body)
+                    .unwrap(),
+            )
+        };
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.url().path().ends_with("/summary"))
+            .then(move |_: &Request| ok(summary.clone()))
+            .when_matches(move |req: &Request| req.url().as_str() == txs_url)
+            .then(move |_: &Request| ok(txs.clone()))
+            .when_matches(move |req: &Request| req.url().as_str() == receipts_url)
+            .then(move |_: &Request| ok(receipts.clone()))
+            .when(|req: &Request| req.method() == Method::GET)
+            .then(move |_: &Request| ok("[]".to_string()));
+
+        let ctx = test_context_with_http_client(key_manager, client);
+        ctx.get_validator_state().set_current_epoch(epoch);
+        ctx.replace_runtime_config(RuntimeConfig {
+            peers: vec![Url::from_str("http://diverged.example.com/").unwrap()],
+            ..RuntimeConfig::default()
+        });
+
+        reconcile_epochs(&ctx).await.unwrap();
+
+        assert_eq!(get_tx(&ctx, &tx_id).await.unwrap().epoch, Epoch(epoch));
+    }
+}
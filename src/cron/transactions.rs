@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::bundler::Bundler;
+use crate::{bundler::Bundler, http};
 
 use super::error::TxsError;
 
@@ -53,11 +53,17 @@ pub struct ReqBody {
     pub variables: GqlVariables,
 }
 
-pub async fn get_transactions(
+/// Page of the transactions the bundler lists on its GraphQL endpoint,
+/// requested with `client` so the proxy and timeouts configured apply
+pub async fn get_transactions<HttpClient>(
+    client: &HttpClient,
     bundler: &Bundler,
     limit: Option<i64>,
     after: Option<String>,
-) -> Result<(Vec<BundleTransaction>, bool, Option<String>), TxsError> {
+) -> Result<(Vec<BundleTransaction>, bool, Option<String>), TxsError>
+where
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
     let raw_query = "query($limit: Int, $after: String) { transaction(limit: $limit, after: $after) { pageInfo { hasNextPage } edges { cursor node { data_item_id address current_block expected_block } } } }".to_string();
 
     let raw_variables = format!(
@@ -70,31 +76,26 @@ pub async fn get_transactions(
     );
 
     let url = format!("{}/graphql", bundler.url);
-    let client = reqwest::Client::new();
     let data = format!(
         "{{\"query\":\"{}\",\"variables\":{}}}",
         raw_query, raw_variables
     );
 
-    let body = serde_json::from_str::<ReqBody>(&data);
-    let res = client.post(&url).json(&body.unwrap()).send().await;
-
-    if res.is_ok() {
-        let res = res.unwrap().json::<GraphqlQueryResponse>().await;
-        if res.is_ok() {
-            let res = res.unwrap();
-            let mut txs = Vec::<BundleTransaction>::new();
-            let mut end_cursor: Option<String> = None;
-            for tx in &res.data.transaction.edges {
-                txs.push(tx.node.clone());
-                end_cursor = Some(tx.cursor.clone());
-            }
-            let has_next_page = res.data.transaction.page_info.has_next_page;
-            return Ok((txs, has_next_page, end_cursor));
-        } else {
-            return Err(TxsError::TxNotFound);
-        }
+    let body = serde_json::from_str::<ReqBody>(&data)?;
+    let req = reqwest::Client::new().post(&url).json(&body).build()?;
+    let res = client
+        .execute(req)
+        .await
+        .map_err(|err| TxsError::Unreachable(format!("{:?}", err)))?
+        .json::<GraphqlQueryResponse>()
+        .await?;
+
+    let mut txs = Vec::<BundleTransaction>::new();
+    let mut end_cursor: Option<String> = None;
+    for tx in &res.data.transaction.edges {
+        txs.push(tx.node.clone());
+        end_cursor = Some(tx.cursor.clone());
     }
-
-    Err(TxsError::TxNotFound)
+    let has_next_page = res.data.transaction.page_info.has_next_page;
+    Ok((txs, has_next_page, end_cursor))
 }
@@ -1,4 +1,9 @@
+use crate::bundle::store::BundleStoreAccess;
+use crate::config::RuntimeConfigAccess;
 use crate::database::queries;
+use crate::events::EventBusAccess;
+use crate::peers::PeerScoresAccess;
+use crate::receipt::ReceiptQueueAccess;
 use crate::state::ValidatorRole;
 use crate::{context, http, key_manager};
 
@@ -11,12 +16,19 @@ where
         + arweave::ArweaveContext<HttpClient>
         + context::ArweaveAccess
         + context::BundlerAccess
-        + key_manager::KeyManagerAccess<KeyManager>,
+        + BundleStoreAccess
+        + EventBusAccess
+        + http::ClientAccess<HttpClient>
+        + key_manager::KeyManagerAccess<KeyManager>
+        + PeerScoresAccess
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + context::ValidatorAddressAccess,
     HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
     KeyManager: key_manager::KeyManager,
 {
     match ctx.get_validator_state().role() {
-        ValidatorRole::Cosigner => validate_bundler(&*ctx)
+        ValidatorRole::Cosigner | ValidatorRole::Leader => validate_bundler(&*ctx)
             .await
             .map_err(CronJobError::ValidatorError)?,
         ValidatorRole::Idle => (),
@@ -25,11 +37,12 @@ where
     Ok(())
 }
 
-pub async fn validate_transactions<Context>(ctx: &Context) -> Result<(), CronJobError>
+pub async fn validate_transactions<Context, HttpClient>(ctx: &Context) -> Result<(), CronJobError>
 where
-    Context: context::BundlerAccess,
+    Context: context::BundlerAccess + http::ClientAccess<HttpClient>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
 {
-    super::bundle::validate_transactions(ctx.bundler())
+    super::bundle::validate_transactions(ctx.get_http_client(), ctx.bundler())
         .await
         .map_err(CronJobError::ValidatorError)?;
 
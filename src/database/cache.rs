@@ -0,0 +1,150 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use super::models::Transaction;
+
+pub const DEFAULT_TX_CACHE_CAPACITY: u64 = 10_000;
+
+/// Bounded in-memory cache of transactions keyed by transaction id.
+///
+/// Sits in front of `BundleQueries::get_cached_tx` so that bundles
+/// containing repeated or recently seen data items don't hit the database
+/// for every lookup. Entries are invalidated whenever the transaction is inserted or
+/// updated, and the whole cache is cleared when the database is pruned.
+/// Once full, the oldest entries are evicted first.
+#[derive(Clone)]
+pub struct TxCache {
+    entries: Arc<Mutex<Entries>>,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Transactions with the generation they were inserted at
+    txs: HashMap<String, (u64, Transaction)>,
+    /// Insertion order, oldest first. Entries replaced or invalidated since
+    /// are left behind and skipped by their generation.
+    order: VecDeque<(String, u64)>,
+    generation: u64,
+}
+
+impl TxCache {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(Entries::default())),
+            capacity: capacity as usize,
+        }
+    }
+
+    pub fn get(&self, tx_id: &str) -> Option<Transaction> {
+        self.entries
+            .lock()
+            .expect("Tx cache lock poisoned")
+            .txs
+            .get(tx_id)
+            .map(|(_, tx)| tx.clone())
+    }
+
+    pub fn insert(&self, tx: Transaction) {
+        let mut entries = self.entries.lock().expect("Tx cache lock poisoned");
+        entries.generation += 1;
+        let generation = entries.generation;
+        entries.order.push_back((tx.id.clone(), generation));
+        entries.txs.insert(tx.id.clone(), (generation, tx));
+        while entries.txs.len() > self.capacity || entries.order.len() > 2 * self.capacity {
+            let (id, generation) = match entries.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if matches!(entries.txs.get(&id), Some((current, _)) if *current == generation) {
+                entries.txs.remove(&id);
+            }
+        }
+    }
+
+    pub fn invalidate(&self, tx_id: &str) {
+        self.entries
+            .lock()
+            .expect("Tx cache lock poisoned")
+            .txs
+            .remove(tx_id);
+    }
+
+    pub fn clear(&self) {
+        *self.entries.lock().expect("Tx cache lock poisoned") = Entries::default();
+    }
+}
+
+impl Default for TxCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TX_CACHE_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use crate::database::models::{Block, Epoch, Transaction};
+
+    use super::TxCache;
+
+    fn test_tx(id: &str) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            epoch: Epoch(1),
+            block_promised: Block(10),
+            block_actual: None,
+            signature: "foo".as_bytes().to_vec(),
+            validated: false,
+            bundle_id: None,
+            bundler_key: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn cached_transaction_is_returned() {
+        let cache = TxCache::default();
+        cache.insert(test_tx("tx_id"));
+
+        assert_eq!(cache.get("tx_id"), Some(test_tx("tx_id")));
+    }
+
+    #[test]
+    fn invalidated_transaction_is_not_returned() {
+        let cache = TxCache::default();
+        cache.insert(test_tx("tx_id"));
+        cache.invalidate("tx_id");
+
+        assert_eq!(cache.get("tx_id"), None);
+    }
+
+    #[test]
+    fn cleared_cache_is_empty() {
+        let cache = TxCache::default();
+        cache.insert(test_tx("first"));
+        cache.insert(test_tx("second"));
+        cache.clear();
+
+        assert_eq!(cache.get("first"), None);
+        assert_eq!(cache.get("second"), None);
+    }
+
+    #[test]
+    fn oldest_transactions_are_evicted_once_full() {
+        let cache = TxCache::new(2);
+        cache.insert(test_tx("first"));
+        cache.insert(test_tx("second"));
+        // Replacing an entry makes it the newest
+        cache.insert(test_tx("first"));
+        cache.insert(test_tx("third"));
+
+        assert_eq!(cache.get("second"), None);
+        assert!(cache.get("first").is_some());
+        assert!(cache.get("third").is_some());
+    }
+}
@@ -0,0 +1,517 @@
+//! In-memory stand-in for the database, for unit tests of logic that only
+//! needs `BundleQueries`, such as the bundle cron.
+//!
+//! Conflicts are resolved like the Postgres queries do: bundles, nested and
+//! duplicate items, equivocations, invalid bundles and raw receipts already
+//! stored are kept, and stored transactions are updated with the validation
+//! result.
+
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use chrono::Utc;
+use diesel::result::Error;
+use url::Url;
+
+use super::{
+    models::{
+        Bundle, DuplicateItem, NestedItem, NewBundle, NewEquivocation, NewInvalidBundle,
+        NewRawReceipt, NewTransaction, NewValidator, RawReceipt, Transaction, Validator,
+    },
+    queries::BundleQueries,
+};
+use crate::{
+    bundle::store::{BundleStorage, BundleStoreAccess},
+    bundler::Bundler,
+    config::{RuntimeConfig, RuntimeConfigAccess, SharedRuntimeConfig},
+    context::{ArweaveAccess, BundlerAccess, ValidatorAddressAccess},
+    cron::{
+        arweave::{Arweave, ArweaveContext, DownloadQuota, DownloadThrottle},
+        indexer::Indexer,
+    },
+    events::{EventBus, EventBusAccess},
+    http::{self, reqwest::mock::MockHttpClient},
+    key_manager::{test_utils::test_keys, InMemoryKeyManager, KeyManager, KeyManagerAccess},
+    metrics::{Metrics, MetricsAccess},
+    peers::{PeerScores, PeerScoresAccess},
+    receipt::{ReceiptQueue, ReceiptQueueAccess},
+    state::{generate_state, SharedValidatorState, ValidatorStateAccess},
+};
+
+#[derive(Default)]
+struct Tables {
+    bundles: HashMap<String, Bundle>,
+    transactions: HashMap<String, Transaction>,
+    /// Keyed by transaction and bundle id
+    nested_items: HashMap<(String, String), NestedItem>,
+    /// Keyed by transaction and duplicate bundle id
+    duplicate_items: HashMap<(String, String), DuplicateItem>,
+    /// Keyed by transaction id and kind
+    equivocations: HashMap<(String, String), NewEquivocation>,
+    invalid_bundles: HashMap<String, NewInvalidBundle>,
+    /// Keyed by transaction id, source and payload hash
+    raw_receipts: HashMap<(String, String, Vec<u8>), RawReceipt>,
+    validators: HashMap<String, Validator>,
+}
+
+/// Bundles and transactions kept in hash maps, clones share the same data
+#[derive(Clone, Default)]
+pub struct MemoryDb {
+    tables: Arc<Mutex<Tables>>,
+}
+
+impl MemoryDb {
+    pub fn get_tx(&self, tx_id: &str) -> Result<Transaction, Error> {
+        let tables = self.tables.lock().unwrap();
+        tables
+            .transactions
+            .get(tx_id)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    /// Stores a transaction as the sign route does, before any bundle is
+    /// validated
+    pub fn insert_tx(&self, tx: &NewTransaction) {
+        let now = Utc::now();
+        let mut tables = self.tables.lock().unwrap();
+        tables.transactions.insert(
+            tx.id.clone(),
+            Transaction {
+                id: tx.id.clone(),
+                epoch: tx.epoch,
+                block_promised: tx.block_promised,
+                block_actual: tx.block_actual,
+                signature: tx.signature.clone(),
+                validated: tx.validated,
+                bundle_id: tx.bundle_id.clone(),
+                bundler_key: tx.bundler_key.clone(),
+                created_at: now,
+                updated_at: now,
+            },
+        );
+    }
+
+    pub fn get_bundle(&self, b_id: &str) -> Result<Bundle, Error> {
+        let tables = self.tables.lock().unwrap();
+        tables.bundles.get(b_id).cloned().ok_or(Error::NotFound)
+    }
+
+    pub fn get_nested_items(&self, b_id: &str) -> Vec<NestedItem> {
+        let tables = self.tables.lock().unwrap();
+        tables
+            .nested_items
+            .values()
+            .filter(|item| item.bundle_id == b_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get_duplicate_items(&self, t_id: &str) -> Vec<DuplicateItem> {
+        let tables = self.tables.lock().unwrap();
+        tables
+            .duplicate_items
+            .values()
+            .filter(|item| item.tx_id == t_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn insert_bundle_with_items(
+        &self,
+        new_bundle: &NewBundle,
+        txs: &[NewTransaction],
+        nested: &[NestedItem],
+    ) {
+        let now = Utc::now();
+        let mut tables = self.tables.lock().unwrap();
+        tables
+            .bundles
+            .entry(new_bundle.id.clone())
+            .or_insert_with(|| Bundle {
+                id: new_bundle.id.clone(),
+                owner_address: new_bundle.owner_address.clone(),
+                block_height: new_bundle.block_height,
+                created_at: now,
+                updated_at: now,
+                size: new_bundle.size,
+                item_count: new_bundle.item_count,
+                block_hash: new_bundle.block_hash.clone(),
+                checksum: new_bundle.checksum.clone(),
+            });
+
+        for tx in txs {
+            match tables.transactions.get_mut(&tx.id) {
+                Some(stored) => {
+                    stored.block_actual = tx.block_actual;
+                    stored.validated = tx.validated;
+                    stored.bundle_id = tx.bundle_id.clone();
+                    stored.updated_at = now;
+                }
+                None => {
+                    tables.transactions.insert(
+                        tx.id.clone(),
+                        Transaction {
+                            id: tx.id.clone(),
+                            epoch: tx.epoch,
+                            block_promised: tx.block_promised,
+                            block_actual: tx.block_actual,
+                            signature: tx.signature.clone(),
+                            validated: tx.validated,
+                            bundle_id: tx.bundle_id.clone(),
+                            bundler_key: tx.bundler_key.clone(),
+                            created_at: now,
+                            updated_at: now,
+                        },
+                    );
+                }
+            }
+        }
+
+        for item in nested {
+            tables
+                .nested_items
+                .entry((item.tx_id.clone(), item.bundle_id.clone()))
+                .or_insert_with(|| item.clone());
+        }
+    }
+
+    /// Returns `false` if the duplicate was recorded before
+    pub fn insert_duplicate_item(&self, item: &DuplicateItem) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+        let key = (item.tx_id.clone(), item.duplicate_bundle_id.clone());
+        if tables.duplicate_items.contains_key(&key) {
+            return false;
+        }
+        tables.duplicate_items.insert(key, item.clone());
+        true
+    }
+
+    pub fn get_equivocations(&self, t_id: &str) -> Vec<NewEquivocation> {
+        let tables = self.tables.lock().unwrap();
+        tables
+            .equivocations
+            .values()
+            .filter(|equivocation| equivocation.tx_id == t_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn insert_equivocation(&self, equivocation: &NewEquivocation) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+        let key = (equivocation.tx_id.clone(), equivocation.kind.clone());
+        if tables.equivocations.contains_key(&key) {
+            return false;
+        }
+        tables.equivocations.insert(key, equivocation.clone());
+        true
+    }
+
+    pub fn is_invalid_bundle(&self, b_id: &str) -> bool {
+        let tables = self.tables.lock().unwrap();
+        tables.invalid_bundles.contains_key(b_id)
+    }
+
+    pub fn insert_invalid_bundle(&self, invalid: &NewInvalidBundle) {
+        let mut tables = self.tables.lock().unwrap();
+        tables
+            .invalid_bundles
+            .entry(invalid.id.clone())
+            .or_insert_with(|| invalid.clone());
+    }
+
+    /// Receipts of `t_id`, oldest first
+    pub fn get_raw_receipts(&self, t_id: &str) -> Vec<RawReceipt> {
+        let tables = self.tables.lock().unwrap();
+        let mut receipts: Vec<RawReceipt> = tables
+            .raw_receipts
+            .values()
+            .filter(|receipt| receipt.tx_id == t_id)
+            .cloned()
+            .collect();
+        receipts.sort_by_key(|receipt| receipt.received_at);
+        receipts
+    }
+
+    pub fn insert_raw_receipts(&self, receipts: &[NewRawReceipt]) {
+        let now = Utc::now();
+        let mut tables = self.tables.lock().unwrap();
+        for receipt in receipts {
+            let key = (
+                receipt.tx_id.clone(),
+                receipt.source.clone(),
+                receipt.payload_hash.clone(),
+            );
+            tables
+                .raw_receipts
+                .entry(key)
+                .or_insert_with(|| RawReceipt {
+                    tx_id: receipt.tx_id.clone(),
+                    source: receipt.source.clone(),
+                    payload: receipt.payload.clone(),
+                    received_at: now,
+                    payload_hash: receipt.payload_hash.clone(),
+                });
+        }
+    }
+
+    pub fn get_validators(&self) -> Vec<Validator> {
+        let tables = self.tables.lock().unwrap();
+        tables.validators.values().cloned().collect()
+    }
+
+    /// Validators already known get the url of `validators`
+    pub fn upsert_validators(&self, validators: &[NewValidator]) {
+        let mut tables = self.tables.lock().unwrap();
+        for validator in validators {
+            tables.validators.insert(
+                validator.address.clone(),
+                Validator {
+                    address: validator.address.clone(),
+                    url: validator.url.clone(),
+                },
+            );
+        }
+    }
+}
+
+/// Context backed by a `MemoryDb`, providing what the bundle cron needs
+/// with test doubles for the other dependencies, like `TestContext` does
+pub struct MemoryContext {
+    pub db: MemoryDb,
+    pub bundler: Bundler,
+    pub event_bus: EventBus,
+    key_manager: Arc<InMemoryKeyManager>,
+    http_client: MockHttpClient,
+    arweave: Arweave,
+    validator_state: SharedValidatorState,
+    runtime_config: SharedRuntimeConfig,
+    metrics: Metrics,
+    bundle_store: BundleStorage,
+    receipt_queue: ReceiptQueue,
+    peer_scores: PeerScores,
+    download_throttle: DownloadThrottle,
+    download_quota: DownloadQuota,
+}
+
+impl MemoryContext {
+    /// Context of test keys, with bundler `bundler_address` and a client
+    /// answering no request
+    pub fn new(bundler_address: &str) -> Self {
+        let mut ctx = Self::with_http_client(test_keys().0, MockHttpClient::new(|_, _| false));
+        ctx.bundler.address = bundler_address.to_string();
+        ctx
+    }
+
+    pub fn with_http_client(key_manager: InMemoryKeyManager, http_client: MockHttpClient) -> Self {
+        Self {
+            db: MemoryDb::default(),
+            bundler: Bundler {
+                address: key_manager.bundler_address().to_owned(),
+                ..Bundler::default()
+            },
+            event_bus: EventBus::default(),
+            key_manager: Arc::new(key_manager),
+            http_client,
+            arweave: Arweave::new(Url::from_str("http://example.com").unwrap()),
+            validator_state: generate_state(),
+            runtime_config: Arc::new(RwLock::new(RuntimeConfig::default())),
+            metrics: Metrics::default(),
+            bundle_store: BundleStorage::default(),
+            receipt_queue: ReceiptQueue::default(),
+            peer_scores: PeerScores::default(),
+            download_throttle: DownloadThrottle::default(),
+            download_quota: DownloadQuota::default(),
+        }
+    }
+}
+
+impl BundleQueries for MemoryContext {
+    fn get_bundle(&self, b_id: &str) -> Result<Bundle, Error> {
+        self.db.get_bundle(b_id)
+    }
+
+    fn get_cached_tx(&self, tx_id: &str) -> Result<Transaction, Error> {
+        self.db.get_tx(tx_id)
+    }
+
+    fn get_raw_receipts(&self, t_id: &str) -> Result<Vec<RawReceipt>, Error> {
+        Ok(self.db.get_raw_receipts(t_id))
+    }
+
+    fn insert_raw_receipts(&self, receipts: &[NewRawReceipt]) -> Result<(), Error> {
+        self.db.insert_raw_receipts(receipts);
+        Ok(())
+    }
+
+    fn get_validators(&self) -> Result<Vec<Validator>, Error> {
+        Ok(self.db.get_validators())
+    }
+
+    fn get_nested_items(&self, b_id: &str) -> Result<Vec<NestedItem>, Error> {
+        Ok(self.db.get_nested_items(b_id))
+    }
+
+    fn insert_bundle_with_items(
+        &self,
+        new_bundle: &NewBundle,
+        txs: &[NewTransaction],
+        nested: &[NestedItem],
+    ) -> Result<(), Error> {
+        self.db.insert_bundle_with_items(new_bundle, txs, nested);
+        Ok(())
+    }
+
+    fn insert_duplicate_item(&self, item: &DuplicateItem) -> Result<bool, Error> {
+        Ok(self.db.insert_duplicate_item(item))
+    }
+
+    fn insert_equivocation(&self, equivocation: &NewEquivocation) -> Result<bool, Error> {
+        Ok(self.db.insert_equivocation(equivocation))
+    }
+
+    fn is_invalid_bundle(&self, b_id: &str) -> Result<bool, Error> {
+        Ok(self.db.is_invalid_bundle(b_id))
+    }
+
+    fn insert_invalid_bundle(&self, invalid: &NewInvalidBundle) -> Result<(), Error> {
+        self.db.insert_invalid_bundle(invalid);
+        Ok(())
+    }
+}
+
+impl BundlerAccess for MemoryContext {
+    fn bundler(&self) -> &Bundler {
+        &self.bundler
+    }
+}
+
+impl EventBusAccess for MemoryContext {
+    fn event_bus(&self) -> &EventBus {
+        &self.event_bus
+    }
+}
+
+impl ArweaveAccess for MemoryContext {
+    fn arweave(&self) -> &Arweave {
+        &self.arweave
+    }
+}
+
+impl ArweaveContext<MockHttpClient> for MemoryContext {
+    fn get_client(&self) -> &MockHttpClient {
+        &self.http_client
+    }
+
+    fn download_throttle(&self) -> Option<(&DownloadThrottle, u64)> {
+        self.max_download_rate()
+            .map(|rate| (&self.download_throttle, rate))
+    }
+
+    fn download_quota(&self) -> Option<&DownloadQuota> {
+        Some(&self.download_quota)
+    }
+
+    fn indexer(&self) -> Indexer {
+        self.graphql_indexer()
+    }
+}
+
+impl KeyManagerAccess<InMemoryKeyManager> for MemoryContext {
+    fn get_key_manager(&self) -> &InMemoryKeyManager {
+        self.key_manager.as_ref()
+    }
+}
+
+impl http::ClientAccess<MockHttpClient> for MemoryContext {
+    fn get_http_client(&self) -> &MockHttpClient {
+        &self.http_client
+    }
+}
+
+impl ValidatorStateAccess for MemoryContext {
+    fn get_validator_state(&self) -> &SharedValidatorState {
+        &self.validator_state
+    }
+}
+
+impl RuntimeConfigAccess for MemoryContext {
+    fn runtime_config(&self) -> &SharedRuntimeConfig {
+        &self.runtime_config
+    }
+}
+
+impl MetricsAccess for MemoryContext {
+    fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+impl BundleStoreAccess for MemoryContext {
+    fn bundle_store(&self) -> &BundleStorage {
+        &self.bundle_store
+    }
+}
+
+impl ReceiptQueueAccess for MemoryContext {
+    fn receipt_queue(&self) -> &ReceiptQueue {
+        &self.receipt_queue
+    }
+}
+
+impl PeerScoresAccess for MemoryContext {
+    fn peer_scores(&self) -> &PeerScores {
+        &self.peer_scores
+    }
+}
+
+impl ValidatorAddressAccess for MemoryContext {
+    fn get_validator_address(&self) -> &str {
+        self.key_manager.validator_address()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryDb;
+    use crate::database::models::{Block, Epoch, NewBundle, NewTransaction};
+
+    #[test]
+    fn stored_transactions_are_updated_with_validation_result() {
+        let db = MemoryDb::default();
+        let bundle = NewBundle {
+            id: "bundle".to_string(),
+            owner_address: "bundler".to_string(),
+            block_height: Block(10),
+            size: None,
+            item_count: None,
+            block_hash: None,
+            checksum: None,
+        };
+        let mut tx = NewTransaction {
+            id: "tx".to_string(),
+            epoch: Epoch(1),
+            block_promised: Block(10),
+            block_actual: None,
+            signature: Vec::new(),
+            validated: false,
+            bundle_id: None,
+            bundler_key: None,
+        };
+        db.insert_bundle_with_items(&bundle, &[tx.clone()], &[]);
+
+        tx.block_actual = Some(Block(9));
+        tx.validated = true;
+        tx.bundle_id = Some("bundle".to_string());
+        db.insert_bundle_with_items(&bundle, &[tx], &[]);
+
+        let stored = db.get_tx("tx").unwrap();
+        assert!(stored.validated);
+        assert_eq!(stored.block_actual, Some(Block(9)));
+        assert_eq!(stored.bundle_id.as_deref(), Some("bundle"));
+        assert!(db.get_bundle("bundle").is_ok());
+    }
+}
@@ -1,3 +1,31 @@
+use diesel::PgConnection;
+use diesel_migrations::RunMigrationsError;
+
+pub mod cache;
+#[cfg(test)]
+pub mod memory;
 pub mod models;
+pub mod prune;
 pub mod queries;
 pub mod schema;
+pub mod snapshot;
+
+embed_migrations!();
+
+/// Run pending database migrations embedded into the binary
+pub fn run_migrations(conn: &PgConnection) -> Result<(), RunMigrationsError> {
+    embedded_migrations::run_with_output(conn, &mut std::io::stdout())
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use data_encoding::BASE64URL_NOPAD;
+
+    /// Random 43 characters id, like transaction and bundle ids, so tests
+    /// pass again against the same database
+    pub fn unique_id() -> String {
+        let mut bytes = [0; 32];
+        openssl::rand::rand_bytes(&mut bytes).expect("Failed to generate random bytes");
+        BASE64URL_NOPAD.encode(&bytes)
+    }
+}
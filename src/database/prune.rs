@@ -0,0 +1,253 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel::sql_types::Timestamptz;
+use serde::Serialize;
+
+use crate::database::schema::{
+    bundle, duplicate_items, invalid_bundles, nested_items, raw_receipts, round_attempts,
+    transactions,
+};
+
+/// Add the validated transactions of bundles created before `$1` to the
+/// counts of their epoch. Transactions bundled after their promised block
+/// are invalid, slashed transactions are counted as validated or invalid
+/// too. Blocks are compared by value with `le_u128`.
+const COUNT_PRUNED_EPOCHS: &str = "
+    INSERT INTO pruned_epochs (epoch, validated, invalid, slashed)
+    SELECT t.epoch,
+        COUNT(*) FILTER (WHERE t.block_actual IS NULL
+            OR le_u128(t.block_actual) <= le_u128(t.block_promised)),
+        COUNT(*) FILTER (WHERE le_u128(t.block_actual) > le_u128(t.block_promised)),
+        COUNT(*) FILTER (WHERE EXISTS (
+            SELECT 1 FROM equivocations e WHERE e.tx_id = t.id AND e.proposed
+        ))
+    FROM transactions t
+    WHERE t.validated
+        AND t.bundle_id IN (SELECT id FROM bundle WHERE created_at < $1)
+    GROUP BY t.epoch
+    ON CONFLICT (epoch) DO UPDATE SET
+        validated = pruned_epochs.validated + excluded.validated,
+        invalid = pruned_epochs.invalid + excluded.invalid,
+        slashed = pruned_epochs.slashed + excluded.slashed,
+        updated_at = NOW()
+";
+
+/// Rows removed, or that would be removed on a dry run, by `prune`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PruneReport {
+    pub before: DateTime<Utc>,
+    pub dry_run: bool,
+    pub bundles: usize,
+    pub transactions: usize,
+    pub nested_items: usize,
+    pub duplicate_items: usize,
+    pub raw_receipts: usize,
+    pub invalid_bundles: usize,
+    /// Epochs whose counts in `pruned_epochs` were updated
+    pub epochs: usize,
+}
+
+/// Delete bundles validated before `before` together with their
+/// transactions, raw receipts, nested items, duplicate records and failed
+/// validation rounds. Bundles found invalid before `before` are forgotten
+/// too.
+///
+/// Transactions not validated yet are kept whatever their age. Deleted
+/// transactions are counted per epoch in `pruned_epochs` first, so
+/// statistics outlive the rows. On a dry run the deletes are rolled back,
+/// so the report shows exactly what would be removed.
+pub fn prune(
+    conn: &PgConnection,
+    before: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<PruneReport, Error> {
+    let mut report = PruneReport {
+        before,
+        dry_run,
+        bundles: 0,
+        transactions: 0,
+        nested_items: 0,
+        duplicate_items: 0,
+        raw_receipts: 0,
+        invalid_bundles: 0,
+        epochs: 0,
+    };
+    let old_bundles = || {
+        bundle::table
+            .select(bundle::id)
+            .filter(bundle::created_at.lt(before))
+    };
+    let old_bundle_refs = || {
+        bundle::table
+            .select(bundle::id.nullable())
+            .filter(bundle::created_at.lt(before))
+    };
+
+    let res = conn.transaction::<_, Error, _>(|| {
+        // Counted in SQL, old bundles may hold more transactions than fit
+        // in memory
+        report.epochs = diesel::sql_query(COUNT_PRUNED_EPOCHS)
+            .bind::<Timestamptz, _>(before)
+            .execute(conn)?;
+
+        report.nested_items = diesel::delete(
+            nested_items::table.filter(nested_items::bundle_id.eq_any(old_bundles())),
+        )
+        .execute(conn)?;
+        report.duplicate_items = diesel::delete(
+            duplicate_items::table.filter(
+                duplicate_items::bundle_id
+                    .eq_any(old_bundles())
+                    .or(duplicate_items::duplicate_bundle_id.eq_any(old_bundles())),
+            ),
+        )
+        .execute(conn)?;
+        report.raw_receipts = diesel::delete(
+            raw_receipts::table.filter(
+                raw_receipts::tx_id.eq_any(
+                    transactions::table
+                        .select(transactions::id)
+                        .filter(transactions::bundle_id.eq_any(old_bundle_refs()))
+                        .filter(transactions::validated.eq(true)),
+                ),
+            ),
+        )
+        .execute(conn)?;
+        report.transactions = diesel::delete(
+            transactions::table.filter(
+                transactions::bundle_id
+                    .eq_any(old_bundle_refs())
+                    .and(transactions::validated.eq(true)),
+            ),
+        )
+        .execute(conn)?;
+        report.invalid_bundles =
+            diesel::delete(invalid_bundles::table.filter(invalid_bundles::detected_at.lt(before)))
+                .execute(conn)?;
+        // Retries of rounds, only bookkeeping so not reported
+        diesel::delete(
+            round_attempts::table.filter(round_attempts::bundle_id.eq_any(old_bundles())),
+        )
+        .execute(conn)?;
+        // Bundles still referenced by a transaction can't be deleted
+        let referenced = transactions::table
+            .select(transactions::bundle_id)
+            .filter(transactions::bundle_id.eq_any(old_bundle_refs()))
+            .distinct()
+            .load::<Option<String>>(conn)?;
+        report.bundles =
+            diesel::delete(bundle::table.filter(bundle::created_at.lt(before)).filter(
+                diesel::dsl::not(
+                    bundle::id.eq_any(referenced.into_iter().flatten().collect::<Vec<_>>()),
+                ),
+            ))
+            .execute(conn)?;
+
+        if dry_run {
+            return Err(Error::RollbackTransaction);
+        }
+        Ok(())
+    });
+
+    match res {
+        Ok(()) | Err(Error::RollbackTransaction) => Ok(report),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use diesel::prelude::*;
+
+    use super::prune;
+    use crate::context::test_utils::test_context;
+    use crate::database::models::{Block, Epoch, NestedItem, NewBundle, NewTransaction};
+    use crate::database::queries::{
+        get_bundle, get_nested_items, insert_bundle_with_items, QueryContext,
+    };
+    use crate::database::schema::{bundle, pruned_epochs};
+    use crate::database::test_utils::unique_id;
+    use crate::key_manager::{test_utils::test_keys, KeyManager};
+
+    #[test]
+    fn old_bundles_are_pruned_unless_dry_run() {
+        let (tx_id, late_tx_id, bundle_id) = (unique_id(), unique_id(), unique_id());
+        let (key_manager, _bundle_pvk) = test_keys();
+        let nested_id = key_manager.validator_address().to_string();
+        let ctx = test_context(key_manager);
+        insert_bundle_with_items(
+            &ctx,
+            &NewBundle {
+                id: bundle_id.to_string(),
+                owner_address: bundle_id.to_string(),
+                block_height: Block(10),
+                size: None,
+                item_count: None,
+                block_hash: None,
+                checksum: None,
+            },
+            &[
+                NewTransaction {
+                    id: tx_id.to_string(),
+                    epoch: Epoch(1),
+                    block_promised: Block(10),
+                    block_actual: Some(Block(10)),
+                    signature: "c2lnbmF0dXJl".as_bytes().to_vec(),
+                    validated: true,
+                    bundle_id: Some(bundle_id.to_string()),
+                    bundler_key: None,
+                },
+                NewTransaction {
+                    id: late_tx_id.to_string(),
+                    epoch: Epoch(1),
+                    block_promised: Block(10),
+                    // Lower than the promised block bytewise, not by value
+                    block_actual: Some(Block(256)),
+                    signature: "c2lnbmF0dXJl".as_bytes().to_vec(),
+                    validated: true,
+                    bundle_id: Some(bundle_id.to_string()),
+                    bundler_key: None,
+                },
+            ],
+            &[NestedItem {
+                tx_id: nested_id,
+                bundle_id: bundle_id.to_string(),
+                parent_id: tx_id.to_string(),
+            }],
+        )
+        .unwrap();
+        let conn = ctx.get_db_connection();
+        diesel::update(bundle::table.filter(bundle::id.eq(&bundle_id)))
+            .set(bundle::created_at.eq(Utc::now() - Duration::days(2)))
+            .execute(&conn)
+            .unwrap();
+        let before = Utc::now() - Duration::days(1);
+
+        let pruned_counts = || {
+            pruned_epochs::table
+                .select((pruned_epochs::validated, pruned_epochs::invalid))
+                .filter(pruned_epochs::epoch.eq(Epoch(1)))
+                .first::<(i64, i64)>(&conn)
+                .optional()
+                .unwrap()
+                .unwrap_or((0, 0))
+        };
+        let counts = pruned_counts();
+
+        let report = prune(&conn, before, true).unwrap();
+        assert!(report.bundles >= 1 && report.transactions >= 1 && report.nested_items >= 1);
+        assert!(get_bundle(&ctx, &bundle_id).is_ok());
+        assert_eq!(pruned_counts(), counts);
+
+        let report = prune(&conn, before, false).unwrap();
+        assert!(report.bundles >= 1 && report.transactions >= 1 && report.nested_items >= 1);
+        assert!(get_bundle(&ctx, &bundle_id).is_err());
+        assert!(get_nested_items(&ctx, &bundle_id).unwrap().is_empty());
+        assert!(report.epochs >= 1);
+        let (validated, invalid) = pruned_counts();
+        assert!(validated > counts.0);
+        assert!(invalid > counts.1);
+    }
+}
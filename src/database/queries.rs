@@ -1,62 +1,614 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use diesel::dsl::{now, sql};
+use diesel::pg::upsert::excluded;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::result::Error;
+use diesel::sql_types::{Nullable, Text};
 use diesel::QueryDsl;
+use prometheus::HistogramTimer;
 extern crate diesel;
-use crate::database::models::{Bundle, NewBundle, NewTransaction, Transaction};
+use crate::database::cache::TxCache;
+use crate::database::models::{
+    Bundle, BundleRef, DuplicateItem, Epoch, Equivocation, NestedItem, NewAttestation, NewBundle,
+    NewEquivocation, NewInvalidBundle, NewRawReceipt, NewTransaction, NewValidator, RawReceipt,
+    Stake, TableRows, Transaction, Validator,
+};
 use crate::database::schema::bundle::dsl::*;
 use crate::database::schema::transactions::dsl::*;
-use crate::database::schema::{bundle, transactions};
+use crate::database::schema::{
+    attestations, bundle, duplicate_items, equivocations, invalid_bundles, nested_items,
+    raw_receipts, round_attempts, transactions, validator_stake, validators,
+};
+use crate::metrics::MetricsAccess;
 use crate::state::ValidatorStateAccess;
 
-pub trait QueryContext: ValidatorStateAccess {
+pub trait QueryContext: ValidatorStateAccess + MetricsAccess {
     fn get_db_connection(&self) -> PooledConnection<ConnectionManager<PgConnection>>;
     fn current_epoch(&self) -> u128;
+    fn tx_cache(&self) -> &TxCache;
+}
+
+/// Queries bundle validation depends on. Implemented for every
+/// `QueryContext`, and by `memory::MemoryContext` so the bundle cron can be
+/// tested without a database.
+pub trait BundleQueries: MetricsAccess + ValidatorStateAccess {
+    fn get_bundle(&self, b_id: &str) -> Result<Bundle, Error>;
+    /// Transaction through the process' transaction cache, see `TxCache`
+    fn get_cached_tx(&self, tx_id: &str) -> Result<Transaction, Error>;
+    fn get_raw_receipts(&self, t_id: &str) -> Result<Vec<RawReceipt>, Error>;
+    fn insert_raw_receipts(&self, receipts: &[NewRawReceipt]) -> Result<(), Error>;
+    fn get_validators(&self) -> Result<Vec<Validator>, Error>;
+    fn get_nested_items(&self, b_id: &str) -> Result<Vec<NestedItem>, Error>;
+    fn insert_bundle_with_items(
+        &self,
+        new_bundle: &NewBundle,
+        txs: &[NewTransaction],
+        nested: &[NestedItem],
+    ) -> Result<(), Error>;
+    fn insert_duplicate_item(&self, item: &DuplicateItem) -> Result<bool, Error>;
+    fn insert_equivocation(&self, equivocation: &NewEquivocation) -> Result<bool, Error>;
+    fn is_invalid_bundle(&self, b_id: &str) -> Result<bool, Error>;
+    fn insert_invalid_bundle(&self, invalid: &NewInvalidBundle) -> Result<(), Error>;
+}
+
+impl<Context> BundleQueries for Context
+where
+    Context: QueryContext,
+{
+    fn get_bundle(&self, b_id: &str) -> Result<Bundle, Error> {
+        get_bundle(self, b_id)
+    }
+
+    fn get_cached_tx(&self, tx_id: &str) -> Result<Transaction, Error> {
+        // Bundle validation looks up the same transactions over and over;
+        // the cache doesn't see writes of other processes
+        if let Some(tx) = self.tx_cache().get(tx_id) {
+            return Ok(tx);
+        }
+
+        let tx = load_tx(self, tx_id)?;
+        self.tx_cache().insert(tx.clone());
+
+        Ok(tx)
+    }
+
+    fn get_raw_receipts(&self, t_id: &str) -> Result<Vec<RawReceipt>, Error> {
+        get_raw_receipts(self, t_id)
+    }
+
+    fn insert_raw_receipts(&self, receipts: &[NewRawReceipt]) -> Result<(), Error> {
+        insert_raw_receipts(self, receipts)
+    }
+
+    fn get_validators(&self) -> Result<Vec<Validator>, Error> {
+        get_validators(self)
+    }
+
+    fn get_nested_items(&self, b_id: &str) -> Result<Vec<NestedItem>, Error> {
+        get_nested_items(self, b_id)
+    }
+
+    fn insert_bundle_with_items(
+        &self,
+        new_bundle: &NewBundle,
+        txs: &[NewTransaction],
+        nested: &[NestedItem],
+    ) -> Result<(), Error> {
+        insert_bundle_with_items(self, new_bundle, txs, nested)
+    }
+
+    fn insert_duplicate_item(&self, item: &DuplicateItem) -> Result<bool, Error> {
+        insert_duplicate_item(self, item)
+    }
+
+    fn insert_equivocation(&self, equivocation: &NewEquivocation) -> Result<bool, Error> {
+        insert_equivocation(self, equivocation)
+    }
+
+    fn is_invalid_bundle(&self, b_id: &str) -> Result<bool, Error> {
+        is_invalid_bundle(self, b_id)
+    }
+
+    fn insert_invalid_bundle(&self, invalid: &NewInvalidBundle) -> Result<(), Error> {
+        insert_invalid_bundle(self, invalid)
+    }
+}
+
+fn query_timer<Context>(ctx: &Context, query: &str) -> HistogramTimer
+where
+    Context: QueryContext,
+{
+    ctx.metrics()
+        .db_queries
+        .with_label_values(&[query])
+        .start_timer()
 }
 
 pub fn get_bundle<Context>(ctx: &Context, b_id: &str) -> Result<Bundle, Error>
 where
     Context: QueryContext,
 {
+    let _timer = query_timer(ctx, "get_bundle");
     let conn = ctx.get_db_connection();
     bundle.filter(bundle::id.eq(b_id)).first::<Bundle>(&conn)
 }
 
-pub fn insert_bundle_in_db<Context>(ctx: &Context, new_bundle: NewBundle) -> std::io::Result<()>
+/// Checksums recorded when validating the bundles of `ids`, bundles unknown
+/// or validated before checksums were recorded are left out
+pub fn get_bundle_checksums<Context>(
+    ctx: &Context,
+    ids: &[String],
+) -> Result<HashMap<String, String>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_bundle_checksums");
+    let conn = ctx.get_db_connection();
+    Ok(bundle
+        .filter(bundle::id.eq_any(ids))
+        .select((bundle::id, bundle::checksum))
+        .load::<(String, Option<String>)>(&conn)?
+        .into_iter()
+        .filter_map(|(b_id, sum)| Some((b_id, sum?)))
+        .collect())
+}
+
+/// Whether the bundle failed verification in an earlier run
+pub fn is_invalid_bundle<Context>(ctx: &Context, b_id: &str) -> Result<bool, Error>
 where
     Context: QueryContext,
 {
+    let _timer = query_timer(ctx, "is_invalid_bundle");
+    let conn = ctx.get_db_connection();
+    diesel::select(diesel::dsl::exists(
+        invalid_bundles::table.filter(invalid_bundles::id.eq(b_id)),
+    ))
+    .get_result(&conn)
+}
+
+/// Record a bundle failing verification, the first reason is kept
+pub fn insert_invalid_bundle<Context>(
+    ctx: &Context,
+    invalid: &NewInvalidBundle,
+) -> Result<(), Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "insert_invalid_bundle");
+    let conn = ctx.get_db_connection();
+    diesel::insert_into(invalid_bundles::table)
+        .values(invalid)
+        .on_conflict_do_nothing()
+        .execute(&conn)?;
+
+    Ok(())
+}
+
+pub fn insert_bundle_in_db<Context>(ctx: &Context, new_bundle: NewBundle) -> Result<(), Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "insert_bundle_in_db");
     let conn = ctx.get_db_connection();
     diesel::insert_into(bundle::table)
         .values(&new_bundle)
-        .execute(&conn)
-        .unwrap_or_else(|err| panic!("Error inserting new bundle {}: {:?}", &new_bundle.id, &err));
+        .execute(&conn)?;
 
     Ok(())
 }
 
-pub fn insert_tx_in_db<Context>(ctx: &Context, new_tx: &NewTransaction) -> std::io::Result<()>
+pub fn insert_tx_in_db<Context>(ctx: &Context, new_tx: &NewTransaction) -> Result<(), Error>
 where
     Context: QueryContext,
 {
+    let _timer = query_timer(ctx, "insert_tx_in_db");
     let conn = ctx.get_db_connection();
     diesel::insert_into(transactions::table)
         .values(new_tx)
-        .execute(&conn)
-        .unwrap_or_else(|_| panic!("Error inserting new tx {}", &new_tx.id));
+        .execute(&conn)?;
+    ctx.tx_cache().invalidate(&new_tx.id);
+
+    Ok(())
+}
+
+/// Store bundle and its validated transactions in a single database
+/// transaction, either all rows are written or none.
+///
+/// Transactions already known are updated with the validation result.
+pub fn insert_bundle_with_txs<Context>(
+    ctx: &Context,
+    new_bundle: &NewBundle,
+    txs: &[NewTransaction],
+) -> Result<(), Error>
+where
+    Context: QueryContext,
+{
+    insert_bundle_with_items(ctx, new_bundle, txs, &[])
+}
+
+/// Like `insert_bundle_with_txs`, also storing items of bundles nested in
+/// the bundle's data items
+pub fn insert_bundle_with_items<Context>(
+    ctx: &Context,
+    new_bundle: &NewBundle,
+    txs: &[NewTransaction],
+    nested: &[NestedItem],
+) -> Result<(), Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "insert_bundle_with_txs");
+    let conn = ctx.get_db_connection();
+    conn.transaction::<_, Error, _>(|| {
+        diesel::insert_into(bundle::table)
+            .values(new_bundle)
+            .on_conflict_do_nothing()
+            .execute(&conn)?;
+
+        if !txs.is_empty() {
+            diesel::insert_into(transactions::table)
+                .values(txs)
+                .on_conflict(transactions::id)
+                .do_update()
+                .set((
+                    transactions::block_actual.eq(excluded(transactions::block_actual)),
+                    transactions::validated.eq(excluded(transactions::validated)),
+                    transactions::bundle_id.eq(excluded(transactions::bundle_id)),
+                    transactions::updated_at.eq(now),
+                ))
+                .execute(&conn)?;
+        }
+
+        if !nested.is_empty() {
+            diesel::insert_into(nested_items::table)
+                .values(nested)
+                .on_conflict_do_nothing()
+                .execute(&conn)?;
+        }
+
+        Ok(())
+    })?;
+
+    for tx in txs {
+        ctx.tx_cache().invalidate(&tx.id);
+    }
+
+    Ok(())
+}
+
+/// Insert transactions not stored yet, returns ids of the inserted ones
+pub fn insert_new_txs<Context>(ctx: &Context, txs: &[NewTransaction]) -> Result<Vec<String>, Error>
+where
+    Context: QueryContext,
+{
+    if txs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let _timer = query_timer(ctx, "insert_new_txs");
+    let conn = ctx.get_db_connection();
+    let inserted = diesel::insert_into(transactions::table)
+        .values(txs)
+        .on_conflict_do_nothing()
+        .returning(transactions::id)
+        .get_results::<String>(&conn)?;
+    for tx_id in &inserted {
+        ctx.tx_cache().invalidate(tx_id);
+    }
+
+    Ok(inserted)
+}
+
+/// Record a data item seen in more than one bundle, returns `false` if the
+/// duplicate was recorded before
+pub fn insert_duplicate_item<Context>(ctx: &Context, item: &DuplicateItem) -> Result<bool, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "insert_duplicate_item");
+    let conn = ctx.get_db_connection();
+    let inserted = diesel::insert_into(duplicate_items::table)
+        .values(item)
+        .on_conflict_do_nothing()
+        .execute(&conn)?;
+
+    Ok(inserted > 0)
+}
+
+/// Record an equivocation of the bundler, returns `false` if one of the same
+/// kind was recorded for the transaction before
+pub fn insert_equivocation<Context>(
+    ctx: &Context,
+    equivocation: &NewEquivocation,
+) -> Result<bool, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "insert_equivocation");
+    let conn = ctx.get_db_connection();
+    let inserted = diesel::insert_into(equivocations::table)
+        .values(equivocation)
+        .on_conflict_do_nothing()
+        .execute(&conn)?;
+
+    Ok(inserted > 0)
+}
+
+/// Oldest equivocations no slash was proposed for yet
+pub fn get_unproposed_equivocations<Context>(
+    ctx: &Context,
+    limit: i64,
+) -> Result<Vec<Equivocation>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_unproposed_equivocations");
+    let conn = ctx.get_db_connection();
+    equivocations::table
+        .filter(equivocations::proposed.eq(false))
+        .order(equivocations::detected_at)
+        .limit(limit)
+        .load::<Equivocation>(&conn)
+}
+
+pub fn set_equivocation_proposed<Context>(
+    ctx: &Context,
+    t_id: &str,
+    kind: &str,
+) -> Result<(), Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "set_equivocation_proposed");
+    let conn = ctx.get_db_connection();
+    diesel::update(equivocations::table.find((t_id, kind)))
+        .set(equivocations::proposed.eq(true))
+        .execute(&conn)?;
 
     Ok(())
 }
 
-pub async fn update_tx<Context>(ctx: &Context, tx: &NewTransaction) -> std::io::Result<()>
+/// Items of bundles nested in data items of `b_id`
+pub fn get_nested_items<Context>(ctx: &Context, b_id: &str) -> Result<Vec<NestedItem>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_nested_items");
+    let conn = ctx.get_db_connection();
+    nested_items::table
+        .filter(nested_items::bundle_id.eq(b_id))
+        .load::<NestedItem>(&conn)
+}
+
+/// Store receipts as received. Payloads already stored for a transaction
+/// and source are kept, other payloads are stored next to them.
+pub fn insert_raw_receipts<Context>(ctx: &Context, receipts: &[NewRawReceipt]) -> Result<(), Error>
 where
     Context: QueryContext,
 {
+    let _timer = query_timer(ctx, "insert_raw_receipts");
+    let conn = ctx.get_db_connection();
+    diesel::insert_into(raw_receipts::table)
+        .values(receipts)
+        .on_conflict_do_nothing()
+        .execute(&conn)?;
+
+    Ok(())
+}
+
+/// Receipts of `t_id` as received from each source
+pub fn get_raw_receipts<Context>(ctx: &Context, t_id: &str) -> Result<Vec<RawReceipt>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_raw_receipts");
+    let conn = ctx.get_db_connection();
+    raw_receipts::table
+        .filter(raw_receipts::tx_id.eq(t_id))
+        .order(raw_receipts::received_at)
+        .load::<RawReceipt>(&conn)
+}
+
+/// Fails unless the database answers queries. Takes a connection rather
+/// than a context so it can run on the blocking thread pool.
+pub fn check_connection(conn: &PgConnection) -> Result<(), Error> {
+    diesel::sql_query("SELECT 1").execute(conn)?;
+
+    Ok(())
+}
+
+/// Estimated rows of each table, as counted by Postgres statistics rather
+/// than scanning the tables
+pub fn get_table_rows<Context>(ctx: &Context) -> Result<Vec<TableRows>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_table_rows");
+    let conn = ctx.get_db_connection();
+    diesel::sql_query(
+        "SELECT relname::TEXT AS table_name, n_live_tup AS row_count FROM pg_stat_user_tables",
+    )
+    .load::<TableRows>(&conn)
+}
+
+/// Fails unless the database accepts writes, e.g. on a read-only replica.
+/// The table written to is rolled back.
+pub fn check_writable(conn: &PgConnection) -> Result<(), Error> {
+    let res = conn.transaction::<(), Error, _>(|| {
+        diesel::sql_query("CREATE TEMPORARY TABLE readiness_check (id INT)").execute(conn)?;
+        Err(Error::RollbackTransaction)
+    });
+
+    match res {
+        Ok(()) | Err(Error::RollbackTransaction) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Validated transactions of up to `limit` bundles of `tx_epoch` no
+/// attestations were stored for yet, ordered by bundle and id. The bundles
+/// stored first are picked first, bundles whose last round failed are left
+/// out until their retry time.
+pub fn get_unattested_txs<Context>(
+    ctx: &Context,
+    tx_epoch: u128,
+    limit: i64,
+) -> Result<Vec<Transaction>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_unattested_txs");
+    let conn = ctx.get_db_connection();
+    let bundle_ids: Vec<String> = diesel::sql_query(
+        "SELECT bundle_id FROM transactions \
+         WHERE epoch = $1 AND validated AND bundle_id IS NOT NULL \
+         AND bundle_id NOT IN (SELECT bundle_id FROM attestations) \
+         AND bundle_id NOT IN (SELECT bundle_id FROM round_attempts WHERE retry_at > NOW()) \
+         GROUP BY bundle_id ORDER BY MIN(created_at), bundle_id LIMIT $2",
+    )
+    .bind::<diesel::sql_types::Binary, _>(Epoch(tx_epoch))
+    .bind::<diesel::sql_types::BigInt, _>(limit)
+    .load::<BundleRef>(&conn)?
+    .into_iter()
+    .map(|bundle_ref| bundle_ref.bundle_id)
+    .collect();
+
+    transactions
+        .filter(transactions::bundle_id.eq_any(bundle_ids))
+        .filter(transactions::epoch.eq(Epoch(tx_epoch)))
+        .filter(transactions::validated.eq(true))
+        .order_by((transactions::bundle_id, transactions::id))
+        .load::<Transaction>(&conn)
+}
+
+/// Postpone the next round for `b_id` after a failed one, by a minute after
+/// the first failure and twice as long after each following one, up to a day
+pub fn record_round_failure<Context>(ctx: &Context, b_id: &str) -> Result<(), Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "record_round_failure");
+    let conn = ctx.get_db_connection();
+    diesel::sql_query(
+        "INSERT INTO round_attempts (bundle_id, attempts, retry_at) \
+         VALUES ($1, 1, NOW() + INTERVAL '1 minute') \
+         ON CONFLICT (bundle_id) DO UPDATE SET \
+         attempts = round_attempts.attempts + 1, \
+         retry_at = NOW() + LEAST(POWER(2, round_attempts.attempts), 1440) * INTERVAL '1 minute'",
+    )
+    .bind::<diesel::sql_types::Text, _>(b_id)
+    .execute(&conn)?;
+
+    Ok(())
+}
+
+/// Store the cosignatures of a submitted round, signatures already stored
+/// for a bundle and validator are kept. Failed rounds of the bundles are
+/// forgotten.
+pub fn insert_attestations<Context>(
+    ctx: &Context,
+    new_attestations: &[NewAttestation],
+) -> Result<(), Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "insert_attestations");
+    let conn = ctx.get_db_connection();
+    conn.transaction(|| {
+        diesel::insert_into(attestations::table)
+            .values(new_attestations)
+            .on_conflict_do_nothing()
+            .execute(&conn)?;
+        diesel::delete(
+            round_attempts::table.filter(
+                round_attempts::bundle_id.eq_any(
+                    new_attestations
+                        .iter()
+                        .map(|attestation| attestation.bundle_id.as_str()),
+                ),
+            ),
+        )
+        .execute(&conn)?;
+
+        Ok(())
+    })
+}
+
+/// Store the stake of a validator, replacing the previously stored one
+pub fn upsert_stake<Context>(ctx: &Context, new_stake: &Stake) -> Result<(), Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "upsert_stake");
+    let conn = ctx.get_db_connection();
+    diesel::insert_into(validator_stake::table)
+        .values(new_stake)
+        .on_conflict(validator_stake::address)
+        .do_update()
+        .set(new_stake)
+        .execute(&conn)?;
+
+    Ok(())
+}
+
+pub fn get_stake<Context>(ctx: &Context, address: &str) -> Result<Option<Stake>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_stake");
+    let conn = ctx.get_db_connection();
+    validator_stake::table
+        .find(address)
+        .first::<Stake>(&conn)
+        .optional()
+}
+
+/// Insert validators, updating URLs of the ones already known
+pub fn upsert_validators<Context>(
+    ctx: &Context,
+    new_validators: &[NewValidator],
+) -> Result<(), Error>
+where
+    Context: QueryContext,
+{
+    if new_validators.is_empty() {
+        return Ok(());
+    }
+
+    let _timer = query_timer(ctx, "upsert_validators");
+    let conn = ctx.get_db_connection();
+    diesel::insert_into(validators::table)
+        .values(new_validators)
+        .on_conflict(validators::address)
+        .do_update()
+        .set(validators::url.eq(excluded(validators::url)))
+        .execute(&conn)?;
+
+    Ok(())
+}
+
+pub fn get_validators<Context>(ctx: &Context) -> Result<Vec<Validator>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_validators");
+    let conn = ctx.get_db_connection();
+    validators::table.load::<Validator>(&conn)
+}
+
+pub async fn update_tx<Context>(ctx: &Context, tx: &NewTransaction) -> Result<(), Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "update_tx");
     let conn = ctx.get_db_connection();
     diesel::update(transactions::table.find(&tx.id))
-        .set(&*tx)
-        .execute(&conn)
-        .unwrap_or_else(|_| panic!("Unable to find transaction {}", &tx.id));
+        .set((tx, transactions::updated_at.eq(now)))
+        .execute(&conn)?;
+    ctx.tx_cache().invalidate(&tx.id);
 
     Ok(())
 }
@@ -66,8 +618,201 @@ pub async fn get_tx<Context>(ctx: &Context, tx_id: &str) -> Result<Transaction,
 where
     Context: QueryContext,
 {
+    load_tx(ctx, tx_id)
+}
+
+fn load_tx<Context>(ctx: &Context, tx_id: &str) -> Result<Transaction, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_tx");
     let conn = ctx.get_db_connection();
     transactions
         .filter(transactions::id.eq(tx_id))
         .first::<Transaction>(&conn)
 }
+
+/// Transactions validated between `from` and `to`, most recent first
+pub fn get_validated_txs_between<Context>(
+    ctx: &Context,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<Transaction>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_validated_txs_between");
+    let conn = ctx.get_db_connection();
+    transactions
+        .filter(transactions::validated.eq(true))
+        .filter(transactions::updated_at.ge(from))
+        .filter(transactions::updated_at.lt(to))
+        .order_by(transactions::updated_at.desc())
+        .limit(limit)
+        .load::<Transaction>(&conn)
+}
+
+/// Bundles stored between `from` and `to`, oldest first
+pub fn get_bundles_between<Context>(
+    ctx: &Context,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<Bundle>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_bundles_between");
+    let conn = ctx.get_db_connection();
+    bundle
+        .filter(bundle::created_at.ge(from))
+        .filter(bundle::created_at.lt(to))
+        .order_by(bundle::created_at)
+        .limit(limit)
+        .load::<Bundle>(&conn)
+}
+
+/// Number of transactions not changed since `before`
+pub fn count_txs_updated_before<Context>(ctx: &Context, before: DateTime<Utc>) -> Result<i64, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "count_txs_updated_before");
+    let conn = ctx.get_db_connection();
+    transactions
+        .filter(transactions::updated_at.lt(before))
+        .count()
+        .get_result(&conn)
+}
+
+/// Number of promised transactions not seen in any bundle yet
+pub fn count_pending_txs<Context>(ctx: &Context) -> Result<i64, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "count_pending_txs");
+    let conn = ctx.get_db_connection();
+    transactions
+        .filter(transactions::bundle_id.is_null())
+        .count()
+        .get_result(&conn)
+}
+
+/// Latest epoch with stored transactions and the highest block a
+/// transaction of that epoch was validated in
+pub fn get_latest_validated<Context>(ctx: &Context) -> Result<Option<(u128, u128)>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_latest_validated");
+    let conn = ctx.get_db_connection();
+    // Epochs and blocks are stored as bytes, `le_u128` reads them as numbers
+    // and the maximum is passed back as text, numerics being wider than any
+    // Rust integer
+    let latest_epoch = transactions
+        .select(sql::<Nullable<Text>>("max(le_u128(epoch))::text"))
+        .get_result::<Option<String>>(&conn)?;
+    let latest_epoch = match latest_epoch {
+        Some(latest_epoch) => parse_u128(&latest_epoch)?,
+        None => return Ok(None),
+    };
+
+    let latest_block = transactions
+        .filter(transactions::epoch.eq(Epoch(latest_epoch)))
+        .filter(transactions::validated.eq(true))
+        .select(sql::<Nullable<Text>>("max(le_u128(block_actual))::text"))
+        .get_result::<Option<String>>(&conn)?
+        .map(|block| parse_u128(&block))
+        .transpose()?
+        .unwrap_or_default();
+
+    Ok(Some((latest_epoch, latest_block)))
+}
+
+fn parse_u128(value: &str) -> Result<u128, Error> {
+    value
+        .parse()
+        .map_err(|err| Error::DeserializationError(Box::new(err)))
+}
+
+/// Transactions validated as part of bundle `b_id`
+pub fn get_bundle_txs<Context>(ctx: &Context, b_id: &str) -> Result<Vec<Transaction>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_bundle_txs");
+    let conn = ctx.get_db_connection();
+    transactions
+        .filter(transactions::bundle_id.eq(b_id))
+        .order_by(transactions::id)
+        .load::<Transaction>(&conn)
+}
+
+/// Ids of all transactions of `tx_epoch` ordered by id, with whether they
+/// were validated
+pub fn get_epoch_tx_ids<Context>(
+    ctx: &Context,
+    tx_epoch: u128,
+) -> Result<Vec<(String, bool)>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_epoch_tx_ids");
+    let conn = ctx.get_db_connection();
+    transactions
+        .select((transactions::id, transactions::validated))
+        .filter(transactions::epoch.eq(Epoch(tx_epoch)))
+        .order_by(transactions::id)
+        .load::<(String, bool)>(&conn)
+}
+
+/// Page of transactions of `tx_epoch` ordered by id, starting after id `after`
+pub fn get_epoch_txs<Context>(
+    ctx: &Context,
+    tx_epoch: u128,
+    after: Option<&str>,
+    limit: i64,
+) -> Result<Vec<Transaction>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_epoch_txs");
+    let conn = ctx.get_db_connection();
+    let mut query = transactions
+        .filter(transactions::epoch.eq(Epoch(tx_epoch)))
+        .order_by(transactions::id)
+        .limit(limit)
+        .into_boxed();
+    if let Some(after) = after {
+        query = query.filter(transactions::id.gt(after.to_string()));
+    }
+    query.load::<Transaction>(&conn)
+}
+
+/// Receipts of the transactions of an epoch as they were received, ordered
+/// by transaction id, all receipts of a transaction following each other
+pub fn get_epoch_raw_receipts<Context>(
+    ctx: &Context,
+    tx_epoch: u128,
+    after: Option<&str>,
+    limit: i64,
+) -> Result<Vec<RawReceipt>, Error>
+where
+    Context: QueryContext,
+{
+    let _timer = query_timer(ctx, "get_epoch_raw_receipts");
+    let conn = ctx.get_db_connection();
+    let epoch_txs = transactions
+        .select(transactions::id)
+        .filter(transactions::epoch.eq(Epoch(tx_epoch)));
+    let mut query = raw_receipts::table
+        .filter(raw_receipts::tx_id.eq_any(epoch_txs))
+        .order_by((raw_receipts::tx_id, raw_receipts::received_at))
+        .limit(limit)
+        .into_boxed();
+    if let Some(after) = after {
+        query = query.filter(raw_receipts::tx_id.gt(after.to_string()));
+    }
+    query.load::<RawReceipt>(&conn)
+}
@@ -1,8 +1,51 @@
+table! {
+    attestations (bundle_id, validator) {
+        bundle_id -> Bpchar,
+        validator -> Bpchar,
+        epoch -> Bytea,
+        signature -> Bytea,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     bundle (id) {
         id -> Bpchar,
         owner_address -> Bpchar,
         block_height -> Bytea,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        size -> Nullable<Int8>,
+        item_count -> Nullable<Int4>,
+        block_hash -> Nullable<Varchar>,
+        checksum -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    duplicate_items (tx_id, duplicate_bundle_id) {
+        tx_id -> Bpchar,
+        bundle_id -> Bpchar,
+        duplicate_bundle_id -> Bpchar,
+    }
+}
+
+table! {
+    equivocations (tx_id, kind) {
+        tx_id -> Bpchar,
+        kind -> Varchar,
+        first_evidence -> Text,
+        second_evidence -> Text,
+        detected_at -> Timestamptz,
+        proposed -> Bool,
+    }
+}
+
+table! {
+    invalid_bundles (id) {
+        id -> Bpchar,
+        reason -> Text,
+        detected_at -> Timestamptz,
     }
 }
 
@@ -12,6 +55,42 @@ table! {
     }
 }
 
+table! {
+    nested_items (tx_id, bundle_id) {
+        tx_id -> Bpchar,
+        bundle_id -> Bpchar,
+        parent_id -> Bpchar,
+    }
+}
+
+table! {
+    pruned_epochs (epoch) {
+        epoch -> Bytea,
+        validated -> Int8,
+        invalid -> Int8,
+        slashed -> Int8,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    raw_receipts (tx_id, source, payload_hash) {
+        tx_id -> Bpchar,
+        source -> Varchar,
+        payload -> Text,
+        received_at -> Timestamptz,
+        payload_hash -> Bytea,
+    }
+}
+
+table! {
+    round_attempts (bundle_id) {
+        bundle_id -> Bpchar,
+        attempts -> Int4,
+        retry_at -> Timestamptz,
+    }
+}
+
 table! {
     transactions (id) {
         id -> Bpchar,
@@ -21,6 +100,20 @@ table! {
         signature -> Bytea,
         validated -> Bool,
         bundle_id -> Nullable<Bpchar>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        bundler_key -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    validator_stake (address) {
+        address -> Bpchar,
+        registered -> Bool,
+        stake -> Varchar,
+        minimum_stake -> Varchar,
+        nominated -> Bool,
+        epoch -> Bytea,
     }
 }
 
@@ -34,4 +127,18 @@ table! {
 joinable!(leaders -> validators (address));
 joinable!(transactions -> bundle (bundle_id));
 
-allow_tables_to_appear_in_same_query!(bundle, leaders, transactions, validators,);
+allow_tables_to_appear_in_same_query!(
+    attestations,
+    bundle,
+    duplicate_items,
+    equivocations,
+    invalid_bundles,
+    leaders,
+    nested_items,
+    pruned_epochs,
+    raw_receipts,
+    round_attempts,
+    transactions,
+    validator_stake,
+    validators,
+);
@@ -0,0 +1,339 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, BufReader, Read, Write},
+};
+
+use chrono::{DateTime, Utc};
+use diesel::{
+    prelude::*,
+    sql_types::{Nullable, Text},
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use thiserror::Error;
+
+use crate::database::{models::Epoch, schema::transactions};
+
+/// Version of the snapshot file layout, bumped on incompatible changes
+pub const SNAPSHOT_FORMAT: u32 = 1;
+
+/// Tables included in a snapshot, in an order their foreign keys can be
+/// restored in
+pub const SNAPSHOT_TABLES: &[&str] = &[
+    "validators",
+    "leaders",
+    "validator_stake",
+    "bundle",
+    "transactions",
+    "nested_items",
+    "duplicate_items",
+    "raw_receipts",
+    "attestations",
+    "equivocations",
+    "invalid_bundles",
+    "pruned_epochs",
+];
+
+/// Rows read from the database, or inserted, at once
+const ROWS_PER_BATCH: usize = 1000;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("database error: {0}")]
+    Database(#[from] diesel::result::Error),
+    #[error("failed to read or write snapshot: {0}")]
+    Io(#[from] io::Error),
+    #[error("malformed snapshot: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("snapshot format {0} is not supported, expecting {}", SNAPSHOT_FORMAT)]
+    UnsupportedFormat(u32),
+    #[error("snapshot was taken at schema version {snapshot:?}, database is at {database:?}")]
+    SchemaMismatch {
+        snapshot: Option<String>,
+        database: Option<String>,
+    },
+    #[error("table {0} is not empty, snapshots are only restored into an empty database")]
+    NotEmpty(&'static str),
+    #[error("snapshot contains unknown table {0}")]
+    UnknownTable(String),
+    #[error("snapshot is missing its header")]
+    MissingHeader,
+}
+
+/// First line of a snapshot, describing what it holds
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SnapshotHeader {
+    pub format: u32,
+    pub created_at: DateTime<Utc>,
+    /// Latest migration applied to the database the snapshot was taken from
+    pub schema_version: Option<String>,
+    /// Latest epoch with stored transactions
+    pub latest_epoch: Option<u64>,
+    /// Number of rows of each table
+    pub tables: BTreeMap<String, usize>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SnapshotRow<'a> {
+    table: &'a str,
+    #[serde(borrow)]
+    row: &'a RawValue,
+}
+
+#[derive(QueryableByName)]
+struct JsonRow {
+    #[sql_type = "Text"]
+    row: String,
+}
+
+#[derive(QueryableByName)]
+struct SchemaVersion {
+    #[sql_type = "Nullable<Text>"]
+    version: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct RowCount {
+    #[sql_type = "diesel::sql_types::BigInt"]
+    count: i64,
+}
+
+/// Gzip compressed JSON lines, the header followed by one line per row
+pub struct SnapshotWriter<W: Write> {
+    encoder: GzEncoder<W>,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    pub fn new(out: W, header: &SnapshotHeader) -> Result<Self, SnapshotError> {
+        let mut writer = Self {
+            encoder: GzEncoder::new(out, Compression::default()),
+        };
+        writer.write_line(header)?;
+        Ok(writer)
+    }
+
+    /// Append `row`, a JSON object of the columns of a row of `table`
+    pub fn row(&mut self, table: &str, row: &RawValue) -> Result<(), SnapshotError> {
+        self.write_line(&SnapshotRow { table, row })
+    }
+
+    pub fn finish(self) -> Result<W, SnapshotError> {
+        Ok(self.encoder.finish()?)
+    }
+
+    fn write_line<T: Serialize>(&mut self, value: &T) -> Result<(), SnapshotError> {
+        serde_json::to_writer(&mut self.encoder, value)?;
+        self.encoder.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+pub struct SnapshotReader<R: Read> {
+    lines: io::Lines<BufReader<GzDecoder<R>>>,
+    pub header: SnapshotHeader,
+}
+
+impl<R: Read> SnapshotReader<R> {
+    pub fn new(input: R) -> Result<Self, SnapshotError> {
+        let mut lines = BufReader::new(GzDecoder::new(input)).lines();
+        let header: SnapshotHeader =
+            serde_json::from_str(&lines.next().ok_or(SnapshotError::MissingHeader)??)?;
+        if header.format != SNAPSHOT_FORMAT {
+            return Err(SnapshotError::UnsupportedFormat(header.format));
+        }
+        Ok(Self { lines, header })
+    }
+
+    /// Next row as the name of its table and its columns as JSON
+    pub fn next_row(&mut self) -> Result<Option<(String, String)>, SnapshotError> {
+        let line = match self.lines.next() {
+            Some(line) => line?,
+            None => return Ok(None),
+        };
+        let row: SnapshotRow = serde_json::from_str(&line)?;
+        Ok(Some((row.table.to_string(), row.row.get().to_string())))
+    }
+}
+
+fn schema_version(conn: &PgConnection) -> Result<Option<String>, diesel::result::Error> {
+    let version =
+        diesel::sql_query("SELECT MAX(version) AS version FROM __diesel_schema_migrations")
+            .get_result::<SchemaVersion>(conn)?;
+    Ok(version.version)
+}
+
+fn count_rows(conn: &PgConnection, table: &str) -> Result<usize, diesel::result::Error> {
+    let rows = diesel::sql_query(format!("SELECT COUNT(*) AS count FROM {}", table))
+        .get_result::<RowCount>(conn)?;
+    Ok(rows.count as usize)
+}
+
+/// Write all rows of the snapshot tables to `out`. Rows are read in a
+/// single read-only transaction, so the snapshot is consistent while the
+/// validator keeps running.
+pub fn create<W: Write>(conn: &PgConnection, out: W) -> Result<SnapshotHeader, SnapshotError> {
+    conn.build_transaction()
+        .read_only()
+        .repeatable_read()
+        .run(|| {
+            let mut tables = BTreeMap::new();
+            for table in SNAPSHOT_TABLES {
+                tables.insert(table.to_string(), count_rows(conn, table)?);
+            }
+            // Epochs are stored as bytes, so they can't be compared in SQL
+            let latest_epoch = transactions::table
+                .select(transactions::epoch)
+                .distinct()
+                .load::<Epoch>(conn)?
+                .into_iter()
+                .map(|epoch| epoch.0 as u64)
+                .max();
+            let header = SnapshotHeader {
+                format: SNAPSHOT_FORMAT,
+                created_at: Utc::now(),
+                schema_version: schema_version(conn)?,
+                latest_epoch,
+                tables,
+            };
+
+            let mut writer = SnapshotWriter::new(out, &header)?;
+            for table in SNAPSHOT_TABLES {
+                diesel::sql_query(format!(
+                    "DECLARE snapshot_rows NO SCROLL CURSOR FOR SELECT row_to_json(t)::text AS row FROM {} t",
+                    table
+                ))
+                .execute(conn)?;
+                loop {
+                    let rows = diesel::sql_query(format!(
+                        "FETCH {} FROM snapshot_rows",
+                        ROWS_PER_BATCH
+                    ))
+                    .load::<JsonRow>(conn)?;
+                    for row in &rows {
+                        writer.row(table, &RawValue::from_string(row.row.clone())?)?;
+                    }
+                    if rows.len() < ROWS_PER_BATCH {
+                        break;
+                    }
+                }
+                diesel::sql_query("CLOSE snapshot_rows").execute(conn)?;
+            }
+            writer.finish()?.flush()?;
+
+            Ok(header)
+        })
+}
+
+fn insert_rows(
+    conn: &PgConnection,
+    table: &str,
+    rows: &mut Vec<String>,
+) -> Result<(), diesel::result::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    diesel::sql_query(format!(
+        "INSERT INTO {0} SELECT * FROM json_populate_recordset(NULL::{0}, $1::json)",
+        table
+    ))
+    .bind::<Text, _>(format!("[{}]", rows.join(",")))
+    .execute(conn)?;
+    rows.clear();
+    Ok(())
+}
+
+/// Insert the rows of the snapshot read from `input` into an empty database
+/// migrated to the same schema version, all of them or none
+pub fn restore<R: Read>(conn: &PgConnection, input: R) -> Result<SnapshotHeader, SnapshotError> {
+    let mut reader = SnapshotReader::new(input)?;
+    let database = schema_version(conn)?;
+    if reader.header.schema_version != database {
+        return Err(SnapshotError::SchemaMismatch {
+            snapshot: reader.header.schema_version.clone(),
+            database,
+        });
+    }
+
+    conn.transaction(|| {
+        for table in SNAPSHOT_TABLES {
+            if count_rows(conn, table)? > 0 {
+                return Err(SnapshotError::NotEmpty(*table));
+            }
+        }
+
+        let mut table: Option<&'static str> = None;
+        let mut rows = Vec::with_capacity(ROWS_PER_BATCH);
+        while let Some((row_table, row)) = reader.next_row()? {
+            if table != Some(row_table.as_str()) {
+                if let Some(table) = table {
+                    insert_rows(conn, table, &mut rows)?;
+                }
+                // Only known names end up in the queries
+                table = Some(
+                    SNAPSHOT_TABLES
+                        .iter()
+                        .find(|known| **known == row_table)
+                        .copied()
+                        .ok_or(SnapshotError::UnknownTable(row_table))?,
+                );
+            }
+            rows.push(row);
+            if rows.len() == ROWS_PER_BATCH {
+                insert_rows(conn, table.unwrap(), &mut rows)?;
+            }
+        }
+        if let Some(table) = table {
+            insert_rows(conn, table, &mut rows)?;
+        }
+
+        Ok(reader.header.clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::Utc;
+    use serde_json::value::RawValue;
+
+    use super::{SnapshotError, SnapshotHeader, SnapshotReader, SnapshotWriter, SNAPSHOT_FORMAT};
+
+    fn header(format: u32) -> SnapshotHeader {
+        SnapshotHeader {
+            format,
+            created_at: Utc::now(),
+            schema_version: Some("20261016000009".to_string()),
+            latest_epoch: Some(3),
+            tables: BTreeMap::from([("bundle".to_string(), 1)]),
+        }
+    }
+
+    #[test]
+    fn snapshot_rows_are_read_back() {
+        let header = header(SNAPSHOT_FORMAT);
+        let row = RawValue::from_string(r#"{"id":"bundle_id","owner_address":null}"#.to_string())
+            .unwrap();
+        let mut writer = SnapshotWriter::new(Vec::new(), &header).unwrap();
+        writer.row("bundle", &row).unwrap();
+        let snapshot = writer.finish().unwrap();
+
+        let mut reader = SnapshotReader::new(snapshot.as_slice()).unwrap();
+        assert_eq!(reader.header, header);
+        assert_eq!(
+            reader.next_row().unwrap(),
+            Some(("bundle".to_string(), row.get().to_string()))
+        );
+        assert_eq!(reader.next_row().unwrap(), None);
+
+        let snapshot = SnapshotWriter::new(Vec::new(), &self::header(SNAPSHOT_FORMAT + 1))
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert!(matches!(
+            SnapshotReader::new(snapshot.as_slice()),
+            Err(SnapshotError::UnsupportedFormat(_))
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+use bundlr_contracts_validators::{Epoch as ContractEpoch, State as ContractState};
+use data_encoding::BASE64URL_NOPAD;
+use openssl::sha::Sha256;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Parameters describing how the chain is split into epochs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EpochParams {
+    /// Block height at which epoch `0` starts
+    pub genesis_height: u128,
+    /// Number of blocks in a single epoch
+    pub epoch_length: u128,
+}
+
+/// Epoch parameters the validator state can't hold
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EpochParamsError {
+    #[error("genesis height {0} is out of range")]
+    GenesisHeightOutOfRange(u128),
+    #[error("epoch length {0} is out of range")]
+    EpochLengthOutOfRange(u128),
+}
+
+/// A single epoch and the block range it covers.
+///
+/// `start_height` is inclusive, `end_height` is exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EpochInfo {
+    pub seq: u128,
+    pub start_height: u128,
+    pub end_height: u128,
+}
+
+impl EpochParams {
+    pub fn new(genesis_height: u128, epoch_length: u128) -> Option<Self> {
+        if epoch_length == 0 {
+            None
+        } else {
+            Some(Self {
+                genesis_height,
+                epoch_length,
+            })
+        }
+    }
+
+    /// Derive epoch parameters from the contract's current epoch and epoch
+    /// duration. Returns `None` if the contract state is not consistent with
+    /// a fixed epoch length since genesis.
+    pub fn from_contract_state(state: &ContractState) -> Option<Self> {
+        let epoch_length = state.epoch_duration;
+        let elapsed = state.epoch.seq.checked_mul(epoch_length)?;
+        let genesis_height = state.epoch.height.checked_sub(elapsed)?;
+        Self::new(genesis_height, epoch_length)
+    }
+
+    /// Epoch with sequence number `seq`
+    pub fn epoch(&self, seq: u128) -> EpochInfo {
+        let start_height = self.genesis_height + seq * self.epoch_length;
+        EpochInfo {
+            seq,
+            start_height,
+            end_height: start_height + self.epoch_length,
+        }
+    }
+
+    /// Epoch that contains block `height`, `None` if `height` is before genesis
+    pub fn epoch_at(&self, height: u128) -> Option<EpochInfo> {
+        let elapsed = height.checked_sub(self.genesis_height)?;
+        Some(self.epoch(elapsed / self.epoch_length))
+    }
+}
+
+impl EpochInfo {
+    pub fn from_contract_epoch(epoch: &ContractEpoch, epoch_length: u128) -> Self {
+        Self {
+            seq: epoch.seq,
+            start_height: epoch.height,
+            end_height: epoch.height + epoch_length,
+        }
+    }
+
+    pub fn has_started(&self, height: u128) -> bool {
+        height >= self.start_height
+    }
+
+    pub fn contains(&self, height: u128) -> bool {
+        self.start_height <= height && height < self.end_height
+    }
+}
+
+/// Digest of the transactions a validator stored for an epoch. Validators
+/// with equal summaries saw the same transactions and validated the same
+/// ones, so peers only need to exchange full lists when summaries differ.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EpochSummary {
+    pub epoch: u64,
+    /// Transactions a receipt was stored for
+    pub tx_count: u64,
+    /// Transactions found in a validated bundle
+    pub validated_count: u64,
+    /// Base64url encoded merkle root of the validated transaction ids,
+    /// `None` if there are none
+    pub validated_root: Option<String>,
+}
+
+impl EpochSummary {
+    /// Summarize `txs`, pairs of transaction id and whether it was
+    /// validated, ordered by id
+    pub fn new(epoch: u64, txs: &[(String, bool)]) -> Self {
+        let validated: Vec<&str> = txs
+            .iter()
+            .filter(|(_, validated)| *validated)
+            .map(|(id, _)| id.as_str())
+            .collect();
+        Self {
+            epoch,
+            tx_count: txs.len() as u64,
+            validated_count: validated.len() as u64,
+            validated_root: merkle_root(&validated).map(|root| BASE64URL_NOPAD.encode(&root)),
+        }
+    }
+}
+
+/// Transaction of an epoch as listed to peers whose summary differs
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EpochTx {
+    pub id: String,
+    pub validated: bool,
+}
+
+/// Root of a SHA-256 merkle tree over `ids`, taken in the given order. A
+/// node without sibling is carried up to the next level unchanged.
+pub fn merkle_root(ids: &[&str]) -> Option<[u8; 32]> {
+    let mut level: Vec<[u8; 32]> = ids
+        .iter()
+        .map(|id| {
+            let mut hasher = Sha256::new();
+            hasher.update(id.as_bytes());
+            hasher.finish()
+        })
+        .collect();
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = Sha256::new();
+                    hasher.update(left);
+                    hasher.update(right);
+                    hasher.finish()
+                }
+                [single] => *single,
+                _ => unreachable!("chunks of two"),
+            })
+            .collect();
+    }
+    level.pop()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merkle_root, EpochInfo, EpochParams, EpochSummary};
+
+    #[test]
+    fn summary_covers_validated_txs_only() {
+        let txs = vec![
+            ("a".to_string(), true),
+            ("b".to_string(), false),
+            ("c".to_string(), true),
+        ];
+        let summary = EpochSummary::new(3, &txs);
+
+        assert_eq!(summary.tx_count, 3);
+        assert_eq!(summary.validated_count, 2);
+        let same_validated =
+            EpochSummary::new(3, &[("a".to_string(), true), ("c".to_string(), true)]);
+        assert_eq!(summary.validated_root, same_validated.validated_root);
+        assert_ne!(merkle_root(&["a", "c"]), merkle_root(&["a", "b", "c"]));
+        assert_eq!(merkle_root(&[]), None);
+    }
+
+    #[test]
+    fn zero_epoch_length_is_rejected() {
+        assert_eq!(EpochParams::new(0, 0), None);
+    }
+
+    #[test]
+    fn height_before_genesis_has_no_epoch() {
+        let params = EpochParams::new(100, 10).unwrap();
+
+        assert_eq!(params.epoch_at(99), None);
+    }
+
+    #[test]
+    fn epoch_at_height_yields_expected_range() {
+        let params = EpochParams::new(100, 10).unwrap();
+
+        assert_eq!(
+            params.epoch_at(125),
+            Some(EpochInfo {
+                seq: 2,
+                start_height: 120,
+                end_height: 130,
+            })
+        );
+    }
+
+    #[test]
+    fn epoch_range_is_end_exclusive() {
+        let params = EpochParams::new(100, 10).unwrap();
+        let epoch = params.epoch(0);
+
+        assert!(epoch.contains(100));
+        assert!(epoch.contains(109));
+        assert!(!epoch.contains(110));
+    }
+}
@@ -0,0 +1,95 @@
+//! Bundler equivocations: the bundler contradicting itself about a
+//! transaction, by including it in two bundles or by promising it for two
+//! different blocks.
+//!
+//! Both pieces of evidence are stored in the `equivocations` table, where
+//! the contract cron picks them up to propose a slash.
+
+use tracing::{error, warn};
+
+use crate::{
+    database::{models::NewEquivocation, queries::BundleQueries},
+    events::{Event, EventBusAccess},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EquivocationKind {
+    /// Data item included in two bundles, evidence are the bundle ids
+    Bundles,
+    /// Receipts promising the transaction for different blocks, evidence
+    /// are the receipts as received
+    Receipts,
+}
+
+impl EquivocationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EquivocationKind::Bundles => "bundles",
+            EquivocationKind::Receipts => "receipts",
+        }
+    }
+}
+
+/// Record that the bundler equivocated on `tx_id`, `first` being the
+/// evidence stored before and `second` the evidence contradicting it. The
+/// first equivocation of each kind on a transaction is kept and published.
+pub fn record_equivocation<Context>(
+    ctx: &Context,
+    tx_id: &str,
+    kind: EquivocationKind,
+    first: &str,
+    second: &str,
+) where
+    Context: BundleQueries + EventBusAccess,
+{
+    warn!(tx = tx_id, kind = kind.as_str(), "Bundler equivocated");
+    let equivocation = NewEquivocation {
+        tx_id: tx_id.to_string(),
+        kind: kind.as_str().to_string(),
+        first_evidence: first.to_string(),
+        second_evidence: second.to_string(),
+    };
+    match ctx.insert_equivocation(&equivocation) {
+        Ok(true) => ctx.event_bus().publish(Event::Equivocation {
+            tx: equivocation.tx_id,
+            kind: equivocation.kind,
+            first_evidence: equivocation.first_evidence,
+            second_evidence: equivocation.second_evidence,
+        }),
+        Ok(false) => (),
+        Err(err) => error!(tx = tx_id, "Failed to record equivocation: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::memory::MemoryContext,
+        events::{Event, EventBusAccess},
+    };
+
+    use super::{record_equivocation, EquivocationKind};
+
+    #[test]
+    fn equivocation_is_published_once() {
+        let ctx = MemoryContext::new("bundler");
+        let mut events = ctx.event_bus().subscribe();
+
+        for _ in 0..2 {
+            record_equivocation(
+                &ctx,
+                "tx_id",
+                EquivocationKind::Receipts,
+                "{\"block\": \"10\"}",
+                "{\"block\": \"20\"}",
+            );
+        }
+
+        assert_eq!(ctx.db.get_equivocations("tx_id").len(), 1);
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            Event::Equivocation { tx, .. } if tx == "tx_id"
+        ));
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Error type common to the whole validator.
+//!
+//! Every module keeps its own error type, naming what failed and carrying
+//! the error it failed with. Each of them converts into `ValidatorError`, so
+//! code combining several modules can propagate their errors with `?`
+//! without flattening them into strings, and the source chain stays
+//! available to whoever reports the failure. The binary and the cron loop
+//! report failures as `ValidatorError`.
+
+use std::io;
+
+use thiserror::Error;
+
+use crate::{
+    broker::BrokerError,
+    bundle::{archive::ArchiveError, store::StoreError, BundleError},
+    bundler::BundlerError,
+    config::ConfigError,
+    contract_gateway::ContractGatewayError,
+    cron::{
+        arweave::ArweaveError,
+        error::{TxsError, ValidatorCronError},
+        CronJobError,
+    },
+    database::{models::DeserializationError, snapshot::SnapshotError},
+    epoch::EpochParamsError,
+    key_manager::BundlerKeyError,
+    peer_auth::PeerAuthError,
+    receipt::ReceiptCheckError,
+    webhooks::WebhookError,
+};
+
+#[derive(Debug, Error)]
+pub enum ValidatorError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    BundlerKey(#[from] BundlerKeyError),
+    #[error(transparent)]
+    EpochParams(#[from] EpochParamsError),
+    #[error(transparent)]
+    Bundler(#[from] BundlerError),
+    #[error(transparent)]
+    Arweave(#[from] ArweaveError),
+    #[error(transparent)]
+    ContractGateway(#[from] ContractGatewayError),
+    #[error(transparent)]
+    Cron(#[from] CronJobError),
+    #[error(transparent)]
+    Validation(#[from] ValidatorCronError),
+    #[error(transparent)]
+    Txs(#[from] TxsError),
+    #[error(transparent)]
+    Bundle(#[from] BundleError),
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error(transparent)]
+    Archive(#[from] ArchiveError),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error(transparent)]
+    Deserialization(#[from] DeserializationError),
+    #[error(transparent)]
+    Receipt(#[from] ReceiptCheckError),
+    #[error(transparent)]
+    PeerAuth(#[from] PeerAuthError),
+    #[error(transparent)]
+    Webhook(#[from] WebhookError),
+    #[error(transparent)]
+    Broker(#[from] BrokerError),
+    #[error("database error: {0}")]
+    Database(#[from] diesel::result::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::ValidatorError;
+    use crate::cron::{arweave::ArweaveError, error::ValidatorCronError, CronJobError};
+
+    fn validate() -> Result<(), ValidatorError> {
+        Err(CronJobError::from(ValidatorCronError::TxInvalid {
+            bundle_id: "bundle".to_string(),
+            tx_id: "tx".to_string(),
+            reason: "invalid signature".to_string(),
+        }))?;
+        Ok(())
+    }
+
+    #[test]
+    fn module_errors_keep_their_context() {
+        let err = validate().unwrap_err();
+        assert!(matches!(
+            err,
+            ValidatorError::Cron(CronJobError::ValidatorError(
+                ValidatorCronError::TxInvalid { .. }
+            ))
+        ));
+        assert_eq!(
+            err.to_string(),
+            "invalid tx tx in bundle bundle: invalid signature"
+        );
+    }
+
+    #[test]
+    fn source_error_is_kept() {
+        let err = ValidatorError::from(ValidatorCronError::BundleDownloadThrottled {
+            bundle_id: "bundle".to_string(),
+            source: ArweaveError::TxsNotFound,
+        });
+        assert_eq!(
+            err.to_string(),
+            "download of bundle bundle was throttled: transaction not found"
+        );
+        assert_eq!(err.source().unwrap().to_string(), "transaction not found");
+    }
+}
@@ -0,0 +1,280 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::state::ValidatorRole;
+
+const EVENT_BUS_CAPACITY: usize = 256;
+
+/// Events other modules publish for operators to be notified about
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Validator voted on a slash proposal
+    SlashVoteCast { proposal: String, vote: String },
+    /// Peer sent its vote on a slash proposal this validator hasn't voted
+    /// on yet
+    SlashVoteReceived {
+        proposal: String,
+        validator: String,
+        vote: String,
+    },
+    /// All data items of the bundle were verified
+    BundleValidated { bundle: String, txs: usize },
+    /// Bundle failed verification
+    BundleInvalid { bundle: String, reason: String },
+    /// Stored bundle file no longer matches the checksum recorded when it
+    /// was validated
+    BundleCorrupt { bundle: String, reason: String },
+    /// Data item receipt in a bundle failed verification
+    TxInvalid {
+        bundle: String,
+        tx: String,
+        reason: String,
+    },
+    /// Bundler key receipts are verified with isn't the key of the bundler
+    /// registered in the contract, receipts aren't verified until it is
+    BundlerKeyMismatch {
+        configured: String,
+        registered: String,
+    },
+    /// Cron task failed `failures` times in a row
+    CronCircuitOpened { task: String, failures: u32 },
+    /// Data item already stored as part of `bundle` was found again in
+    /// `duplicate_bundle`
+    DuplicateItem {
+        tx: String,
+        bundle: String,
+        duplicate_bundle: String,
+    },
+    /// Bundler balance dropped below the estimated cost of uploading its
+    /// pending transactions, amounts are in winston
+    BundlerUnderfunded { balance: String, required: String },
+    /// Bundler contradicted itself about `tx`, `kind` tells whether
+    /// the evidence are bundle ids or receipts
+    Equivocation {
+        tx: String,
+        kind: String,
+        first_evidence: String,
+        second_evidence: String,
+    },
+    /// Contract moved from epoch `previous` to `epoch`, in which this
+    /// validator has `role`
+    EpochStarted {
+        epoch: u64,
+        previous: u64,
+        role: ValidatorRole,
+    },
+    /// Report of epoch `epoch` once the next one started: transactions a
+    /// receipt was stored for, how many were validated so far and the merkle
+    /// root of the validated ones, as in `GET /epochs/{epoch}/summary`
+    EpochFinished {
+        epoch: u64,
+        txs: u64,
+        validated: u64,
+        validated_root: Option<String>,
+    },
+}
+
+impl Event {
+    /// Name of the event, same as the `event` field of the serialized event
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::SlashVoteCast { .. } => "slash_vote_cast",
+            Event::SlashVoteReceived { .. } => "slash_vote_received",
+            Event::BundleValidated { .. } => "bundle_validated",
+            Event::BundleInvalid { .. } => "bundle_invalid",
+            Event::BundleCorrupt { .. } => "bundle_corrupt",
+            Event::TxInvalid { .. } => "tx_invalid",
+            Event::BundlerKeyMismatch { .. } => "bundler_key_mismatch",
+            Event::CronCircuitOpened { .. } => "cron_circuit_opened",
+            Event::DuplicateItem { .. } => "duplicate_item",
+            Event::BundlerUnderfunded { .. } => "bundler_underfunded",
+            Event::Equivocation { .. } => "equivocation",
+            Event::EpochStarted { .. } => "epoch_started",
+            Event::EpochFinished { .. } => "epoch_finished",
+        }
+    }
+
+    /// Events operators should be notified about, routine events are only
+    /// meant for machine consumers
+    pub fn is_critical(&self) -> bool {
+        !matches!(
+            self,
+            Event::BundleValidated { .. }
+                | Event::EpochStarted { .. }
+                | Event::EpochFinished { .. }
+                | Event::SlashVoteReceived { .. }
+        )
+    }
+
+    /// Human readable one-line description of the event
+    pub fn summary(&self) -> String {
+        match self {
+            Event::SlashVoteCast { proposal, vote } => {
+                format!("Voted {} on slash proposal {}", vote, proposal)
+            }
+            Event::SlashVoteReceived {
+                proposal,
+                validator,
+                vote,
+            } => {
+                format!(
+                    "Validator {} voted {} on slash proposal {}",
+                    validator, vote, proposal
+                )
+            }
+            Event::BundleValidated { bundle, txs } => {
+                format!("Bundle {} is valid, {} transactions verified", bundle, txs)
+            }
+            Event::BundleInvalid { bundle, reason } => {
+                format!("Bundle {} is invalid: {}", bundle, reason)
+            }
+            Event::BundleCorrupt { bundle, reason } => {
+                format!("Stored file of bundle {} is corrupt: {}", bundle, reason)
+            }
+            Event::TxInvalid { bundle, tx, reason } => {
+                format!(
+                    "Transaction {} in bundle {} is invalid: {}",
+                    tx, bundle, reason
+                )
+            }
+            Event::BundlerKeyMismatch {
+                configured,
+                registered,
+            } => {
+                format!(
+                    "Bundler key of address {} doesn't match bundler {} registered in the contract, receipts are not verified",
+                    configured, registered
+                )
+            }
+            Event::CronCircuitOpened { task, failures } => {
+                format!("Task {} failed {} times in a row", task, failures)
+            }
+            Event::DuplicateItem {
+                tx,
+                bundle,
+                duplicate_bundle,
+            } => {
+                format!(
+                    "Transaction {} of bundle {} is also in bundle {}",
+                    tx, bundle, duplicate_bundle
+                )
+            }
+            Event::BundlerUnderfunded { balance, required } => {
+                format!(
+                    "Bundler balance of {} winston is below the {} winston needed for its pending transactions",
+                    balance, required
+                )
+            }
+            Event::Equivocation { tx, kind, .. } => {
+                format!("Bundler equivocated on transaction {} with {}", tx, kind)
+            }
+            Event::EpochStarted {
+                epoch,
+                previous,
+                role,
+            } => {
+                format!(
+                    "Epoch {} started after epoch {}, validator is {:?}",
+                    epoch, previous, role
+                )
+            }
+            Event::EpochFinished {
+                epoch,
+                txs,
+                validated,
+                ..
+            } => {
+                format!(
+                    "Epoch {} finished with {} transactions, {} validated",
+                    epoch, txs, validated
+                )
+            }
+        }
+    }
+}
+
+pub trait EventBusAccess {
+    fn event_bus(&self) -> &EventBus;
+}
+
+/// Broadcast channel distributing published events to all subscribers.
+///
+/// Publishing never blocks, subscribers that fall behind miss the oldest
+/// events.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(EVENT_BUS_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, EventBus};
+
+    #[actix_rt::test]
+    async fn subscribers_receive_published_events() {
+        let bus = EventBus::default();
+        let mut receiver = bus.subscribe();
+
+        let event = Event::BundleInvalid {
+            bundle: "bundle-id".to_string(),
+            reason: "invalid signature".to_string(),
+        };
+        bus.publish(event.clone());
+
+        assert_eq!(receiver.recv().await.unwrap(), event);
+    }
+
+    #[test]
+    fn publishing_without_subscribers_is_ignored() {
+        EventBus::default().publish(Event::CronCircuitOpened {
+            task: "sync network info".to_string(),
+            failures: 5,
+        });
+    }
+
+    #[test]
+    fn event_serializes_with_tag() {
+        let value = serde_json::to_value(Event::SlashVoteCast {
+            proposal: "proposal-id".to_string(),
+            vote: "for".to_string(),
+        })
+        .unwrap();
+
+        assert_eq!(value["event"], "slash_vote_cast");
+        assert_eq!(value["proposal"], "proposal-id");
+    }
+
+    #[test]
+    fn event_name_matches_serialized_tag() {
+        let event = Event::TxInvalid {
+            bundle: "bundle-id".to_string(),
+            tx: "tx-id".to_string(),
+            reason: "invalid receipt".to_string(),
+        };
+
+        assert_eq!(serde_json::to_value(&event).unwrap()["event"], event.name());
+    }
+}
@@ -0,0 +1,302 @@
+//! Recording of real responses to disk, so tests can replay them through
+//! the mock client instead of hitting the network.
+//!
+//! A fixture file holds one JSON entry per line. A response is written when
+//! it arrives and its body follows in chunks as the caller reads it, so
+//! recording only ever appends to the file and never holds a body in
+//! memory. Responses are replayed by method and URL, when the same request
+//! was recorded more than once the first response is used.
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use data_encoding::BASE64;
+use futures::{future::BoxFuture, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::{Client, Middleware, Next};
+
+/// Recorded request and the response to it
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Fixture {
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// Response body, base64 encoded
+    pub body: String,
+}
+
+impl Fixture {
+    /// Response as it was received
+    pub fn to_response(&self) -> reqwest::Response {
+        let mut builder = http::response::Builder::new().status(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        let body = BASE64
+            .decode(self.body.as_bytes())
+            .expect("Fixture body is not valid base64");
+        reqwest::Response::from(builder.body(body).expect("Invalid fixture response"))
+    }
+}
+
+/// Line of a fixture file
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "entry", rename_all = "lowercase")]
+enum Entry {
+    /// Response to a request, its body follows in chunks with the same `id`
+    Response {
+        id: u64,
+        method: String,
+        url: String,
+        status: u16,
+        headers: Vec<(String, String)>,
+    },
+    /// Part of the body of response `id`, base64 encoded
+    Chunk { id: u64, data: String },
+}
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Read fixtures recorded by [`Record`]
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<Fixture>> {
+    let mut fixtures: Vec<(Fixture, Vec<u8>)> = Vec::new();
+    // Ids restart with every run recording to the file, a chunk belongs to
+    // the latest response with its id
+    let mut responses = HashMap::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line).map_err(invalid_data)? {
+            Entry::Response {
+                id,
+                method,
+                url,
+                status,
+                headers,
+            } => {
+                responses.insert(id, fixtures.len());
+                let fixture = Fixture {
+                    method,
+                    url,
+                    status,
+                    headers,
+                    body: String::new(),
+                };
+                fixtures.push((fixture, Vec::new()));
+            }
+            Entry::Chunk { id, data } => {
+                let index = responses
+                    .get(&id)
+                    .ok_or_else(|| invalid_data(format!("chunk of unknown response {}", id)))?;
+                let data = BASE64.decode(data.as_bytes()).map_err(invalid_data)?;
+                fixtures[*index].1.extend_from_slice(&data);
+            }
+        }
+    }
+
+    Ok(fixtures
+        .into_iter()
+        .map(|(fixture, body)| Fixture {
+            body: BASE64.encode(&body),
+            ..fixture
+        })
+        .collect())
+}
+
+/// Fixture file shared by [`Record`] and the bodies it is recording
+struct FixtureFile {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl FixtureFile {
+    fn append(&self, entry: &Entry) {
+        let mut line = serde_json::to_vec(entry).expect("Failed to serialize fixture");
+        line.push(b'\n');
+        let mut file = self.file.lock().expect("Fixtures lock poisoned");
+        if let Err(err) = file.write_all(&line) {
+            warn!(path = ?self.path, "Failed to write HTTP fixtures - {}", err);
+        }
+    }
+}
+
+/// Middleware appending every response to a fixture file. Bodies are
+/// passed on to the caller as they arrive and recorded as they are read,
+/// so a body the caller drops unread is recorded up to where it stopped.
+pub struct Record {
+    file: Arc<FixtureFile>,
+    next_id: AtomicU64,
+}
+
+impl Record {
+    /// Record to `path`, appending to it if it exists
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            file: Arc::new(FixtureFile {
+                path,
+                file: Mutex::new(file),
+            }),
+            next_id: AtomicU64::new(0),
+        })
+    }
+}
+
+impl<C> Middleware<C> for Record
+where
+    C: Client<Request = reqwest::Request, Response = reqwest::Response> + Sync,
+{
+    fn handle<'a>(
+        &'a self,
+        req: reqwest::Request,
+        next: Next<'a, C>,
+    ) -> BoxFuture<'a, Result<C::Response, C::Error>> {
+        Box::pin(async move {
+            let method = req.method().to_string();
+            let url = req.url().to_string();
+            let res = next.run(req).await?;
+
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let headers = res
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect();
+            self.file.append(&Entry::Response {
+                id,
+                method,
+                url,
+                status: res.status().as_u16(),
+                headers,
+            });
+
+            let mut builder = http::response::Builder::new()
+                .status(res.status())
+                .version(res.version());
+            if let Some(headers) = builder.headers_mut() {
+                *headers = res.headers().clone();
+            }
+            let file = self.file.clone();
+            let body = res.bytes_stream().inspect_ok(move |chunk| {
+                file.append(&Entry::Chunk {
+                    id,
+                    data: BASE64.encode(chunk),
+                })
+            });
+            let res = builder
+                .body(reqwest::Body::wrap_stream(body))
+                .expect("Invalid recorded response");
+
+            Ok(reqwest::Response::from(res))
+        })
+    }
+}
+
+/// Mock client answering requests with recorded responses
+#[cfg(test)]
+pub fn replay(fixtures: Vec<Fixture>) -> super::reqwest::mock::MockHttpClient {
+    let mut client =
+        super::reqwest::mock::MockHttpClient::new(|a: &reqwest::Request, b: &reqwest::Request| {
+            a.url() == b.url()
+        });
+    for fixture in fixtures {
+        let (method, url) = (fixture.method.clone(), fixture.url.clone());
+        client = client
+            .when_matches(move |req: &reqwest::Request| {
+                req.method().as_str() == method && req.url().as_str() == url
+            })
+            .then(move |_: &reqwest::Request| fixture.to_response());
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use reqwest::{Method, Request, Response, Url};
+
+    use super::{load, replay, Record};
+    use crate::http::{reqwest::mock::MockHttpClient, Client};
+
+    fn recording_client(path: &Path) -> impl Client<Request = Request, Response = Response> {
+        MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.url().as_str() == "http://arweave.example.com/info")
+            .then(|_: &Request| {
+                Response::from(
+                    http::response::Builder::new()
+                        .status(200)
+                        .header("Content-Type", "application/json")
+                        .body(r#"{"height":1000}"#)
+                        .unwrap(),
+                )
+            })
+            .when(|req: &Request| req.url().as_str() == "http://arweave.example.com/tx/id")
+            .then(|_: &Request| {
+                Response::from(
+                    http::response::Builder::new()
+                        .status(404)
+                        .body("Not Found")
+                        .unwrap(),
+                )
+            })
+            .with(Record::new(path.to_path_buf()).unwrap())
+    }
+
+    fn get(url: &str) -> Request {
+        Request::new(Method::GET, Url::parse(url).unwrap())
+    }
+
+    #[actix_rt::test]
+    async fn recorded_responses_are_replayed() {
+        let path = std::env::temp_dir().join("validator_http_fixtures.jsonl");
+        let _ = fs::remove_file(&path);
+        let info = "http://arweave.example.com/info";
+        let tx = "http://arweave.example.com/tx/id";
+
+        let recorded = recording_client(&path)
+            .execute(get(info))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        // A later run appends to the same file
+        let missing = recording_client(&path).execute(get(tx)).await.unwrap();
+        assert_eq!(missing.text().await.unwrap(), "Not Found");
+
+        let fixtures = load(&path).unwrap();
+        assert_eq!(fixtures.len(), 2);
+        let client = replay(fixtures);
+        let replayed = client.execute(get(info)).await.unwrap();
+        assert_eq!(
+            replayed.headers()["content-type"].to_str().unwrap(),
+            "application/json"
+        );
+        assert_eq!(replayed.text().await.unwrap(), recorded);
+        assert_eq!(recorded, r#"{"height":1000}"#);
+        let replayed = client.execute(get(tx)).await.unwrap();
+        assert_eq!(replayed.status(), 404);
+        assert_eq!(replayed.text().await.unwrap(), "Not Found");
+    }
+}
@@ -0,0 +1,562 @@
+//! Middlewares for clients sending reqwest requests
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use reqwest::{
+    header::{HeaderMap, HeaderName},
+    Method, StatusCode,
+};
+use serde::Deserialize;
+use tracing::debug;
+
+use super::{Client, Middleware, Next};
+use crate::{config::SharedRuntimeConfig, metrics::Metrics};
+
+/// Log method, url, status and duration of every request
+pub struct Logging;
+
+impl<C> Middleware<C> for Logging
+where
+    C: Client<Request = reqwest::Request, Response = reqwest::Response> + Sync,
+{
+    fn handle<'a>(
+        &'a self,
+        req: reqwest::Request,
+        next: Next<'a, C>,
+    ) -> BoxFuture<'a, Result<C::Response, C::Error>> {
+        Box::pin(async move {
+            let method = req.method().clone();
+            let url = req.url().clone();
+            let start = Instant::now();
+            let res = next.run(req).await;
+            let elapsed_ms = start.elapsed().as_millis() as u64;
+            match &res {
+                Ok(res) => debug!(
+                    %method,
+                    %url,
+                    status = res.status().as_u16(),
+                    elapsed_ms,
+                    "HTTP request"
+                ),
+                Err(err) => debug!(%method, %url, elapsed_ms, "HTTP request failed - {:?}", err),
+            }
+            res
+        })
+    }
+}
+
+/// Count requests by host and status in `outbound_requests` and record
+/// their duration by dependency in `outbound_request_duration`
+pub struct RecordMetrics(pub Metrics);
+
+impl<C> Middleware<C> for RecordMetrics
+where
+    C: Client<Request = reqwest::Request, Response = reqwest::Response> + Sync,
+{
+    fn handle<'a>(
+        &'a self,
+        req: reqwest::Request,
+        next: Next<'a, C>,
+    ) -> BoxFuture<'a, Result<C::Response, C::Error>> {
+        Box::pin(async move {
+            let host = req.url().host_str().unwrap_or("unknown").to_string();
+            let timer = self
+                .0
+                .outbound_request_duration
+                .with_label_values(&[self.0.dependency(req.url())])
+                .start_timer();
+            let res = next.run(req).await;
+            timer.observe_duration();
+            let status = match &res {
+                Ok(res) => res.status().as_str().to_string(),
+                Err(_) => "error".to_string(),
+            };
+            self.0
+                .outbound_requests
+                .with_label_values(&[&host, &status])
+                .inc();
+            res
+        })
+    }
+}
+
+/// Longest `Retry-After` waited for before retrying a request, longer
+/// delays are left to the caller
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Header marking a request as safe to send again whatever its method, the
+/// server applies requests with the same key only once
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Retry idempotent requests that failed or got a response indicating the
+/// server is temporarily unavailable, waiting twice as long before each retry.
+/// Requests are idempotent by method or when they carry [`IDEMPOTENCY_KEY`].
+/// A 429 or 503 response with `Retry-After` is retried after the delay it
+/// asks for instead, or returned right away if that's over `max_retry_after`.
+pub struct Retry {
+    pub max_retries: u32,
+    pub backoff: Duration,
+    pub max_retry_after: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            backoff: Duration::from_millis(500),
+            max_retry_after: MAX_RETRY_AFTER,
+        }
+    }
+}
+
+fn is_idempotent(req: &reqwest::Request) -> bool {
+    matches!(
+        *req.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE
+    ) || req.headers().contains_key(IDEMPOTENCY_KEY)
+}
+
+/// Statuses worth retrying a request for, the server may answer it later
+pub fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Delay a 429 or 503 response asks to wait before the next request, from
+/// its `Retry-After` header in seconds or as an HTTP date
+pub fn retry_after(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if !matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    ) {
+        return None;
+    }
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        date.with_timezone(&Utc)
+            .signed_duration_since(Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+impl<C> Middleware<C> for Retry
+where
+    C: Client<Request = reqwest::Request, Response = reqwest::Response> + Sync,
+{
+    fn handle<'a>(
+        &'a self,
+        req: reqwest::Request,
+        next: Next<'a, C>,
+    ) -> BoxFuture<'a, Result<C::Response, C::Error>> {
+        Box::pin(async move {
+            let mut req = req;
+            let mut attempt = 0;
+            loop {
+                let retry_req = if attempt < self.max_retries && is_idempotent(&req) {
+                    req.try_clone()
+                } else {
+                    None
+                };
+
+                let res = next.run(req).await;
+                let (failed, delay) = match &res {
+                    Ok(res) => (
+                        is_transient(res.status()),
+                        retry_after(res.status(), res.headers()),
+                    ),
+                    Err(_) => (true, None),
+                };
+                match (retry_req, delay) {
+                    (Some(_), Some(delay)) if delay > self.max_retry_after => return res,
+                    (Some(retry_req), delay) if failed => {
+                        let delay = delay.unwrap_or(self.backoff * 2u32.pow(attempt));
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                        req = retry_req;
+                    }
+                    _ => return res,
+                }
+            }
+        })
+    }
+}
+
+/// Space requests evenly so no more than the given number is sent per second
+pub struct RateLimit {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimit {
+    pub fn per_second(requests: u32) -> Self {
+        Self {
+            interval: Self::interval(requests),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn interval(requests: u32) -> Duration {
+        Duration::from_secs(1) / requests.max(1)
+    }
+
+    /// Reserve the next free slot, returns how long to wait for it
+    fn reserve(&self) -> Duration {
+        let mut next_slot = self.next_slot.lock().expect("Rate limit lock poisoned");
+        let now = Instant::now();
+        let slot = (*next_slot).max(now);
+        *next_slot = slot + self.interval;
+        slot - now
+    }
+}
+
+impl<C> Middleware<C> for RateLimit
+where
+    C: Client<Request = reqwest::Request, Response = reqwest::Response> + Sync,
+{
+    fn handle<'a>(
+        &'a self,
+        req: reqwest::Request,
+        next: Next<'a, C>,
+    ) -> BoxFuture<'a, Result<C::Response, C::Error>> {
+        Box::pin(async move {
+            let wait = self.reserve();
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+            next.run(req).await
+        })
+    }
+}
+
+/// Requests per second sent to each host of a dependency, unlimited when
+/// unset
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub arweave: Option<u32>,
+    pub contract_gateway: Option<u32>,
+    pub peer: Option<u32>,
+}
+
+impl RateLimitConfig {
+    /// Requests per second allowed to hosts of `dependency`
+    fn per_second(&self, dependency: &str) -> Option<u32> {
+        match dependency {
+            "arweave" => self.arweave,
+            "contract_gateway" => self.contract_gateway,
+            "peer" => self.peer,
+            _ => None,
+        }
+    }
+}
+
+/// [`RateLimit`] for every host of the dependencies limited in the runtime
+/// config, dependencies are told apart the same way as in [`RecordMetrics`].
+/// Limits are read on every request, so a reloaded config applies to the
+/// next one.
+pub struct DependencyRateLimit {
+    metrics: Metrics,
+    config: SharedRuntimeConfig,
+    hosts: Mutex<HashMap<String, Arc<RateLimit>>>,
+}
+
+impl DependencyRateLimit {
+    pub fn new(metrics: Metrics, config: SharedRuntimeConfig) -> Self {
+        Self {
+            metrics,
+            config,
+            hosts: Mutex::default(),
+        }
+    }
+
+    /// Reserve the next free slot for a request to `url`, returns how long
+    /// to wait for it
+    fn reserve(&self, url: &reqwest::Url) -> Duration {
+        let requests = self
+            .config
+            .read()
+            .expect("Runtime config lock poisoned")
+            .rate_limits
+            .per_second(self.metrics.dependency(url));
+        let requests = match requests {
+            Some(requests) => requests,
+            None => return Duration::ZERO,
+        };
+        let host = url.host_str().unwrap_or_default().to_string();
+        let limit = {
+            let mut hosts = self.hosts.lock().expect("Rate limits lock poisoned");
+            let limit = hosts
+                .entry(host)
+                .or_insert_with(|| Arc::new(RateLimit::per_second(requests)));
+            if limit.interval != RateLimit::interval(requests) {
+                *limit = Arc::new(RateLimit::per_second(requests));
+            }
+            limit.clone()
+        };
+        limit.reserve()
+    }
+}
+
+impl<C> Middleware<C> for DependencyRateLimit
+where
+    C: Client<Request = reqwest::Request, Response = reqwest::Response> + Sync,
+{
+    fn handle<'a>(
+        &'a self,
+        req: reqwest::Request,
+        next: Next<'a, C>,
+    ) -> BoxFuture<'a, Result<C::Response, C::Error>> {
+        Box::pin(async move {
+            let wait = self.reserve(req.url());
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+            next.run(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, RwLock,
+        },
+        time::Duration,
+    };
+
+    use reqwest::{
+        header::{HeaderMap, HeaderValue, RETRY_AFTER},
+        Method, Request, Response, StatusCode, Url,
+    };
+
+    use super::{
+        retry_after, DependencyRateLimit, RateLimit, RateLimitConfig, RecordMetrics, Retry,
+        IDEMPOTENCY_KEY,
+    };
+    use crate::{
+        config::RuntimeConfig,
+        http::{reqwest::mock::MockHttpClient, Client},
+        metrics::Metrics,
+    };
+
+    fn get(url: &str) -> Request {
+        Request::new(Method::GET, Url::parse(url).unwrap())
+    }
+
+    #[actix_rt::test]
+    async fn retry_repeats_request_until_success() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.url().as_str() == "http://example.com/")
+            .then(move |_: &Request| {
+                let status = if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    503
+                } else {
+                    200
+                };
+                Response::from(
+                    http::response::Builder::new()
+                        .status(status)
+                        .body("")
+                        .unwrap(),
+                )
+            })
+            .with(Retry {
+                max_retries: 2,
+                backoff: Duration::from_millis(1),
+                ..Retry::default()
+            });
+
+        let res = client.execute(get("http://example.com/")).await.unwrap();
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn retry_after_longer_than_allowed_is_left_to_caller() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.url().as_str() == "http://example.com/")
+            .then(move |_: &Request| {
+                let retry_after = if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    "0"
+                } else {
+                    "120"
+                };
+                Response::from(
+                    http::response::Builder::new()
+                        .status(429)
+                        .header("Retry-After", retry_after)
+                        .body("")
+                        .unwrap(),
+                )
+            })
+            .with(Retry {
+                max_retries: 5,
+                backoff: Duration::from_secs(60),
+                max_retry_after: Duration::from_secs(30),
+            });
+
+        let res = client.execute(get("http://example.com/")).await.unwrap();
+
+        // Retried right away as asked instead of after the backoff, then
+        // given up on once the gateway asks for more than allowed
+        assert_eq!(res.status(), 429);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn post_is_only_retried_with_idempotency_key() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.url().as_str() == "http://example.com/")
+            .then(move |_: &Request| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Response::from(http::response::Builder::new().status(503).body("").unwrap())
+            })
+            .with(Retry {
+                max_retries: 1,
+                backoff: Duration::from_millis(1),
+                ..Retry::default()
+            });
+        let post = || Request::new(Method::POST, Url::parse("http://example.com/").unwrap());
+
+        client.execute(post()).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let mut req = post();
+        req.headers_mut()
+            .insert(IDEMPOTENCY_KEY, HeaderValue::from_static("42"));
+        client.execute(req).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn retry_after_is_read_as_seconds_or_date() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(
+            retry_after(StatusCode::TOO_MANY_REQUESTS, &headers),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(retry_after(StatusCode::BAD_GATEWAY, &headers), None);
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(
+            retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers),
+            Some(Duration::ZERO)
+        );
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers), None);
+    }
+
+    #[actix_rt::test]
+    async fn middlewares_run_in_order() {
+        let metrics = Metrics::new();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.url().as_str() == "http://example.com/")
+            .then(|_: &Request| {
+                Response::from(http::response::Builder::new().status(503).body("").unwrap())
+            })
+            .with(RecordMetrics(metrics.clone()))
+            .with(Retry {
+                max_retries: 1,
+                backoff: Duration::from_millis(1),
+                ..Retry::default()
+            });
+
+        let res = client.execute(get("http://example.com/")).await.unwrap();
+
+        assert_eq!(res.status(), 503);
+        // Retries happen inside the metrics middleware, so only the final
+        // response is counted
+        assert_eq!(
+            metrics
+                .outbound_requests
+                .with_label_values(&["example.com", "503"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .outbound_request_duration
+                .with_label_values(&["other"])
+                .get_sample_count(),
+            1
+        );
+    }
+
+    #[test]
+    fn rate_limit_spaces_requests() {
+        let limit = RateLimit::per_second(10);
+
+        assert!(limit.reserve().is_zero());
+        assert!(limit.reserve() > Duration::from_millis(90));
+    }
+
+    #[test]
+    fn only_limited_dependencies_are_spaced() {
+        let metrics = Metrics::new();
+        let arweave = Url::parse("https://arweave.net/info").unwrap();
+        let peer = Url::parse("https://validator.example.com/").unwrap();
+        let other_peer = Url::parse("https://other.example.com/").unwrap();
+        metrics.register_dependency(&arweave, "arweave");
+        metrics.register_dependency(&peer, "peer");
+        metrics.register_dependency(&other_peer, "peer");
+        let config = Arc::new(RwLock::new(RuntimeConfig {
+            rate_limits: RateLimitConfig {
+                arweave: Some(10),
+                peer: Some(10),
+                ..RateLimitConfig::default()
+            },
+            ..RuntimeConfig::default()
+        }));
+        let limit = DependencyRateLimit::new(metrics, config.clone());
+
+        assert!(limit.reserve(&arweave).is_zero());
+        assert!(limit.reserve(&arweave) > Duration::from_millis(90));
+        // Each peer is limited on its own
+        assert!(limit.reserve(&peer).is_zero());
+        assert!(limit.reserve(&other_peer).is_zero());
+        let unlimited = Url::parse("http://example.com/").unwrap();
+        assert!(limit.reserve(&unlimited).is_zero());
+        assert!(limit.reserve(&unlimited).is_zero());
+
+        // Reloaded limits apply to the next request
+        config.write().unwrap().rate_limits.arweave = Some(1);
+        assert!(limit.reserve(&arweave).is_zero());
+        assert!(limit.reserve(&arweave) > Duration::from_millis(900));
+        config.write().unwrap().rate_limits = RateLimitConfig::default();
+        assert!(limit.reserve(&arweave).is_zero());
+    }
+}
@@ -1,10 +1,40 @@
+use std::sync::Arc;
+
 use futures::future::BoxFuture;
+use serde::Deserialize;
 
+#[cfg(feature = "reqwest-client")]
+pub mod fixtures;
+#[cfg(feature = "reqwest-client")]
+pub mod middleware;
 #[cfg(feature = "reqwest-client")]
 pub mod reqwest;
 
 pub use http::{method, request, response};
 
+/// Settings of the client all outgoing requests are sent with, unset values
+/// keep reqwest defaults
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpClientConfig {
+    /// Idle connections kept open to each host
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Seconds an idle connection is kept open
+    pub pool_idle_timeout: Option<u64>,
+    /// Seconds between TCP keep-alive probes
+    pub tcp_keepalive: Option<u64>,
+    /// Never negotiate HTTP/2, for hosts or proxies that mishandle it
+    pub http1_only: bool,
+    /// Speak HTTP/2 without negotiating it, only for hosts known to
+    /// support it
+    pub http2_prior_knowledge: bool,
+    /// Seconds a connection may take to be established
+    pub connect_timeout: Option<u64>,
+    /// Seconds a whole request may take, including reading the body. Applies
+    /// to bundle downloads too, so it must leave room for the largest ones.
+    pub timeout: Option<u64>,
+}
+
 pub trait ClientAccess<HttpClient>
 where
     HttpClient: Client,
@@ -18,6 +48,127 @@ pub trait Client {
     type Error: std::fmt::Debug;
 
     fn execute(&self, req: Self::Request) -> BoxFuture<Result<Self::Response, Self::Error>>;
+
+    /// Wrap the client with `middleware`, see [`Layered`]
+    fn with<M>(self, middleware: M) -> Layered<Self>
+    where
+        Self: Sized,
+        M: Middleware<Self> + 'static,
+    {
+        Layered::new(self).with(middleware)
+    }
+}
+
+/// Behavior shared by all requests sent through a client, like retries or
+/// logging. A middleware gets the request and decides whether, and how many
+/// times, to pass it on to the rest of the chain through `next`.
+pub trait Middleware<C>: Send + Sync
+where
+    C: Client,
+{
+    fn handle<'a>(
+        &'a self,
+        req: C::Request,
+        next: Next<'a, C>,
+    ) -> BoxFuture<'a, Result<C::Response, C::Error>>;
+}
+
+/// Rest of the middleware chain, ending with the wrapped client
+pub struct Next<'a, C>
+where
+    C: Client,
+{
+    client: &'a C,
+    middlewares: &'a [Arc<dyn Middleware<C>>],
+}
+
+impl<'a, C> Clone for Next<'a, C>
+where
+    C: Client,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, C> Copy for Next<'a, C> where C: Client {}
+
+impl<'a, C> Next<'a, C>
+where
+    C: Client,
+{
+    pub fn run(self, req: C::Request) -> BoxFuture<'a, Result<C::Response, C::Error>> {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                req,
+                Next {
+                    client: self.client,
+                    middlewares: rest,
+                },
+            ),
+            None => self.client.execute(req),
+        }
+    }
+}
+
+/// Client passing requests through a chain of middlewares, the first added
+/// middleware sees the request first
+pub struct Layered<C>
+where
+    C: Client,
+{
+    client: C,
+    middlewares: Vec<Arc<dyn Middleware<C>>>,
+}
+
+impl<C> Layered<C>
+where
+    C: Client,
+{
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Add `middleware` at the end of the chain
+    pub fn with<M>(mut self, middleware: M) -> Self
+    where
+        M: Middleware<C> + 'static,
+    {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+}
+
+impl<C> Clone for Layered<C>
+where
+    C: Client + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            middlewares: self.middlewares.clone(),
+        }
+    }
+}
+
+impl<C> Client for Layered<C>
+where
+    C: Client,
+{
+    type Request = C::Request;
+    type Response = C::Response;
+    type Error = C::Error;
+
+    fn execute(&self, req: Self::Request) -> BoxFuture<Result<Self::Response, Self::Error>> {
+        Next {
+            client: &self.client,
+            middlewares: &self.middlewares,
+        }
+        .run(req)
+    }
 }
 
 #[cfg(test)]
@@ -32,9 +183,14 @@ pub mod mock {
 
     use super::Client;
 
+    type Matcher<Request> = Pin<Box<dyn Fn(&Request) -> bool>>;
+
     struct Handler<Request, Response> {
-        matcher: fn(&Request) -> bool,
+        matcher: Matcher<Request>,
         response_builder: Pin<Box<dyn Fn(&Request) -> Response>>,
+        /// Number of calls the handler expects, any number if not set
+        expected_calls: Option<usize>,
+        calls: usize,
     }
 
     unsafe impl<Request, Response> Send for Handler<Request, Response>
@@ -49,33 +205,73 @@ pub mod mock {
         count: usize,
     }
 
+    impl<Request> Call<Request> {
+        pub fn req(&self) -> &Request {
+            &self.req
+        }
+
+        /// Number of times an equal request was made
+        pub fn count(&self) -> usize {
+            self.count
+        }
+    }
+
     pub struct When<Request, Response> {
         client: MockClient<Request, Response>,
-        matcher: fn(&Request) -> bool,
+        matcher: Matcher<Request>,
+        expected_calls: Option<usize>,
     }
 
     impl<Request, Response> When<Request, Response> {
-        fn new(client: MockClient<Request, Response>, matcher: fn(&Request) -> bool) -> Self {
-            Self { client, matcher }
+        fn new(client: MockClient<Request, Response>, matcher: Matcher<Request>) -> Self {
+            Self {
+                client,
+                matcher,
+                expected_calls: None,
+            }
+        }
+
+        /// Expect the handler to be called exactly `times`, checked by
+        /// [`MockClient::assert_expectations`]
+        pub fn times(mut self, times: usize) -> Self {
+            self.expected_calls = Some(times);
+            self
         }
 
         pub fn then<F>(self, response_builder: F) -> MockClient<Request, Response>
         where
             F: Fn(&Request) -> Response + 'static,
         {
-            self.client
-                .register_handler(self.matcher, Box::pin(response_builder))
+            self.client.register_handler(Handler {
+                matcher: self.matcher,
+                response_builder: Box::pin(response_builder),
+                expected_calls: self.expected_calls,
+                calls: 0,
+            })
         }
     }
 
     #[derive(Debug)]
     pub enum MockHttpClientError {
         ResponseNotSet,
+        #[cfg(feature = "reqwest-client")]
+        Reqwest(reqwest::Error),
+    }
+
+    #[cfg(feature = "reqwest-client")]
+    impl From<reqwest::Error> for MockHttpClientError {
+        fn from(err: reqwest::Error) -> Self {
+            Self::Reqwest(err)
+        }
     }
 
     struct State<Request, Response> {
         handlers: Vec<Handler<Request, Response>>,
         calls: Vec<Call<Request>>,
+        /// Indexes of matched handlers, in the order requests were made
+        matched: Vec<usize>,
+        unmatched: Vec<Request>,
+        in_order: bool,
     }
 
     impl<Request, Response> State<Request, Response> {
@@ -83,6 +279,9 @@ pub mod mock {
             Self {
                 handlers: Vec::new(),
                 calls: Vec::new(),
+                matched: Vec::new(),
+                unmatched: Vec::new(),
+                in_order: false,
             }
         }
     }
@@ -109,23 +308,64 @@ pub mod mock {
             }
         }
 
-        fn register_handler(
-            self,
-            matcher: fn(&Request) -> bool,
-            response_builder: Pin<Box<dyn Fn(&Request) -> Response>>,
-        ) -> Self {
-            {
-                let mut state = self.state.lock().unwrap();
-                state.handlers.push(Handler {
-                    matcher,
-                    response_builder,
-                });
-            }
+        fn register_handler(self, handler: Handler<Request, Response>) -> Self {
+            self.state.lock().unwrap().handlers.push(handler);
             self
         }
 
         pub fn when(self, matcher: fn(&Request) -> bool) -> When<Request, Response> {
-            When::new(self, matcher)
+            When::new(self, Box::pin(matcher))
+        }
+
+        /// Same as `when`, for matchers that need to capture values
+        pub fn when_matches<F>(self, matcher: F) -> When<Request, Response>
+        where
+            F: Fn(&Request) -> bool + 'static,
+        {
+            When::new(self, Box::pin(matcher))
+        }
+
+        /// Expect handlers to be matched in the order they were registered,
+        /// checked by [`MockClient::assert_expectations`]
+        pub fn in_order(self) -> Self {
+            self.state.lock().unwrap().in_order = true;
+            self
+        }
+
+        /// Remove and return requests no handler matched
+        pub fn take_unmatched(&self) -> Vec<Request> {
+            std::mem::take(&mut self.state.lock().unwrap().unmatched)
+        }
+
+        /// Panic if a handler wasn't called the expected number of times, a
+        /// request wasn't matched, or handlers were matched out of order when
+        /// `in_order` is set
+        pub fn assert_expectations(&self)
+        where
+            Request: fmt::Debug,
+        {
+            let state = self.state.lock().unwrap();
+            for (index, handler) in state.handlers.iter().enumerate() {
+                if let Some(expected) = handler.expected_calls {
+                    assert_eq!(
+                        handler.calls, expected,
+                        "handler {} called {} times, expected {}",
+                        index, handler.calls, expected
+                    );
+                }
+            }
+            assert!(
+                state.unmatched.is_empty(),
+                "unmatched requests: {:?}",
+                state.unmatched
+            );
+            if state.in_order {
+                assert!(
+                    state.matched.windows(2).all(|pair| pair[0] <= pair[1]),
+                    "handlers matched out of order: {:?}",
+                    state.matched
+                );
+            }
         }
 
         pub fn verify(self, verifier: fn(Vec<Call<Request>>)) {
@@ -151,11 +391,14 @@ pub mod mock {
             let mut state = self.state.lock().unwrap();
             let handler = state
                 .handlers
-                .iter()
-                .find(|handler| (handler.matcher)(&req));
+                .iter_mut()
+                .enumerate()
+                .find(|(_, handler)| (handler.matcher)(&req));
             match handler {
-                Some(handler) => {
+                Some((index, handler)) => {
                     let res = (handler.response_builder)(&req);
+                    handler.calls += 1;
+                    state.matched.push(index);
                     if let Some(call) = state
                         .calls
                         .iter_mut()
@@ -169,6 +412,7 @@ pub mod mock {
                 }
                 None => {
                     eprintln!("no handler found for {:?}", req);
+                    state.unmatched.push(req);
                     Box::pin(std::future::ready(Err(MockHttpClientError::ResponseNotSet)))
                 }
             }
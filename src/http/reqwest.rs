@@ -1,5 +1,9 @@
+use std::time::Duration;
+
 use futures::future::BoxFuture;
 
+use super::HttpClientConfig;
+
 #[derive(Clone)]
 pub struct ReqwestClient(reqwest::Client);
 
@@ -19,6 +23,40 @@ impl super::Client for ReqwestClient {
     }
 }
 
+impl HttpClientConfig {
+    /// Apply these settings to `builder`
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        if let Some(max_idle) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(secs) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(Duration::from_secs(secs));
+        }
+        // HTTP/2 is offered to HTTPS hosts through ALPN, those that don't
+        // support it keep talking HTTP/1.1
+        if self.http1_only {
+            builder = builder.http1_only();
+        } else {
+            // Many concurrent downloads share a connection, let its flow
+            // control window grow with the bandwidth available
+            builder = builder.http2_adaptive_window(true);
+            if self.http2_prior_knowledge {
+                builder = builder.http2_prior_knowledge();
+            }
+        }
+        if let Some(secs) = self.connect_timeout {
+            builder = builder.connect_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.timeout {
+            builder = builder.timeout(Duration::from_secs(secs));
+        }
+        builder
+    }
+}
+
 #[cfg(test)]
 pub mod mock {
     pub type MockHttpClient = super::super::mock::MockClient<reqwest::Request, reqwest::Response>;
@@ -98,5 +136,77 @@ pub mod mock {
                 "/foo"
             );
         }
+
+        fn get(url: &str) -> Request {
+            Request::new(Method::GET, reqwest::Url::from_str(url).unwrap())
+        }
+
+        fn ok(_: &Request) -> Response {
+            Response::from(http::response::Builder::new().status(200).body("").unwrap())
+        }
+
+        #[actix_rt::test]
+        async fn mock_counts_calls_and_unmatched_requests() {
+            let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+                .when(|req: &Request| req.url().path() == "/info")
+                .times(2)
+                .then(ok);
+
+            client
+                .execute(get("http://example.com/info"))
+                .await
+                .unwrap();
+            client
+                .execute(get("http://example.com/info"))
+                .await
+                .unwrap();
+            assert!(client
+                .execute(get("http://example.com/other"))
+                .await
+                .is_err());
+
+            let unmatched = client.take_unmatched();
+            assert_eq!(unmatched.len(), 1);
+            assert_eq!(unmatched[0].url().path(), "/other");
+            client.assert_expectations();
+        }
+
+        #[actix_rt::test]
+        #[should_panic(expected = "handler 0 called 1 times, expected 2")]
+        async fn mock_panics_on_unmet_call_count() {
+            let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+                .when(|req: &Request| req.url().path() == "/info")
+                .times(2)
+                .then(ok);
+
+            client
+                .execute(get("http://example.com/info"))
+                .await
+                .unwrap();
+
+            client.assert_expectations();
+        }
+
+        #[actix_rt::test]
+        #[should_panic(expected = "handlers matched out of order")]
+        async fn mock_panics_on_out_of_order_calls() {
+            let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+                .when(|req: &Request| req.url().path() == "/first")
+                .then(ok)
+                .when(|req: &Request| req.url().path() == "/second")
+                .then(ok)
+                .in_order();
+
+            client
+                .execute(get("http://example.com/second"))
+                .await
+                .unwrap();
+            client
+                .execute(get("http://example.com/first"))
+                .await
+                .unwrap();
+
+            client.assert_expectations();
+        }
     }
 }
@@ -1,14 +1,16 @@
-use std::ops::Deref;
+use std::{ops::Deref, str::FromStr, sync::RwLock};
 
 use data_encoding::BASE64URL_NOPAD;
-use jsonwebkey::JsonWebKey;
+use jsonwebkey::{JsonWebKey, Key, PublicExponent, RsaPrivate, RsaPublic};
 use openssl::{
+    bn::BigNum,
     hash::MessageDigest,
     pkey::{PKey, Private, Public},
-    rsa::Padding,
+    rsa::{Padding, Rsa},
     sha::Sha256,
     sign,
 };
+use thiserror::Error;
 
 pub trait KeyManagerAccess<KeyManager>
 where
@@ -19,12 +21,38 @@ where
 
 pub trait KeyManager {
     fn bundler_address(&self) -> &str; // FIXME: replace with Address
+    /// Addresses of every bundler key receipts are accepted from, the main
+    /// key first
+    fn bundler_addresses(&self) -> Vec<String>;
     fn validator_address(&self) -> &str; // FIXME: replace with Address
+    /// Public modulus of the validator key, `owner` in Arweave terms
+    fn validator_owner(&self) -> Vec<u8>;
+    /// Public exponent of the validator key
+    fn validator_exponent(&self) -> Vec<u8>;
     fn validator_sign(&self, data: &[u8]) -> Vec<u8>;
     // FIXME: return Result
     fn verify_bundler_signature(&self, data: &[u8], sig: &[u8]) -> bool;
+    /// Identifier of the bundler key `sig` was made with, `None` if it
+    /// wasn't made with any of the configured bundler keys
+    fn bundler_key_id(&self, data: &[u8], sig: &[u8]) -> Option<String>;
     // FIXME: return Result
     fn verify_validator_signature(&self, data: &[u8], sig: &[u8]) -> bool;
+    /// Accept receipts signed with the key with public modulus `owner` the
+    /// bundler serves now, in place of the one it served before. Callers
+    /// check first that the key belongs to the bundler in the contract.
+    fn use_served_bundler_key(&self, owner: &[u8]) -> ServedBundlerKey;
+}
+
+/// Outcome of `KeyManager::use_served_bundler_key`
+#[derive(Debug, PartialEq)]
+pub enum ServedBundlerKey {
+    /// Key is already accepted
+    Known,
+    /// Key with this address is accepted from now on
+    Adopted(String),
+    /// Key with this address isn't accepted, the bundler key is pinned or
+    /// the modulus isn't a valid RSA key
+    Refused(String),
 }
 
 impl<T, K> KeyManager for T
@@ -36,10 +64,22 @@ where
         self.deref().bundler_address()
     }
 
+    fn bundler_addresses(&self) -> Vec<String> {
+        self.deref().bundler_addresses()
+    }
+
     fn validator_address(&self) -> &str {
         self.deref().validator_address()
     }
 
+    fn validator_owner(&self) -> Vec<u8> {
+        self.deref().validator_owner()
+    }
+
+    fn validator_exponent(&self) -> Vec<u8> {
+        self.deref().validator_exponent()
+    }
+
     fn validator_sign(&self, data: &[u8]) -> Vec<u8> {
         self.deref().validator_sign(data)
     }
@@ -48,9 +88,17 @@ where
         self.deref().verify_bundler_signature(data, sig)
     }
 
+    fn bundler_key_id(&self, data: &[u8], sig: &[u8]) -> Option<String> {
+        self.deref().bundler_key_id(data, sig)
+    }
+
     fn verify_validator_signature(&self, data: &[u8], sig: &[u8]) -> bool {
         self.deref().verify_validator_signature(data, sig)
     }
+
+    fn use_served_bundler_key(&self, owner: &[u8]) -> ServedBundlerKey {
+        self.deref().use_served_bundler_key(owner)
+    }
 }
 
 pub fn split_jwk(jwk: &JsonWebKey) -> (PKey<Private>, PKey<Public>, String) {
@@ -85,14 +133,125 @@ pub fn split_public_only_jwk(jwk: &JsonWebKey) -> (PKey<Public>, String) {
     (pub_key, address)
 }
 
+/// Public part of `jwk`, to be shared without the private key
+pub fn public_jwk(jwk: &JsonWebKey) -> JsonWebKey {
+    JsonWebKey::new(jwk.key.to_public().unwrap().into_owned())
+}
+
+/// Arweave address of the key with public modulus `owner`
+pub fn owner_address(owner: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(owner);
+    BASE64URL_NOPAD.encode(&hasher.finish())
+}
+
+/// RSA public key with modulus `owner` and the exponent Arweave uses
+fn owner_public_key(owner: &[u8]) -> Result<PKey<Public>, openssl::error::ErrorStack> {
+    BigNum::from_slice(owner)
+        .and_then(|n| Ok((n, BigNum::from_u32(65537)?)))
+        .and_then(|(n, e)| Rsa::from_public_components(n, e))
+        .and_then(PKey::from_rsa)
+}
+
+/// Verify `sig` was made over `data` by the key with public modulus `owner`
+pub fn verify_owner_signature(owner: &[u8], data: &[u8], sig: &[u8]) -> bool {
+    let pub_key = match owner_public_key(owner) {
+        Ok(pub_key) => pub_key,
+        Err(_) => return false,
+    };
+
+    let mut verifier = sign::Verifier::new(MessageDigest::sha256(), &pub_key).unwrap();
+    verifier.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+    verifier.update(data).unwrap();
+    verifier.verify(sig).unwrap_or(false)
+}
+
+/// Type of keys generated by `generate_jwk`. Only RSA keys are offered,
+/// Arweave wallets and the signatures checked by the bundler and contract
+/// are RSA.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum KeyType {
+    #[default]
+    Rsa2048,
+    Rsa4096,
+}
+
+impl KeyType {
+    pub fn bits(&self) -> u32 {
+        match self {
+            KeyType::Rsa2048 => 2048,
+            KeyType::Rsa4096 => 4096,
+        }
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rsa-2048" => Ok(KeyType::Rsa2048),
+            "rsa-4096" => Ok(KeyType::Rsa4096),
+            other => Err(format!(
+                "unsupported key type {:?}, expecting rsa-2048 or rsa-4096",
+                other
+            )),
+        }
+    }
+}
+
+/// Generate new RSA key usable as an Arweave wallet
+pub fn generate_jwk(key_type: KeyType) -> JsonWebKey {
+    let rsa = Rsa::generate(key_type.bits()).unwrap();
+
+    JsonWebKey::new(Key::RSA {
+        public: RsaPublic {
+            e: PublicExponent,
+            n: rsa.n().to_vec().into(),
+        },
+        private: Some(RsaPrivate {
+            d: rsa.d().to_vec().into(),
+            p: rsa.p().map(|v| v.to_vec().into()),
+            q: rsa.q().map(|v| v.to_vec().into()),
+            dp: rsa.dmp1().map(|v| v.to_vec().into()),
+            dq: rsa.dmq1().map(|v| v.to_vec().into()),
+            qi: rsa.iqmp().map(|v| v.to_vec().into()),
+        }),
+    })
+}
+
 pub trait InMemoryKeyManagerConfig {
     fn bundler_jwk(&self) -> &JsonWebKey;
     fn validator_jwk(&self) -> &JsonWebKey;
 }
 
+/// Bundler key given in the config that can't be used
+#[derive(Debug, Error)]
+pub enum BundlerKeyError {
+    #[error("invalid base64url encoding: {0}")]
+    Encoding(#[from] data_encoding::DecodeError),
+    #[error("invalid RSA modulus: {0}")]
+    Key(#[from] openssl::error::ErrorStack),
+}
+
+/// Address and key of the base64url encoded modulus `n`
+fn decode_bundler_key(n: &str) -> Result<(String, PKey<Public>), BundlerKeyError> {
+    let owner = BASE64URL_NOPAD.decode(n.trim().as_bytes())?;
+    Ok((owner_address(&owner), owner_public_key(&owner)?))
+}
+
 pub struct InMemoryKeyManager {
     bundler_address: String,
     bundler_public: PKey<Public>,
+    /// Other keys the bundler may sign receipts with, e.g. while it rotates
+    /// its key, with their addresses
+    additional_bundler_keys: RwLock<Vec<(String, PKey<Public>)>>,
+    /// Key the bundler served on `/public` since startup, if it moved to
+    /// one not otherwise accepted
+    served_bundler_key: RwLock<Option<(String, PKey<Public>)>>,
+    /// Address of the pinned bundler key, a key served later is refused
+    /// unless it is this one
+    pinned_bundler_key: RwLock<Option<String>>,
     validator_address: String,
     validator_public: PKey<Public>,
     validator_private: PKey<Private>,
@@ -112,11 +271,103 @@ impl InMemoryKeyManager {
         Self {
             bundler_address,
             bundler_public,
+            additional_bundler_keys: RwLock::new(Vec::new()),
+            served_bundler_key: RwLock::new(None),
+            pinned_bundler_key: RwLock::new(None),
             validator_address,
             validator_private,
             validator_public,
         }
     }
+
+    /// Only accept the bundler key given at startup and additional keys,
+    /// never one the bundler serves later
+    pub fn with_pinned_bundler_key(self) -> Self {
+        *self
+            .pinned_bundler_key
+            .write()
+            .expect("Pinned bundler key lock poisoned") = Some(self.bundler_address.clone());
+        self
+    }
+
+    /// Replace the pinned bundler key and the additional bundler keys with
+    /// the base64url encoded moduli given in the config. Nothing is
+    /// replaced if any of them is invalid.
+    pub fn set_bundler_keys(
+        &self,
+        pinned: Option<&str>,
+        additional: &[String],
+    ) -> Result<(), BundlerKeyError> {
+        let pinned = pinned
+            .map(decode_bundler_key)
+            .transpose()?
+            .map(|(address, _)| address);
+        let additional = additional
+            .iter()
+            .map(|n| decode_bundler_key(n))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut served = self
+            .served_bundler_key
+            .write()
+            .expect("Served bundler key lock poisoned");
+        // A served key other than the one now pinned isn't accepted anymore
+        if matches!((&pinned, &*served), (Some(pinned), Some((address, _))) if pinned != address) {
+            *served = None;
+        }
+        *self
+            .additional_bundler_keys
+            .write()
+            .expect("Additional bundler keys lock poisoned") = additional;
+        *self
+            .pinned_bundler_key
+            .write()
+            .expect("Pinned bundler key lock poisoned") = pinned;
+        Ok(())
+    }
+
+    /// Addresses and keys of configured bundler keys, the main key first
+    fn configured_bundler_keys(&self) -> Vec<(String, PKey<Public>)> {
+        std::iter::once((self.bundler_address.clone(), self.bundler_public.clone()))
+            .chain(
+                self.additional_bundler_keys
+                    .read()
+                    .expect("Additional bundler keys lock poisoned")
+                    .iter()
+                    .cloned(),
+            )
+            .collect()
+    }
+
+    fn served_bundler_key(&self) -> Option<(String, PKey<Public>)> {
+        self.served_bundler_key
+            .read()
+            .expect("Served bundler key lock poisoned")
+            .clone()
+    }
+
+    /// Also accept bundler signatures made with `jwks`
+    pub fn with_bundler_keys(self, jwks: &[JsonWebKey]) -> Self {
+        *self
+            .additional_bundler_keys
+            .write()
+            .expect("Additional bundler keys lock poisoned") = jwks
+            .iter()
+            .map(|jwk| {
+                let (public, address) = split_public_only_jwk(jwk);
+                (address, public)
+            })
+            .collect();
+        self
+    }
+}
+
+fn verify_signature(key: &PKey<Public>, data: &[u8], sig: &[u8]) -> bool {
+    let mut verifier = sign::Verifier::new(MessageDigest::sha256(), key).unwrap();
+    verifier.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+    verifier.update(data).unwrap();
+    // TODO: we shouldn't probably hide errors here, at least we should log them
+    verifier.verify(sig).unwrap_or(false)
 }
 
 impl KeyManager for InMemoryKeyManager {
@@ -124,10 +375,26 @@ impl KeyManager for InMemoryKeyManager {
         &self.bundler_address
     }
 
+    fn bundler_addresses(&self) -> Vec<String> {
+        self.configured_bundler_keys()
+            .into_iter()
+            .map(|(address, _)| address)
+            .chain(self.served_bundler_key().map(|(address, _)| address))
+            .collect()
+    }
+
     fn validator_address(&self) -> &str {
         &self.validator_address
     }
 
+    fn validator_owner(&self) -> Vec<u8> {
+        self.validator_public.rsa().unwrap().n().to_vec()
+    }
+
+    fn validator_exponent(&self) -> Vec<u8> {
+        self.validator_public.rsa().unwrap().e().to_vec()
+    }
+
     // TODO: should this return Result?
     // When returning Result, caller can decide what needs to be done if
     // this call fails, instea of panicking internally.
@@ -140,26 +407,66 @@ impl KeyManager for InMemoryKeyManager {
     }
 
     fn verify_bundler_signature(&self, data: &[u8], sig: &[u8]) -> bool {
-        let mut verifier =
-            sign::Verifier::new(MessageDigest::sha256(), &self.bundler_public).unwrap();
-        verifier.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
-        verifier.update(data).unwrap();
-        // TODO: we shouldn't probably hide errors here, at least we should log them
-        verifier.verify(sig).unwrap_or(false)
+        self.bundler_key_id(data, sig).is_some()
+    }
+
+    fn bundler_key_id(&self, data: &[u8], sig: &[u8]) -> Option<String> {
+        if let Some((address, _)) = self
+            .configured_bundler_keys()
+            .into_iter()
+            .find(|(_, public)| verify_signature(public, data, sig))
+        {
+            return Some(address);
+        }
+        self.served_bundler_key()
+            .filter(|(_, public)| verify_signature(public, data, sig))
+            .map(|(address, _)| address)
     }
 
     fn verify_validator_signature(&self, data: &[u8], sig: &[u8]) -> bool {
-        let mut verifier =
-            sign::Verifier::new(MessageDigest::sha256(), &self.validator_public).unwrap();
-        verifier.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
-        verifier.update(data).unwrap();
-        // TODO: we shouldn't probably hide errors here, at least we should log them
-        verifier.verify(sig).unwrap_or(false)
+        verify_signature(&self.validator_public, data, sig)
+    }
+
+    fn use_served_bundler_key(&self, owner: &[u8]) -> ServedBundlerKey {
+        let address = owner_address(owner);
+        let configured = self
+            .configured_bundler_keys()
+            .iter()
+            .any(|(configured, _)| *configured == address);
+        let mut served = self
+            .served_bundler_key
+            .write()
+            .expect("Served bundler key lock poisoned");
+        if configured {
+            // Back on a configured key, the one served meanwhile is dropped
+            *served = None;
+            return ServedBundlerKey::Known;
+        }
+        if matches!(&*served, Some((served, _)) if *served == address) {
+            return ServedBundlerKey::Known;
+        }
+        let pinned = self
+            .pinned_bundler_key
+            .read()
+            .expect("Pinned bundler key lock poisoned")
+            .clone();
+        if matches!(pinned, Some(pinned) if pinned != address) {
+            return ServedBundlerKey::Refused(address);
+        }
+        match owner_public_key(owner) {
+            Ok(public) => {
+                *served = Some((address.clone(), public));
+                ServedBundlerKey::Adopted(address)
+            }
+            Err(_) => ServedBundlerKey::Refused(address),
+        }
     }
 }
 
 #[cfg(test)]
 pub mod test_utils {
+    use std::sync::RwLock;
+
     use data_encoding::BASE64URL_NOPAD;
     use jsonwebkey::{JsonWebKey, Key, PublicExponent, RsaPrivate, RsaPublic};
     use openssl::pkey::{PKey, Private, Public};
@@ -179,6 +486,9 @@ pub mod test_utils {
             InMemoryKeyManager {
                 bundler_address,
                 bundler_public,
+                additional_bundler_keys: RwLock::new(Vec::new()),
+                served_bundler_key: RwLock::new(None),
+                pinned_bundler_key: RwLock::new(None),
                 validator_address,
                 validator_private,
                 validator_public,
@@ -268,13 +578,31 @@ pub mod test_utils {
 
 #[cfg(test)]
 mod tests {
+    use data_encoding::BASE64URL_NOPAD;
+    use jsonwebkey::JsonWebKey;
     use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
     use openssl::rsa::Padding;
     use openssl::sign::{Signer, Verifier};
 
     use super::test_utils::{
-        bundler_key, to_address, to_private_key, to_public_key, validator_key,
+        bundler_key, test_keys, to_address, to_private_key, to_public_key, validator_key,
     };
+    use super::{
+        generate_jwk, owner_address, public_jwk, split_jwk, verify_owner_signature, KeyManager,
+        KeyType, ServedBundlerKey,
+    };
+
+    #[test]
+    fn generated_key_has_requested_size() {
+        let jwk = generate_jwk(KeyType::Rsa4096);
+        let (_, pub_key, address) = split_jwk(&jwk);
+        assert_eq!(pub_key.rsa().unwrap().size(), 512);
+
+        let public = public_jwk(&jwk);
+        assert!(!public.key.is_private());
+        assert_eq!(to_address(&public).unwrap(), address);
+    }
 
     #[test]
     fn extract_address_from_public_key_only_jwk() {
@@ -362,4 +690,128 @@ mod tests {
         verifier.update(data).unwrap();
         assert!(verifier.verify(&signature).unwrap());
     }
+
+    #[test]
+    fn validator_signature_verifies_with_owner() {
+        let (key_manager, _) = test_keys();
+        let owner = key_manager.validator_owner();
+        let signature = key_manager.validator_sign(b"hello, world!");
+
+        assert_eq!(owner_address(&owner), key_manager.validator_address());
+        assert!(verify_owner_signature(&owner, b"hello, world!", &signature));
+        assert!(!verify_owner_signature(&owner, b"goodbye", &signature));
+    }
+
+    #[test]
+    fn signature_of_additional_bundler_key_is_attributed_to_it() {
+        let (key_manager, bundler_private) = test_keys();
+        let (rotated_jwk, rotated_private) = bundler_key();
+        let key_manager = key_manager.with_bundler_keys(&[rotated_jwk.clone()]);
+        let sign = |key: &PKey<Private>| {
+            let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+            signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+            signer.update(b"receipt").unwrap();
+            signer.sign_to_vec().unwrap()
+        };
+
+        assert_eq!(
+            key_manager.bundler_key_id(b"receipt", &sign(&bundler_private)),
+            Some(key_manager.bundler_address().to_string())
+        );
+        assert_eq!(
+            key_manager.bundler_key_id(b"receipt", &sign(&rotated_private)),
+            Some(to_address(&rotated_jwk).unwrap())
+        );
+        assert!(key_manager.verify_bundler_signature(b"receipt", &sign(&rotated_private)));
+        assert!(!key_manager.verify_bundler_signature(b"other", &sign(&rotated_private)));
+    }
+
+    #[test]
+    fn key_served_later_replaces_the_previously_served_one() {
+        let (key_manager, bundler_private) = test_keys();
+        let owner = |jwk: &JsonWebKey| to_public_key(jwk).unwrap().rsa().unwrap().n().to_vec();
+        let sign = |key: &PKey<Private>| {
+            let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+            signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+            signer.update(b"receipt").unwrap();
+            signer.sign_to_vec().unwrap()
+        };
+        let (first_jwk, first_private) = bundler_key();
+        let (second_jwk, second_private) = bundler_key();
+        let first = to_address(&first_jwk).unwrap();
+
+        assert_eq!(
+            key_manager.use_served_bundler_key(&owner(&first_jwk)),
+            ServedBundlerKey::Adopted(first.clone())
+        );
+        assert_eq!(
+            key_manager.use_served_bundler_key(&owner(&first_jwk)),
+            ServedBundlerKey::Known
+        );
+        assert_eq!(
+            key_manager.bundler_key_id(b"receipt", &sign(&first_private)),
+            Some(first.clone())
+        );
+        assert!(key_manager.bundler_addresses().contains(&first));
+
+        key_manager.use_served_bundler_key(&owner(&second_jwk));
+        assert!(!key_manager.verify_bundler_signature(b"receipt", &sign(&first_private)));
+        assert!(key_manager.verify_bundler_signature(b"receipt", &sign(&second_private)));
+        // Configured keys stay accepted
+        assert!(key_manager.verify_bundler_signature(b"receipt", &sign(&bundler_private)));
+
+        let pinned = test_keys().0.with_pinned_bundler_key();
+        assert_eq!(
+            pinned.use_served_bundler_key(&owner(&first_jwk)),
+            ServedBundlerKey::Refused(first)
+        );
+        assert!(!pinned.verify_bundler_signature(b"receipt", &sign(&first_private)));
+    }
+
+    #[test]
+    fn reloaded_bundler_keys_replace_configured_ones() {
+        let (key_manager, _) = test_keys();
+        let owner = |jwk: &JsonWebKey| to_public_key(jwk).unwrap().rsa().unwrap().n().to_vec();
+        let sign = |key: &PKey<Private>| {
+            let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+            signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+            signer.update(b"receipt").unwrap();
+            signer.sign_to_vec().unwrap()
+        };
+        let (served_jwk, served_private) = bundler_key();
+        let (additional_jwk, additional_private) = bundler_key();
+        let (pinned_jwk, _) = bundler_key();
+        let encoded = |jwk: &JsonWebKey| BASE64URL_NOPAD.encode(&owner(jwk));
+        key_manager.use_served_bundler_key(&owner(&served_jwk));
+
+        key_manager
+            .set_bundler_keys(None, &[encoded(&additional_jwk)])
+            .unwrap();
+        assert!(key_manager.verify_bundler_signature(b"receipt", &sign(&additional_private)));
+        assert!(key_manager.verify_bundler_signature(b"receipt", &sign(&served_private)));
+
+        // Pinning another key drops the served one
+        key_manager
+            .set_bundler_keys(Some(&encoded(&pinned_jwk)), &[])
+            .unwrap();
+        assert!(!key_manager.verify_bundler_signature(b"receipt", &sign(&additional_private)));
+        assert!(!key_manager.verify_bundler_signature(b"receipt", &sign(&served_private)));
+        assert_eq!(
+            key_manager.use_served_bundler_key(&owner(&served_jwk)),
+            ServedBundlerKey::Refused(to_address(&served_jwk).unwrap())
+        );
+        assert_eq!(
+            key_manager.use_served_bundler_key(&owner(&pinned_jwk)),
+            ServedBundlerKey::Adopted(to_address(&pinned_jwk).unwrap())
+        );
+
+        // Invalid keys leave the current ones in place
+        assert!(key_manager
+            .set_bundler_keys(None, &["not base64!".to_string()])
+            .is_err());
+        assert_eq!(
+            key_manager.use_served_bundler_key(&owner(&served_jwk)),
+            ServedBundlerKey::Refused(to_address(&served_jwk).unwrap())
+        );
+    }
 }
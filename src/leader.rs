@@ -0,0 +1,141 @@
+//! Selection of the validator leading an epoch.
+//!
+//! All validators derive the leader from the same contract state. The seed
+//! is the SHA-256 hash of the epoch transaction id and sequence number, and
+//! the leader is drawn from the nominated validators with a probability
+//! proportional to their stake. Candidates are ordered by address first, so
+//! the result doesn't depend on the order the contract lists them in.
+
+use std::str::FromStr;
+
+use bundlr_contracts_validators::State as ContractState;
+use openssl::sha::Sha256;
+
+use crate::key_manager::{owner_address, verify_owner_signature};
+
+/// Validator that can be selected as leader
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub address: String,
+    pub stake: u128,
+}
+
+/// Seed of the leader selection for epoch `seq` started by `epoch_tx`
+pub fn epoch_seed(epoch_tx: &str, seq: u128) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(epoch_tx.as_bytes());
+    hasher.update(&seq.to_be_bytes());
+    hasher.finish()
+}
+
+/// Pick the leader among `candidates` using `seed`, `None` if no candidate
+/// has any stake
+pub fn select_leader<'a>(candidates: &'a [Candidate], seed: &[u8; 32]) -> Option<&'a Candidate> {
+    let mut candidates: Vec<&Candidate> = candidates
+        .iter()
+        .filter(|candidate| candidate.stake > 0)
+        .collect();
+    candidates.sort_by(|a, b| a.address.cmp(&b.address));
+
+    let total = candidates.iter().fold(0u128, |total, candidate| {
+        total.saturating_add(candidate.stake)
+    });
+    if total == 0 {
+        return None;
+    }
+
+    let mut draw = [0u8; 16];
+    draw.copy_from_slice(&seed[..16]);
+    let mut target = u128::from_be_bytes(draw) % total;
+    for candidate in candidates {
+        if target < candidate.stake {
+            return Some(candidate);
+        }
+        target -= candidate.stake;
+    }
+
+    unreachable!("Target is below the total stake")
+}
+
+/// Nominated validators of the contract's current epoch with their stake
+pub fn candidates(state: &ContractState) -> Vec<Candidate> {
+    state
+        .nominated_validators
+        .iter()
+        .filter_map(|address| state.validators.get(address))
+        // Stakes too large for u128 are capped
+        .map(|validator| Candidate {
+            address: validator.address.to_string(),
+            stake: u128::from_str(&validator.stake.to_string()).unwrap_or(u128::MAX),
+        })
+        .collect()
+}
+
+/// Address of the validator leading the contract's current epoch
+pub fn current_leader(state: &ContractState) -> Option<String> {
+    let seed = epoch_seed(&state.epoch.tx.to_string(), state.epoch.seq);
+    select_leader(&candidates(state), &seed).map(|leader| leader.address.clone())
+}
+
+/// Verify that `message` was signed by the validator leading the contract's
+/// current epoch, `owner` being the public modulus of the signing key
+pub fn verify_leader_signature(
+    state: &ContractState,
+    owner: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    current_leader(state).as_deref() == Some(owner_address(owner).as_str())
+        && verify_owner_signature(owner, message, signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{epoch_seed, select_leader, Candidate};
+
+    fn candidate(address: &str, stake: u128) -> Candidate {
+        Candidate {
+            address: address.to_string(),
+            stake,
+        }
+    }
+
+    #[test]
+    fn selection_does_not_depend_on_candidate_order() {
+        let candidates = vec![candidate("a", 10), candidate("b", 20), candidate("c", 30)];
+        let reversed: Vec<Candidate> = candidates.iter().rev().cloned().collect();
+
+        for seq in 0..100 {
+            let seed = epoch_seed("epoch_tx", seq);
+            assert_eq!(
+                select_leader(&candidates, &seed),
+                select_leader(&reversed, &seed)
+            );
+        }
+    }
+
+    #[test]
+    fn leaders_are_picked_in_proportion_to_stake() {
+        let candidates = vec![candidate("a", 1), candidate("b", 3), candidate("c", 0)];
+
+        let picked_b = (0..1000)
+            .filter_map(|seq| select_leader(&candidates, &epoch_seed("epoch_tx", seq)))
+            .inspect(|leader| assert_ne!(leader.address, "c"))
+            .filter(|leader| leader.address == "b")
+            .count();
+
+        assert!(
+            (650..850).contains(&picked_b),
+            "b picked {} times",
+            picked_b
+        );
+    }
+
+    #[test]
+    fn no_leader_without_stake() {
+        let seed = epoch_seed("epoch_tx", 1);
+
+        assert_eq!(select_leader(&[], &seed), None);
+        assert_eq!(select_leader(&[candidate("a", 0)], &seed), None);
+    }
+}
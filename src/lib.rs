@@ -4,17 +4,36 @@ extern crate diesel;
 #[macro_use]
 extern crate diesel_migrations;
 
+pub mod broker;
+pub mod build_info;
 pub mod bundle;
 pub mod bundler;
+pub mod config;
 pub mod consts;
 pub mod context;
 pub mod contract_gateway;
 pub mod cron;
 pub mod database;
+pub mod epoch;
+pub mod equivocation;
+pub mod error;
+pub mod events;
 pub mod http;
 pub mod hardware;
 pub mod key_manager;
+pub mod leader;
+pub mod logging;
+pub mod metrics;
+pub mod network;
+pub mod peer_auth;
+pub mod peers;
+pub mod pid_file;
+pub mod receipt;
 pub mod server;
+pub mod slash_votes;
 pub mod state;
+pub mod telemetry;
 pub mod types;
 pub mod utils;
+pub mod watchdog;
+pub mod webhooks;
@@ -0,0 +1,171 @@
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use opentelemetry::sdk::trace::Tracer;
+use serde::Deserialize;
+use tracing::{level_filters::LevelFilter, Subscriber};
+use tracing_subscriber::{
+    filter::filter_fn,
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+/// Output format of log lines
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "unknown log format {:?}, expecting text or json",
+                other
+            )),
+        }
+    }
+}
+
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::OFF,
+    LevelFilter::ERROR,
+    LevelFilter::WARN,
+    LevelFilter::INFO,
+    LevelFilter::DEBUG,
+    LevelFilter::TRACE,
+];
+
+/// Index into `LEVELS`, defaults to info
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(3);
+
+/// Change the level of printed log lines, takes effect immediately
+pub fn set_max_level(level: LevelFilter) {
+    let index = LEVELS.iter().position(|l| *l == level).unwrap_or(3);
+    MAX_LEVEL.store(index, Ordering::Relaxed);
+}
+
+pub fn max_level() -> LevelFilter {
+    LEVELS[MAX_LEVEL.load(Ordering::Relaxed)]
+}
+
+/// Initialize global tracing subscriber.
+///
+/// Log lines are filtered by `RUST_LOG` if set, and by the level passed to
+/// `set_max_level`, which can be changed at runtime. Records emitted through
+/// the `log` crate by dependencies are forwarded as well. When `tracer` is
+/// given, spans are exported with it regardless of the log level.
+pub fn init(format: LogFormat, tracer: Option<Tracer>) {
+    let otel = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+
+    tracing_subscriber::registry()
+        .with(fmt_layer(format, std::io::stdout))
+        .with(otel)
+        .init();
+}
+
+fn fmt_layer<S, W>(format: LogFormat, writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("trace"));
+
+    let (text, json) = match format {
+        LogFormat::Text => (Some(fmt::layer().with_writer(writer)), None),
+        LogFormat::Json => (
+            None,
+            Some(
+                fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_current_span(true)
+                    .with_span_list(false)
+                    .with_file(true)
+                    .with_line_number(true)
+                    .with_writer(writer),
+            ),
+        ),
+    };
+
+    text.and_then(json)
+        .with_filter(env_filter)
+        .with_filter(filter_fn(|metadata| max_level() >= *metadata.level()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::level_filters::LevelFilter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{fmt_layer, max_level, set_max_level, LogFormat};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parse_log_format() {
+        assert_eq!("json".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert_eq!("text".parse::<LogFormat>(), Ok(LogFormat::Text));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn json_line_contains_event_fields() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber =
+            tracing_subscriber::registry().with(fmt_layer(LogFormat::Json, move || writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("validate_bundle", bundle = "bundle-id");
+            let _entered = span.enter();
+            tracing::info!(tx = "tx-id", "Task running - {}", "sync network info");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert!(value["timestamp"].is_string());
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["target"], "validator::logging::tests");
+        assert_eq!(value["message"], "Task running - sync network info");
+        assert_eq!(value["tx"], "tx-id");
+        assert_eq!(value["span"]["bundle"], "bundle-id");
+    }
+
+    #[test]
+    fn max_level_can_be_changed() {
+        set_max_level(LevelFilter::DEBUG);
+        assert_eq!(max_level(), LevelFilter::DEBUG);
+        set_max_level(LevelFilter::INFO);
+        assert_eq!(max_level(), LevelFilter::INFO);
+    }
+}
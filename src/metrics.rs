@@ -0,0 +1,401 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use chrono::Utc;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use url::Url;
+
+const NAMESPACE: &str = "validator";
+/// Dependency label of requests to hosts not registered as a dependency
+const OTHER_DEPENDENCY: &str = "other";
+
+pub trait MetricsAccess {
+    fn metrics(&self) -> &Metrics;
+}
+
+/// Prometheus registry shared by all validator modules.
+///
+/// Collectors are reference counted, cloning `Metrics` yields a handle to
+/// the same registry.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    /// Requests sent to Arweave, by endpoint and result
+    pub arweave_requests: IntCounterVec,
+    /// Validated bundles, by result
+    pub bundle_validations: IntCounterVec,
+    /// Data items that made their bundle invalid, by the rule they broke
+    pub invalid_items: IntCounterVec,
+    /// Stored bundle files re-hashed against their recorded checksum, by
+    /// result
+    pub bundle_integrity_checks: IntCounterVec,
+    /// Database query duration, by query
+    pub db_queries: HistogramVec,
+    /// Cron job runs, by job and result
+    pub cron_runs: IntCounterVec,
+    /// Handled HTTP requests, by method, route and status code
+    pub http_requests: IntCounterVec,
+    /// Time spent handling HTTP requests, by method and route
+    pub http_request_duration: HistogramVec,
+    /// HTTP requests sent by the validator, by host and status code
+    pub outbound_requests: IntCounterVec,
+    /// Time until responses to requests sent by the validator arrived,
+    /// retries included, by dependency
+    pub outbound_request_duration: HistogramVec,
+    /// Bundler wallet balance in winston
+    pub bundler_balance: Gauge,
+    /// 1 when the bundler balance doesn't cover its pending transactions
+    pub bundler_underfunded: IntGauge,
+    /// Receipts with a valid bundler signature, by the key that signed them
+    pub receipt_signatures: IntCounterVec,
+    /// Seconds since a bundle was last validated, or since the validator
+    /// started, updated when metrics are encoded
+    pub seconds_since_validation: IntGauge,
+    /// Highest Arweave block a validated bundle was included in
+    pub last_validated_block: IntGauge,
+    /// Estimated rows, by database table
+    pub db_rows: IntGaugeVec,
+    /// Unix time of the last bundle validation, see `record_validation`
+    last_validation: Arc<AtomicI64>,
+    /// Dependency names by host and port, see `register_dependency`
+    dependencies: Arc<RwLock<HashMap<String, &'static str>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let arweave_requests = IntCounterVec::new(
+            Opts::new("arweave_requests_total", "Requests sent to Arweave").namespace(NAMESPACE),
+            &["endpoint", "result"],
+        )
+        .expect("Invalid metric");
+        let bundle_validations = IntCounterVec::new(
+            Opts::new("bundle_validations_total", "Validated bundles").namespace(NAMESPACE),
+            &["result"],
+        )
+        .expect("Invalid metric");
+        let invalid_items = IntCounterVec::new(
+            Opts::new(
+                "invalid_data_items_total",
+                "Data items that made their bundle invalid",
+            )
+            .namespace(NAMESPACE),
+            &["fault"],
+        )
+        .expect("Invalid metric");
+        let bundle_integrity_checks = IntCounterVec::new(
+            Opts::new(
+                "bundle_integrity_checks_total",
+                "Stored bundle files checked against their recorded checksum",
+            )
+            .namespace(NAMESPACE),
+            &["result"],
+        )
+        .expect("Invalid metric");
+        let db_queries = HistogramVec::new(
+            HistogramOpts::new("db_query_duration_seconds", "Database query duration")
+                .namespace(NAMESPACE),
+            &["query"],
+        )
+        .expect("Invalid metric");
+        let cron_runs = IntCounterVec::new(
+            Opts::new("cron_runs_total", "Cron job runs").namespace(NAMESPACE),
+            &["job", "result"],
+        )
+        .expect("Invalid metric");
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Handled HTTP requests").namespace(NAMESPACE),
+            &["method", "route", "status"],
+        )
+        .expect("Invalid metric");
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent handling HTTP requests",
+            )
+            .namespace(NAMESPACE),
+            &["method", "route"],
+        )
+        .expect("Invalid metric");
+        let outbound_requests = IntCounterVec::new(
+            Opts::new(
+                "outbound_requests_total",
+                "HTTP requests sent by the validator",
+            )
+            .namespace(NAMESPACE),
+            &["host", "status"],
+        )
+        .expect("Invalid metric");
+        let outbound_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "outbound_request_duration_seconds",
+                "Duration of HTTP requests sent by the validator",
+            )
+            .namespace(NAMESPACE),
+            &["dependency"],
+        )
+        .expect("Invalid metric");
+        let bundler_balance = Gauge::with_opts(
+            Opts::new("bundler_balance_winston", "Bundler wallet balance").namespace(NAMESPACE),
+        )
+        .expect("Invalid metric");
+        let bundler_underfunded = IntGauge::with_opts(
+            Opts::new(
+                "bundler_underfunded",
+                "Bundler balance doesn't cover its pending transactions",
+            )
+            .namespace(NAMESPACE),
+        )
+        .expect("Invalid metric");
+        let receipt_signatures = IntCounterVec::new(
+            Opts::new(
+                "receipt_signatures_total",
+                "Receipts with a valid bundler signature",
+            )
+            .namespace(NAMESPACE),
+            &["key"],
+        )
+        .expect("Invalid metric");
+        let seconds_since_validation = IntGauge::with_opts(
+            Opts::new(
+                "seconds_since_bundle_validation",
+                "Seconds since a bundle was last validated",
+            )
+            .namespace(NAMESPACE),
+        )
+        .expect("Invalid metric");
+        let last_validated_block = IntGauge::with_opts(
+            Opts::new(
+                "last_validated_block",
+                "Highest Arweave block of a validated bundle",
+            )
+            .namespace(NAMESPACE),
+        )
+        .expect("Invalid metric");
+        let db_rows = IntGaugeVec::new(
+            Opts::new("db_rows", "Estimated rows by database table").namespace(NAMESPACE),
+            &["table"],
+        )
+        .expect("Invalid metric");
+
+        registry
+            .register(Box::new(arweave_requests.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(bundle_validations.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(invalid_items.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(bundle_integrity_checks.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(db_queries.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(cron_runs.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(http_requests.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(outbound_requests.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(outbound_request_duration.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(bundler_balance.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(bundler_underfunded.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(receipt_signatures.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(seconds_since_validation.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(last_validated_block.clone()))
+            .expect("Failed to register metric");
+        registry
+            .register(Box::new(db_rows.clone()))
+            .expect("Failed to register metric");
+
+        Self {
+            registry,
+            arweave_requests,
+            bundle_validations,
+            invalid_items,
+            bundle_integrity_checks,
+            db_queries,
+            cron_runs,
+            http_requests,
+            http_request_duration,
+            outbound_requests,
+            outbound_request_duration,
+            bundler_balance,
+            bundler_underfunded,
+            receipt_signatures,
+            seconds_since_validation,
+            last_validated_block,
+            db_rows,
+            last_validation: Arc::new(AtomicI64::new(Utc::now().timestamp())),
+            dependencies: Arc::default(),
+        }
+    }
+
+    /// Record that a bundle included in `block` was validated
+    pub fn record_validation(&self, block: u128) {
+        self.last_validation
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+        let block = i64::try_from(block).unwrap_or(i64::MAX);
+        if block > self.last_validated_block.get() {
+            self.last_validated_block.set(block);
+        }
+    }
+
+    /// Label requests to the host and port of `url` with `dependency`,
+    /// hosts keep the dependency they were registered with first
+    pub fn register_dependency(&self, url: &Url, dependency: &'static str) {
+        if let Some(key) = dependency_key(url) {
+            self.dependencies
+                .write()
+                .expect("Dependencies lock poisoned")
+                .entry(key)
+                .or_insert(dependency);
+        }
+    }
+
+    /// Dependency requests to `url` are sent to
+    pub fn dependency(&self, url: &Url) -> &'static str {
+        dependency_key(url)
+            .and_then(|key| {
+                self.dependencies
+                    .read()
+                    .expect("Dependencies lock poisoned")
+                    .get(&key)
+                    .copied()
+            })
+            .unwrap_or(OTHER_DEPENDENCY)
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Encode all registered metrics in Prometheus text format
+    pub fn encode(&self) -> Result<String, prometheus::Error> {
+        self.seconds_since_validation
+            .set(Utc::now().timestamp() - self.last_validation.load(Ordering::Relaxed));
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn dependency_key(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(match url.port_or_known_default() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+/// Label value for the outcome of an operation
+pub fn result_label<T, E>(res: &Result<T, E>) -> &'static str {
+    match res {
+        Ok(_) => "ok",
+        Err(_) => "error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use url::Url;
+
+    use super::Metrics;
+
+    #[test]
+    fn encode_contains_recorded_values() {
+        let metrics = Metrics::new();
+        metrics
+            .cron_runs
+            .with_label_values(&["sync network info", "ok"])
+            .inc();
+
+        let encoded = metrics.encode().unwrap();
+
+        assert!(encoded
+            .contains("validator_cron_runs_total{job=\"sync network info\",result=\"ok\"} 1"));
+    }
+
+    #[test]
+    fn clones_share_registry() {
+        let metrics = Metrics::new();
+        let clone = metrics.clone();
+        clone.bundle_validations.with_label_values(&["valid"]).inc();
+
+        assert_eq!(
+            metrics
+                .bundle_validations
+                .with_label_values(&["valid"])
+                .get(),
+            1
+        );
+    }
+
+    #[test]
+    fn validation_freshness_is_recorded() {
+        let metrics = Metrics::new();
+        metrics.record_validation(20);
+        metrics.record_validation(10);
+
+        let encoded = metrics.encode().unwrap();
+
+        assert!(encoded.contains("validator_last_validated_block 20"));
+        assert!(metrics.seconds_since_validation.get() <= 1);
+    }
+
+    #[test]
+    fn dependencies_are_told_apart_by_host_and_port() {
+        let metrics = Metrics::new();
+        let arweave = Url::from_str("http://localhost:1984/").unwrap();
+        let gateway = Url::from_str("http://localhost:3000/").unwrap();
+        metrics.register_dependency(&arweave, "arweave");
+        metrics.register_dependency(&gateway, "contract_gateway");
+        metrics.register_dependency(&gateway, "peer");
+
+        assert_eq!(
+            metrics.dependency(&Url::from_str("http://localhost:1984/tx/id").unwrap()),
+            "arweave"
+        );
+        assert_eq!(metrics.dependency(&gateway), "contract_gateway");
+        assert_eq!(
+            metrics.dependency(&Url::from_str("https://peer.example.com/").unwrap()),
+            "other"
+        );
+    }
+}
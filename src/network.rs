@@ -0,0 +1,101 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+use url::Url;
+
+use crate::epoch::EpochParams;
+
+/// Network a validator joins, selecting defaults for the services it talks
+/// to
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    /// Local setup, with Arweave served by ArLocal
+    Devnet,
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "devnet" => Ok(Network::Devnet),
+            other => Err(format!(
+                "unknown network {:?}, expecting mainnet, testnet or devnet",
+                other
+            )),
+        }
+    }
+}
+
+/// Defaults of a network, each of them is overridden by the matching
+/// option when set
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetworkPreset {
+    pub bundler_url: Url,
+    pub arweave_url: Url,
+    pub contract_gateway_url: Url,
+    /// Used until the contract state is first fetched
+    pub epoch_params: EpochParams,
+}
+
+impl Network {
+    pub fn preset(&self) -> NetworkPreset {
+        let (bundler_url, arweave_url, contract_gateway_url, genesis_height, epoch_length) =
+            match self {
+                Network::Mainnet => (
+                    "https://node1.bundlr.network",
+                    "https://arweave.net",
+                    "https://validators.bundlr.network",
+                    1_000_000,
+                    720,
+                ),
+                Network::Testnet => (
+                    "https://devnet.bundlr.network",
+                    "https://arweave.net",
+                    "https://validators.devnet.bundlr.network",
+                    1_000_000,
+                    30,
+                ),
+                Network::Devnet => (
+                    "http://localhost:10000",
+                    "http://localhost:1984",
+                    "http://localhost:3000",
+                    0,
+                    10,
+                ),
+            };
+
+        NetworkPreset {
+            bundler_url: Url::from_str(bundler_url).unwrap(),
+            arweave_url: Url::from_str(arweave_url).unwrap(),
+            contract_gateway_url: Url::from_str(contract_gateway_url).unwrap(),
+            epoch_params: EpochParams::new(genesis_height, epoch_length)
+                .expect("Preset epoch length is zero"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::Network;
+
+    #[test]
+    fn every_network_has_a_preset() {
+        for name in ["mainnet", "testnet", "devnet"] {
+            let network = Network::from_str(name).unwrap();
+            assert_eq!(
+                serde_json::from_value::<Network>(serde_json::json!(name)).unwrap(),
+                network
+            );
+            assert!(network.preset().epoch_params.epoch_length > 0);
+        }
+        assert!(Network::from_str("localnet").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Authentication of requests sent between validators.
+//!
+//! The sender signs the request method, path, a unix timestamp and the body
+//! with its validator key and sends the signature along with the public key
+//! in headers. The receiver checks the signature, that the timestamp is
+//! recent, and that the key belongs to a validator registered in the
+//! contract.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use data_encoding::BASE64URL_NOPAD;
+use reqwest::header::HeaderValue;
+use thiserror::Error;
+
+use crate::key_manager::{self, owner_address, verify_owner_signature};
+
+/// Public modulus of the sender's validator key, base64url encoded
+pub const OWNER_HEADER: &str = "x-validator-owner";
+/// Unix timestamp in seconds of when the request was signed
+pub const TIMESTAMP_HEADER: &str = "x-validator-timestamp";
+/// Signature of the request, base64url encoded
+pub const SIGNATURE_HEADER: &str = "x-validator-signature";
+
+/// Seconds a signed request is accepted for, also covers clock differences
+/// between validators
+const MAX_REQUEST_AGE: u64 = 300;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PeerAuthError {
+    #[error("missing header {0}")]
+    MissingHeader(&'static str),
+    #[error("malformed header {0}")]
+    MalformedHeader(&'static str),
+    #[error("request timestamp is too far from current time")]
+    Expired,
+    #[error("invalid request signature")]
+    InvalidSignature,
+    #[error("{0} is not a registered validator")]
+    UnknownValidator(String),
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time before unix epoch")
+        .as_secs()
+}
+
+fn signature_message(method: &str, path: &str, timestamp: u64, body: &[u8]) -> Vec<u8> {
+    let mut message = format!("{}\n{}\n{}\n", method, path, timestamp).into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+/// Add authentication headers to a request sent to a peer
+pub fn sign_request<KeyManager>(key_manager: &KeyManager, req: &mut reqwest::Request)
+where
+    KeyManager: key_manager::KeyManager,
+{
+    let timestamp = now();
+    let path = match req.url().query() {
+        Some(query) => format!("{}?{}", req.url().path(), query),
+        None => req.url().path().to_string(),
+    };
+    let body = req
+        .body()
+        .and_then(|body| body.as_bytes())
+        .unwrap_or_default();
+    let message = signature_message(req.method().as_str(), &path, timestamp, body);
+    let signature = key_manager.validator_sign(&message);
+
+    let headers = req.headers_mut();
+    for (name, value) in [
+        (
+            OWNER_HEADER,
+            BASE64URL_NOPAD.encode(&key_manager.validator_owner()),
+        ),
+        (TIMESTAMP_HEADER, timestamp.to_string()),
+        (SIGNATURE_HEADER, BASE64URL_NOPAD.encode(&signature)),
+    ] {
+        headers.insert(
+            name,
+            HeaderValue::from_str(&value).expect("Header value is not valid ASCII"),
+        );
+    }
+}
+
+/// Authentication headers of a request received from a peer
+#[derive(Debug)]
+pub struct PeerSignature {
+    owner: Vec<u8>,
+    timestamp: u64,
+    signature: Vec<u8>,
+}
+
+impl PeerSignature {
+    /// Read headers through `header`, which returns the value of the header
+    /// with given name
+    pub fn from_headers<'a, F>(header: F) -> Result<Self, PeerAuthError>
+    where
+        F: Fn(&'static str) -> Option<&'a str>,
+    {
+        let base64url = |name| {
+            let value = header(name).ok_or(PeerAuthError::MissingHeader(name))?;
+            BASE64URL_NOPAD
+                .decode(value.as_bytes())
+                .map_err(|_| PeerAuthError::MalformedHeader(name))
+        };
+        let timestamp = header(TIMESTAMP_HEADER)
+            .ok_or(PeerAuthError::MissingHeader(TIMESTAMP_HEADER))?
+            .parse()
+            .map_err(|_| PeerAuthError::MalformedHeader(TIMESTAMP_HEADER))?;
+
+        Ok(Self {
+            owner: base64url(OWNER_HEADER)?,
+            timestamp,
+            signature: base64url(SIGNATURE_HEADER)?,
+        })
+    }
+
+    /// Verify the request was signed by the owner, returns the owner's address
+    pub fn verify(&self, method: &str, path: &str, body: &[u8]) -> Result<String, PeerAuthError> {
+        if now().abs_diff(self.timestamp) > MAX_REQUEST_AGE {
+            return Err(PeerAuthError::Expired);
+        }
+
+        let message = signature_message(method, path, self.timestamp, body);
+        if !verify_owner_signature(&self.owner, &message, &self.signature) {
+            return Err(PeerAuthError::InvalidSignature);
+        }
+
+        Ok(owner_address(&self.owner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{Method, Url};
+
+    use super::{sign_request, PeerAuthError, PeerSignature};
+    use crate::key_manager::{test_utils::test_keys, KeyManager};
+
+    /// Request signed by a new validator key and the key's address
+    fn signed_request(body: &'static str) -> (reqwest::Request, String) {
+        let (key_manager, _) = test_keys();
+        let url = Url::parse("http://peer.example.com/receipts?epoch=1").unwrap();
+        let mut req = reqwest::Request::new(Method::POST, url);
+        *req.body_mut() = Some(body.into());
+        sign_request(&key_manager, &mut req);
+        (req, key_manager.validator_address().to_string())
+    }
+
+    fn peer_signature(req: &reqwest::Request) -> PeerSignature {
+        PeerSignature::from_headers(|name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn signed_request_verifies() {
+        let (req, address) = signed_request("[]");
+
+        assert_eq!(
+            peer_signature(&req).verify("POST", "/receipts?epoch=1", b"[]"),
+            Ok(address)
+        );
+    }
+
+    #[test]
+    fn modified_body_is_rejected() {
+        let (req, _) = signed_request("[]");
+
+        assert_eq!(
+            peer_signature(&req).verify("POST", "/receipts?epoch=1", b"[{}]"),
+            Err(PeerAuthError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn missing_headers_are_rejected() {
+        let res = PeerSignature::from_headers(|_| None);
+
+        assert!(matches!(res, Err(PeerAuthError::MissingHeader(_))));
+    }
+}
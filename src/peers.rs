@@ -0,0 +1,511 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tracing::{debug, error, warn};
+use url::Url;
+
+use crate::{
+    config::RuntimeConfigAccess, context::ValidatorAddressAccess, database::queries::BundleQueries,
+    http, metrics::MetricsAccess,
+};
+
+/// Score below which a peer gets blacklisted
+const BLACKLIST_THRESHOLD: i64 = -100;
+/// How long a blacklisted peer is not contacted
+const BLACKLIST_DURATION: Duration = Duration::from_secs(10 * 60);
+/// Upper bound of the score, so a long well-behaved history can't hide
+/// a peer that started misbehaving
+const MAX_SCORE: i64 = 100;
+/// How long the addresses a seed DNS name resolved to are used before
+/// resolving it again
+const SEED_DNS_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Result of a request to a peer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeerOutcome {
+    /// Peer answered as expected
+    Success,
+    /// Request failed or timed out
+    Unreachable,
+    /// Peer answered with something that isn't a valid receipt
+    InvalidReceipt,
+    /// Peer served a receipt not signed by the bundler
+    BadSignature,
+}
+
+impl PeerOutcome {
+    fn score(self) -> i64 {
+        match self {
+            PeerOutcome::Success => 1,
+            PeerOutcome::Unreachable => -5,
+            PeerOutcome::InvalidReceipt => -20,
+            PeerOutcome::BadSignature => -50,
+        }
+    }
+}
+
+/// Response quality of a single peer
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PeerScore {
+    pub score: i64,
+    pub successes: u64,
+    pub failures: u64,
+    pub invalid_receipts: u64,
+    pub bad_signatures: u64,
+    /// Unix timestamp of the last successful response
+    pub last_seen: Option<u64>,
+    #[serde(skip)]
+    blacklisted_until: Option<Instant>,
+}
+
+impl PeerScore {
+    fn is_blacklisted(&self, now: Instant) -> bool {
+        matches!(self.blacklisted_until, Some(until) if until > now)
+    }
+}
+
+pub trait PeerScoresAccess {
+    fn peer_scores(&self) -> &PeerScores;
+}
+
+/// Scores of peers contacted since the validator started, along with the
+/// seed peers last resolved from DNS
+#[derive(Clone, Default)]
+pub struct PeerScores {
+    scores: Arc<Mutex<HashMap<Url, PeerScore>>>,
+    seeds: SeedCache,
+}
+
+/// Peers the seed DNS name last resolved to, with when it was resolved
+#[derive(Clone, Default)]
+struct SeedCache {
+    resolved: Arc<Mutex<Option<(Url, Instant, Vec<Url>)>>>,
+}
+
+impl SeedCache {
+    /// Peers `dns` resolved to less than [`SEED_DNS_TTL`] ago, `stale`
+    /// also returns older ones
+    fn get(&self, dns: &Url, stale: bool) -> Option<Vec<Url>> {
+        let resolved = self.resolved.lock().expect("Seed cache lock poisoned");
+        match &*resolved {
+            Some((url, at, peers)) if url == dns && (stale || at.elapsed() < SEED_DNS_TTL) => {
+                Some(peers.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn set(&self, dns: &Url, peers: Vec<Url>) {
+        *self.resolved.lock().expect("Seed cache lock poisoned") =
+            Some((dns.clone(), Instant::now(), peers));
+    }
+}
+
+impl PeerScores {
+    pub fn record(&self, peer: &Url, outcome: PeerOutcome) {
+        self.record_at(peer, outcome, Instant::now())
+    }
+
+    fn record_at(&self, peer: &Url, outcome: PeerOutcome, now: Instant) {
+        let mut scores = self.scores.lock().expect("Peer scores lock poisoned");
+        let entry = scores.entry(peer.clone()).or_default();
+        match outcome {
+            PeerOutcome::Success => {
+                entry.successes += 1;
+                entry.last_seen = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|since| since.as_secs());
+            }
+            PeerOutcome::Unreachable => entry.failures += 1,
+            PeerOutcome::InvalidReceipt => entry.invalid_receipts += 1,
+            PeerOutcome::BadSignature => entry.bad_signatures += 1,
+        }
+        entry.score = (entry.score + outcome.score()).min(MAX_SCORE);
+
+        if entry.score <= BLACKLIST_THRESHOLD && !entry.is_blacklisted(now) {
+            warn!(peer = %peer, score = entry.score, "Blacklisting peer");
+            entry.blacklisted_until = Some(now + BLACKLIST_DURATION);
+            // Peer gets another chance once the blacklist expires
+            entry.score = BLACKLIST_THRESHOLD / 2;
+        }
+    }
+
+    /// Score of `peer`, `None` if it wasn't contacted yet
+    pub fn get(&self, peer: &Url) -> Option<PeerScore> {
+        let scores = self.scores.lock().expect("Peer scores lock poisoned");
+        scores.get(peer).cloned()
+    }
+
+    /// Current score of all peers, with seconds left until blacklisted
+    /// peers are contacted again
+    pub fn snapshot(&self) -> Vec<(Url, PeerScore, Option<u64>)> {
+        let now = Instant::now();
+        let scores = self.scores.lock().expect("Peer scores lock poisoned");
+        let mut peers: Vec<_> = scores
+            .iter()
+            .map(|(peer, score)| {
+                let blacklisted = score
+                    .blacklisted_until
+                    .filter(|until| *until > now)
+                    .map(|until| (until - now).as_secs());
+                (peer.clone(), score.clone(), blacklisted)
+            })
+            .collect();
+        peers.sort_by(|a, b| b.1.score.cmp(&a.1.score));
+        peers
+    }
+
+    /// Drop blacklisted peers and order the rest by score, peers with equal
+    /// score keep their order
+    fn rank_at(&self, peers: Vec<Url>, now: Instant) -> Vec<Url> {
+        let scores = self.scores.lock().expect("Peer scores lock poisoned");
+        let mut peers: Vec<(Url, i64)> = peers
+            .into_iter()
+            .filter_map(|peer| match scores.get(&peer) {
+                Some(score) if score.is_blacklisted(now) => None,
+                Some(score) => Some((peer, score.score)),
+                None => Some((peer, 0)),
+            })
+            .collect();
+        peers.sort_by(|a, b| b.1.cmp(&a.1));
+        peers.into_iter().map(|(peer, _)| peer).collect()
+    }
+}
+
+/// Peer validators to contact: peers from runtime config and validators
+/// registered in the contract, excluding this validator. Blacklisted peers
+/// are left out and the rest is ordered by their score.
+pub async fn peer_urls<Context>(ctx: &Context) -> Vec<Url>
+where
+    Context: BundleQueries + PeerScoresAccess + RuntimeConfigAccess + ValidatorAddressAccess,
+{
+    let peers = known_peers(ctx).await;
+    for peer in &peers {
+        ctx.metrics().register_dependency(peer, "peer");
+    }
+    ctx.peer_scores().rank_at(peers, Instant::now())
+}
+
+/// Peers from runtime config followed by validators registered in the
+/// contract, or by seed peers while no validator is known
+async fn known_peers<Context>(ctx: &Context) -> Vec<Url>
+where
+    Context: BundleQueries + PeerScoresAccess + RuntimeConfigAccess + ValidatorAddressAccess,
+{
+    let mut peers = ctx.peers();
+
+    let validators = match ctx.get_validators() {
+        Ok(validators) => validators,
+        Err(err) => {
+            error!("Failed to load known validators: {}", err);
+            Vec::new()
+        }
+    };
+
+    let own_address = ctx.get_validator_address();
+    let mut discovered = 0;
+    for validator in validators {
+        if validator.address.trim() == own_address {
+            continue;
+        }
+        let url = match validator.url.as_deref().map(Url::from_str) {
+            Some(Ok(url)) => url,
+            Some(Err(err)) => {
+                warn!(validator = %validator.address, "Invalid validator url - {}", err);
+                continue;
+            }
+            None => continue,
+        };
+        discovered += 1;
+        if !peers.contains(&url) {
+            peers.push(url);
+        }
+    }
+
+    if discovered == 0 {
+        let (seeds, dns) = ctx.seed_peers();
+        let seeds = resolve_seeds(&ctx.peer_scores().seeds, seeds, dns.as_ref()).await;
+        if !seeds.is_empty() {
+            debug!(
+                seeds = seeds.len(),
+                "No validators known yet, using seed peers"
+            );
+        }
+        for url in seeds {
+            if !peers.contains(&url) {
+                peers.push(url);
+            }
+        }
+    }
+
+    peers
+}
+
+/// Static seed peers followed by one peer per address `dns` resolves to.
+///
+/// An `https` seed is contacted by name instead, certificates are issued for
+/// the name and it has to be sent for SNI; which of its addresses is used is
+/// left to the HTTP client. Resolved addresses are cached, and the last
+/// ones kept when resolving fails.
+async fn resolve_seeds(cache: &SeedCache, mut seeds: Vec<Url>, dns: Option<&Url>) -> Vec<Url> {
+    let dns = match dns {
+        Some(dns) => dns,
+        None => return seeds,
+    };
+    let resolved = if dns.scheme() == "https" {
+        vec![dns.clone()]
+    } else if let Some(resolved) = cache.get(dns, false) {
+        resolved
+    } else {
+        let host = dns.host_str().unwrap_or_default();
+        let port = dns.port_or_known_default().unwrap_or(80);
+        match tokio::net::lookup_host((host, port)).await {
+            Ok(addrs) => {
+                let resolved: Vec<Url> = addrs
+                    .filter_map(|addr| {
+                        let mut url = dns.clone();
+                        url.set_ip_host(addr.ip()).ok().map(|_| url)
+                    })
+                    .collect();
+                cache.set(dns, resolved.clone());
+                resolved
+            }
+            Err(err) => {
+                warn!(seed = %dns, "Failed to resolve seed peers: {}", err);
+                cache.get(dns, true).unwrap_or_default()
+            }
+        }
+    };
+    for url in resolved {
+        if !seeds.contains(&url) {
+            seeds.push(url);
+        }
+    }
+    seeds
+}
+
+/// Outcome of a single request made while pinging a peer
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Probe {
+    pub path: String,
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
+
+/// Reachability of a peer and latency of its endpoints
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PeerPing {
+    pub peer: Url,
+    /// Peer answered `/health` successfully
+    pub reachable: bool,
+    pub health: Probe,
+    /// Lookup of a sample transaction, a 404 still shows the endpoint works
+    pub tx: Option<Probe>,
+}
+
+/// Request `/health` of `peer`, and `/tx/{tx_id}` when a sample transaction
+/// is given, recording status and latency of each
+pub async fn ping_peer<Context, HttpClient>(
+    ctx: &Context,
+    peer: &Url,
+    tx_id: Option<&str>,
+) -> PeerPing
+where
+    Context: http::ClientAccess<HttpClient>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let health = probe(ctx, peer, "health").await;
+    let tx = match tx_id {
+        Some(tx_id) => Some(probe(ctx, peer, &format!("tx/{}", tx_id)).await),
+        None => None,
+    };
+
+    PeerPing {
+        peer: peer.clone(),
+        reachable: matches!(health.status, Some(status) if (200..300).contains(&status)),
+        health,
+        tx,
+    }
+}
+
+async fn probe<Context, HttpClient>(ctx: &Context, peer: &Url, path: &str) -> Probe
+where
+    Context: http::ClientAccess<HttpClient>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let mut probe = Probe {
+        path: format!("/{}", path),
+        status: None,
+        latency_ms: 0,
+        error: None,
+    };
+    let url = match peer.join(path) {
+        Ok(url) => url,
+        Err(err) => {
+            probe.error = Some(err.to_string());
+            return probe;
+        }
+    };
+
+    let started = Instant::now();
+    let res = ctx
+        .get_http_client()
+        .execute(reqwest::Request::new(reqwest::Method::GET, url))
+        .await;
+    probe.latency_ms = started.elapsed().as_millis() as u64;
+    match res {
+        Ok(response) => probe.status = Some(response.status().as_u16()),
+        Err(err) => probe.error = Some(format!("{:?}", err)),
+    }
+    probe
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        time::{Duration, Instant},
+    };
+
+    use url::Url;
+
+    use reqwest::{Request, Response};
+
+    use super::{peer_urls, ping_peer, resolve_seeds, PeerOutcome, PeerScores, SeedCache};
+    use crate::{
+        config::{RuntimeConfig, RuntimeConfigAccess},
+        context::ValidatorAddressAccess,
+        database::{memory::MemoryContext, models::NewValidator},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::test_utils::test_keys,
+    };
+
+    #[actix_rt::test]
+    async fn discovered_validators_follow_configured_peers() {
+        let ctx = MemoryContext::new("bundler");
+        ctx.replace_runtime_config(RuntimeConfig {
+            peers: vec![Url::from_str("http://configured.example.com/").unwrap()],
+            ..RuntimeConfig::default()
+        });
+        ctx.db.upsert_validators(&[
+            NewValidator {
+                address: ctx.get_validator_address().to_string(),
+                url: Some("http://self.example.com/".to_string()),
+            },
+            NewValidator {
+                address: "discovered-validator-address-00000000000000".to_string(),
+                url: Some("http://discovered.example.com/".to_string()),
+            },
+        ]);
+
+        let peers = peer_urls(&ctx).await;
+
+        assert_eq!(
+            peers[0],
+            Url::from_str("http://configured.example.com/").unwrap()
+        );
+        assert!(peers.contains(&Url::from_str("http://discovered.example.com/").unwrap()));
+        assert!(!peers.contains(&Url::from_str("http://self.example.com/").unwrap()));
+    }
+
+    #[actix_rt::test]
+    async fn dns_seed_resolves_to_a_peer_per_address() {
+        let cache = SeedCache::default();
+        let seed = Url::from_str("https://seed.example.com/").unwrap();
+        let dns = Url::from_str("http://localhost:1985/").unwrap();
+
+        let seeds = resolve_seeds(&cache, vec![seed.clone()], Some(&dns)).await;
+
+        assert_eq!(seeds[0], seed);
+        assert!(seeds
+            .iter()
+            .any(|url| url.as_str() == "http://127.0.0.1:1985/"
+                || url.as_str() == "http://[::1]:1985/"));
+        assert_eq!(cache.get(&dns, false).unwrap(), seeds[1..].to_vec());
+        assert_eq!(
+            resolve_seeds(&cache, vec![seed.clone()], None).await,
+            vec![seed]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn https_dns_seed_is_contacted_by_name() {
+        let dns = Url::from_str("https://localhost:1985/").unwrap();
+
+        let seeds = resolve_seeds(&SeedCache::default(), Vec::new(), Some(&dns)).await;
+
+        assert_eq!(seeds, vec![dns]);
+    }
+
+    #[actix_rt::test]
+    async fn ping_reports_status_of_each_endpoint() {
+        let (key_manager, _) = test_keys();
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| req.url().as_str() == "http://peer.example.com/health")
+            .then(|_: &Request| {
+                let response = http::response::Builder::new()
+                    .status(200)
+                    .body("{}")
+                    .unwrap();
+                Response::from(response)
+            })
+            .when(|req: &Request| req.url().as_str() == "http://peer.example.com/tx/tx_id")
+            .then(|_: &Request| {
+                let response = http::response::Builder::new().status(404).body("").unwrap();
+                Response::from(response)
+            });
+        let ctx = MemoryContext::with_http_client(key_manager, client);
+        let peer = Url::from_str("http://peer.example.com/").unwrap();
+        let down = Url::from_str("http://down.example.com/").unwrap();
+
+        let ping = ping_peer(&ctx, &peer, Some("tx_id")).await;
+        assert!(ping.reachable);
+        assert_eq!(ping.health.status, Some(200));
+        assert_eq!(ping.tx.unwrap().status, Some(404));
+
+        let ping = ping_peer(&ctx, &down, None).await;
+        assert!(!ping.reachable);
+        assert!(ping.health.error.is_some());
+        assert_eq!(ping.tx, None);
+    }
+
+    #[test]
+    fn peers_are_ranked_by_score() {
+        let scores = PeerScores::default();
+        let good = Url::from_str("http://good.example.com/").unwrap();
+        let flaky = Url::from_str("http://flaky.example.com/").unwrap();
+        let unknown = Url::from_str("http://unknown.example.com/").unwrap();
+        scores.record(&good, PeerOutcome::Success);
+        scores.record(&flaky, PeerOutcome::Unreachable);
+
+        assert_eq!(
+            scores.rank_at(
+                vec![flaky.clone(), unknown.clone(), good.clone()],
+                Instant::now()
+            ),
+            vec![good, unknown, flaky]
+        );
+    }
+
+    #[test]
+    fn peer_serving_bad_signatures_is_blacklisted_for_a_while() {
+        let scores = PeerScores::default();
+        let peer = Url::from_str("http://malicious.example.com/").unwrap();
+        let now = Instant::now();
+        scores.record_at(&peer, PeerOutcome::BadSignature, now);
+        scores.record_at(&peer, PeerOutcome::BadSignature, now);
+
+        assert!(scores.rank_at(vec![peer.clone()], now).is_empty());
+        assert_eq!(
+            scores.rank_at(vec![peer.clone()], now + Duration::from_secs(11 * 60)),
+            vec![peer]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
+};
+
+use sysinfo::{Pid, PidExt, System, SystemExt};
+use tracing::warn;
+
+/// Times the PID file is looked at before giving up, when other processes
+/// keep replacing it or it stays empty
+const ATTEMPTS: u32 = 5;
+/// Wait before looking at a PID file again
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// File holding the process id of the running validator, removed when
+/// dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    pid: u32,
+}
+
+impl PidFile {
+    /// Write the current process id to `path`. Fails if the file names
+    /// another process that is still running, a file left behind by a
+    /// process that exited is replaced.
+    ///
+    /// The file is only ever created with `create_new`, so of two
+    /// validators started together exactly one gets it.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let pid = process::id();
+
+        let (mut waits, mut removals) = (0, 0);
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(format!("{}\n", pid).as_bytes())?;
+                    return Ok(Self { path, pid });
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+                Err(err) => return Err(err),
+            }
+
+            match read_pid(&path)? {
+                Some(other) if other != pid && is_running(other) => {
+                    return Err(running(&path, other));
+                }
+                // Just created by another process that didn't write its pid
+                // yet, unless it stays empty
+                None if waits < ATTEMPTS && path.exists() => {
+                    waits += 1;
+                    thread::sleep(RETRY_DELAY);
+                }
+                _ if removals == ATTEMPTS => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} keeps being replaced by other processes", path.display()),
+                    ));
+                }
+                stale => {
+                    removals += 1;
+                    remove_stale(&path, stale, pid)?;
+                }
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Leave the file alone if another process took it over
+        match read_pid(&self.path) {
+            Ok(Some(pid)) if pid == self.pid => {
+                if let Err(err) = fs::remove_file(&self.path) {
+                    warn!(path = %self.path.display(), "Failed to remove PID file: {}", err);
+                }
+            }
+            Ok(_) => (),
+            Err(err) => warn!(path = %self.path.display(), "Failed to read PID file: {}", err),
+        }
+    }
+}
+
+/// Process id stored in `path`, `None` if there is no file or it doesn't
+/// hold a process id
+fn read_pid(path: &Path) -> io::Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content.trim().parse().ok()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn running(path: &Path, pid: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} belongs to running process {}", path.display(), pid),
+    )
+}
+
+/// Remove the PID file at `path` holding `stale`. The file is moved aside
+/// before being removed, and put back if another process took it over after
+/// it was read, so a validator starting meanwhile doesn't lose its file.
+fn remove_stale(path: &Path, stale: Option<u32>, pid: u32) -> io::Result<()> {
+    let aside = path.with_extension(format!("stale.{}", pid));
+    match fs::rename(path, &aside) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    }
+    if matches!(read_pid(&aside), Ok(moved) if moved != stale) {
+        // Fails if yet another process created the file meanwhile, which
+        // then holds it
+        match fs::hard_link(&aside, path) {
+            Err(err) if err.kind() != io::ErrorKind::AlreadyExists => {
+                fs::remove_file(&aside)?;
+                return Err(err);
+            }
+            _ => (),
+        }
+    }
+    fs::remove_file(&aside)
+}
+
+fn is_running(pid: u32) -> bool {
+    System::new().refresh_process(Pid::from_u32(pid))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io, process};
+
+    use super::PidFile;
+
+    #[test]
+    fn pid_file_is_removed_when_dropped() {
+        let path = std::env::temp_dir().join("validator_test_removed.pid");
+        let pid_file = PidFile::create(&path).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn stale_pid_file_is_replaced() {
+        let path = std::env::temp_dir().join("validator_test_stale.pid");
+        // Larger than any pid Linux hands out
+        fs::write(&path, "4194305\n").unwrap();
+
+        let pid_file = PidFile::create(&path).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(pid_file.path()).unwrap(),
+            format!("{}\n", process::id())
+        );
+    }
+
+    #[test]
+    fn pid_file_left_empty_is_replaced() {
+        let path = std::env::temp_dir().join("validator_test_empty.pid");
+        fs::write(&path, "").unwrap();
+
+        let pid_file = PidFile::create(&path).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(pid_file.path()).unwrap(),
+            format!("{}\n", process::id())
+        );
+    }
+
+    #[test]
+    fn pid_file_of_running_process_is_kept() {
+        let path = std::env::temp_dir().join("validator_test_running.pid");
+        // Init process is always running
+        fs::write(&path, "1\n").unwrap();
+
+        let err = PidFile::create(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+}
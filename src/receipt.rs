@@ -0,0 +1,602 @@
+use std::sync::{Arc, Mutex};
+
+use bundlr_sdk::deep_hash::DeepHashChunk;
+use bundlr_sdk::deep_hash_sync::{deep_hash_sync, ONE_AS_BUFFER};
+use bytes::Bytes;
+use data_encoding::BASE64URL_NOPAD;
+use openssl::{bn::BigNum, rsa::Rsa};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::value::RawValue;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::{
+    consts::BUNDLR_AS_BUFFER,
+    database::{
+        models::{Epoch, NewRawReceipt, NewTransaction, RawReceipt},
+        queries::{self, QueryContext},
+    },
+    equivocation::{record_equivocation, EquivocationKind},
+    events::EventBusAccess,
+    key_manager,
+    metrics::MetricsAccess,
+    server::routes::sign::SignRequest,
+};
+
+/// Receipts waiting to be gossiped, newer receipts are dropped once full
+const MAX_QUEUED_RECEIPTS: usize = 10_000;
+
+/// Source of receipts received in the bundler's sign requests
+pub const SOURCE_BUNDLER: &str = "bundler";
+/// Source of receipts shared by peers
+pub const SOURCE_PEER: &str = "peer";
+
+/// Bundler's promise to include a data item in a bundle by `block`
+#[derive(Clone, Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct TxReceipt {
+    pub block: u128,
+    pub tx_id: String,
+    /// Raw bundler signature, base64url encoded when serialized
+    #[serde(deserialize_with = "de_base64url", serialize_with = "ser_base64url")]
+    pub signature: Vec<u8>,
+}
+
+/// Receipt together with the JSON it was parsed from, so the exact payload
+/// can be kept as evidence and shared as received. The payload is either a
+/// `TxReceipt` or the sign request the bundler sent a validator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawTxReceipt {
+    /// Transaction and block the receipt promises. For a sign request the
+    /// signature covers the whole request rather than `receipt_message`.
+    pub receipt: TxReceipt,
+    pub payload: String,
+    /// Set when the payload is a sign request of the bundler
+    pub sign_request: Option<SignRequest>,
+}
+
+impl RawTxReceipt {
+    /// Receipt promised by a sign request of the bundler, `payload` being
+    /// the request as received
+    pub fn from_sign_request(request: SignRequest, payload: String) -> Self {
+        let receipt = TxReceipt {
+            block: request.block,
+            tx_id: request.id.clone(),
+            signature: BASE64URL_NOPAD
+                .decode(request.signature.as_bytes())
+                .unwrap_or_default(),
+        };
+        Self {
+            receipt,
+            payload,
+            sign_request: Some(request),
+        }
+    }
+
+    /// Receipt kept in `raw_receipts`. Payloads received from the bundler
+    /// are sign requests, payloads shared by peers are either shape.
+    pub fn from_stored(raw: &RawReceipt) -> serde_json::Result<Self> {
+        if raw.source == SOURCE_BUNDLER {
+            let request = serde_json::from_str(&raw.payload)?;
+            Ok(Self::from_sign_request(request, raw.payload.clone()))
+        } else {
+            serde_json::from_str(&raw.payload)
+        }
+    }
+
+    /// Record of the payload received from `source`
+    pub fn to_new_raw_receipt(&self, source: &str) -> NewRawReceipt {
+        NewRawReceipt::new(self.receipt.tx_id.clone(), source, self.payload.clone())
+    }
+
+    /// Address of the bundler key the receipt is signed with, `None` if it
+    /// isn't signed with any of them
+    pub fn verify<KeyManager>(&self, key_manager: &KeyManager) -> Option<String>
+    where
+        KeyManager: key_manager::KeyManager,
+    {
+        match &self.sign_request {
+            Some(request) => request.verify(key_manager).ok().flatten(),
+            None => {
+                let message = receipt_message(&self.receipt)
+                    .map_err(|err| warn!(tx = %self.receipt.tx_id, "{}", err))
+                    .ok()?;
+                key_manager.bundler_key_id(&message, &self.receipt.signature)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RawTxReceipt {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw: Box<RawValue> = Deserialize::deserialize(deserializer)?;
+        let payload = raw.get().to_string();
+        if let Ok(receipt) = serde_json::from_str(raw.get()) {
+            return Ok(RawTxReceipt {
+                receipt,
+                payload,
+                sign_request: None,
+            });
+        }
+        let request = serde_json::from_str(raw.get()).map_err(|err| {
+            de::Error::custom(format!("neither a receipt nor a sign request: {}", err))
+        })?;
+        Ok(RawTxReceipt::from_sign_request(request, payload))
+    }
+}
+
+/// Serialized as the payload it was received as
+impl Serialize for RawTxReceipt {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RawValue::from_string(self.payload.clone())
+            .map_err(ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+fn de_base64url<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let s: String = Deserialize::deserialize(deserializer)?;
+    BASE64URL_NOPAD
+        .decode(s.as_bytes())
+        .map_err(de::Error::custom)
+}
+
+fn ser_base64url<S: Serializer>(val: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64URL_NOPAD.encode(val))
+}
+
+/// Deep hash of the receipt fields, the message the bundler signs
+pub fn receipt_message(tx_receipt: &TxReceipt) -> Result<Bytes, ReceiptCheckError> {
+    deep_hash_sync(DeepHashChunk::Chunks(vec![
+        DeepHashChunk::Chunk(BUNDLR_AS_BUFFER.into()),
+        DeepHashChunk::Chunk(ONE_AS_BUFFER.into()),
+        DeepHashChunk::Chunk(Bytes::copy_from_slice(tx_receipt.tx_id.as_bytes())),
+        DeepHashChunk::Chunk(tx_receipt.block.to_string().into_bytes().into()),
+    ]))
+    .map_err(|err| ReceiptCheckError::Hash(format!("{:?}", err)))
+}
+
+/// Check receipt is signed by the bundler
+pub fn verify_tx_receipt<KeyManager>(
+    key_manager: &KeyManager,
+    tx_receipt: &TxReceipt,
+) -> Result<(), ReceiptCheckError>
+where
+    KeyManager: key_manager::KeyManager,
+{
+    let message = receipt_message(tx_receipt)?;
+    if key_manager.verify_bundler_signature(&message, &tx_receipt.signature) {
+        Ok(())
+    } else {
+        Err(ReceiptCheckError::Mismatch)
+    }
+}
+
+/// Check receipt is signed by one of the bundler keys and count it under
+/// the key that signed it, so operators can tell when the bundler stopped
+/// using a key it rotated away from. Returns the address of that key, to be
+/// stored with the transaction, `None` if no bundler key signed it.
+pub fn verify_and_record_receipt<Context, KeyManager>(
+    ctx: &Context,
+    key_manager: &KeyManager,
+    raw: &RawTxReceipt,
+) -> Option<String>
+where
+    Context: MetricsAccess,
+    KeyManager: key_manager::KeyManager,
+{
+    let key = raw.verify(key_manager)?;
+    debug!(tx = %raw.receipt.tx_id, %key, "Receipt signed by bundler key");
+    ctx.metrics()
+        .receipt_signatures
+        .with_label_values(&[&key])
+        .inc();
+    Some(key)
+}
+
+/// Why a receipt doesn't verify against a bundler key
+#[derive(Debug, Error, PartialEq)]
+pub enum ReceiptCheckError {
+    #[error("invalid bundler public key: {0}")]
+    InvalidKey(String),
+    #[error("failed to hash receipt: {0}")]
+    Hash(String),
+    #[error("signature is {actual} bytes long, the bundler key signs {expected} byte signatures")]
+    SignatureLength { actual: usize, expected: usize },
+    #[error(
+        "signature doesn't match tx_id and block, the receipt was changed or signed with another key"
+    )]
+    Mismatch,
+}
+
+/// Run the `verify_tx_receipt` check against the bundler key with public
+/// modulus `owner`, telling why the receipt doesn't verify
+pub fn check_receipt(owner: &[u8], tx_receipt: &TxReceipt) -> Result<(), ReceiptCheckError> {
+    let key_size = BigNum::from_slice(owner)
+        .and_then(|n| Ok((n, BigNum::from_u32(65537)?)))
+        .and_then(|(n, e)| Rsa::from_public_components(n, e))
+        .map(|rsa| rsa.size() as usize)
+        .map_err(|err| ReceiptCheckError::InvalidKey(err.to_string()))?;
+    if tx_receipt.signature.len() != key_size {
+        return Err(ReceiptCheckError::SignatureLength {
+            actual: tx_receipt.signature.len(),
+            expected: key_size,
+        });
+    }
+
+    let message = receipt_message(tx_receipt)?;
+    if key_manager::verify_owner_signature(owner, &message, &tx_receipt.signature) {
+        Ok(())
+    } else {
+        Err(ReceiptCheckError::Mismatch)
+    }
+}
+
+/// Store receipts shared by peers and signed by the bundler as transactions
+/// of `epoch`, keeping their payloads as received. Returns the receipts that
+/// weren't stored before and the number of receipts with an invalid
+/// signature. Known receipts promising another block are recorded as
+/// equivocations.
+pub fn store_receipts<Context, KeyManager>(
+    ctx: &Context,
+    key_manager: &KeyManager,
+    epoch: u128,
+    receipts: Vec<RawTxReceipt>,
+) -> Result<(Vec<RawTxReceipt>, usize), diesel::result::Error>
+where
+    Context: QueryContext + EventBusAccess,
+    KeyManager: key_manager::KeyManager,
+{
+    let mut valid = Vec::with_capacity(receipts.len());
+    let mut keys = Vec::with_capacity(receipts.len());
+    let mut invalid = 0;
+    for raw in receipts {
+        match verify_and_record_receipt(ctx, key_manager, &raw) {
+            Some(key) => {
+                valid.push(raw);
+                keys.push(key);
+            }
+            None => {
+                warn!(tx = %raw.receipt.tx_id, "Rejected receipt with invalid signature");
+                invalid += 1;
+            }
+        }
+    }
+
+    let raw_receipts: Vec<NewRawReceipt> = valid
+        .iter()
+        .map(|raw| raw.to_new_raw_receipt(SOURCE_PEER))
+        .collect();
+    queries::insert_raw_receipts(ctx, &raw_receipts)?;
+
+    let new_txs: Vec<NewTransaction> = valid
+        .iter()
+        .zip(keys)
+        .map(|(RawTxReceipt { receipt, .. }, key)| NewTransaction {
+            id: receipt.tx_id.clone(),
+            epoch: Epoch(epoch),
+            block_promised: receipt.block.into(),
+            block_actual: None,
+            signature: BASE64URL_NOPAD.encode(&receipt.signature).into_bytes(),
+            validated: false,
+            bundle_id: None,
+            bundler_key: Some(key),
+        })
+        .collect();
+    let inserted = queries::insert_new_txs(ctx, &new_txs)?;
+    let (stored, known): (Vec<RawTxReceipt>, Vec<RawTxReceipt>) = valid
+        .into_iter()
+        .partition(|raw| inserted.contains(&raw.receipt.tx_id));
+    for raw in &known {
+        check_conflicting_receipt(ctx, raw)?;
+    }
+
+    Ok((stored, invalid))
+}
+
+/// Record an equivocation if a receipt of the same transaction promising
+/// another block was received before. Signatures alone aren't compared as
+/// the bundler signs with a randomized padding.
+fn check_conflicting_receipt<Context>(
+    ctx: &Context,
+    raw: &RawTxReceipt,
+) -> Result<(), diesel::result::Error>
+where
+    Context: QueryContext + EventBusAccess,
+{
+    let conflicting = queries::get_raw_receipts(ctx, &raw.receipt.tx_id)?
+        .into_iter()
+        .find(|known| {
+            RawTxReceipt::from_stored(known)
+                .map(|known| known.receipt.block != raw.receipt.block)
+                .unwrap_or(false)
+        });
+    if let Some(known) = conflicting {
+        record_equivocation(
+            ctx,
+            &raw.receipt.tx_id,
+            EquivocationKind::Receipts,
+            &known.payload,
+            &raw.payload,
+        );
+    }
+
+    Ok(())
+}
+
+pub trait ReceiptQueueAccess {
+    fn receipt_queue(&self) -> &ReceiptQueue;
+}
+
+/// Newly learned receipts waiting to be shared with peers, as they were
+/// received
+#[derive(Clone, Default)]
+pub struct ReceiptQueue {
+    receipts: Arc<Mutex<Vec<RawTxReceipt>>>,
+}
+
+impl ReceiptQueue {
+    pub fn push(&self, receipt: RawTxReceipt) {
+        let mut receipts = self.receipts.lock().expect("Receipt queue lock poisoned");
+        if receipts.len() >= MAX_QUEUED_RECEIPTS {
+            warn!(tx = %receipt.receipt.tx_id, "Receipt queue full, receipt won't be gossiped");
+            return;
+        }
+        receipts.push(receipt);
+    }
+
+    /// Remove and return all queued receipts
+    pub fn take(&self) -> Vec<RawTxReceipt> {
+        std::mem::take(&mut *self.receipts.lock().expect("Receipt queue lock poisoned"))
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use bundlr_sdk::deep_hash::DeepHashChunk;
+    use bundlr_sdk::deep_hash_sync::{deep_hash_sync, ONE_AS_BUFFER};
+    use openssl::{
+        hash::MessageDigest,
+        pkey::{PKey, Private},
+        rsa::Padding,
+        sign::Signer,
+    };
+
+    use data_encoding::BASE64URL_NOPAD;
+    use serde::Serialize;
+
+    use super::{RawTxReceipt, TxReceipt};
+    use crate::{consts::BUNDLR_AS_BUFFER, server::routes::sign::SignRequest};
+
+    /// Receipt signed with bundler key
+    pub fn signed_receipt(bundler_key: &PKey<Private>, tx_id: &str, block: u128) -> TxReceipt {
+        let message = deep_hash_sync(DeepHashChunk::Chunks(vec![
+            DeepHashChunk::Chunk(BUNDLR_AS_BUFFER.into()),
+            DeepHashChunk::Chunk(ONE_AS_BUFFER.into()),
+            DeepHashChunk::Chunk(tx_id.as_bytes().to_owned().into()),
+            DeepHashChunk::Chunk(block.to_string().into_bytes().into()),
+        ]))
+        .unwrap();
+
+        let mut signer = Signer::new(MessageDigest::sha256(), bundler_key).unwrap();
+        signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+        signer.update(&message).unwrap();
+
+        TxReceipt {
+            block,
+            tx_id: tx_id.to_string(),
+            signature: signer.sign_to_vec().unwrap(),
+        }
+    }
+
+    /// Sign request for `validator` signed with bundler key
+    pub fn signed_sign_request(
+        bundler_key: &PKey<Private>,
+        tx_id: &str,
+        block: u128,
+        validator: &str,
+    ) -> SignRequest {
+        let size = 0usize;
+        let fee = 0u128;
+        let currency = "FOO";
+        let message = deep_hash_sync(DeepHashChunk::Chunks(vec![
+            DeepHashChunk::Chunk(BUNDLR_AS_BUFFER.into()),
+            DeepHashChunk::Chunk(ONE_AS_BUFFER.into()),
+            DeepHashChunk::Chunk(tx_id.as_bytes().to_owned().into()),
+            DeepHashChunk::Chunk(size.to_string().into_bytes().into()),
+            DeepHashChunk::Chunk(fee.to_string().into_bytes().into()),
+            DeepHashChunk::Chunk(currency.as_bytes().to_owned().into()),
+            DeepHashChunk::Chunk(block.to_string().into_bytes().into()),
+            DeepHashChunk::Chunk(validator.as_bytes().to_owned().into()),
+        ]))
+        .unwrap();
+
+        let mut signer = Signer::new(MessageDigest::sha256(), bundler_key).unwrap();
+        signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+        signer.update(&message).unwrap();
+
+        SignRequest {
+            id: tx_id.to_string(),
+            size,
+            fee,
+            currency: currency.to_string(),
+            block,
+            validator: validator.to_string(),
+            signature: BASE64URL_NOPAD.encode(&signer.sign_to_vec().unwrap()),
+        }
+    }
+
+    /// Receipt as received in the JSON of `value`
+    pub fn raw_receipt<T: Serialize>(value: &T) -> RawTxReceipt {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_receipt, store_receipts,
+        test_utils::{raw_receipt, signed_receipt, signed_sign_request},
+        verify_tx_receipt, ReceiptCheckError, ReceiptQueue, TxReceipt, SOURCE_BUNDLER,
+    };
+    use crate::context::test_utils::test_context;
+    use crate::database::models::{Block, Epoch, NewTransaction};
+    use crate::database::queries::{get_raw_receipts, get_tx, insert_new_txs, insert_raw_receipts};
+    use crate::events::{Event, EventBusAccess};
+    use crate::key_manager::test_utils::test_keys;
+    use crate::key_manager::{KeyManager, KeyManagerAccess};
+    use crate::server::routes::sign::SignRequest;
+
+    #[actix_rt::test]
+    async fn conflicting_payloads_of_a_source_are_all_kept() {
+        let (key_manager, bundler_key) = test_keys();
+        let ctx = test_context(key_manager);
+        let tx_id = "raw-receipts-conflict-000000000000000000000";
+        let raw = |block| raw_receipt(&signed_receipt(&bundler_key, tx_id, block));
+
+        store_receipts(&ctx, ctx.get_key_manager(), 1, vec![raw(10)]).unwrap();
+        store_receipts(&ctx, ctx.get_key_manager(), 1, vec![raw(20)]).unwrap();
+
+        let stored = get_raw_receipts(&ctx, tx_id).unwrap();
+        assert_eq!(stored.len(), 2);
+        assert!(stored.iter().all(|raw| raw.source == "peer"));
+        let tx = get_tx(&ctx, tx_id).await.unwrap();
+        assert_eq!(
+            tx.bundler_key.as_deref(),
+            Some(ctx.get_key_manager().bundler_address())
+        );
+    }
+
+    #[actix_rt::test]
+    async fn peer_receipt_conflicting_with_bundler_request_is_an_equivocation() {
+        let (key_manager, bundler_key) = test_keys();
+        let validator = key_manager.validator_address().to_string();
+        let ctx = test_context(key_manager);
+        let mut events = ctx.event_bus().subscribe();
+        let tx_id = "raw-receipts-bundler-conflict-0000000000000";
+        let request = raw_receipt(&signed_sign_request(&bundler_key, tx_id, 10, &validator));
+        insert_raw_receipts(&ctx, &[request.to_new_raw_receipt(SOURCE_BUNDLER)]).unwrap();
+        insert_new_txs(
+            &ctx,
+            &[NewTransaction {
+                id: tx_id.to_string(),
+                epoch: Epoch(1),
+                block_promised: Block(10),
+                block_actual: None,
+                signature: Vec::new(),
+                validated: false,
+                bundle_id: None,
+                bundler_key: None,
+            }],
+        )
+        .unwrap();
+
+        let peer_receipt = raw_receipt(&signed_receipt(&bundler_key, tx_id, 20));
+        store_receipts(&ctx, ctx.get_key_manager(), 1, vec![peer_receipt.clone()]).unwrap();
+
+        match events.try_recv().unwrap() {
+            Event::Equivocation {
+                tx,
+                first_evidence,
+                second_evidence,
+                ..
+            } => {
+                assert_eq!(tx, tx_id);
+                assert_eq!(first_evidence, request.payload);
+                assert_eq!(second_evidence, peer_receipt.payload);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn take_empties_queue() {
+        let queue = ReceiptQueue::default();
+        queue.push(raw_receipt(&TxReceipt {
+            block: 10,
+            tx_id: "tx_id".to_string(),
+            signature: b"signature".to_vec(),
+        }));
+
+        assert_eq!(queue.take().len(), 1);
+        assert!(queue.take().is_empty());
+    }
+
+    #[test]
+    fn sign_request_of_bundler_is_a_receipt() {
+        let (key_manager, bundler_key) = test_keys();
+        let request =
+            signed_sign_request(&bundler_key, "tx_id", 10, key_manager.validator_address());
+        let raw = raw_receipt(&request);
+        assert_eq!(raw.receipt.tx_id, "tx_id");
+        assert_eq!(raw.receipt.block, 10);
+        assert_eq!(raw.sign_request.as_ref(), Some(&request));
+        assert_eq!(
+            raw.verify(&key_manager).as_deref(),
+            Some(key_manager.bundler_address())
+        );
+        // Shared exactly as received
+        assert_eq!(serde_json::to_string(&raw).unwrap(), raw.payload);
+
+        let tampered = raw_receipt(&SignRequest {
+            block: 11,
+            ..request
+        });
+        assert_eq!(tampered.verify(&key_manager), None);
+    }
+
+    #[test]
+    fn signature_is_base64url_encoded() {
+        let receipt = TxReceipt {
+            block: 10,
+            tx_id: "tx_id".to_string(),
+            signature: b"signature".to_vec(),
+        };
+
+        let json = serde_json::to_value(&receipt).unwrap();
+        assert_eq!(json["signature"], "c2lnbmF0dXJl");
+        assert_eq!(serde_json::from_value::<TxReceipt>(json).unwrap(), receipt);
+    }
+
+    #[test]
+    fn receipt_check_tells_why_verification_failed() {
+        let (key_manager, bundler_private) = test_keys();
+        let owner = bundler_private.rsa().unwrap().n().to_vec();
+        let receipt = signed_receipt(&bundler_private, "tx_id", 10);
+        assert_eq!(verify_tx_receipt(&key_manager, &receipt), Ok(()));
+        assert_eq!(
+            verify_tx_receipt(
+                &key_manager,
+                &TxReceipt {
+                    block: 11,
+                    ..receipt.clone()
+                }
+            ),
+            Err(ReceiptCheckError::Mismatch)
+        );
+
+        assert_eq!(check_receipt(&owner, &receipt), Ok(()));
+        assert_eq!(
+            check_receipt(
+                &owner,
+                &TxReceipt {
+                    block: 11,
+                    ..receipt.clone()
+                }
+            ),
+            Err(ReceiptCheckError::Mismatch)
+        );
+        assert_eq!(
+            check_receipt(
+                &owner,
+                &TxReceipt {
+                    signature: b"signature".to_vec(),
+                    ..receipt
+                }
+            ),
+            Err(ReceiptCheckError::SignatureLength {
+                actual: 9,
+                expected: owner.len(),
+            })
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    web::Bytes,
+    HttpRequest,
+};
+use tracing::warn;
+
+use crate::{
+    config::RuntimeConfigAccess,
+    database::queries::{self, QueryContext},
+    peer_auth::{PeerAuthError, PeerSignature},
+    server::error::ValidatorServerError,
+};
+
+/// Middleware rejecting requests that aren't signed by a registered
+/// validator, see [`crate::peer_auth`]. Requests pass through unchecked
+/// when `require_peer_auth` is turned off.
+pub struct PeerAuth<Context> {
+    ctx: Context,
+}
+
+impl<Context> PeerAuth<Context> {
+    pub fn new(ctx: Context) -> Self {
+        Self { ctx }
+    }
+}
+
+impl<S, B, Context> Transform<S, ServiceRequest> for PeerAuth<Context>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+    Context: QueryContext + RuntimeConfigAccess + Clone + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = PeerAuthMiddleware<S, Context>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PeerAuthMiddleware {
+            service: Rc::new(service),
+            ctx: self.ctx.clone(),
+        }))
+    }
+}
+
+pub struct PeerAuthMiddleware<S, Context> {
+    service: Rc<S>,
+    ctx: Context,
+}
+
+impl<S, B, Context> Service<ServiceRequest> for PeerAuthMiddleware<S, Context>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+    Context: QueryContext + RuntimeConfigAccess + Clone + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let ctx = self.ctx.clone();
+
+        Box::pin(async move {
+            if ctx.require_peer_auth() {
+                let body = req.extract::<Bytes>().await?;
+                if let Err(err) = authenticate(&ctx, &req, &body) {
+                    warn!(
+                        peer = ?req.peer_addr(),
+                        path = req.path(),
+                        "Rejected unauthenticated request - {}",
+                        err
+                    );
+                    return Ok(req
+                        .error_response(ValidatorServerError::Unauthorized)
+                        .map_into_right_body());
+                }
+                req.set_payload(body.into());
+            }
+
+            service.call(req).await.map(|res| res.map_into_left_body())
+        })
+    }
+}
+
+/// Address of the validator that signed `req`, whether it's registered or
+/// not
+pub(crate) fn request_signer(req: &HttpRequest, body: &[u8]) -> Result<String, PeerAuthError> {
+    let signature = PeerSignature::from_headers(|name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    })?;
+    let path = match req.query_string() {
+        "" => req.path().to_string(),
+        query => format!("{}?{}", req.path(), query),
+    };
+    signature.verify(req.method().as_str(), &path, body)
+}
+
+fn authenticate<Context>(
+    ctx: &Context,
+    req: &ServiceRequest,
+    body: &[u8],
+) -> Result<(), PeerAuthError>
+where
+    Context: QueryContext,
+{
+    let address = request_signer(req.request(), body)?;
+
+    let registered = queries::get_validators(ctx)
+        .map(|validators| {
+            validators
+                .iter()
+                .any(|validator| validator.address == address)
+        })
+        .unwrap_or_else(|err| {
+            warn!("Failed to load registered validators - {}", err);
+            false
+        });
+    if !registered {
+        return Err(PeerAuthError::UnknownValidator(address));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test::{call_service, init_service, TestRequest},
+        web::{self, Data},
+        App, HttpResponse,
+    };
+    use reqwest::StatusCode;
+
+    use super::PeerAuth;
+    use crate::{
+        config::{RuntimeConfig, RuntimeConfigAccess},
+        context::test_utils::test_context,
+        database::{models::NewValidator, queries::upsert_validators},
+        key_manager::{test_utils::test_keys, KeyManager},
+        peer_auth::sign_request,
+    };
+
+    async fn echo(body: String) -> HttpResponse {
+        HttpResponse::Ok().body(body)
+    }
+
+    /// Request to `/receipts` signed by `key_manager`'s validator key
+    fn signed_request<K: KeyManager>(key_manager: &K) -> TestRequest {
+        let url = reqwest::Url::parse("http://validator.example.com/receipts?epoch=1").unwrap();
+        let mut req = reqwest::Request::new(reqwest::Method::POST, url);
+        *req.body_mut() = Some("[]".into());
+        sign_request(key_manager, &mut req);
+
+        let mut test_req = TestRequest::post().uri(&req.url()[url::Position::BeforePath..]);
+        for (name, value) in req.headers() {
+            test_req = test_req.insert_header((name.as_str(), value.to_str().unwrap()));
+        }
+        test_req.set_payload("[]")
+    }
+
+    #[actix_web::test]
+    async fn only_registered_validators_are_let_through() {
+        let (key_manager, _) = test_keys();
+        let (peer_key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        upsert_validators(
+            &ctx,
+            &[NewValidator {
+                address: peer_key_manager.validator_address().to_string(),
+                url: None,
+            }],
+        )
+        .unwrap();
+
+        let app = init_service(
+            App::new().app_data(Data::new(ctx.clone())).service(
+                web::resource("/receipts")
+                    .wrap(PeerAuth::new(ctx.clone()))
+                    .route(web::post().to(echo)),
+            ),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/receipts?epoch=1")
+            .set_payload("[]")
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let (unknown_key_manager, _) = test_keys();
+        let res = call_service(&app, signed_request(&unknown_key_manager).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = call_service(&app, signed_request(&peer_key_manager).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn unsigned_requests_pass_when_turned_off() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        ctx.replace_runtime_config(RuntimeConfig {
+            require_peer_auth: false,
+            ..RuntimeConfig::default()
+        });
+
+        let app = init_service(
+            App::new().app_data(Data::new(ctx.clone())).service(
+                web::resource("/receipts")
+                    .wrap(PeerAuth::new(ctx.clone()))
+                    .route(web::post().to(echo)),
+            ),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/receipts?epoch=1")
+            .set_payload("[]")
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    cell::Cell,
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    http::header,
+    ResponseError,
+};
+use futures::{future::LocalBoxFuture, StreamExt};
+
+use crate::{
+    config::{BodyLimits, RuntimeConfigAccess},
+    server::error::ValidatorServerError,
+};
+
+/// Middleware answering 413 to requests with a body larger than the limit
+/// `limit` picks from the runtime config. A declared `Content-Length` over
+/// the limit is refused before reading anything, other bodies fail once
+/// the limit is crossed while they are read. Both are answered with the
+/// same problem details body.
+pub struct BodyLimit<Context> {
+    ctx: Context,
+    limit: fn(&BodyLimits) -> usize,
+}
+
+impl<Context> BodyLimit<Context> {
+    pub fn new(ctx: Context, limit: fn(&BodyLimits) -> usize) -> Self {
+        Self { ctx, limit }
+    }
+}
+
+impl<S, B, Context> Transform<S, ServiceRequest> for BodyLimit<Context>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+    Context: RuntimeConfigAccess + Clone + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = BodyLimitMiddleware<S, Context>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(BodyLimitMiddleware {
+            service: Rc::new(service),
+            ctx: self.ctx.clone(),
+            limit: self.limit,
+        }))
+    }
+}
+
+pub struct BodyLimitMiddleware<S, Context> {
+    service: Rc<S>,
+    ctx: Context,
+    limit: fn(&BodyLimits) -> usize,
+}
+
+impl<S, B, Context> Service<ServiceRequest> for BodyLimitMiddleware<S, Context>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+    Context: RuntimeConfigAccess + Clone + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let limit = (self.limit)(&self.ctx.body_limits());
+        let declared = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        if matches!(declared, Some(length) if length > limit) {
+            let res = req
+                .error_response(ValidatorServerError::PayloadTooLarge(limit))
+                .map_into_right_body();
+            return Box::pin(async move { Ok(res) });
+        }
+
+        let mut read = 0;
+        let overflowed = Rc::new(Cell::new(false));
+        let overflow = overflowed.clone();
+        let limited = req.take_payload().map(move |chunk| {
+            let chunk = chunk?;
+            read += chunk.len();
+            if read > limit {
+                overflow.set(true);
+                return Err(PayloadError::Overflow);
+            }
+            Ok(chunk)
+        });
+        req.set_payload(Payload::Stream {
+            payload: Box::pin(limited),
+        });
+
+        let http_req = req.request().clone();
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            // The extractor that hit the overflow answers with its own
+            // error, replaced so every refused body gets the same answer
+            if overflowed.get() {
+                let res = ValidatorServerError::PayloadTooLarge(limit).error_response();
+                return Ok(ServiceResponse::new(http_req, res).map_into_right_body());
+            }
+            res.map(|res| res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::header,
+        test::{call_service, init_service, read_body_json, TestRequest},
+        web::{self, Bytes, Data},
+        App, HttpResponse,
+    };
+    use reqwest::StatusCode;
+
+    use super::BodyLimit;
+    use crate::{
+        config::{BodyLimits, RuntimeConfig, RuntimeConfigAccess},
+        context::test_utils::test_context,
+        key_manager::test_utils::test_keys,
+    };
+
+    async fn echo(body: Bytes) -> HttpResponse {
+        HttpResponse::Ok().body(body)
+    }
+
+    #[actix_web::test]
+    async fn bodies_over_the_limit_are_refused() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        ctx.replace_runtime_config(RuntimeConfig {
+            body_limits: BodyLimits {
+                cosign: 4,
+                ..BodyLimits::default()
+            },
+            ..RuntimeConfig::default()
+        });
+        let app = init_service(
+            App::new().app_data(Data::new(ctx.clone())).service(
+                web::resource("/cosign")
+                    .wrap(BodyLimit::new(ctx.clone(), |limits| limits.cosign))
+                    .route(web::post().to(echo)),
+            ),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/cosign")
+            .set_payload("1234")
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = TestRequest::post()
+            .uri("/cosign")
+            .set_payload("12345")
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        // Refused on the declared length, before the body is read
+        let req = TestRequest::post()
+            .uri("/cosign")
+            .insert_header((header::CONTENT_LENGTH, "1000"))
+            .set_payload("12")
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[actix_web::test]
+    async fn chunked_bodies_over_the_limit_are_refused_with_problem_details() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        ctx.replace_runtime_config(RuntimeConfig {
+            body_limits: BodyLimits {
+                cosign: 4,
+                ..BodyLimits::default()
+            },
+            ..RuntimeConfig::default()
+        });
+        let app = init_service(
+            App::new().app_data(Data::new(ctx.clone())).service(
+                web::resource("/cosign")
+                    .wrap(BodyLimit::new(ctx.clone(), |limits| limits.cosign))
+                    .route(web::post().to(echo)),
+            ),
+        )
+        .await;
+        let chunked = |body: &'static str| {
+            let mut req = TestRequest::post()
+                .uri("/cosign")
+                .insert_header((header::TRANSFER_ENCODING, "chunked"))
+                .set_payload(body)
+                .to_request();
+            req.headers_mut().remove(header::CONTENT_LENGTH);
+            req
+        };
+
+        assert_eq!(
+            call_service(&app, chunked("1234")).await.status(),
+            StatusCode::OK
+        );
+
+        let res = call_service(&app, chunked("12345")).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["status"], 413);
+    }
+}
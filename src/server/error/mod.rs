@@ -6,7 +6,8 @@ use actix_web::{
 };
 use derive_more::{Display, Error};
 use openssl::error::ErrorStack;
-use paris::log;
+use serde::{Deserialize, Serialize};
+use tracing::error;
 
 #[warn(dead_code)]
 #[derive(Debug, Display, Error)]
@@ -14,46 +15,130 @@ pub enum ValidatorServerError {
     #[display(fmt = "internal error")]
     InternalError,
 
-    #[display(fmt = "bad request")]
-    BadClientData,
+    #[display(fmt = "{}", _0)]
+    BadRequest(#[error(not(source))] String),
+
+    #[display(fmt = "{}", _0)]
+    NotFound(#[error(not(source))] String),
+
+    #[display(fmt = "{}", _0)]
+    Conflict(#[error(not(source))] String),
 
     #[display(fmt = "timeout")]
     Timeout,
+
+    #[display(fmt = "unauthorized")]
+    Unauthorized,
+
+    #[display(fmt = "{}", _0)]
+    Forbidden(#[error(not(source))] String),
+
+    /// Too much work queued, the client should retry after the given
+    /// number of seconds
+    #[display(fmt = "validator overloaded: {}", _0)]
+    Overloaded(#[error(not(source))] String, u64),
+
+    /// Request body is larger than the given number of bytes
+    #[display(fmt = "request body exceeds {} bytes", _0)]
+    PayloadTooLarge(#[error(not(source))] usize),
+}
+
+/// Body of every error response, see RFC 7807
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Id of the failed request, also sent in the `x-request-id` header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, detail: String) -> Self {
+        Self {
+            problem_type: "about:blank".to_string(),
+            title: status
+                .canonical_reason()
+                .unwrap_or_else(|| status.as_str())
+                .to_string(),
+            status: status.as_u16(),
+            detail,
+            request_id: None,
+        }
+    }
+
+    /// Problem describing an error returned by a handler or extractor
+    pub fn from_error(err: &actix_web::Error) -> Self {
+        Self::new(err.as_response_error().status_code(), err.to_string())
+    }
+
+    pub fn with_request_id(self, request_id: String) -> Self {
+        Self {
+            request_id: Some(request_id),
+            ..self
+        }
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        HttpResponseBuilder::new(status)
+            .insert_header((header::CONTENT_TYPE, "application/problem+json"))
+            .body(serde_json::to_string(self).expect("Problem is always serializable"))
+    }
 }
 
 impl error::ResponseError for ValidatorServerError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponseBuilder::new(self.status_code())
-            .insert_header((header::CONTENT_TYPE, "text/html; charset=utf-8"))
-            .body(self.to_string())
+        let mut res = Problem::new(self.status_code(), self.to_string()).to_response();
+        if let ValidatorServerError::Overloaded(_, retry_after) = self {
+            res.headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from(*retry_after));
+        }
+        res
     }
 
     fn status_code(&self) -> StatusCode {
         match *self {
             ValidatorServerError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
-            ValidatorServerError::BadClientData => StatusCode::BAD_REQUEST,
+            ValidatorServerError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ValidatorServerError::NotFound(_) => StatusCode::NOT_FOUND,
+            ValidatorServerError::Conflict(_) => StatusCode::CONFLICT,
             ValidatorServerError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ValidatorServerError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ValidatorServerError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ValidatorServerError::Overloaded(..) => StatusCode::SERVICE_UNAVAILABLE,
+            ValidatorServerError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
         }
     }
 }
 
 impl From<ErrorStack> for ValidatorServerError {
     fn from(e: ErrorStack) -> Self {
-        log!("Error occurred while performing crypto function - {}", e);
+        error!("Error occurred while performing crypto function - {}", e);
         ValidatorServerError::InternalError
     }
 }
 
 impl From<JoinError> for ValidatorServerError {
     fn from(e: JoinError) -> Self {
-        log!("Error occurred while performing blocking task - {}", e);
+        error!("Error occurred while performing blocking task - {}", e);
         ValidatorServerError::InternalError
     }
 }
 
 impl From<diesel::result::Error> for ValidatorServerError {
     fn from(e: diesel::result::Error) -> Self {
-        log!("Error occurred while db op - {}", e);
+        error!("Error occurred while db op - {}", e);
+        ValidatorServerError::InternalError
+    }
+}
+
+impl From<prometheus::Error> for ValidatorServerError {
+    fn from(e: prometheus::Error) -> Self {
+        error!("Error occurred while encoding metrics - {}", e);
         ValidatorServerError::InternalError
     }
 }
@@ -0,0 +1,44 @@
+use std::{io, net::SocketAddr, net::TcpListener};
+
+use listenfd::ListenFd;
+use socket2::{Domain, Socket, Type};
+use tracing::info;
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Listening socket for the API server.
+///
+/// A socket passed in by the service manager (systemd socket activation or
+/// `LISTEN_FDS`) is used as is. Otherwise a socket is bound to `addr`, with
+/// `SO_REUSEPORT` set when `reuse_port` is true so a new validator process
+/// can start accepting connections before the old one exits.
+pub fn bind_listener(addr: &SocketAddr, reuse_port: bool) -> io::Result<TcpListener> {
+    if let Some(listener) = ListenFd::from_env().take_tcp_listener(0)? {
+        info!(addr = ?listener.local_addr()?, "Using inherited listener");
+        return Ok(listener);
+    }
+
+    let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    socket.bind(&(*addr).into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bind_listener;
+
+    #[test]
+    fn processes_can_share_port() {
+        let first = bind_listener(&"127.0.0.1:0".parse().unwrap(), true).unwrap();
+        let addr = first.local_addr().unwrap();
+
+        let second = bind_listener(&addr, true).unwrap();
+
+        assert_eq!(second.local_addr().unwrap(), addr);
+    }
+}
@@ -1,26 +1,69 @@
+mod auth;
+mod body_limit;
 pub mod error;
+mod listener;
+pub mod request_id;
 pub mod routes;
 
-use std::net::SocketAddr;
+use std::{
+    future::Future,
+    net::{SocketAddr, TcpListener},
+    time::Instant,
+};
 
 use actix_web::{
-    middleware::Logger,
-    web::{self, Data},
+    dev::{Server, Service, ServiceRequest, ServiceResponse},
+    guard,
+    http::header,
+    middleware::{Compress, Condition, DefaultHeaders, Logger},
+    web::{self, Data, JsonConfig, PayloadConfig},
     App, HttpServer,
 };
 use diesel::{
     r2d2::{ConnectionManager, PooledConnection},
     PgConnection,
 };
-use paris::info;
+use routes::address::address;
+use routes::cosign::cosign;
+use routes::crons::crons;
+use routes::epochs::{epoch_summary, epoch_txs};
 use routes::get_tx::get_tx;
+use routes::health::health;
 use routes::index::index;
+use routes::info::info;
+use routes::metrics::metrics;
+use routes::peers::peers;
+use routes::ready::{ready, DeepChecksCache};
+use routes::receipts::{get_receipts, get_tx_receipt, get_tx_receipts, post_receipts};
+use routes::slash_votes::post_slash_vote;
+use routes::stake::stake;
+use routes::state::state;
+use routes::tx_status::{get_tx_status, wait_tx_status, TxWaiters};
+use routes::validators::validators;
+use routes::verify_receipt::verify_receipt;
 
 use crate::{
-    database::queries::QueryContext, key_manager, server::routes::sign::sign_route,
+    config::RuntimeConfigAccess,
+    context::{ArweaveAccess, BundlerAccess, ValidatorAddressAccess},
+    contract_gateway::ContractGatewayAccess,
+    cron::arweave::ArweaveContext,
+    database::queries::QueryContext,
+    events::EventBusAccess,
+    http, key_manager,
+    metrics::MetricsAccess,
+    peers::PeerScoresAccess,
+    receipt::ReceiptQueueAccess,
+    server::routes::sign::sign_route,
+    slash_votes::SlashVotesAccess,
     state::ValidatorStateAccess,
+    watchdog::HeartbeatAccess,
 };
 
+use auth::PeerAuth;
+use body_limit::BodyLimit;
+pub use listener::bind_listener;
+use request_id::with_request_id;
+
 #[cfg(feature = "test-routes")]
 use crate::server::routes::test::set_state;
 
@@ -29,45 +72,372 @@ pub trait RuntimeContext {
     fn get_db_connection(&self) -> PooledConnection<ConnectionManager<PgConnection>>;
 }
 
-pub async fn run_server<Context, KeyManager>(ctx: Context) -> std::io::Result<()>
+/// Count handled requests by method, matched route and response status,
+/// and record how long handling them took
+pub(crate) fn record_http_request<Context, S, B>(
+    ctx: &Context,
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    Context: MetricsAccess,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let metrics = ctx.metrics().clone();
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let fut = srv.call(req);
+    async move {
+        let res = fut.await?;
+        let route = res
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        metrics
+            .http_requests
+            .with_label_values(&[&method, &route, res.status().as_str()])
+            .inc();
+        metrics
+            .http_request_duration
+            .with_label_values(&[&method, &route])
+            .observe(started.elapsed().as_secs_f64());
+        Ok(res)
+    }
+}
+
+pub async fn run_server<Context, HttpClient, KeyManager>(ctx: Context) -> std::io::Result<()>
 where
     Context: RuntimeContext
         + routes::sign::Config<KeyManager>
+        + ArweaveAccess
+        + ArweaveContext<HttpClient>
+        + BundlerAccess
+        + ContractGatewayAccess
+        + EventBusAccess
+        + HeartbeatAccess
+        + http::ClientAccess<HttpClient>
         + ValidatorStateAccess
+        + PeerScoresAccess
         + QueryContext
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + SlashVotesAccess
+        + ValidatorAddressAccess
         + Clone
         + Send
         + 'static,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response> + 'static,
     KeyManager: key_manager::KeyManager + Clone + Send + 'static,
 {
-    info!("Starting up HTTP server...");
-
-    let runtime_context = ctx.clone();
-    HttpServer::new(move || {
-        {
-            // use double braces to enable inner attributes
-            #![allow(clippy::let_and_return)]
-
-            let app = App::new()
-                .app_data(Data::new(runtime_context.clone()))
-                .wrap(Logger::default())
-                .route("/", web::get().to(index))
-                .route("/tx/{tx_id}", web::get().to(get_tx::<Context>))
-                .service(
-                    web::scope("/cosigner")
-                        .route("/sign", web::post().to(sign_route::<Context, KeyManager>)),
-                )
-                .service(web::scope("/idle").route("/", web::get().to(index)));
-
-            #[cfg(feature = "test-routes")]
-            let app = app
-                .service(web::scope("/test").route("/state", web::post().to(set_state::<Context>)));
-
-            app
-        }
+    let listener = bind_listener(ctx.bind_address(), false)?;
+    start_server(ctx, listener)?.await
+}
+
+/// Start serving requests on `listener`, requests are served once the
+/// returned server is awaited
+pub fn start_server<Context, HttpClient, KeyManager>(
+    ctx: Context,
+    listener: TcpListener,
+) -> std::io::Result<Server>
+where
+    Context: RuntimeContext
+        + routes::sign::Config<KeyManager>
+        + ArweaveAccess
+        + ArweaveContext<HttpClient>
+        + BundlerAccess
+        + ContractGatewayAccess
+        + EventBusAccess
+        + HeartbeatAccess
+        + http::ClientAccess<HttpClient>
+        + ValidatorStateAccess
+        + PeerScoresAccess
+        + QueryContext
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + SlashVotesAccess
+        + ValidatorAddressAccess
+        + Clone
+        + Send
+        + 'static,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response> + 'static,
+    KeyManager: key_manager::KeyManager + Clone + Send + 'static,
+{
+    tracing::info!("Starting up HTTP server...");
+
+    Ok(HttpServer::new(move || {
+        let metrics_context = ctx.clone();
+        App::new()
+            .app_data(Data::new(ctx.clone()))
+            .wrap(Compress::default())
+            .wrap(Logger::default())
+            .wrap_fn(move |req, srv| record_http_request(&metrics_context, req, srv))
+            .wrap_fn(|req, srv| with_request_id(req, srv))
+            .configure(|cfg| configure_app::<Context, HttpClient, KeyManager>(cfg, &ctx))
     })
     .shutdown_timeout(5)
-    .bind(ctx.bind_address())?
-    .run()
-    .await
+    .listen(listener)?
+    .run())
+}
+
+/// Versions of the API served side by side. A breaking change to an
+/// endpoint adds a version, earlier versions keep being served, marked as
+/// deprecated, until clients have moved on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// Endpoints at the root path, as served before the API was versioned
+    Unversioned,
+    V1,
+}
+
+impl ApiVersion {
+    pub const LATEST: ApiVersion = ApiVersion::V1;
+
+    /// Versions in the order they are mounted, the root path has to come
+    /// last as it matches any path
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::Unversioned];
+
+    /// Path the endpoints of this version are mounted under
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::Unversioned => "",
+            ApiVersion::V1 => "/v1",
+        }
+    }
+
+    pub fn is_deprecated(self) -> bool {
+        self != Self::LATEST
+    }
+}
+
+/// Register every API version, responses of deprecated versions carry a
+/// `Deprecation` header and a link to the latest version
+pub fn configure_app<Context, HttpClient, KeyManager>(cfg: &mut web::ServiceConfig, ctx: &Context)
+where
+    Context: routes::sign::Config<KeyManager>
+        + ArweaveAccess
+        + ArweaveContext<HttpClient>
+        + BundlerAccess
+        + ContractGatewayAccess
+        + EventBusAccess
+        + HeartbeatAccess
+        + http::ClientAccess<HttpClient>
+        + ValidatorStateAccess
+        + PeerScoresAccess
+        + QueryContext
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + SlashVotesAccess
+        + ValidatorAddressAccess
+        + Clone
+        + 'static,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response> + 'static,
+    KeyManager: key_manager::KeyManager + Clone + 'static,
+{
+    cfg.app_data(Data::new(DeepChecksCache::default()))
+        .app_data(Data::new(TxWaiters::default()));
+
+    #[cfg(feature = "test-routes")]
+    cfg.service(web::scope("/test").route("/state", web::post().to(set_state::<Context>)));
+
+    let successor = format!(
+        "<{}/>; rel=\"successor-version\"",
+        ApiVersion::LATEST.prefix()
+    );
+    for version in ApiVersion::ALL {
+        cfg.service(
+            web::scope(version.prefix())
+                .wrap(Condition::new(
+                    version.is_deprecated(),
+                    DefaultHeaders::new()
+                        .add(("Deprecation", "true"))
+                        .add((header::LINK, successor.as_str())),
+                ))
+                .configure(|cfg| configure_api::<Context, HttpClient, KeyManager>(cfg, ctx)),
+        );
+    }
+}
+
+/// Register the endpoints of one API version. All versions serve the same
+/// endpoints so far, a version changing one registers its own handler here.
+fn configure_api<Context, HttpClient, KeyManager>(cfg: &mut web::ServiceConfig, ctx: &Context)
+where
+    Context: routes::sign::Config<KeyManager>
+        + ArweaveAccess
+        + ArweaveContext<HttpClient>
+        + BundlerAccess
+        + ContractGatewayAccess
+        + EventBusAccess
+        + HeartbeatAccess
+        + http::ClientAccess<HttpClient>
+        + ValidatorStateAccess
+        + PeerScoresAccess
+        + QueryContext
+        + ReceiptQueueAccess
+        + RuntimeConfigAccess
+        + SlashVotesAccess
+        + ValidatorAddressAccess
+        + Clone
+        + 'static,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response> + 'static,
+    KeyManager: key_manager::KeyManager + Clone + 'static,
+{
+    cfg.route("/", web::get().to(index))
+        .route("/info", web::get().to(info::<Context>))
+        .route("/health", web::get().to(health))
+        .route("/ready", web::get().to(ready::<Context, HttpClient>))
+        .route("/address", web::get().to(address::<Context, KeyManager>))
+        .route("/metrics", web::get().to(metrics::<Context>))
+        .route("/peers", web::get().to(peers::<Context>))
+        .route("/tx/{tx_id}", web::get().to(get_tx::<Context>))
+        .route(
+            "/tx/{tx_id}/status",
+            web::get().to(get_tx_status::<Context>),
+        )
+        .route("/tx/{tx_id}/wait", web::get().to(wait_tx_status::<Context>))
+        .route(
+            "/tx/{tx_id}/receipt",
+            web::get().to(get_tx_receipt::<Context>),
+        )
+        .route(
+            "/tx/{tx_id}/receipts",
+            web::get().to(get_tx_receipts::<Context>),
+        )
+        .route("/stake", web::get().to(stake::<Context, KeyManager>))
+        .route("/state", web::get().to(state::<Context>))
+        .route("/crons", web::get().to(crons::<Context>))
+        .route("/validators", web::get().to(validators::<Context>))
+        // Resources wrapped in `BodyLimit` are capped by it, the limits of
+        // the extractors are lifted on those only so the configured limits
+        // can go past their defaults
+        .service(
+            web::resource("/receipts")
+                .guard(guard::Post())
+                .wrap(PeerAuth::new(ctx.clone()))
+                .wrap(BodyLimit::new(ctx.clone(), |limits| limits.receipts))
+                .app_data(PayloadConfig::new(usize::MAX))
+                .app_data(JsonConfig::default().limit(usize::MAX))
+                .route(web::post().to(post_receipts::<Context, KeyManager>)),
+        )
+        .route("/receipts", web::get().to(get_receipts::<Context>))
+        .route(
+            "/epochs/{epoch}/summary",
+            web::get().to(epoch_summary::<Context>),
+        )
+        .route("/epochs/{epoch}/txs", web::get().to(epoch_txs::<Context>))
+        .service(
+            web::resource("/receipt/verify")
+                .wrap(BodyLimit::new(ctx.clone(), |limits| limits.default))
+                .app_data(PayloadConfig::new(usize::MAX))
+                .app_data(JsonConfig::default().limit(usize::MAX))
+                .route(web::post().to(verify_receipt::<Context, KeyManager>)),
+        )
+        .service(
+            web::resource("/slash-votes")
+                .wrap(PeerAuth::new(ctx.clone()))
+                .wrap(BodyLimit::new(ctx.clone(), |limits| limits.default))
+                .app_data(PayloadConfig::new(usize::MAX))
+                .app_data(JsonConfig::default().limit(usize::MAX))
+                .route(web::post().to(post_slash_vote::<Context>)),
+        )
+        .service(
+            web::resource("/cosign")
+                .wrap(PeerAuth::new(ctx.clone()))
+                .wrap(BodyLimit::new(ctx.clone(), |limits| limits.cosign))
+                .app_data(PayloadConfig::new(usize::MAX))
+                .app_data(JsonConfig::default().limit(usize::MAX))
+                .route(web::post().to(cosign::<Context, HttpClient, KeyManager>)),
+        )
+        // Posted to by the bundler, which isn't a validator, and
+        // authenticated by the bundler signature in the body
+        .service(
+            web::scope("/cosigner")
+                .wrap(BodyLimit::new(ctx.clone(), |limits| limits.default))
+                .app_data(PayloadConfig::new(usize::MAX))
+                .app_data(JsonConfig::default().limit(usize::MAX))
+                .route("/sign", web::post().to(sign_route::<Context, KeyManager>)),
+        )
+        .service(web::scope("/idle").route("/", web::get().to(index)));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpListener, sync::Arc};
+
+    use actix_web::{
+        test::{call_service, init_service, TestRequest},
+        web::Data,
+        App,
+    };
+    use flate2::read::GzDecoder;
+
+    use crate::{
+        context::test_utils::{test_context, TestContext},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::{test_utils::test_keys, InMemoryKeyManager},
+    };
+
+    use super::{configure_app, start_server};
+
+    #[actix_web::test]
+    async fn api_is_served_under_version_prefix_and_at_root() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ctx.clone()))
+                .configure(|cfg| {
+                    configure_app::<TestContext, MockHttpClient, Arc<InMemoryKeyManager>>(cfg, &ctx)
+                }),
+        )
+        .await;
+
+        let res = call_service(&app, TestRequest::get().uri("/v1/").to_request()).await;
+        assert!(res.status().is_success());
+        assert!(res.headers().get("Deprecation").is_none());
+
+        let res = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        assert!(res.status().is_success());
+        assert_eq!(res.headers().get("Deprecation").unwrap(), "true");
+        assert_eq!(
+            res.headers().get("Link").unwrap(),
+            "</v1/>; rel=\"successor-version\""
+        );
+
+        let res = call_service(&app, TestRequest::get().uri("/v2/").to_request()).await;
+        assert_eq!(res.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn responses_are_compressed_when_client_accepts_gzip() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/", listener.local_addr().unwrap());
+        let server =
+            start_server::<TestContext, MockHttpClient, Arc<InMemoryKeyManager>>(ctx, listener)
+                .unwrap();
+        let handle = server.handle();
+        actix_rt::spawn(server);
+
+        let client = reqwest::Client::new();
+        let compressed = client
+            .get(&url)
+            .header("Accept-Encoding", "gzip")
+            .send()
+            .await
+            .unwrap();
+        let plain = client.get(&url).send().await.unwrap();
+
+        assert_eq!(compressed.headers()["Content-Encoding"], "gzip");
+        assert!(plain.headers().get("Content-Encoding").is_none());
+        let compressed = compressed.bytes().await.unwrap();
+        let plain = plain.bytes().await.unwrap();
+        handle.stop(true).await;
+
+        let mut decoded = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, plain);
+    }
 }
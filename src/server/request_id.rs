@@ -0,0 +1,127 @@
+use std::future::Future;
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    HttpMessage,
+};
+use data_encoding::HEXLOWER;
+
+use crate::server::error::Problem;
+
+/// Header carrying the request id, taken from the request when the client
+/// sets it
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Id of the request being handled, stored in request extensions
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+fn generate_request_id() -> String {
+    let mut bytes = [0; 8];
+    openssl::rand::rand_bytes(&mut bytes).expect("Failed to generate request id");
+    HEXLOWER.encode(&bytes)
+}
+
+/// Tag every response with a request id and turn errors into
+/// `application/problem+json` responses carrying that id
+pub(crate) fn with_request_id<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody + 'static,
+{
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 64)
+        .map(str::to_string)
+        .unwrap_or_else(generate_request_id);
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+    let fut = srv.call(req);
+
+    async move {
+        let res = fut.await?.map_into_boxed_body();
+        let mut res = match res.response().error() {
+            Some(err) => {
+                let problem = Problem::from_error(err).with_request_id(request_id.clone());
+                res.into_response(problem.to_response())
+            }
+            None => res,
+        };
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            res.headers_mut()
+                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test::{call_service, init_service, read_body_json, TestRequest},
+        web::{self, Json},
+        App, HttpResponse,
+    };
+    use reqwest::StatusCode;
+    use serde::Deserialize;
+
+    use super::{with_request_id, REQUEST_ID_HEADER};
+    use crate::server::error::{Problem, ValidatorServerError};
+
+    #[derive(Deserialize)]
+    struct Body {
+        #[allow(dead_code)]
+        value: u64,
+    }
+
+    async fn fail(_: Json<Body>) -> Result<HttpResponse, ValidatorServerError> {
+        Err(ValidatorServerError::Conflict("already exists".to_string()))
+    }
+
+    #[actix_web::test]
+    async fn errors_are_problems_with_request_id() {
+        let app = init_service(
+            App::new()
+                .wrap_fn(|req, srv| with_request_id(req, srv))
+                .route("/fail", web::post().to(fail)),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/fail")
+            .insert_header((REQUEST_ID_HEADER, "request-1"))
+            .set_json(serde_json::json!({ "value": 1 }))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            res.headers()["content-type"].to_str().unwrap(),
+            "application/problem+json"
+        );
+        let problem: Problem = read_body_json(res).await;
+        assert_eq!(problem.title, "Conflict");
+        assert_eq!(problem.detail, "already exists");
+        assert_eq!(problem.request_id.as_deref(), Some("request-1"));
+
+        // Extractor errors are reported the same way
+        let req = TestRequest::post()
+            .uri("/fail")
+            .set_json(serde_json::json!({ "value": "one" }))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let request_id = res.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let problem: Problem = read_body_json(res).await;
+        assert_eq!(problem.status, 400);
+        assert_eq!(problem.request_id, Some(request_id));
+    }
+}
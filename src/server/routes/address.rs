@@ -0,0 +1,71 @@
+use actix_web::{web::Data, HttpResponse};
+use data_encoding::BASE64URL_NOPAD;
+use serde_json::json;
+
+use crate::{key_manager, server::routes::sign::Config};
+
+/// Schemes the validator key signs receipts and peer requests with
+const SIGNATURE_SCHEMES: [&str; 1] = ["RSA-PSS-SHA256"];
+
+/// Identity of this validator: its address, the public part of the
+/// validator key as JWK, and the signature schemes it uses
+pub async fn address<Context, KeyManager>(ctx: Data<Context>) -> HttpResponse
+where
+    Context: Config<KeyManager>,
+    KeyManager: key_manager::KeyManager,
+{
+    let key_manager = ctx.key_manager();
+    HttpResponse::Ok().json(json!({
+        "address": key_manager.validator_address(),
+        "public_key": {
+            "kty": "RSA",
+            "e": BASE64URL_NOPAD.encode(&key_manager.validator_exponent()),
+            "n": BASE64URL_NOPAD.encode(&key_manager.validator_owner()),
+        },
+        "signature_schemes": SIGNATURE_SCHEMES,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test::{call_and_read_body_json, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use data_encoding::BASE64URL_NOPAD;
+
+    use crate::{
+        context::{test_utils::test_context, AppContext},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::{owner_address, test_utils::test_keys, KeyManager},
+    };
+
+    use super::address;
+
+    #[actix_web::test]
+    async fn address_matches_public_key() {
+        let (key_manager, _) = test_keys();
+        let validator_address = key_manager.validator_address().to_string();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(test_context(key_manager)))
+                .route(
+                    "/address",
+                    web::get().to(address::<AppContext<MockHttpClient>, _>),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/address").to_request();
+        let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+        let owner = BASE64URL_NOPAD
+            .decode(body["public_key"]["n"].as_str().unwrap().as_bytes())
+            .unwrap();
+
+        assert_eq!(body["address"], validator_address.as_str());
+        assert_eq!(body["public_key"]["e"], "AQAB");
+        assert_eq!(owner_address(&owner), validator_address);
+        assert_eq!(body["signature_schemes"][0], "RSA-PSS-SHA256");
+    }
+}
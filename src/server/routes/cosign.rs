@@ -0,0 +1,345 @@
+use actix_web::{
+    web::{Bytes, Data},
+    HttpRequest, HttpResponse,
+};
+use bundlr_sdk::{
+    deep_hash::DeepHashChunk,
+    deep_hash_sync::{deep_hash_sync, ONE_AS_BUFFER},
+};
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::{
+    config::RuntimeConfigAccess,
+    consts::VALIDATOR_AS_BUFFER,
+    contract_gateway::ContractGatewayAccess,
+    database::queries::{self, QueryContext},
+    http,
+    key_manager::{self, owner_address, verify_owner_signature},
+    leader::current_leader,
+    server::{auth::request_signer, error::ValidatorServerError, routes::sign::Config},
+};
+
+/// Request of the epoch leader to attest that a bundle and the listed
+/// transactions in it were validated
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AttestationRequest {
+    pub bundle: String,
+    pub epoch: u64,
+    pub txs: Vec<String>,
+}
+
+/// Signature of a validator over an attestation request
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Attestation {
+    pub validator: String,
+    /// Public modulus of the validator key, base64url encoded
+    pub owner: String,
+    /// Base64url encoded
+    pub signature: String,
+}
+
+impl AttestationRequest {
+    /// Data signed by validators attesting the request
+    pub fn signature_data(&self, validator: &str) -> Result<Vec<u8>, ValidatorServerError> {
+        let txs = self
+            .txs
+            .iter()
+            .map(|tx| DeepHashChunk::Chunk(tx.as_bytes().to_owned().into()))
+            .collect();
+        deep_hash_sync(DeepHashChunk::Chunks(vec![
+            DeepHashChunk::Chunk(VALIDATOR_AS_BUFFER.into()),
+            DeepHashChunk::Chunk(ONE_AS_BUFFER.into()),
+            DeepHashChunk::Chunk(self.bundle.as_bytes().to_owned().into()),
+            DeepHashChunk::Chunk(self.epoch.to_string().into_bytes().into()),
+            DeepHashChunk::Chunks(txs),
+            DeepHashChunk::Chunk(validator.as_bytes().to_owned().into()),
+        ]))
+        .map(|hash| hash.to_vec())
+        .map_err(|err| {
+            error!("Failed to build data for signing: {:?}", err);
+            ValidatorServerError::InternalError
+        })
+    }
+}
+
+impl Attestation {
+    /// Whether the signature was made over `request` by the key of
+    /// `validator`, with `owner` belonging to that address
+    pub fn verify(&self, request: &AttestationRequest) -> bool {
+        let owner = match BASE64URL_NOPAD.decode(self.owner.as_bytes()) {
+            Ok(owner) if owner_address(&owner) == self.validator => owner,
+            _ => return false,
+        };
+        let signature = match BASE64URL_NOPAD.decode(self.signature.as_bytes()) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        match request.signature_data(&self.validator) {
+            Ok(data) => verify_owner_signature(&owner, &data, &signature),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Sign an attestation requested by the epoch leader, after checking the
+/// bundle and its transactions were validated by this validator too. The
+/// request must be signed by the leader of the contract's current epoch,
+/// even when `require_peer_auth` is turned off.
+pub async fn cosign<Context, HttpClient, KeyManager>(
+    ctx: Data<Context>,
+    http_req: HttpRequest,
+    body: Bytes,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: Config<KeyManager>
+        + ContractGatewayAccess
+        + http::ClientAccess<HttpClient>
+        + QueryContext
+        + RuntimeConfigAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    if !ctx.get_validator_state().role().signs() {
+        return Err(ValidatorServerError::BadRequest(
+            "Validator is not a cosigner".to_string(),
+        ));
+    }
+
+    let signer = request_signer(&http_req, &body).map_err(|err| {
+        warn!(peer = ?http_req.peer_addr(), "Rejected unsigned attestation request - {}", err);
+        ValidatorServerError::Unauthorized
+    })?;
+    let contract_state = ctx
+        .contract_gateway()
+        .get_current_state(&**ctx)
+        .await
+        .map_err(|err| {
+            error!("Failed to fetch contract state - {}", err);
+            ValidatorServerError::InternalError
+        })?;
+    if current_leader(&contract_state).as_deref() != Some(signer.as_str()) {
+        return Err(ValidatorServerError::Forbidden(format!(
+            "{} is not the epoch leader",
+            signer
+        )));
+    }
+
+    let req: AttestationRequest = serde_json::from_slice(&body)
+        .map_err(|err| ValidatorServerError::BadRequest(err.to_string()))?;
+    if u128::from(req.epoch) > ctx.get_validator_state().current_epoch() {
+        return Err(ValidatorServerError::BadRequest(
+            "Invalid epoch".to_string(),
+        ));
+    }
+
+    match queries::get_bundle(&**ctx, &req.bundle) {
+        Ok(_) => (),
+        Err(diesel::result::Error::NotFound) => {
+            return Err(ValidatorServerError::NotFound(
+                "Bundle not validated".to_string(),
+            ))
+        }
+        Err(err) => return Err(err.into()),
+    }
+    for tx_id in &req.txs {
+        let in_bundle = match queries::get_tx(&**ctx, tx_id).await {
+            Ok(tx) => tx.validated && tx.bundle_id.as_deref() == Some(req.bundle.as_str()),
+            Err(diesel::result::Error::NotFound) => false,
+            Err(err) => return Err(err.into()),
+        };
+        if !in_bundle {
+            return Err(ValidatorServerError::Conflict(format!(
+                "Transaction {} not validated in bundle",
+                tx_id
+            )));
+        }
+    }
+
+    let validator = ctx.validator_address().to_string();
+    let data = req.signature_data(&validator)?;
+    let signature = BASE64URL_NOPAD.encode(&ctx.key_manager().validator_sign(&data));
+
+    Ok(HttpResponse::Ok().json(Attestation {
+        validator,
+        owner: BASE64URL_NOPAD.encode(&ctx.key_manager().validator_owner()),
+        signature,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::{
+        test::{call_and_read_body_json, call_service, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use bundlr_contracts_validators::{Address, Epoch as ContractEpoch, State, Validator};
+    use data_encoding::BASE64URL_NOPAD;
+    use http::Method;
+    use reqwest::{Request, Response, StatusCode};
+
+    use crate::{
+        context::{test_utils::test_context_with_http_client, AppContext, BundlerAccess},
+        database::{
+            models::{Block, Epoch, NewBundle, NewTransaction},
+            queries::insert_bundle_with_txs,
+            test_utils::unique_id,
+        },
+        http::reqwest::mock::MockHttpClient,
+        key_manager::{test_utils::test_keys, InMemoryKeyManager, KeyManager},
+        peer_auth::sign_request,
+        server::routes::sign::Config,
+    };
+
+    use super::{cosign, Attestation, AttestationRequest};
+
+    /// Contract state in which `leader` is the only nominated validator
+    fn contract_state(leader: &InMemoryKeyManager) -> State {
+        let address: Address = leader.validator_address().try_into().unwrap();
+        State {
+            bundler: "bundler_address".try_into().unwrap(),
+            bundlers_contract: "bundlers_contract_address".try_into().unwrap(),
+            epoch: ContractEpoch {
+                seq: 0,
+                tx: "tx0".try_into().unwrap(),
+                height: 1,
+            },
+            epoch_duration: 2,
+            minimum_stake: 1.into(),
+            token: "token_contract_address".try_into().unwrap(),
+            max_num_nominated_validators: 10,
+            validators: HashMap::from([(
+                address.clone(),
+                Validator {
+                    address: address.clone(),
+                    url: "http://leader.example.com".parse().unwrap(),
+                    stake: 1.into(),
+                },
+            )]),
+            nominated_validators: vec![address],
+            slash_proposal_lifetime: 10,
+            slash_proposals: HashMap::new(),
+        }
+    }
+
+    /// `POST /cosign` of `request` signed by `key_manager`'s validator key
+    fn signed_request<K: KeyManager>(key_manager: &K, request: &AttestationRequest) -> TestRequest {
+        let body = serde_json::to_string(request).unwrap();
+        let url = reqwest::Url::parse("http://validator.example.com/cosign").unwrap();
+        let mut req = reqwest::Request::new(reqwest::Method::POST, url);
+        *req.body_mut() = Some(body.clone().into());
+        sign_request(key_manager, &mut req);
+
+        let mut test_req = TestRequest::post().uri("/cosign");
+        for (name, value) in req.headers() {
+            test_req = test_req.insert_header((name.as_str(), value.to_str().unwrap()));
+        }
+        test_req.set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn validated_bundle_is_attested() {
+        let (bundle_id, tx_id) = (unique_id(), unique_id());
+        let (key_manager, _) = test_keys();
+        let (leader_keys, _) = test_keys();
+        let validator_address = key_manager.validator_address().to_string();
+        let state = contract_state(&leader_keys);
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::GET
+                    && &req.url().to_string() == "http://localhost:3000/validators/state"
+            })
+            .then(move |_: &Request| {
+                let body = serde_json::to_string(&state).unwrap();
+                let response = http::response::Builder::new().status(200).body(body);
+                Response::from(response.unwrap())
+            });
+        let ctx = test_context_with_http_client(key_manager, client);
+        insert_bundle_with_txs(
+            &ctx,
+            &NewBundle {
+                id: bundle_id.to_string(),
+                owner_address: ctx.bundler().address.clone(),
+                block_height: Block(10),
+                size: None,
+                item_count: None,
+                block_hash: None,
+                checksum: None,
+            },
+            &[NewTransaction {
+                id: tx_id.to_string(),
+                epoch: Epoch(0),
+                block_promised: Block(10),
+                block_actual: Some(Block(10)),
+                signature: Vec::new(),
+                validated: true,
+                bundle_id: Some(bundle_id.to_string()),
+                bundler_key: None,
+            }],
+        )
+        .unwrap();
+        let app = init_service(App::new().app_data(Data::new(ctx.clone())).route(
+            "/cosign",
+            web::post().to(cosign::<AppContext<MockHttpClient>, _, _>),
+        ))
+        .await;
+
+        let unknown = AttestationRequest {
+            bundle: tx_id.to_string(),
+            epoch: 0,
+            txs: Vec::new(),
+        };
+        let req = signed_request(&leader_keys, &unknown).to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let attestation_req = AttestationRequest {
+            bundle: bundle_id.to_string(),
+            epoch: 0,
+            txs: vec![tx_id.to_string()],
+        };
+
+        // Only the epoch leader gets attestations
+        let req = TestRequest::post()
+            .uri("/cosign")
+            .set_json(&attestation_req)
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let (other_keys, _) = test_keys();
+        let req = signed_request(&other_keys, &attestation_req).to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+
+        let req = signed_request(&leader_keys, &attestation_req).to_request();
+        let attestation: Attestation = call_and_read_body_json(&app, req).await;
+
+        let data = attestation_req.signature_data(&validator_address).unwrap();
+        let signature = BASE64URL_NOPAD
+            .decode(attestation.signature.as_bytes())
+            .unwrap();
+        assert_eq!(attestation.validator, validator_address);
+        assert!(ctx
+            .key_manager()
+            .verify_validator_signature(&data, &signature));
+        assert!(attestation.verify(&attestation_req));
+
+        // Signature doesn't cover another bundle
+        let other_req = AttestationRequest {
+            bundle: tx_id.to_string(),
+            ..attestation_req
+        };
+        assert!(!attestation.verify(&other_req));
+    }
+}
@@ -0,0 +1,14 @@
+use actix_web::{web::Data, HttpResponse};
+
+use crate::{server::error::ValidatorServerError, watchdog::HeartbeatAccess};
+
+/// Status of the cron tasks of this process, empty when it only serves
+/// requests
+pub async fn crons<Context>(
+    ctx: Data<Context>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: HeartbeatAccess,
+{
+    Ok(HttpResponse::Ok().json(ctx.heartbeats().statuses()))
+}
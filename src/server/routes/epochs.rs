@@ -0,0 +1,152 @@
+use actix_web::{
+    web::{Data, Path, Query},
+    HttpResponse,
+};
+use serde::Deserialize;
+
+use crate::{
+    database::queries::{self, QueryContext},
+    epoch::{EpochSummary, EpochTx},
+    server::error::ValidatorServerError,
+};
+
+/// Transaction ids returned in a single request
+pub const MAX_EPOCH_TXS_PER_REQUEST: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct EpochTxsQuery {
+    /// Return transactions with id greater than this one
+    after: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Number of transactions stored for an epoch and merkle root of the
+/// validated ones, compared by peers to detect divergence
+pub async fn epoch_summary<Context>(
+    ctx: Data<Context>,
+    epoch: Path<u64>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: QueryContext,
+{
+    let epoch = epoch.into_inner();
+    let txs = queries::get_epoch_tx_ids(&**ctx, epoch.into())?;
+
+    Ok(HttpResponse::Ok().json(EpochSummary::new(epoch, &txs)))
+}
+
+/// Transactions stored for an epoch ordered by id, listed by peers whose
+/// summary differs
+pub async fn epoch_txs<Context>(
+    ctx: Data<Context>,
+    epoch: Path<u64>,
+    query: Query<EpochTxsQuery>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: QueryContext,
+{
+    let limit = query
+        .limit
+        .unwrap_or(MAX_EPOCH_TXS_PER_REQUEST)
+        .min(MAX_EPOCH_TXS_PER_REQUEST);
+    let txs = queries::get_epoch_txs(
+        &**ctx,
+        epoch.into_inner().into(),
+        query.after.as_deref(),
+        limit as i64,
+    )?;
+    let txs: Vec<EpochTx> = txs
+        .into_iter()
+        .map(|tx| EpochTx {
+            id: tx.id,
+            validated: tx.validated,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(txs))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test::{call_and_read_body_json, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use chrono::Utc;
+
+    use crate::{
+        context::test_utils::TestContext,
+        database::{
+            models::{Block, Epoch, NewTransaction},
+            queries::insert_tx_in_db,
+        },
+        epoch::{EpochSummary, EpochTx},
+        key_manager::{test_utils::test_keys, KeyManager},
+    };
+
+    use super::{epoch_summary, epoch_txs};
+
+    #[actix_web::test]
+    async fn summary_and_txs_of_epoch() {
+        let ctx = TestContext::builder().build();
+        // Fresh epoch below the ones peers claim to be at in sync tests
+        let epoch = 3_000_000_000 + Utc::now().timestamp_subsec_nanos() as u64;
+        let mut ids = [test_keys().0, test_keys().0]
+            .map(|key_manager| key_manager.validator_address().to_string());
+        ids.sort();
+        for (index, id) in ids.iter().enumerate() {
+            insert_tx_in_db(
+                &ctx,
+                &NewTransaction {
+                    id: id.to_string(),
+                    epoch: Epoch(epoch.into()),
+                    block_promised: Block(10),
+                    block_actual: None,
+                    signature: Vec::new(),
+                    validated: index == 0,
+                    bundle_id: None,
+                    bundler_key: None,
+                },
+            )
+            .unwrap();
+        }
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ctx.clone()))
+                .route(
+                    "/epochs/{epoch}/summary",
+                    web::get().to(epoch_summary::<TestContext>),
+                )
+                .route(
+                    "/epochs/{epoch}/txs",
+                    web::get().to(epoch_txs::<TestContext>),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri(&format!("/epochs/{}/summary", epoch))
+            .to_request();
+        let summary: EpochSummary = call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            summary,
+            EpochSummary::new(
+                epoch,
+                &[(ids[0].to_string(), true), (ids[1].to_string(), false)]
+            )
+        );
+
+        let req = TestRequest::get()
+            .uri(&format!("/epochs/{}/txs?after={}", epoch, ids[0]))
+            .to_request();
+        let txs: Vec<EpochTx> = call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            txs,
+            [EpochTx {
+                id: ids[1].to_string(),
+                validated: false
+            }]
+        );
+    }
+}
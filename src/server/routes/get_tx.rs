@@ -14,16 +14,19 @@ where
     Context: RuntimeContext,
 {
     let conn = ctx.get_db_connection();
+    let tx_id = path.0;
+    let filter = id.eq(tx_id.clone());
     let res = actix_rt::task::spawn_blocking(move || {
-        transactions
-            .filter(id.eq(path.0))
-            .first::<Transaction>(&conn)
+        transactions.filter(filter).first::<Transaction>(&conn)
     })
     .await?;
 
-    if let Ok(r) = res {
-        Ok(HttpResponse::Ok().json(r))
-    } else {
-        Ok(HttpResponse::NotFound().finish())
+    match res {
+        Ok(r) => Ok(HttpResponse::Ok().json(r)),
+        Err(diesel::result::Error::NotFound) => Err(ValidatorServerError::NotFound(format!(
+            "Transaction {} not found",
+            tx_id
+        ))),
+        Err(err) => Err(err.into()),
     }
 }
@@ -0,0 +1,14 @@
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+}
+
+/// Answers as long as the server is serving requests, without contacting
+/// any dependency, so peers can probe it cheaply. `/ready` checks the
+/// dependencies.
+pub async fn health() -> actix_web::Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(Health { status: "ok" }))
+}
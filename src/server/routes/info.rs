@@ -0,0 +1,106 @@
+use actix_web::{web::Data, HttpResponse};
+use serde::Serialize;
+
+use crate::{
+    build_info::{build_info, BuildInfo},
+    bundler::BundlerConfig,
+    context::BundlerAccess,
+    database::queries::{self, QueryContext},
+    server::error::ValidatorServerError,
+};
+
+#[derive(Serialize)]
+struct Info {
+    #[serde(flatten)]
+    build: BuildInfo,
+    /// Latest epoch with stored transactions, peers compare it with their
+    /// own to find out whether they missed receipts
+    latest_epoch: Option<u64>,
+    /// Highest block a transaction of `latest_epoch` was validated in
+    latest_block: Option<u64>,
+    /// Configuration of the bundler as served on its `/info`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bundler: Option<BundlerConfig>,
+}
+
+pub async fn info<Context>(
+    ctx: Data<Context>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: BundlerAccess + QueryContext,
+{
+    let latest = queries::get_latest_validated(&**ctx)?;
+    // Out of range values are left out rather than wrapped around, peers
+    // would otherwise take them for an older epoch or block
+    Ok(HttpResponse::Ok().json(Info {
+        build: build_info(),
+        latest_epoch: latest.and_then(|(epoch, _)| u64::try_from(epoch).ok()),
+        latest_block: latest.and_then(|(_, block)| u64::try_from(block).ok()),
+        bundler: ctx.bundler().config(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::{
+        test::{call_and_read_body_json, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+
+    use crate::{
+        bundler::BundlerConfig,
+        context::{test_utils::test_context, AppContext, BundlerAccess},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::test_utils::test_keys,
+    };
+
+    use super::info;
+
+    #[actix_web::test]
+    async fn info_contains_build_metadata() {
+        let (key_manager, _) = test_keys();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(test_context(key_manager)))
+                .route("/info", web::get().to(info::<AppContext<MockHttpClient>>)),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/info").to_request();
+        let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["git_commit"].is_string());
+        assert!(body["build_date"].is_string());
+        assert!(body["features"].is_array());
+        assert!(body.get("bundler").is_none());
+    }
+
+    #[actix_web::test]
+    async fn info_contains_bundler_config() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        ctx.bundler().set_config(BundlerConfig {
+            version: "0.2.0".to_string(),
+            gateway: "arweave.net".to_string(),
+            addresses: HashMap::from([("arweave".to_string(), "address".to_string())]),
+            receipt_version: Some("1.0.0".to_string()),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ctx))
+                .route("/info", web::get().to(info::<AppContext<MockHttpClient>>)),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/info").to_request();
+        let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["bundler"]["version"], "0.2.0");
+        assert_eq!(body["bundler"]["addresses"]["arweave"], "address");
+        assert_eq!(body["bundler"]["receipt_version"], "1.0.0");
+    }
+}
@@ -0,0 +1,81 @@
+use actix_web::{web::Data, HttpResponse};
+use tracing::warn;
+
+use crate::{
+    database::queries::{self, QueryContext},
+    metrics::MetricsAccess,
+    server::error::ValidatorServerError,
+};
+
+/// Metrics in Prometheus text format. Table row counts are refreshed on
+/// every scrape, a database error leaves the previous counts.
+pub async fn metrics<Context>(
+    ctx: Data<Context>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: MetricsAccess + QueryContext,
+{
+    match queries::get_table_rows(&**ctx) {
+        Ok(tables) => {
+            for table in tables {
+                ctx.metrics()
+                    .db_rows
+                    .with_label_values(&[&table.table_name])
+                    .set(table.row_count);
+            }
+        }
+        Err(err) => warn!("Failed to count table rows: {}", err),
+    }
+    let body = ctx.metrics().encode()?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test::{call_and_read_body, call_service, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+
+    use crate::{
+        context::{test_utils::test_context, AppContext},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::test_utils::test_keys,
+        server::record_http_request,
+    };
+
+    use super::metrics;
+
+    #[actix_web::test]
+    async fn metrics_include_handled_requests() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        let registry = ctx.clone();
+
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ctx))
+                .wrap_fn(move |req, srv| record_http_request(&registry, req, srv))
+                .route(
+                    "/metrics",
+                    web::get().to(metrics::<AppContext<MockHttpClient>>),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/metrics").to_request();
+        call_service(&app, req).await;
+
+        let req = TestRequest::get().uri("/metrics").to_request();
+        let body = call_and_read_body(&app, req).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains(
+            "validator_http_requests_total{method=\"GET\",route=\"/metrics\",status=\"200\"} 1"
+        ));
+        assert!(body.contains("validator_db_rows{table=\"transactions\"}"));
+    }
+}
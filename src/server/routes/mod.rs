@@ -1,4 +1,20 @@
+pub mod address;
+pub mod cosign;
+pub mod crons;
+pub mod epochs;
 pub mod get_tx;
+pub mod health;
 pub mod index;
+pub mod info;
+pub mod metrics;
+pub mod peers;
+pub mod ready;
+pub mod receipts;
 pub mod sign;
+pub mod slash_votes;
+pub mod stake;
+pub mod state;
 pub mod test;
+pub mod tx_status;
+pub mod validators;
+pub mod verify_receipt;
@@ -0,0 +1,77 @@
+use actix_web::{web::Data, HttpResponse};
+use serde::Serialize;
+
+use crate::peers::{PeerScore, PeerScoresAccess};
+
+#[derive(Serialize)]
+struct PeerStatus {
+    url: String,
+    #[serde(flatten)]
+    score: PeerScore,
+    /// Seconds until a blacklisted peer is contacted again
+    blacklisted_for: Option<u64>,
+}
+
+/// Scores of peers contacted since the validator started, best first
+pub async fn peers<Context>(ctx: Data<Context>) -> HttpResponse
+where
+    Context: PeerScoresAccess,
+{
+    let peers: Vec<PeerStatus> = ctx
+        .peer_scores()
+        .snapshot()
+        .into_iter()
+        .map(|(url, score, blacklisted_for)| PeerStatus {
+            url: url.to_string(),
+            score,
+            blacklisted_for,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(peers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use actix_web::{
+        test::{call_and_read_body_json, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use url::Url;
+
+    use crate::{
+        context::{test_utils::test_context, AppContext},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::test_utils::test_keys,
+        peers::{PeerOutcome, PeerScoresAccess},
+    };
+
+    use super::peers;
+
+    #[actix_web::test]
+    async fn peers_lists_scores() {
+        let (key_manager, _) = test_keys();
+        let ctx = test_context(key_manager);
+        ctx.peer_scores().record(
+            &Url::from_str("http://peer.example.com/").unwrap(),
+            PeerOutcome::Unreachable,
+        );
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ctx))
+                .route("/peers", web::get().to(peers::<AppContext<MockHttpClient>>)),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/peers").to_request();
+        let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body[0]["url"], "http://peer.example.com/");
+        assert_eq!(body[0]["score"], -5);
+        assert_eq!(body[0]["failures"], 1);
+        assert_eq!(body[0]["blacklisted_for"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    fmt::Display,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    web::{Data, Query},
+    HttpResponse,
+};
+use diesel::{
+    r2d2::{ConnectionManager, PooledConnection},
+    result::Error,
+    PgConnection,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::{
+    bundler::BundlerConfig,
+    config::RuntimeConfigAccess,
+    context::{ArweaveAccess, BundlerAccess},
+    contract_gateway::ContractGatewayAccess,
+    cron::arweave::ArweaveContext,
+    database::queries::{self, QueryContext},
+    http,
+};
+
+#[derive(Deserialize)]
+pub struct ReadyQuery {
+    /// Also check the dependencies outside of this process
+    #[serde(default)]
+    deep: bool,
+}
+
+/// How long results of deep checks are served again before the
+/// dependencies are contacted anew
+const DEEP_CHECKS_TTL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Serialize)]
+struct Check {
+    check: &'static str,
+    ok: bool,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+struct Readiness {
+    /// Every check passed
+    ready: bool,
+    checks: Vec<Check>,
+}
+
+/// Last results of the deep checks, so frequent `?deep=true` requests
+/// don't each contact every dependency. Requests arriving while the checks
+/// run wait for their results. Each server worker has its own.
+#[derive(Default)]
+pub struct DeepChecksCache {
+    last: Mutex<Option<(Instant, Vec<Check>)>>,
+}
+
+async fn run_check<T, E>(check: &'static str, f: impl Future<Output = Result<T, E>>) -> Check
+where
+    E: Display,
+{
+    let started = Instant::now();
+    let res = f.await;
+    Check {
+        check,
+        ok: res.is_ok(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        error: res.err().map(|err| err.to_string()),
+    }
+}
+
+/// Run a database check on the blocking thread pool
+async fn run_db_check(
+    check: &'static str,
+    conn: PooledConnection<ConnectionManager<PgConnection>>,
+    f: fn(&PgConnection) -> Result<(), Error>,
+) -> Check {
+    run_check(check, async move {
+        actix_rt::task::spawn_blocking(move || f(&conn))
+            .await
+            .map_err(|err| err.to_string())?
+            .map_err(|err| err.to_string())
+    })
+    .await
+}
+
+/// Whether the validator can serve requests. With `?deep=true`, the Arweave
+/// gateway, contract gateway and bundler are contacted and the database is
+/// written to as well, each reported with its latency. Deep check results
+/// are reused for [`DEEP_CHECKS_TTL`]. Answers 503 if any check failed.
+pub async fn ready<Context, HttpClient>(
+    ctx: Data<Context>,
+    cache: Data<DeepChecksCache>,
+    query: Query<ReadyQuery>,
+) -> HttpResponse
+where
+    Context: ArweaveAccess
+        + ArweaveContext<HttpClient>
+        + BundlerAccess
+        + ContractGatewayAccess
+        + http::ClientAccess<HttpClient>
+        + QueryContext
+        + RuntimeConfigAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let ctx = &**ctx;
+    let mut checks = vec![
+        run_db_check(
+            "database",
+            ctx.get_db_connection(),
+            queries::check_connection,
+        )
+        .await,
+    ];
+
+    if query.deep {
+        let mut last = cache.last.lock().await;
+        match &*last {
+            Some((checked_at, deep_checks)) if checked_at.elapsed() < DEEP_CHECKS_TTL => {
+                checks.extend(deep_checks.iter().cloned());
+            }
+            _ => {
+                let deep_checks = deep_checks(ctx).await;
+                checks.extend(deep_checks.iter().cloned());
+                *last = Some((Instant::now(), deep_checks));
+            }
+        }
+    }
+
+    let ready = checks.iter().all(|check| check.ok);
+    let body = Readiness { ready, checks };
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+async fn deep_checks<Context, HttpClient>(ctx: &Context) -> Vec<Check>
+where
+    Context: ArweaveAccess
+        + ArweaveContext<HttpClient>
+        + BundlerAccess
+        + ContractGatewayAccess
+        + http::ClientAccess<HttpClient>
+        + QueryContext
+        + RuntimeConfigAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    vec![
+        run_db_check(
+            "database_writable",
+            ctx.get_db_connection(),
+            queries::check_writable,
+        )
+        .await,
+        run_check("arweave", ctx.arweave().get_network_info(ctx)).await,
+        run_check(
+            "contract_gateway",
+            ctx.contract_gateway().get_current_state(ctx),
+        )
+        .await,
+        run_check("bundler", async {
+            let url = Url::parse(&ctx.bundler().url).map_err(|err| err.to_string())?;
+            BundlerConfig::fetch_config(ctx.get_http_client(), &url)
+                .await
+                .map_err(|err| err.to_string())
+        })
+        .await,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, read_body_json, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use serde_json::Value;
+
+    use crate::{context::test_utils::TestContext, http::reqwest::mock::MockHttpClient};
+
+    use super::{ready, DeepChecksCache};
+
+    #[actix_web::test]
+    async fn deep_checks_report_each_dependency() {
+        let ctx = TestContext::builder().build();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ctx))
+                .app_data(Data::new(DeepChecksCache::default()))
+                .route(
+                    "/ready",
+                    web::get().to(ready::<TestContext, MockHttpClient>),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/ready").to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = read_body_json(res).await;
+        assert_eq!(body["checks"].as_array().unwrap().len(), 1);
+
+        // Mock client answers no request, so external dependencies fail
+        let req = TestRequest::get().uri("/ready?deep=true").to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = read_body_json(res).await;
+        let failed: Vec<&str> = body["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|check| check["ok"] == false)
+            .map(|check| check["check"].as_str().unwrap())
+            .collect();
+        assert_eq!(failed, ["arweave", "contract_gateway", "bundler"]);
+    }
+
+    #[actix_web::test]
+    async fn deep_checks_are_reused_within_ttl() {
+        let client =
+            MockHttpClient::new(|a: &reqwest::Request, b: &reqwest::Request| a.url() == b.url())
+                .when(|req: &reqwest::Request| req.url().path() == "/info")
+                .times(1)
+                .then(|_: &reqwest::Request| {
+                    reqwest::Response::from(
+                        http::response::Builder::new()
+                            .status(200)
+                            .body("{}")
+                            .unwrap(),
+                    )
+                });
+        let ctx = TestContext::builder()
+            .with_http_client(client.clone())
+            .build();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ctx))
+                .app_data(Data::new(DeepChecksCache::default()))
+                .route(
+                    "/ready",
+                    web::get().to(ready::<TestContext, MockHttpClient>),
+                ),
+        )
+        .await;
+
+        for _ in 0..3 {
+            let req = TestRequest::get().uri("/ready?deep=true").to_request();
+            call_service(&app, req).await;
+        }
+
+        client.assert_expectations();
+    }
+}
@@ -0,0 +1,383 @@
+use actix_web::{
+    http::header::ContentType,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    database::queries::{self, QueryContext},
+    events::EventBusAccess,
+    key_manager,
+    receipt::{store_receipts, RawTxReceipt, ReceiptQueueAccess, SOURCE_BUNDLER},
+    server::{error::ValidatorServerError, routes::sign::Config},
+};
+
+/// Receipts accepted or returned in a single request
+const MAX_RECEIPTS_PER_REQUEST: usize = 100;
+
+/// Accept receipts gossiped by peers. Receipts with a valid bundler signature
+/// are stored, the ones not known before are queued to be gossiped further.
+pub async fn post_receipts<Context, KeyManager>(
+    ctx: Data<Context>,
+    body: Json<Vec<RawTxReceipt>>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: Config<KeyManager> + EventBusAccess + QueryContext + ReceiptQueueAccess,
+    KeyManager: key_manager::KeyManager,
+{
+    let receipts = body.into_inner();
+    if receipts.len() > MAX_RECEIPTS_PER_REQUEST {
+        return Err(ValidatorServerError::BadRequest(format!(
+            "At most {} receipts are accepted at once",
+            MAX_RECEIPTS_PER_REQUEST
+        )));
+    }
+
+    let epoch = ctx.get_validator_state().current_epoch();
+    let (stored, rejected) = store_receipts(&**ctx, ctx.key_manager(), epoch, receipts)?;
+    let accepted = stored.len();
+    for receipt in stored {
+        ctx.receipt_queue().push(receipt);
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "accepted": accepted,
+        "rejected": rejected,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct ReceiptsQuery {
+    epoch: u64,
+    /// Return receipts with transaction id greater than this one
+    after: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Receipts stored for an epoch as they were received, ordered by
+/// transaction id: the bundler's sign requests and receipts shared by
+/// peers. Used by peers catching up after being offline, which verify
+/// the bundler's signature on them.
+pub async fn get_receipts<Context>(
+    ctx: Data<Context>,
+    query: Query<ReceiptsQuery>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: QueryContext,
+{
+    let limit = query
+        .limit
+        .unwrap_or(MAX_RECEIPTS_PER_REQUEST)
+        .min(MAX_RECEIPTS_PER_REQUEST);
+    let stored = queries::get_epoch_raw_receipts(
+        &**ctx,
+        query.epoch.into(),
+        query.after.as_deref(),
+        limit as i64,
+    )?;
+    let receipts: Vec<RawTxReceipt> = stored
+        .iter()
+        .filter_map(|raw| RawTxReceipt::from_stored(raw).ok())
+        .collect();
+
+    Ok(HttpResponse::Ok().json(receipts))
+}
+
+/// Receipts of a transaction exactly as they were received from the
+/// bundler and from peers, evidence for disputes over the transaction
+pub async fn get_tx_receipts<Context>(
+    ctx: Data<Context>,
+    tx_id: Path<String>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: QueryContext,
+{
+    let receipts = queries::get_raw_receipts(&**ctx, &tx_id)?;
+    if receipts.is_empty() {
+        return Err(ValidatorServerError::NotFound(format!(
+            "No receipts stored for transaction {}",
+            tx_id
+        )));
+    }
+
+    Ok(HttpResponse::Ok().json(receipts))
+}
+
+/// Receipt of a transaction for peers that missed it, the payload as it was
+/// received. The bundler's sign request is preferred over receipts shared
+/// by peers.
+pub async fn get_tx_receipt<Context>(
+    ctx: Data<Context>,
+    tx_id: Path<String>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: QueryContext,
+{
+    let receipts = queries::get_raw_receipts(&**ctx, &tx_id)?;
+    let receipt = receipts
+        .iter()
+        .find(|raw| raw.source == SOURCE_BUNDLER)
+        .or_else(|| receipts.first());
+    match receipt {
+        Some(raw) => Ok(HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .body(raw.payload.clone())),
+        None => Err(ValidatorServerError::NotFound(format!(
+            "No receipt stored for transaction {}",
+            tx_id
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::header::ContentType,
+        test::{call_service, init_service, read_body, read_body_json, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use data_encoding::BASE64URL_NOPAD;
+    use reqwest::StatusCode;
+    use serde_json::Value;
+
+    use crate::{
+        context::{test_utils::test_context, AppContext},
+        database::{models::NewRawReceipt, queries::insert_raw_receipts, test_utils::unique_id},
+        events::{Event, EventBusAccess},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::test_utils::test_keys,
+        key_manager::KeyManager,
+        receipt::{
+            test_utils::{signed_receipt, signed_sign_request},
+            ReceiptQueueAccess, TxReceipt, SOURCE_BUNDLER, SOURCE_PEER,
+        },
+        server::routes::sign::Config,
+    };
+
+    use super::{get_receipts, get_tx_receipt, get_tx_receipts, post_receipts};
+
+    #[actix_web::test]
+    async fn receipt_with_invalid_signature_is_rejected() {
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = test_context(key_manager);
+        let app = App::new().app_data(Data::new(ctx.clone())).route(
+            "/receipts",
+            web::post().to(post_receipts::<AppContext<MockHttpClient>, _>),
+        );
+        let app = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/receipts")
+            .set_json(vec![TxReceipt {
+                block: 10,
+                tx_id: "gossiped-invalid-receipt-000000000000000000".to_string(),
+                signature: b"signature".to_vec(),
+            }])
+            .to_request();
+        let res = call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = read_body_json(res).await;
+        assert_eq!(body["accepted"], 0);
+        assert_eq!(body["rejected"], 1);
+        assert!(ctx.receipt_queue().take().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn sign_request_gossiped_by_peer_is_accepted() {
+        let (key_manager, bundle_pvk) = test_keys();
+        let ctx = test_context(key_manager);
+        let request = signed_sign_request(
+            &bundle_pvk,
+            &unique_id(),
+            10,
+            ctx.key_manager().validator_address(),
+        );
+        let app = App::new().app_data(Data::new(ctx.clone())).route(
+            "/receipts",
+            web::post().to(post_receipts::<AppContext<MockHttpClient>, _>),
+        );
+        let app = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/receipts")
+            .set_json(vec![&request])
+            .to_request();
+        let body: Value = read_body_json(call_service(&app, req).await).await;
+
+        assert_eq!(body["accepted"], 1);
+        assert_eq!(body["rejected"], 0);
+        let queued = ctx.receipt_queue().take();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].sign_request.as_ref(), Some(&request));
+    }
+
+    #[actix_web::test]
+    async fn posted_receipts_are_served_by_epoch() {
+        let (key_manager, bundle_pvk) = test_keys();
+        let ctx = test_context(key_manager);
+        let receipt = signed_receipt(
+            &bundle_pvk,
+            "gossiped-valid-receipt-00000000000000000000",
+            10,
+        );
+        let request = signed_sign_request(
+            &bundle_pvk,
+            "gossiped-valid-receipt-00000000000000000001",
+            10,
+            ctx.key_manager().validator_address(),
+        );
+        let app = App::new()
+            .app_data(Data::new(ctx.clone()))
+            .route(
+                "/receipts",
+                web::post().to(post_receipts::<AppContext<MockHttpClient>, _>),
+            )
+            .route(
+                "/receipts",
+                web::get().to(get_receipts::<AppContext<MockHttpClient>>),
+            );
+        let app = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/receipts")
+            .set_json(vec![
+                serde_json::to_value(&receipt).unwrap(),
+                serde_json::to_value(&request).unwrap(),
+            ])
+            .to_request();
+        call_service(&app, req).await;
+
+        // Served as received, sign requests included
+        let req = TestRequest::get()
+            .uri("/receipts?epoch=0&after=gossiped-valid-receipt-0000000000000000000")
+            .to_request();
+        let res = call_service(&app, req).await;
+        let body: Vec<Value> = read_body_json(res).await;
+        assert!(body.contains(&serde_json::to_value(&receipt).unwrap()));
+        assert!(body.contains(&serde_json::to_value(&request).unwrap()));
+    }
+
+    #[actix_web::test]
+    async fn posted_receipts_are_kept_as_received() {
+        let (key_manager, bundle_pvk) = test_keys();
+        let ctx = test_context(key_manager);
+        let tx_id = "gossiped-raw-receipt-0000000000000000000000";
+        let receipt = signed_receipt(&bundle_pvk, tx_id, 10);
+        // Whitespace and key order are kept as well
+        let payload = format!(
+            "{{ \"tx_id\": \"{}\", \"signature\": \"{}\", \"block\": 10 }}",
+            tx_id,
+            BASE64URL_NOPAD.encode(&receipt.signature)
+        );
+        let app = App::new()
+            .app_data(Data::new(ctx.clone()))
+            .route(
+                "/receipts",
+                web::post().to(post_receipts::<AppContext<MockHttpClient>, _>),
+            )
+            .route(
+                "/tx/{tx_id}/receipts",
+                web::get().to(get_tx_receipts::<AppContext<MockHttpClient>>),
+            );
+        let app = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/receipts")
+            .insert_header(ContentType::json())
+            .set_payload(format!("[{}]", payload))
+            .to_request();
+        call_service(&app, req).await;
+
+        let req = TestRequest::get()
+            .uri(&format!("/tx/{}/receipts", tx_id))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = read_body_json(res).await;
+        assert_eq!(body[0]["source"], "peer");
+        assert_eq!(body[0]["payload"], payload);
+    }
+
+    #[actix_web::test]
+    async fn sign_request_of_bundler_is_served_as_receipt() {
+        let (key_manager, bundle_pvk) = test_keys();
+        let ctx = test_context(key_manager);
+        let tx_id = unique_id();
+        let peer = serde_json::to_string(&signed_receipt(&bundle_pvk, &tx_id, 10)).unwrap();
+        let bundler = serde_json::to_string(&signed_sign_request(
+            &bundle_pvk,
+            &tx_id,
+            10,
+            ctx.key_manager().validator_address(),
+        ))
+        .unwrap();
+        let app = App::new().app_data(Data::new(ctx.clone())).route(
+            "/tx/{tx_id}/receipt",
+            web::get().to(get_tx_receipt::<AppContext<MockHttpClient>>),
+        );
+        let app = init_service(app).await;
+        let req = || {
+            TestRequest::get()
+                .uri(&format!("/tx/{}/receipt", tx_id))
+                .to_request()
+        };
+
+        let res = call_service(&app, req()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        insert_raw_receipts(
+            &ctx,
+            &[
+                NewRawReceipt::new(tx_id.clone(), SOURCE_PEER, peer.clone()),
+                NewRawReceipt::new(tx_id.clone(), SOURCE_BUNDLER, bundler.clone()),
+            ],
+        )
+        .unwrap();
+        let res = call_service(&app, req()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(read_body(res).await, bundler.as_bytes());
+    }
+
+    #[actix_web::test]
+    async fn receipt_promising_another_block_is_an_equivocation() {
+        let (key_manager, bundle_pvk) = test_keys();
+        let ctx = test_context(key_manager);
+        let tx_id = unique_id();
+        let mut events = ctx.event_bus().subscribe();
+        let app = App::new().app_data(Data::new(ctx.clone())).route(
+            "/receipts",
+            web::post().to(post_receipts::<AppContext<MockHttpClient>, _>),
+        );
+        let app = init_service(app).await;
+
+        for (block, accepted) in [(10, 1), (10, 0), (20, 0)] {
+            let req = TestRequest::post()
+                .uri("/receipts")
+                .set_json(vec![signed_receipt(&bundle_pvk, &tx_id, block)])
+                .to_request();
+            let body: Value = read_body_json(call_service(&app, req).await).await;
+            assert_eq!(body["accepted"], accepted);
+        }
+
+        match events.try_recv().unwrap() {
+            Event::Equivocation {
+                tx,
+                kind,
+                first_evidence,
+                second_evidence,
+            } => {
+                assert_eq!(tx, tx_id);
+                assert_eq!(kind, "receipts");
+                let first: TxReceipt = serde_json::from_str(&first_evidence).unwrap();
+                let second: TxReceipt = serde_json::from_str(&second_evidence).unwrap();
+                assert_eq!((first.block, second.block), (10, 20));
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(events.try_recv().is_err());
+    }
+}
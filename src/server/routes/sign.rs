@@ -1,23 +1,27 @@
 use actix_web::{
-    web::{Data, Json},
+    web::{Bytes, Data},
     HttpResponse,
 };
 use bundlr_sdk::deep_hash::{deep_hash, DeepHashChunk, ONE_AS_BUFFER};
+use bundlr_sdk::deep_hash_sync::deep_hash_sync;
 
 use data_encoding::BASE64URL_NOPAD;
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
-use paris::error;
+use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use tracing::error;
 
 use crate::{
+    config::RuntimeConfigAccess,
     consts::{BUNDLR_AS_BUFFER, VALIDATOR_AS_BUFFER},
     database::{
-        models::{Epoch, NewTransaction},
+        models::{Epoch, NewRawReceipt, NewTransaction},
+        schema::raw_receipts,
         schema::transactions::dsl::*,
     },
     key_manager,
+    receipt::SOURCE_BUNDLER,
     server::{error::ValidatorServerError, RuntimeContext},
-    state::{ValidatorRole, ValidatorStateAccess},
+    state::ValidatorStateAccess,
 };
 
 pub trait Config<KeyManager>: ValidatorStateAccess
@@ -33,7 +37,7 @@ where
 
 /// Deserializer from string to u128
 fn de_u128<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
-    let s: &str = de::Deserialize::deserialize(deserializer)?;
+    let s: String = de::Deserialize::deserialize(deserializer)?;
     s.parse().map_err(de::Error::custom)
 }
 
@@ -46,27 +50,32 @@ where
     serializer.serialize_str(&val.to_string())
 }
 
-#[derive(Deserialize, Serialize)]
+/// Request of the bundler to sign a receipt, the bundler's own signature
+/// over it is the receipt shared with peers
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SignRequest {
-    id: String,
-    size: usize,
+    pub id: String,
+    pub size: usize,
     #[serde(deserialize_with = "de_u128", serialize_with = "ser_as_string")]
-    fee: u128,
-    currency: String,
+    pub fee: u128,
+    pub currency: String,
     #[serde(deserialize_with = "de_u128", serialize_with = "ser_as_string")]
-    block: u128,
-    validator: String,
-    signature: String,
+    pub block: u128,
+    pub validator: String,
+    /// Bundler signature, base64url encoded
+    pub signature: String,
 }
 
 impl SignRequest {
+    /// Address of the bundler key the request is signed with, `None` if it
+    /// isn't signed with any of them
     // FIXME: needs proper error type
-    pub async fn verify<KeyManager>(&self, key_manager: &KeyManager) -> Result<bool, ()>
+    pub fn verify<KeyManager>(&self, key_manager: &KeyManager) -> Result<Option<String>, ()>
     where
         KeyManager: key_manager::KeyManager,
     {
         // FIXME: fix lifetimes in DeepHashChunk::Chunk and deep_hash to avoid copying the data
-        let signature_data = deep_hash(DeepHashChunk::Chunks(vec![
+        let signature_data = deep_hash_sync(DeepHashChunk::Chunks(vec![
             DeepHashChunk::Chunk(BUNDLR_AS_BUFFER.into()),
             DeepHashChunk::Chunk(ONE_AS_BUFFER.into()),
             DeepHashChunk::Chunk(self.id.as_bytes().to_owned().into()),
@@ -76,7 +85,6 @@ impl SignRequest {
             DeepHashChunk::Chunk(self.block.to_string().as_bytes().to_owned().into()),
             DeepHashChunk::Chunk(self.validator.as_bytes().to_owned().into()),
         ]))
-        .await
         .map_err(|err| {
             error!("Failed to build data for signing: {:?}", err);
         })?;
@@ -88,7 +96,7 @@ impl SignRequest {
                     error!("Failed to decode signature: {:?}", err);
                 })?;
 
-        Ok(key_manager.verify_bundler_signature(&signature_data, &decoded_signature))
+        Ok(key_manager.bundler_key_id(&signature_data, &decoded_signature))
     }
 
     pub async fn sign<KeyManager>(&self, key_manager: &KeyManager) -> Result<String, ()>
@@ -117,23 +125,86 @@ impl SignRequest {
     }
 }
 
+/// Seconds a bundler is asked to wait before retrying a refused request
+const OVERLOAD_RETRY_AFTER: u64 = 5;
+
+/// Seconds the validation backlog is trusted before it is counted again in
+/// the database. Transactions are only taken out of the backlog by a
+/// count, and the bundler balance check counting it may run in another
+/// process, or not at all.
+const BACKLOG_RECOUNT_INTERVAL: u64 = 10;
+
+/// Refuse new receipts while too many are being written to the database
+/// or wait to be seen in a bundle
+async fn check_load<Context>(ctx: &Context) -> Result<(), ValidatorServerError>
+where
+    Context: ValidatorStateAccess + RuntimeConfigAccess + RuntimeContext,
+{
+    let state = ctx.get_validator_state();
+    let (max_pending_writes, max_validation_backlog) = ctx.ingestion_limits();
+    if max_validation_backlog.is_some()
+        && state.validation_backlog_age() >= BACKLOG_RECOUNT_INTERVAL
+    {
+        let conn = ctx.get_db_connection();
+        let pending: i64 = actix_rt::task::spawn_blocking(move || {
+            transactions
+                .filter(bundle_id.is_null())
+                .count()
+                .get_result(&conn)
+        })
+        .await??;
+        state.set_validation_backlog(pending.try_into().unwrap_or_default());
+    }
+    if let Some(max) = max_pending_writes {
+        if state.pending_writes() >= max {
+            return Err(ValidatorServerError::Overloaded(
+                format!("{} receipts being written", state.pending_writes()),
+                OVERLOAD_RETRY_AFTER,
+            ));
+        }
+    }
+    if let Some(max) = max_validation_backlog {
+        if state.validation_backlog() >= max {
+            return Err(ValidatorServerError::Overloaded(
+                format!(
+                    "{} transactions waiting to be validated",
+                    state.validation_backlog()
+                ),
+                OVERLOAD_RETRY_AFTER,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 pub async fn sign_route<Context, KeyManager>(
     ctx: Data<Context>,
-    body: Json<SignRequest>,
+    payload: Bytes,
 ) -> actix_web::Result<HttpResponse, ValidatorServerError>
 where
-    Context: self::Config<KeyManager> + RuntimeContext + Send,
+    Context: self::Config<KeyManager> + RuntimeConfigAccess + RuntimeContext + Send,
     KeyManager: key_manager::KeyManager,
 {
     // FIXME: checking role should be a function of block height
     // we probably need to track role for previous, current and next epochs
     // to allow bundler node and this validator to see new blocks at different
     // times
-    if ctx.get_validator_state().role() != ValidatorRole::Cosigner {
-        return Ok(HttpResponse::BadRequest().finish());
+    if !ctx.get_validator_state().role().signs() {
+        return Err(ValidatorServerError::BadRequest(
+            "Validator is not a cosigner".to_string(),
+        ));
+    }
+    check_load(&**ctx).await?;
+    if ctx.get_validator_state().bundler_key_mismatch() {
+        return Err(ValidatorServerError::Conflict(
+            "Bundler key doesn't match the bundler registered in the contract".to_string(),
+        ));
     }
 
-    let body = body.into_inner();
+    // Parsed by hand so the request can be kept exactly as received
+    let body: SignRequest = serde_json::from_slice(&payload)
+        .map_err(|err| ValidatorServerError::BadRequest(format!("Invalid request: {}", err)))?;
 
     // Verify
     let exists = {
@@ -159,26 +230,31 @@ where
     let key_manager = ctx.key_manager();
 
     if body.validator != *ctx.validator_address() {
-        return Ok(HttpResponse::BadRequest().body("Invalid validator address"));
+        return Err(ValidatorServerError::BadRequest(
+            "Invalid validator address".to_string(),
+        ));
     }
 
     // Check that the body.block is not too far in the past nor too far in the future
+    let invalid_block = || ValidatorServerError::BadRequest("Invalid block number".to_string());
     match body.block.cmp(&current_block) {
-        std::cmp::Ordering::Less => {
-            return Ok(HttpResponse::BadRequest().body("Invalid block number"))
-        }
+        std::cmp::Ordering::Less => return Err(invalid_block()),
         std::cmp::Ordering::Greater if body.block - current_block < 395 => {
-            return Ok(HttpResponse::BadRequest().body("Invalid block number"))
+            return Err(invalid_block())
         }
         std::cmp::Ordering::Greater if body.block - current_block > 405 => {
-            return Ok(HttpResponse::BadRequest().body("Invalid block number"))
+            return Err(invalid_block())
         }
         _ => (),
     }
 
-    match body.verify(key_manager).await {
-        Ok(true) => (),
-        Ok(false) => return Ok(HttpResponse::BadRequest().body("Invalid bundler signature")),
+    let bundler_key = match body.verify(key_manager) {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            return Err(ValidatorServerError::BadRequest(
+                "Invalid bundler signature".to_string(),
+            ))
+        }
         Err(()) => return Err(ValidatorServerError::InternalError),
     };
 
@@ -191,33 +267,47 @@ where
     // Add to db
     let current_epoch = ctx.current_epoch();
 
+    let raw_receipt = NewRawReceipt::new(
+        body.id.clone(),
+        SOURCE_BUNDLER,
+        String::from_utf8_lossy(&payload).into_owned(),
+    );
     let new_transaction = NewTransaction {
         id: body.id,
         epoch: Epoch(current_epoch),
         block_promised: body.block.into(),
         block_actual: None,
-        signature: sig.as_bytes().to_vec(),
+        signature: sig.clone().into_bytes(),
         validated: false,
         bundle_id: None,
+        bundler_key: Some(bundler_key),
     };
 
+    let _write = ctx.get_validator_state().start_write();
     let conn = ctx.get_db_connection();
     actix_rt::task::spawn_blocking(move || {
-        diesel::insert_into(transactions)
-            .values::<NewTransaction>(new_transaction)
-            .execute(&conn)
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::insert_into(transactions)
+                .values::<NewTransaction>(new_transaction)
+                .execute(&conn)?;
+            diesel::insert_into(raw_receipts::table)
+                .values(raw_receipt)
+                .on_conflict_do_nothing()
+                .execute(&conn)
+        })
     })
     .await??;
+    ctx.get_validator_state().add_to_validation_backlog();
 
     Ok(HttpResponse::Ok()
         .insert_header(("Content-Type", "application/octet-stream"))
-        .body(sig.as_bytes().to_vec()))
+        .body(sig))
 }
 
 #[cfg(test)]
 mod tests {
     use actix_web::{
-        http::header::ContentType,
+        http::header::{ContentType, RETRY_AFTER},
         test::{call_service, init_service, TestRequest},
         web::{self, Data},
         App,
@@ -236,15 +326,20 @@ mod tests {
     use reqwest::StatusCode;
 
     use crate::{
+        config::RuntimeConfig,
         consts::{BUNDLR_AS_BUFFER, VALIDATOR_AS_BUFFER},
-        context::{test_utils::test_context, AppContext},
+        context::{
+            test_utils::{test_context, TestContext},
+            AppContext,
+        },
+        database::queries::get_raw_receipts,
         http::reqwest::mock::MockHttpClient,
         key_manager::{test_utils::test_keys, KeyManager},
         server::routes::sign::{sign_route, Config},
         state::ValidatorStateAccess,
     };
 
-    use super::SignRequest;
+    use super::{check_load, SignRequest, BACKLOG_RECOUNT_INTERVAL};
     fn test_message(
         signing_key: &PKey<Private>,
         block: u128,
@@ -299,7 +394,10 @@ mod tests {
             "dtdOmHZMOtGb2C0zLqLBUABrONDZ5rzRh9NengT1-Z1",
         );
 
-        assert!(msg.verify(&key_manager).await.unwrap())
+        assert_eq!(
+            msg.verify(&key_manager).unwrap().as_deref(),
+            Some(key_manager.bundler_address())
+        )
     }
 
     #[actix_rt::test]
@@ -355,11 +453,12 @@ mod tests {
             ctx.key_manager().validator_address().to_string(),
             "dtdOmHZMOtGb2C0zLqLBUABrONDZ5rzRh9NengT1-Z3",
         );
+        let payload = serde_json::to_string(&msg).unwrap();
 
         let req = TestRequest::post()
             .uri("/")
             .insert_header(ContentType::json())
-            .set_json(msg)
+            .set_payload(payload.clone())
             .to_request();
 
         let res = call_service(&app, req).await;
@@ -369,6 +468,58 @@ mod tests {
             "Failed: {:?}",
             res.into_body()
         );
+        let raw = get_raw_receipts(&ctx, "dtdOmHZMOtGb2C0zLqLBUABrONDZ5rzRh9NengT1-Z3").unwrap();
+        assert_eq!(raw[0].source, "bundler");
+        assert_eq!(raw[0].payload, payload);
+    }
+
+    #[actix_web::test]
+    async fn sign_request_is_refused_while_backlog_is_full() {
+        let (key_manager, _bundler_private_key) = test_keys();
+        let ctx = TestContext::builder()
+            .with_key_manager(key_manager)
+            .with_runtime_config(RuntimeConfig {
+                max_validation_backlog: Some(10),
+                ..RuntimeConfig::default()
+            })
+            .build();
+        ctx.get_validator_state().set_validation_backlog(10);
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ctx.clone()))
+                .route("/", web::post().to(sign_route::<TestContext, _>)),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/")
+            .insert_header(ContentType::json())
+            .set_payload("{}")
+            .to_request();
+        let res = call_service(&app, req).await;
+
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers().get(RETRY_AFTER).unwrap(), "5");
+    }
+
+    #[actix_web::test]
+    async fn stale_backlog_is_counted_again() {
+        let (key_manager, _bundler_private_key) = test_keys();
+        let ctx = TestContext::builder()
+            .with_key_manager(key_manager)
+            .with_runtime_config(RuntimeConfig {
+                max_validation_backlog: Some(u64::MAX),
+                ..RuntimeConfig::default()
+            })
+            .build();
+        // Never counted, only grown by signed receipts
+        let state = ctx.get_validator_state();
+        state.add_to_validation_backlog();
+        assert!(state.validation_backlog_age() >= BACKLOG_RECOUNT_INTERVAL);
+
+        check_load(&ctx).await.unwrap();
+
+        assert!(state.validation_backlog_age() < BACKLOG_RECOUNT_INTERVAL);
     }
 
     #[actix_web::test]
@@ -0,0 +1,178 @@
+use actix_web::{
+    web::{Bytes, Data},
+    HttpRequest, HttpResponse,
+};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+    context::ValidatorAddressAccess,
+    events::{Event, EventBusAccess},
+    peer_auth::PeerSignature,
+    server::error::ValidatorServerError,
+    slash_votes::{SlashVote, SlashVotesAccess},
+};
+
+/// Accept a slash vote sent by the validator that cast it, which must have
+/// signed the request, see [`crate::peer_auth`]. A vote on a proposal this
+/// validator hasn't voted on yet has it evaluate the proposal right away.
+pub async fn post_slash_vote<Context>(
+    ctx: Data<Context>,
+    req: HttpRequest,
+    body: Bytes,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: EventBusAccess + SlashVotesAccess + ValidatorAddressAccess + 'static,
+{
+    let vote: SlashVote = serde_json::from_slice(&body)
+        .map_err(|err| ValidatorServerError::BadRequest(err.to_string()))?;
+    if vote.proposal.is_empty() || vote.validator.is_empty() {
+        return Err(ValidatorServerError::BadRequest(
+            "Slash vote needs a proposal and a validator".to_string(),
+        ));
+    }
+
+    let path = match req.query_string() {
+        "" => req.path().to_string(),
+        query => format!("{}?{}", req.path(), query),
+    };
+    let signer = PeerSignature::from_headers(|name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    })
+    .and_then(|signature| signature.verify(req.method().as_str(), &path, &body))
+    .map_err(|err| {
+        warn!(validator = %vote.validator, "Rejected unsigned slash vote - {}", err);
+        ValidatorServerError::Unauthorized
+    })?;
+    if signer != vote.validator {
+        warn!(
+            validator = %vote.validator,
+            signer = %signer,
+            "Rejected slash vote signed by another validator"
+        );
+        return Err(ValidatorServerError::Unauthorized);
+    }
+
+    let new = ctx.slash_votes().record(&vote);
+    if new {
+        info!(
+            proposal = %vote.proposal,
+            validator = %vote.validator,
+            vote = vote.vote.as_str(),
+            "Slash vote received"
+        );
+        if !ctx
+            .slash_votes()
+            .has_voted(&vote.proposal, ctx.get_validator_address())
+        {
+            ctx.event_bus().publish(Event::SlashVoteReceived {
+                proposal: vote.proposal,
+                validator: vote.validator,
+                vote: vote.vote.as_str().to_string(),
+            });
+        }
+    }
+
+    Ok(HttpResponse::Accepted().json(json!({ "new": new })))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, read_body_json, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use serde_json::Value;
+
+    use crate::{
+        context::test_utils::TestContext,
+        events::{Event, EventBusAccess},
+        key_manager::{test_utils::test_keys, KeyManager},
+        peer_auth::sign_request,
+        slash_votes::{Ballot, SlashVote},
+    };
+
+    use super::post_slash_vote;
+
+    /// Request posting `vote` signed by `key_manager`'s validator key
+    fn signed_vote<K: KeyManager>(key_manager: &K, vote: &SlashVote) -> TestRequest {
+        let body = serde_json::to_string(vote).unwrap();
+        let url = reqwest::Url::parse("http://validator.example.com/slash-votes").unwrap();
+        let mut req = reqwest::Request::new(reqwest::Method::POST, url);
+        *req.body_mut() = Some(body.clone().into());
+        sign_request(key_manager, &mut req);
+
+        let mut test_req = TestRequest::post().uri("/slash-votes");
+        for (name, value) in req.headers() {
+            test_req = test_req.insert_header((name.as_str(), value.to_str().unwrap()));
+        }
+        test_req.set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn vote_is_accepted_once() {
+        let ctx = TestContext::builder().build();
+        let mut events = ctx.event_bus().subscribe();
+        let app = init_service(App::new().app_data(Data::new(ctx.clone())).route(
+            "/slash-votes",
+            web::post().to(post_slash_vote::<TestContext>),
+        ))
+        .await;
+        let (peer_key_manager, _) = test_keys();
+        let vote = SlashVote {
+            proposal: "proposal".to_string(),
+            validator: peer_key_manager.validator_address().to_string(),
+            vote: Ballot::For,
+        };
+
+        for new in [true, false] {
+            let req = signed_vote(&peer_key_manager, &vote).to_request();
+            let res = call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::ACCEPTED);
+            let body: Value = read_body_json(res).await;
+            assert_eq!(body["new"], new);
+        }
+
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            Event::SlashVoteReceived { proposal, .. } if proposal == "proposal"
+        ));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[actix_web::test]
+    async fn vote_must_be_signed_by_its_validator() {
+        let ctx = TestContext::builder().build();
+        let app = init_service(App::new().app_data(Data::new(ctx.clone())).route(
+            "/slash-votes",
+            web::post().to(post_slash_vote::<TestContext>),
+        ))
+        .await;
+        let (peer_key_manager, _) = test_keys();
+        let (other_key_manager, _) = test_keys();
+        let vote = SlashVote {
+            proposal: "proposal".to_string(),
+            validator: peer_key_manager.validator_address().to_string(),
+            vote: Ballot::For,
+        };
+
+        let req = TestRequest::post()
+            .uri("/slash-votes")
+            .set_json(&vote)
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let req = signed_vote(&other_key_manager, &vote).to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
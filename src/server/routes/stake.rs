@@ -0,0 +1,84 @@
+use actix_web::{web::Data, HttpResponse};
+
+use crate::{
+    database::queries::{self, QueryContext},
+    key_manager,
+    server::{error::ValidatorServerError, routes::sign::Config},
+};
+
+/// Stake and nomination of this validator as of the last contract sync
+pub async fn stake<Context, KeyManager>(
+    ctx: Data<Context>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: Config<KeyManager> + QueryContext,
+    KeyManager: key_manager::KeyManager,
+{
+    let mut stake = queries::get_stake(&**ctx, ctx.validator_address())?.ok_or_else(|| {
+        ValidatorServerError::NotFound("stake not synced from contract yet".to_string())
+    })?;
+    stake.address = stake.address.trim().to_string();
+
+    Ok(HttpResponse::Ok().json(stake))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{call_and_read_body_json, call_service, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+
+    use crate::{
+        context::{test_utils::test_context, AppContext},
+        database::{
+            models::{Epoch, Stake},
+            queries::upsert_stake,
+        },
+        http::reqwest::mock::MockHttpClient,
+        key_manager::{test_utils::test_keys, KeyManager},
+    };
+
+    use super::stake;
+
+    #[actix_web::test]
+    async fn stake_is_served_once_synced() {
+        let (key_manager, _) = test_keys();
+        let address = key_manager.validator_address().to_string();
+        let ctx = test_context(key_manager);
+        let app = init_service(App::new().app_data(Data::new(ctx.clone())).route(
+            "/stake",
+            web::get().to(stake::<AppContext<MockHttpClient>, _>),
+        ))
+        .await;
+
+        let req = TestRequest::get().uri("/stake").to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        upsert_stake(
+            &ctx,
+            &Stake {
+                address: address.clone(),
+                registered: true,
+                stake: "1000".to_string(),
+                minimum_stake: "100".to_string(),
+                nominated: false,
+                epoch: Epoch(3),
+            },
+        )
+        .unwrap();
+
+        let req = TestRequest::get().uri("/stake").to_request();
+        let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["address"], address.as_str());
+        assert_eq!(body["stake"], "1000");
+        assert_eq!(body["nominated"], false);
+        assert_eq!(body["epoch"], 3);
+    }
+}
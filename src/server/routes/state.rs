@@ -0,0 +1,78 @@
+use actix_web::{web::Data, HttpResponse};
+use serde::Serialize;
+
+use crate::{
+    server::error::ValidatorServerError,
+    state::{ValidatorRole, ValidatorStateAccess},
+};
+
+#[derive(Serialize)]
+struct State {
+    role: ValidatorRole,
+    epoch: u64,
+    block: u64,
+    bundler_underfunded: bool,
+    /// Receipts aren't verified while the bundler key differs from the
+    /// bundler registered in the contract
+    bundler_key_mismatch: bool,
+    /// Signed transactions not seen in a bundle yet
+    validation_backlog: u64,
+    /// Receipts being written to the database
+    pending_writes: u64,
+}
+
+/// Role of this validator in the current epoch, and the chain state it was
+/// derived from
+pub async fn state<Context>(
+    ctx: Data<Context>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: ValidatorStateAccess,
+{
+    let state = ctx.get_validator_state();
+    Ok(HttpResponse::Ok().json(State {
+        role: state.role(),
+        epoch: state.current_epoch() as u64,
+        block: state.current_block() as u64,
+        bundler_underfunded: state.bundler_underfunded(),
+        bundler_key_mismatch: state.bundler_key_mismatch(),
+        validation_backlog: state.validation_backlog(),
+        pending_writes: state.pending_writes(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test::{call_and_read_body_json, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use serde_json::Value;
+
+    use crate::{
+        context::test_utils::TestContext,
+        state::{generate_state, ValidatorRole},
+    };
+
+    use super::state;
+
+    #[actix_web::test]
+    async fn state_shows_current_role() {
+        let validator_state = generate_state();
+        validator_state.set_role(ValidatorRole::Leader);
+        validator_state.set_current_epoch(3);
+        let ctx = TestContext::builder().with_state(validator_state).build();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ctx.clone()))
+                .route("/state", web::get().to(state::<TestContext>)),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/state").to_request();
+        let body: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(body["role"], "leader");
+        assert_eq!(body["epoch"], 3);
+    }
+}
@@ -0,0 +1,411 @@
+use std::time::Duration;
+
+use actix_web::{
+    web::{Data, Path, Query},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{broadcast::error::RecvError, Semaphore},
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    database::{
+        models::Transaction,
+        queries::{self, QueryContext},
+    },
+    events::{Event, EventBusAccess},
+    server::error::ValidatorServerError,
+};
+
+/// Longest a `/tx/{tx_id}/wait` request is held
+pub const MAX_WAIT: Duration = Duration::from_secs(60);
+
+const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+
+/// Waiting requests re-read the status this often even without events, as
+/// pending transactions turn invalid when blocks pass
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Requests each server worker holds at once on `/tx/{tx_id}/wait`
+const MAX_WAITERS: usize = 256;
+
+/// Slots of the requests held on `/tx/{tx_id}/wait`, so waiting clients
+/// can't pile up without bound. Each server worker has its own.
+pub struct TxWaiters(Semaphore);
+
+impl Default for TxWaiters {
+    fn default() -> Self {
+        Self(Semaphore::new(MAX_WAITERS))
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ValidationStatus {
+    /// Promised block not reached yet and not seen in a bundle
+    Pending,
+    /// Found in a bundle by the promised block
+    Valid,
+    /// Found in a bundle after the promised block, or not found at all
+    /// once the promised block passed
+    Invalid,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct TxStatus {
+    id: String,
+    status: ValidationStatus,
+    /// Why the transaction is invalid
+    reason: Option<&'static str>,
+    bundle_id: Option<String>,
+    block_promised: u128,
+    block_actual: Option<u128>,
+    epoch: u128,
+}
+
+fn tx_status(tx: Transaction, current_block: u128) -> TxStatus {
+    let block_promised = tx.block_promised.into();
+    let block_actual = tx.block_actual.map(u128::from);
+    let (status, reason) = match (&tx.bundle_id, block_actual) {
+        (Some(_), Some(actual)) if actual > block_promised => (
+            ValidationStatus::Invalid,
+            Some("bundled after the promised block"),
+        ),
+        (Some(_), _) if tx.validated => (ValidationStatus::Valid, None),
+        (Some(_), _) => (
+            ValidationStatus::Invalid,
+            Some("receipt failed verification"),
+        ),
+        (None, _) if current_block > block_promised => (
+            ValidationStatus::Invalid,
+            Some("not bundled by the promised block"),
+        ),
+        (None, _) => (ValidationStatus::Pending, None),
+    };
+
+    TxStatus {
+        id: tx.id.trim().to_string(),
+        status,
+        reason,
+        bundle_id: tx.bundle_id.map(|id| id.trim().to_string()),
+        block_promised,
+        block_actual,
+        epoch: tx.epoch.0,
+    }
+}
+
+async fn current_status<Context>(
+    ctx: &Context,
+    tx_id: &str,
+) -> Result<TxStatus, ValidatorServerError>
+where
+    Context: QueryContext,
+{
+    let tx = match queries::get_tx(ctx, tx_id).await {
+        Ok(tx) => tx,
+        Err(diesel::result::Error::NotFound) => {
+            return Err(ValidatorServerError::NotFound(format!(
+                "Transaction {} not found",
+                tx_id
+            )))
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let current_block = ctx.get_validator_state().current_block();
+
+    Ok(tx_status(tx, current_block))
+}
+
+/// Validation state of a transaction: whether it was found in a bundle,
+/// in which block compared to the promised one, and why it is invalid
+pub async fn get_tx_status<Context>(
+    ctx: Data<Context>,
+    path: Path<(String,)>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: QueryContext,
+{
+    let (tx_id,) = path.into_inner();
+
+    Ok(HttpResponse::Ok().json(current_status(&**ctx, &tx_id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct WaitQuery {
+    /// How long to wait, e.g. `30s`, `1m` or seconds without unit
+    timeout: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TxWait {
+    #[serde(flatten)]
+    status: TxStatus,
+    /// Whether the status changed while the request was held
+    changed: bool,
+}
+
+/// Timeout of a wait request, capped at `MAX_WAIT`
+fn parse_wait_timeout(timeout: &str) -> Result<Duration, String> {
+    let (value, unit) = match timeout.strip_suffix('m') {
+        Some(minutes) => (minutes, 60),
+        None => (timeout.strip_suffix('s').unwrap_or(timeout), 1),
+    };
+    value
+        .parse::<u64>()
+        .map(|value| {
+            value
+                .checked_mul(unit)
+                .map_or(MAX_WAIT, Duration::from_secs)
+                .min(MAX_WAIT)
+        })
+        .map_err(|_| format!("Invalid timeout {:?}, expecting e.g. 30s", timeout))
+}
+
+/// Whether `event` may have changed the status of `tx_id`. Bundles are
+/// matched by id once the transaction is known to be in one, before that
+/// any bundle may contain it.
+fn affects_tx(event: &Event, tx_id: &str, bundle_id: Option<&str>) -> bool {
+    match event {
+        Event::TxInvalid { tx, .. }
+        | Event::DuplicateItem { tx, .. }
+        | Event::Equivocation { tx, .. } => tx == tx_id,
+        Event::BundleValidated { bundle, .. } | Event::BundleInvalid { bundle, .. } => {
+            bundle_id.map_or(true, |bundle_id| bundle == bundle_id)
+        }
+        _ => false,
+    }
+}
+
+/// Same as `get_tx_status`, but the response is held until the status
+/// changes or `timeout` passes, so clients awaiting finality don't need to
+/// poll
+pub async fn wait_tx_status<Context>(
+    ctx: Data<Context>,
+    path: Path<(String,)>,
+    query: Query<WaitQuery>,
+    waiters: Data<TxWaiters>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: QueryContext + EventBusAccess,
+{
+    let timeout = match &query.timeout {
+        Some(timeout) => parse_wait_timeout(timeout).map_err(ValidatorServerError::BadRequest)?,
+        None => DEFAULT_WAIT,
+    };
+    let (tx_id,) = path.into_inner();
+    let _slot = waiters.0.try_acquire().map_err(|_| {
+        ValidatorServerError::Overloaded(
+            "too many requests waiting for transactions".to_string(),
+            WAIT_POLL_INTERVAL.as_secs(),
+        )
+    })?;
+
+    // Subscribed before the first read so no change is missed in between
+    let mut events = ctx.event_bus().subscribe();
+    let initial = current_status(&**ctx, &tx_id).await?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(HttpResponse::Ok().json(TxWait {
+                status: initial,
+                changed: false,
+            }));
+        }
+
+        let poll = sleep_until((now + WAIT_POLL_INTERVAL).min(deadline));
+        tokio::pin!(poll);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) if affects_tx(&event, &tx_id, initial.bundle_id.as_deref()) => break,
+                    Ok(_) => (),
+                    // Missed events may have been about the transaction
+                    Err(RecvError::Lagged(_)) => break,
+                    Err(RecvError::Closed) => {
+                        poll.as_mut().await;
+                        break;
+                    }
+                },
+                _ = poll.as_mut() => break,
+            }
+        }
+        let status = current_status(&**ctx, &tx_id).await?;
+        if status != initial {
+            return Ok(HttpResponse::Ok().json(TxWait {
+                status,
+                changed: true,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, read_body_json, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use chrono::Utc;
+    use serde_json::Value;
+
+    use crate::{
+        context::test_utils::TestContext,
+        database::{
+            models::{Block, Epoch, NewTransaction, Transaction},
+            queries::insert_tx_in_db,
+            test_utils::unique_id,
+        },
+        events::{Event, EventBusAccess},
+        key_manager::test_utils::test_keys,
+        state::ValidatorStateAccess,
+    };
+
+    use super::{
+        affects_tx, parse_wait_timeout, tx_status, wait_tx_status, TxWaiters, ValidationStatus,
+        MAX_WAIT,
+    };
+
+    fn tx(block_actual: Option<u128>, bundle_id: Option<&str>) -> Transaction {
+        Transaction {
+            id: "tx_id".to_string(),
+            epoch: Epoch(1),
+            block_promised: Block(10),
+            block_actual: block_actual.map(Block),
+            signature: Vec::new(),
+            validated: bundle_id.is_some(),
+            bundle_id: bundle_id.map(str::to_string),
+            bundler_key: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn status_compares_actual_and_promised_block() {
+        let on_time = tx_status(tx(Some(9), Some("bundle_id")), 20);
+        assert_eq!(on_time.status, ValidationStatus::Valid);
+        assert_eq!(on_time.block_actual, Some(9));
+
+        let late = tx_status(tx(Some(11), Some("bundle_id")), 20);
+        assert_eq!(late.status, ValidationStatus::Invalid);
+        assert_eq!(late.reason, Some("bundled after the promised block"));
+
+        assert_eq!(
+            tx_status(tx(None, None), 5).status,
+            ValidationStatus::Pending
+        );
+        assert_eq!(
+            tx_status(tx(None, None), 20).reason,
+            Some("not bundled by the promised block")
+        );
+    }
+
+    #[test]
+    fn wait_timeout_takes_a_unit() {
+        assert_eq!(parse_wait_timeout("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_wait_timeout("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_wait_timeout("15"), Ok(Duration::from_secs(15)));
+        assert!(parse_wait_timeout("soon").is_err());
+        // Capped instead of overflowing
+        assert_eq!(parse_wait_timeout("10m"), Ok(MAX_WAIT));
+        assert_eq!(parse_wait_timeout(&format!("{}m", u64::MAX)), Ok(MAX_WAIT));
+    }
+
+    #[test]
+    fn only_events_about_the_tx_wake_waiters() {
+        let tx_invalid = |tx: &str| Event::TxInvalid {
+            bundle: "bundle".to_string(),
+            tx: tx.to_string(),
+            reason: "reason".to_string(),
+        };
+        let validated = |bundle: &str| Event::BundleValidated {
+            bundle: bundle.to_string(),
+            txs: 1,
+        };
+
+        assert!(affects_tx(&tx_invalid("tx_id"), "tx_id", None));
+        assert!(!affects_tx(&tx_invalid("other"), "tx_id", None));
+        assert!(affects_tx(&validated("other"), "tx_id", None));
+        assert!(!affects_tx(&validated("other"), "tx_id", Some("bundle")));
+        assert!(!affects_tx(
+            &Event::CronCircuitOpened {
+                task: "test".to_string(),
+                failures: 1,
+            },
+            "tx_id",
+            None
+        ));
+    }
+
+    #[actix_web::test]
+    async fn wait_returns_once_status_changes() {
+        let ctx = TestContext::builder().build();
+        let tx_id = unique_id();
+        insert_tx_in_db(
+            &ctx,
+            &NewTransaction {
+                id: tx_id.clone(),
+                epoch: Epoch(1),
+                block_promised: Block(10),
+                block_actual: None,
+                signature: Vec::new(),
+                validated: false,
+                bundle_id: None,
+                bundler_key: None,
+            },
+        )
+        .unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ctx.clone()))
+                .app_data(Data::new(TxWaiters::default()))
+                .route(
+                    "/tx/{tx_id}/wait",
+                    web::get().to(wait_tx_status::<TestContext>),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri(&format!("/tx/{}/wait?timeout=0s", tx_id))
+            .to_request();
+        let body: Value = read_body_json(call_service(&app, req).await).await;
+        assert_eq!(body["status"], "pending");
+        assert_eq!(body["changed"], false);
+
+        let promised_block_passed = ctx.clone();
+        actix_rt::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            promised_block_passed
+                .get_validator_state()
+                .set_current_block(20);
+            promised_block_passed
+                .event_bus()
+                .publish(Event::BundleValidated {
+                    bundle: "bundle".to_string(),
+                    txs: 1,
+                });
+        });
+        let req = TestRequest::get()
+            .uri(&format!("/tx/{}/wait?timeout=10s", tx_id))
+            .to_request();
+        let body: Value = read_body_json(call_service(&app, req).await).await;
+        assert_eq!(body["status"], "invalid");
+        assert_eq!(body["changed"], true);
+
+        let req = TestRequest::get()
+            .uri(&format!("/tx/{}/wait?timeout=soon", tx_id))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::str::FromStr;
+
+use actix_web::{web::Data, HttpResponse};
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+    database::queries::{self, QueryContext},
+    peers::PeerScoresAccess,
+    server::error::ValidatorServerError,
+};
+
+#[derive(Serialize)]
+struct ValidatorStatus {
+    address: String,
+    url: Option<String>,
+    /// Unix timestamp of the last successful response from the validator
+    last_seen: Option<u64>,
+    /// Peer score, `None` until the validator was contacted
+    score: Option<i64>,
+}
+
+/// Validators known from the contract, with how they answered so far
+pub async fn validators<Context>(
+    ctx: Data<Context>,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: PeerScoresAccess + QueryContext,
+{
+    let validators: Vec<ValidatorStatus> = queries::get_validators(&**ctx)?
+        .into_iter()
+        .map(|validator| {
+            let score = validator
+                .url
+                .as_deref()
+                .and_then(|url| Url::from_str(url).ok())
+                .and_then(|url| ctx.peer_scores().get(&url));
+            ValidatorStatus {
+                address: validator.address.trim().to_string(),
+                url: validator.url,
+                last_seen: score.as_ref().and_then(|score| score.last_seen),
+                score: score.map(|score| score.score),
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(validators))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use actix_web::{
+        test::{call_and_read_body_json, init_service, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use url::Url;
+
+    use crate::{
+        context::{test_utils::test_context, AppContext},
+        database::{models::NewValidator, queries::upsert_validators},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::{test_utils::test_keys, KeyManager},
+        peers::{PeerOutcome, PeerScoresAccess},
+    };
+
+    use super::validators;
+
+    #[actix_web::test]
+    async fn validators_include_peer_scores() {
+        let (key_manager, _) = test_keys();
+        let (validator_key_manager, _) = test_keys();
+        let address = validator_key_manager.validator_address().to_string();
+        let ctx = test_context(key_manager);
+        upsert_validators(
+            &ctx,
+            &[NewValidator {
+                address: address.clone(),
+                url: Some("http://known.example.com/".to_string()),
+            }],
+        )
+        .unwrap();
+        ctx.peer_scores().record(
+            &Url::from_str("http://known.example.com/").unwrap(),
+            PeerOutcome::Success,
+        );
+        let app = init_service(App::new().app_data(Data::new(ctx)).route(
+            "/validators",
+            web::get().to(validators::<AppContext<MockHttpClient>>),
+        ))
+        .await;
+
+        let req = TestRequest::get().uri("/validators").to_request();
+        let body: Vec<serde_json::Value> = call_and_read_body_json(&app, req).await;
+        let validator = body
+            .iter()
+            .find(|validator| validator["address"] == address.as_str())
+            .unwrap();
+
+        assert_eq!(validator["url"], "http://known.example.com/");
+        assert_eq!(validator["score"], 1);
+        assert!(validator["last_seen"].is_u64());
+    }
+}
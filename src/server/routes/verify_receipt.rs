@@ -0,0 +1,329 @@
+use actix_web::{
+    web::{Bytes, Data},
+    HttpResponse,
+};
+use data_encoding::BASE64URL_NOPAD;
+use serde::Serialize;
+
+use crate::{
+    database::{
+        models::Transaction,
+        queries::{self, QueryContext},
+    },
+    key_manager,
+    receipt::{receipt_message, TxReceipt},
+    server::{error::ValidatorServerError, routes::sign::Config},
+};
+
+/// Length of a decoded transaction id
+const TX_ID_LENGTH: usize = 32;
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Passed,
+    Failed,
+    /// Nothing to compare the receipt with
+    Skipped,
+}
+
+/// Outcome of one step of receipt verification
+#[derive(Debug, PartialEq, Serialize)]
+struct Check {
+    check: &'static str,
+    status: CheckStatus,
+    detail: String,
+}
+
+impl Check {
+    fn new(check: &'static str, passed: bool, detail: String) -> Self {
+        let status = if passed {
+            CheckStatus::Passed
+        } else {
+            CheckStatus::Failed
+        };
+        Self {
+            check,
+            status,
+            detail,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Verification {
+    /// No check failed
+    valid: bool,
+    checks: Vec<Check>,
+}
+
+impl From<Vec<Check>> for Verification {
+    fn from(checks: Vec<Check>) -> Self {
+        Self {
+            valid: checks
+                .iter()
+                .all(|check| check.status != CheckStatus::Failed),
+            checks,
+        }
+    }
+}
+
+/// Run every check on `receipt`, `stored` being the transaction this
+/// validator stored for it if any
+fn run_checks<KeyManager>(
+    key_manager: &KeyManager,
+    receipt: &TxReceipt,
+    stored: Option<&Transaction>,
+    current_block: u128,
+) -> Vec<Check>
+where
+    KeyManager: key_manager::KeyManager,
+{
+    let mut checks = vec![match BASE64URL_NOPAD.decode(receipt.tx_id.as_bytes()) {
+        Ok(id) if id.len() == TX_ID_LENGTH => Check::new(
+            "format",
+            !receipt.signature.is_empty(),
+            format!(
+                "tx_id {}, block {}, {} byte signature",
+                receipt.tx_id,
+                receipt.block,
+                receipt.signature.len()
+            ),
+        ),
+        Ok(id) => Check::new(
+            "format",
+            false,
+            format!(
+                "tx_id is {} bytes long, transaction ids are {} bytes",
+                id.len(),
+                TX_ID_LENGTH
+            ),
+        ),
+        Err(err) => Check::new(
+            "format",
+            false,
+            format!("tx_id is not base64url encoded: {}", err),
+        ),
+    }];
+
+    // The deep hash of tx_id and block is the message the bundler signs
+    checks.push(match receipt_message(receipt) {
+        Ok(message) => {
+            let deep_hash = BASE64URL_NOPAD.encode(&message);
+            match key_manager.bundler_key_id(&message, &receipt.signature) {
+                Some(key) => Check::new(
+                    "signature",
+                    true,
+                    format!("deep hash {} signed by bundler key {}", deep_hash, key),
+                ),
+                None => Check::new(
+                    "signature",
+                    false,
+                    format!(
+                        "{} byte signature doesn't match deep hash {} under any bundler key",
+                        receipt.signature.len(),
+                        deep_hash
+                    ),
+                ),
+            }
+        }
+        Err(err) => Check::new("signature", false, err.to_string()),
+    });
+
+    let block_actual = stored.and_then(|tx| tx.block_actual).map(u128::from);
+    checks.push(match block_actual {
+        Some(actual) => Check::new(
+            "block",
+            actual <= receipt.block,
+            format!(
+                "bundled in block {}, promised by block {}",
+                actual, receipt.block
+            ),
+        ),
+        None if current_block > receipt.block => Check::new(
+            "block",
+            false,
+            format!(
+                "not bundled by promised block {}, current block is {}",
+                receipt.block, current_block
+            ),
+        ),
+        None => Check::new(
+            "block",
+            true,
+            format!(
+                "promised block {} not reached yet, current block is {}",
+                receipt.block, current_block
+            ),
+        ),
+    });
+
+    checks.push(match stored {
+        Some(tx) => {
+            let promised = u128::from(tx.block_promised);
+            Check::new(
+                "stored_receipt",
+                promised == receipt.block,
+                format!(
+                    "receipt stored by this validator promises block {}",
+                    promised
+                ),
+            )
+        }
+        None => Check {
+            check: "stored_receipt",
+            status: CheckStatus::Skipped,
+            detail: "no receipt stored for this transaction".to_string(),
+        },
+    });
+
+    checks
+}
+
+/// Verify a receipt posted in the body and explain the outcome of every
+/// check, for debugging receipts a bundler and validators disagree on
+pub async fn verify_receipt<Context, KeyManager>(
+    ctx: Data<Context>,
+    payload: Bytes,
+) -> actix_web::Result<HttpResponse, ValidatorServerError>
+where
+    Context: Config<KeyManager> + QueryContext,
+    KeyManager: key_manager::KeyManager,
+{
+    let receipt: TxReceipt = match serde_json::from_slice(&payload) {
+        Ok(receipt) => receipt,
+        Err(err) => {
+            let check = Check::new("format", false, format!("invalid receipt: {}", err));
+            return Ok(HttpResponse::Ok().json(Verification::from(vec![check])));
+        }
+    };
+
+    let stored = match queries::get_tx(&**ctx, &receipt.tx_id).await {
+        Ok(tx) => Some(tx),
+        Err(diesel::result::Error::NotFound) => None,
+        Err(err) => return Err(err.into()),
+    };
+    let checks = run_checks(
+        ctx.key_manager(),
+        &receipt,
+        stored.as_ref(),
+        ctx.current_block(),
+    );
+
+    Ok(HttpResponse::Ok().json(Verification::from(checks)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test::{call_service, init_service, read_body_json, TestRequest},
+        web::{self, Data},
+        App,
+    };
+    use serde_json::Value;
+
+    use crate::{
+        context::{test_utils::test_context, AppContext},
+        http::reqwest::mock::MockHttpClient,
+        key_manager::test_utils::test_keys,
+        receipt::test_utils::signed_receipt,
+        state::ValidatorStateAccess,
+    };
+
+    use super::verify_receipt;
+
+    #[actix_web::test]
+    async fn every_check_is_explained() {
+        let (key_manager, bundle_pvk) = test_keys();
+        let ctx = test_context(key_manager);
+        ctx.get_validator_state().set_current_block(20);
+        let app = App::new().app_data(Data::new(ctx.clone())).route(
+            "/receipt/verify",
+            web::post().to(verify_receipt::<AppContext<MockHttpClient>, _>),
+        );
+        let app = init_service(app).await;
+
+        let receipt = signed_receipt(
+            &bundle_pvk,
+            "verified-receipt-AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            30,
+        );
+        let req = TestRequest::post()
+            .uri("/receipt/verify")
+            .set_json(&receipt)
+            .to_request();
+        let body: Value = read_body_json(call_service(&app, req).await).await;
+        assert_eq!(body["valid"], true);
+        let statuses: Vec<&str> = body["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|check| check["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, ["passed", "passed", "passed", "skipped"]);
+        let checks: Vec<&str> = body["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|check| check["check"].as_str().unwrap())
+            .collect();
+        assert_eq!(checks, ["format", "signature", "block", "stored_receipt"]);
+
+        // Block no longer matches the signature, and has passed already
+        let mut tampered = receipt;
+        tampered.block = 10;
+        let req = TestRequest::post()
+            .uri("/receipt/verify")
+            .set_json(&tampered)
+            .to_request();
+        let body: Value = read_body_json(call_service(&app, req).await).await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["checks"][0]["status"], "passed");
+        assert_eq!(body["checks"][1]["status"], "failed");
+        assert_eq!(body["checks"][2]["status"], "failed");
+    }
+
+    #[actix_web::test]
+    async fn invalid_tx_id_fails_format_check() {
+        let (key_manager, bundle_pvk) = test_keys();
+        let ctx = test_context(key_manager);
+        ctx.get_validator_state().set_current_block(20);
+        let app = App::new().app_data(Data::new(ctx.clone())).route(
+            "/receipt/verify",
+            web::post().to(verify_receipt::<AppContext<MockHttpClient>, _>),
+        );
+        let app = init_service(app).await;
+
+        // Signed by the bundler, but not the id of any transaction
+        let receipt = signed_receipt(&bundle_pvk, "short-id", 30);
+        let req = TestRequest::post()
+            .uri("/receipt/verify")
+            .set_json(&receipt)
+            .to_request();
+        let body: Value = read_body_json(call_service(&app, req).await).await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["checks"][0]["check"], "format");
+        assert_eq!(body["checks"][0]["status"], "failed");
+        assert_eq!(body["checks"][1]["status"], "passed");
+    }
+
+    #[actix_web::test]
+    async fn malformed_receipt_fails_format_check() {
+        let (key_manager, _bundle_pvk) = test_keys();
+        let ctx = test_context(key_manager);
+        let app = App::new().app_data(Data::new(ctx.clone())).route(
+            "/receipt/verify",
+            web::post().to(verify_receipt::<AppContext<MockHttpClient>, _>),
+        );
+        let app = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/receipt/verify")
+            .set_payload("{\"tx_id\": \"tx\"}")
+            .to_request();
+        let body: Value = read_body_json(call_service(&app, req).await).await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["checks"][0]["check"], "format");
+        assert_eq!(body["checks"][0]["status"], "failed");
+    }
+}
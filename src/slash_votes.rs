@@ -0,0 +1,208 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{
+    config::RuntimeConfigAccess,
+    context::ValidatorAddressAccess,
+    database::queries::QueryContext,
+    http, key_manager,
+    peer_auth::sign_request,
+    peers::{peer_urls, PeerOutcome, PeerScoresAccess},
+};
+
+/// Votes remembered to tell new votes from repeated ones, the oldest ones
+/// are forgotten first when exceeded
+const MAX_SEEN_VOTES: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Ballot {
+    For,
+    Against,
+}
+
+impl Ballot {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Ballot::For => "for",
+            Ballot::Against => "against",
+        }
+    }
+}
+
+/// Vote of a validator on a slash proposal, sent by the validator to its
+/// peers so they learn about a proposal before their next contract sync
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SlashVote {
+    /// Id of the slash proposal
+    pub proposal: String,
+    /// Address of the validator that voted
+    pub validator: String,
+    pub vote: Ballot,
+}
+
+pub trait SlashVotesAccess {
+    fn slash_votes(&self) -> &SlashVotes;
+}
+
+#[derive(Default)]
+struct SeenVotes {
+    votes: HashSet<(String, String)>,
+    /// Same votes as `votes`, oldest first
+    order: VecDeque<(String, String)>,
+}
+
+/// Slash votes cast or received, one per proposal and validator
+#[derive(Clone, Default)]
+pub struct SlashVotes {
+    seen: Arc<Mutex<SeenVotes>>,
+    voting: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl SlashVotes {
+    /// Remember `vote`, returns `false` if a vote of the same validator on
+    /// the same proposal was seen before
+    pub fn record(&self, vote: &SlashVote) -> bool {
+        let mut seen = self.seen.lock().expect("Slash votes lock poisoned");
+        let key = (vote.proposal.clone(), vote.validator.clone());
+        if !seen.votes.insert(key.clone()) {
+            return false;
+        }
+        seen.order.push_back(key);
+        while seen.order.len() > MAX_SEEN_VOTES {
+            if let Some(oldest) = seen.order.pop_front() {
+                seen.votes.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Whether a vote of `validator` on `proposal` was seen
+    pub fn has_voted(&self, proposal: &str, validator: &str) -> bool {
+        self.seen
+            .lock()
+            .expect("Slash votes lock poisoned")
+            .votes
+            .contains(&(proposal.to_string(), validator.to_string()))
+    }
+
+    /// Held while this validator votes, so a proposal isn't voted on twice
+    pub async fn lock_voting(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.voting.lock().await
+    }
+}
+
+/// Send `vote` to all peers, signed with the validator key of
+/// `key_manager`. Peers only accept votes signed by the validator that
+/// cast them, so only this validator's own votes are sent.
+pub async fn forward_slash_vote<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+    key_manager: &KeyManager,
+    vote: &SlashVote,
+) where
+    Context: QueryContext
+        + http::ClientAccess<HttpClient>
+        + PeerScoresAccess
+        + RuntimeConfigAccess
+        + ValidatorAddressAccess,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let peers = peer_urls(ctx).await;
+    info!(
+        proposal = %vote.proposal,
+        validator = %vote.validator,
+        peers = peers.len(),
+        "Forwarding slash vote"
+    );
+    for peer in peers {
+        match send_slash_vote(ctx, key_manager, &peer, vote).await {
+            Ok(()) => ctx.peer_scores().record(&peer, PeerOutcome::Success),
+            Err(err) => {
+                warn!(peer = %peer, "Failed to forward slash vote - {}", err);
+                ctx.peer_scores().record(&peer, PeerOutcome::Unreachable);
+            }
+        }
+    }
+}
+
+async fn send_slash_vote<Context, HttpClient, KeyManager>(
+    ctx: &Context,
+    key_manager: &KeyManager,
+    peer: &Url,
+    vote: &SlashVote,
+) -> Result<(), String>
+where
+    Context: http::ClientAccess<HttpClient>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+    KeyManager: key_manager::KeyManager,
+{
+    let url = peer.join("slash-votes").map_err(|err| err.to_string())?;
+    let mut req = reqwest::Client::new()
+        .post(url)
+        .json(vote)
+        .build()
+        .map_err(|err| err.to_string())?;
+    sign_request(key_manager, &mut req);
+
+    let res = ctx
+        .get_http_client()
+        .execute(req)
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+    if !res.status().is_success() {
+        return Err(format!("unexpected status {}", res.status()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ballot, SlashVote, SlashVotes, MAX_SEEN_VOTES};
+
+    #[test]
+    fn vote_is_recorded_once_per_validator() {
+        let votes = SlashVotes::default();
+        let vote = SlashVote {
+            proposal: "proposal".to_string(),
+            validator: "validator".to_string(),
+            vote: Ballot::For,
+        };
+
+        assert!(votes.record(&vote));
+        assert!(!votes.record(&SlashVote {
+            vote: Ballot::Against,
+            ..vote.clone()
+        }));
+        assert!(votes.record(&SlashVote {
+            validator: "other".to_string(),
+            ..vote
+        }));
+        assert!(votes.has_voted("proposal", "other"));
+    }
+
+    #[test]
+    fn oldest_votes_are_forgotten_first() {
+        let votes = SlashVotes::default();
+        let vote = |proposal: usize| SlashVote {
+            proposal: proposal.to_string(),
+            validator: "validator".to_string(),
+            vote: Ballot::For,
+        };
+
+        for proposal in 0..=MAX_SEEN_VOTES {
+            assert!(votes.record(&vote(proposal)));
+        }
+
+        assert!(!votes.has_voted("0", "validator"));
+        assert!(votes.has_voted("1", "validator"));
+        assert!(!votes.record(&vote(MAX_SEEN_VOTES)));
+    }
+}
@@ -1,13 +1,47 @@
-use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use serde::Deserialize;
+use bundlr_contracts_validators::{Address, State as ContractState};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+use crate::epoch::{EpochInfo, EpochParams, EpochParamsError};
+use crate::leader::current_leader;
+
+/// Role of the validator in the current epoch, changing when a new epoch
+/// starts. Nominated validators cosign, and the one selected as leader
+/// also proposes validation rounds to the others.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ValidatorRole {
     Cosigner = 1,
     Idle = 2,
+    Leader = 3,
+}
+
+impl ValidatorRole {
+    /// Role of the validator with `address` in the epoch of the contract
+    /// `state`
+    pub fn for_epoch(state: &ContractState, address: &Address) -> ValidatorRole {
+        if current_leader(state).as_deref() == Some(address.to_string().as_str()) {
+            ValidatorRole::Leader
+        } else if state.nominated_validators.contains(address) {
+            ValidatorRole::Cosigner
+        } else {
+            ValidatorRole::Idle
+        }
+    }
+
+    /// Whether the role validates bundles and signs receipts and
+    /// attestations, the leader cosigns its own epoch
+    pub fn signs(&self) -> bool {
+        matches!(self, ValidatorRole::Cosigner | ValidatorRole::Leader)
+    }
+
+    /// Whether the role proposes validation rounds
+    pub fn proposes(&self) -> bool {
+        *self == ValidatorRole::Leader
+    }
 }
 
 impl From<&ValidatorRole> for u8 {
@@ -15,6 +49,7 @@ impl From<&ValidatorRole> for u8 {
         match v {
             ValidatorRole::Cosigner => 1,
             ValidatorRole::Idle => 2,
+            ValidatorRole::Leader => 3,
         }
     }
 }
@@ -24,6 +59,7 @@ impl From<u8> for ValidatorRole {
         match v {
             1 => ValidatorRole::Cosigner,
             2 => ValidatorRole::Idle,
+            3 => ValidatorRole::Leader,
             _ => unreachable!(),
         }
     }
@@ -31,10 +67,7 @@ impl From<u8> for ValidatorRole {
 
 impl From<ValidatorRole> for u8 {
     fn from(v: ValidatorRole) -> u8 {
-        match v {
-            ValidatorRole::Cosigner => 1,
-            ValidatorRole::Idle => 2,
-        }
+        u8::from(&v)
     }
 }
 
@@ -53,7 +86,24 @@ impl PartialEq<ValidatorRole> for u8 {
 pub struct State {
     current_block: AtomicU64, // FIXME: this should be u128
     current_epoch: AtomicU64, // FIXME: this should be u128
+    genesis_height: AtomicU64,
+    epoch_length: AtomicU64,
     role: AtomicU8,
+    bundler_underfunded: AtomicBool,
+    bundler_key_mismatch: AtomicBool,
+    pending_writes: AtomicU64,
+    validation_backlog: AtomicU64,
+    /// Unix timestamp in seconds of the last count of the backlog
+    validation_backlog_counted_at: AtomicU64,
+}
+
+/// Receipt write in progress, see `State::start_write`
+pub struct PendingWrite<'a>(&'a AtomicU64);
+
+impl Drop for PendingWrite<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl State {
@@ -65,6 +115,11 @@ impl State {
         self.role.store(role.into(), Ordering::Relaxed);
     }
 
+    /// Move to `role`, returns the previous role
+    pub fn transition(&self, role: ValidatorRole) -> ValidatorRole {
+        self.role.swap(role.into(), Ordering::Relaxed).into()
+    }
+
     pub fn current_block(&self) -> u128 {
         self.current_block.load(Ordering::Relaxed).into()
     }
@@ -86,6 +141,100 @@ impl State {
             .expect("Failed to cast epoch from u128 to u64");
         self.current_epoch.store(epoch, Ordering::Relaxed);
     }
+
+    pub fn epoch_params(&self) -> Option<EpochParams> {
+        EpochParams::new(
+            self.genesis_height.load(Ordering::Relaxed).into(),
+            self.epoch_length.load(Ordering::Relaxed).into(),
+        )
+    }
+
+    /// Replace the epoch parameters, refusing ones that don't fit in the
+    /// state and keeping the previous ones
+    pub fn set_epoch_params(&self, params: EpochParams) -> Result<(), EpochParamsError> {
+        let genesis_height: u64 = params
+            .genesis_height
+            .try_into()
+            .map_err(|_| EpochParamsError::GenesisHeightOutOfRange(params.genesis_height))?;
+        let epoch_length: u64 = params
+            .epoch_length
+            .try_into()
+            .map_err(|_| EpochParamsError::EpochLengthOutOfRange(params.epoch_length))?;
+        self.genesis_height.store(genesis_height, Ordering::Relaxed);
+        self.epoch_length.store(epoch_length, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Whether the bundler's wallet balance was too low to pay for the data
+    /// it promised to upload at the last check
+    pub fn bundler_underfunded(&self) -> bool {
+        self.bundler_underfunded.load(Ordering::Relaxed)
+    }
+
+    /// Update the underfunded flag, returns the previous value
+    pub fn set_bundler_underfunded(&self, underfunded: bool) -> bool {
+        self.bundler_underfunded
+            .swap(underfunded, Ordering::Relaxed)
+    }
+
+    /// Whether the bundler key receipts are verified with differs from the
+    /// bundler registered in the contract at the last check
+    pub fn bundler_key_mismatch(&self) -> bool {
+        self.bundler_key_mismatch.load(Ordering::Relaxed)
+    }
+
+    /// Update the key mismatch flag, returns the previous value
+    pub fn set_bundler_key_mismatch(&self, mismatch: bool) -> bool {
+        self.bundler_key_mismatch.swap(mismatch, Ordering::Relaxed)
+    }
+
+    /// Receipts being written to the database
+    pub fn pending_writes(&self) -> u64 {
+        self.pending_writes.load(Ordering::Relaxed)
+    }
+
+    /// Count a receipt write until the returned guard is dropped
+    pub fn start_write(&self) -> PendingWrite<'_> {
+        self.pending_writes.fetch_add(1, Ordering::Relaxed);
+        PendingWrite(&self.pending_writes)
+    }
+
+    /// Promised transactions not seen in a bundle yet, as of the last count
+    /// plus the receipts signed since
+    pub fn validation_backlog(&self) -> u64 {
+        self.validation_backlog.load(Ordering::Relaxed)
+    }
+
+    /// Replace the backlog with a count of the database
+    pub fn set_validation_backlog(&self, backlog: u64) {
+        self.validation_backlog.store(backlog, Ordering::Relaxed);
+        self.validation_backlog_counted_at
+            .store(unix_now(), Ordering::Relaxed);
+    }
+
+    /// Seconds since the backlog was last counted, the receipts signed
+    /// since are added to it but the transactions bundled since aren't
+    /// taken out
+    pub fn validation_backlog_age(&self) -> u64 {
+        unix_now().saturating_sub(self.validation_backlog_counted_at.load(Ordering::Relaxed))
+    }
+
+    pub fn add_to_validation_backlog(&self) {
+        self.validation_backlog.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Epoch containing the current block, `None` until epoch parameters
+    /// have been received from the contract
+    pub fn current_epoch_info(&self) -> Option<EpochInfo> {
+        self.epoch_params()?.epoch_at(self.current_block())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time before unix epoch")
+        .as_secs()
 }
 
 pub type SharedValidatorState = Arc<State>;
@@ -94,7 +243,14 @@ pub fn generate_state() -> SharedValidatorState {
     Arc::new(State {
         current_block: AtomicU64::new(0),
         current_epoch: AtomicU64::new(0),
+        genesis_height: AtomicU64::new(0),
+        epoch_length: AtomicU64::new(0),
         role: AtomicU8::from(&ValidatorRole::Cosigner),
+        bundler_underfunded: AtomicBool::new(false),
+        bundler_key_mismatch: AtomicBool::new(false),
+        pending_writes: AtomicU64::new(0),
+        validation_backlog: AtomicU64::new(0),
+        validation_backlog_counted_at: AtomicU64::new(0),
     })
 }
 
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use opentelemetry::{
+    global,
+    sdk::{
+        trace::{self, Tracer},
+        Resource,
+    },
+    trace::TraceError,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tokio::runtime::{self, Runtime};
+use url::Url;
+
+const SERVICE_NAME: &str = "validator";
+
+/// Time left to the exporter to send the last spans on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// OTLP exporter together with the runtime it sends spans from. The
+/// exporter's gRPC channel and batch task need a Tokio runtime, the one
+/// owned here outlives the runtimes of the commands.
+pub struct Exporter {
+    tracer: Tracer,
+    runtime: Runtime,
+}
+
+impl Exporter {
+    /// Start exporting spans to the OTLP collector listening on `endpoint`,
+    /// pass `tracer` to `logging::init` to install it.
+    ///
+    /// Spans are batched and sent in the background, call `shutdown`
+    /// before exiting to flush pending spans.
+    pub fn install(endpoint: &Url) -> Result<Self, TraceError> {
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("otlp-exporter")
+            .enable_all()
+            .build()
+            .map_err(|err| TraceError::Other(Box::new(err)))?;
+        let tracer = {
+            let _guard = runtime.enter();
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint.as_str()),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", SERVICE_NAME),
+                ])))
+                .install_batch(opentelemetry::runtime::Tokio)?
+        };
+
+        Ok(Self { tracer, runtime })
+    }
+
+    pub fn tracer(&self) -> Tracer {
+        self.tracer.clone()
+    }
+
+    /// Flush pending spans and stop the exporter
+    pub fn shutdown(self) {
+        global::shutdown_tracer_provider();
+        self.runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use sd_notify::NotifyState;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+/// Time a cron task may spend in a single run on top of its interval before
+/// it is considered stalled
+const STALL_GRACE: Duration = Duration::from_secs(15 * 60);
+
+/// How often to check for running tasks while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub trait HeartbeatAccess {
+    fn heartbeats(&self) -> &Heartbeats;
+}
+
+/// Last time each cron task was seen alive, which tasks are currently
+/// running and how their last runs went
+#[derive(Clone, Default)]
+pub struct Heartbeats {
+    tasks: Arc<Mutex<HashMap<String, (Instant, Duration)>>>,
+    running: Arc<Mutex<HashSet<String>>>,
+    results: Arc<Mutex<HashMap<String, TaskResults>>>,
+    draining: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct TaskResults {
+    last_finished: Option<DateTime<Utc>>,
+    consecutive_failures: u32,
+    last_error: Option<(DateTime<Utc>, String)>,
+}
+
+/// Status of a cron task, as served on `GET /crons`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CronStatus {
+    pub task: String,
+    pub running: bool,
+    pub stalled: bool,
+    pub last_finished: Option<DateTime<Utc>>,
+    /// Failed runs since the last successful one
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+impl Heartbeats {
+    /// Record that `task` is alive and will run again within `interval`
+    pub fn beat(&self, task: &str, interval: Duration) {
+        self.tasks
+            .lock()
+            .expect("Heartbeats lock poisoned")
+            .insert(task.to_string(), (Instant::now(), interval));
+    }
+
+    /// Run `run` and keep recording that `task` is alive every `interval`
+    /// until it finishes, so a run longer than its interval isn't reported
+    /// as stalled. A run still going after the time a task may go without
+    /// beating is hung, it stops beating and is reported as stalled.
+    pub async fn beat_while<F: Future>(&self, task: &str, interval: Duration, run: F) -> F::Output {
+        self.beat_for(task, interval, interval * 2 + STALL_GRACE, run)
+            .await
+    }
+
+    async fn beat_for<F: Future>(
+        &self,
+        task: &str,
+        interval: Duration,
+        max_duration: Duration,
+        run: F,
+    ) -> F::Output {
+        tokio::pin!(run);
+        let hung = tokio::time::sleep(max_duration);
+        tokio::pin!(hung);
+        let mut beats = tokio::time::interval(interval.max(Duration::from_secs(1)));
+        loop {
+            tokio::select! {
+                output = &mut run => return output,
+                _ = &mut hung => break,
+                _ = beats.tick() => self.beat(task, interval),
+            }
+        }
+
+        warn!(
+            task,
+            max_duration = ?max_duration,
+            "Task run is taking too long, no longer reporting it alive"
+        );
+        run.await
+    }
+
+    /// Tasks that haven't reported for longer than twice their interval
+    pub fn stalled(&self) -> Vec<String> {
+        self.stalled_at(Instant::now())
+    }
+
+    /// Mark `task` as running, returns false once the validator is draining
+    /// and no new runs should be started
+    pub fn start_run(&self, task: &str) -> bool {
+        let mut running = self.running.lock().expect("Heartbeats lock poisoned");
+        if self.draining.load(Ordering::SeqCst) {
+            return false;
+        }
+        running.insert(task.to_string());
+        true
+    }
+
+    pub fn finish_run(&self, task: &str) {
+        self.running
+            .lock()
+            .expect("Heartbeats lock poisoned")
+            .remove(task);
+    }
+
+    /// Record how a run of `task` went, `error` tells why it failed
+    pub fn record_result(&self, task: &str, error: Option<String>) {
+        let mut results = self.results.lock().expect("Heartbeats lock poisoned");
+        let results = results.entry(task.to_string()).or_default();
+        let now = Utc::now();
+        results.last_finished = Some(now);
+        match error {
+            Some(error) => {
+                results.consecutive_failures += 1;
+                results.last_error = Some((now, error));
+            }
+            None => results.consecutive_failures = 0,
+        }
+    }
+
+    /// Status of every task seen alive, ordered by name
+    pub fn statuses(&self) -> Vec<CronStatus> {
+        let stalled = self.stalled();
+        let running = self
+            .running
+            .lock()
+            .expect("Heartbeats lock poisoned")
+            .clone();
+        let results = self.results.lock().expect("Heartbeats lock poisoned");
+        let mut tasks: Vec<String> = self
+            .tasks
+            .lock()
+            .expect("Heartbeats lock poisoned")
+            .keys()
+            .cloned()
+            .collect();
+        tasks.sort();
+
+        tasks
+            .into_iter()
+            .map(|task| {
+                let task_results = results.get(&task).cloned().unwrap_or_default();
+                CronStatus {
+                    running: running.contains(&task),
+                    stalled: stalled.contains(&task),
+                    last_finished: task_results.last_finished,
+                    consecutive_failures: task_results.consecutive_failures,
+                    last_error: task_results.last_error.as_ref().map(|(_, err)| err.clone()),
+                    last_error_at: task_results.last_error.map(|(at, _)| at),
+                    task,
+                }
+            })
+            .collect()
+    }
+
+    /// Stop starting new task runs and wait for the running ones to finish
+    pub async fn drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        loop {
+            let running = self
+                .running
+                .lock()
+                .expect("Heartbeats lock poisoned")
+                .clone();
+            if running.is_empty() {
+                return;
+            }
+            info!(tasks = ?running, "Waiting for running tasks to finish");
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+
+    fn stalled_at(&self, now: Instant) -> Vec<String> {
+        self.tasks
+            .lock()
+            .expect("Heartbeats lock poisoned")
+            .iter()
+            .filter(|(_, (last, interval))| now.duration_since(*last) > *interval * 2 + STALL_GRACE)
+            .map(|(task, _)| task.clone())
+            .collect()
+    }
+}
+
+/// Tell systemd the validator finished starting up, does nothing when not
+/// started by systemd
+pub fn notify_ready() {
+    if let Err(err) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("Failed to notify systemd: {}", err);
+    }
+}
+
+/// Ping systemd watchdog while all cron tasks are alive, so systemd restarts
+/// a validator whose cron loops died. Returns immediately when the watchdog
+/// is not enabled for the service.
+pub async fn run_watchdog<Context>(ctx: Context)
+where
+    Context: HeartbeatAccess,
+{
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        debug!("Systemd watchdog not enabled");
+        return;
+    }
+
+    let period = Duration::from_micros(usec) / 2;
+    info!(period = ?period, "Pinging systemd watchdog");
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let stalled = ctx.heartbeats().stalled();
+        if stalled.is_empty() {
+            if let Err(err) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                warn!("Failed to ping systemd watchdog: {}", err);
+            }
+        } else {
+            warn!(tasks = ?stalled, "Cron tasks stalled, skipping watchdog ping");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Heartbeats, STALL_GRACE};
+
+    #[test]
+    fn task_without_recent_beat_is_stalled() {
+        let heartbeats = Heartbeats::default();
+        heartbeats.beat("validate bundler", Duration::from_secs(30));
+        heartbeats.beat("sync network info", Duration::from_secs(3600));
+
+        let later = Instant::now() + Duration::from_secs(60) + STALL_GRACE + Duration::from_secs(1);
+
+        assert!(heartbeats.stalled().is_empty());
+        assert_eq!(heartbeats.stalled_at(later), vec!["validate bundler"]);
+    }
+
+    #[test]
+    fn status_keeps_last_error() {
+        let heartbeats = Heartbeats::default();
+        heartbeats.beat("sync network info", Duration::from_secs(30));
+        heartbeats.record_result("sync network info", Some("timeout".to_string()));
+        heartbeats.record_result("sync network info", Some("refused".to_string()));
+        heartbeats.record_result("sync network info", None);
+        assert!(heartbeats.start_run("sync network info"));
+
+        let status = &heartbeats.statuses()[0];
+        assert_eq!(status.task, "sync network info");
+        assert!(status.running && !status.stalled);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.last_error.as_deref(), Some("refused"));
+    }
+
+    #[actix_rt::test]
+    async fn long_run_keeps_beating() {
+        let heartbeats = Heartbeats::default();
+        let interval = Duration::from_secs(1);
+        let started = Instant::now();
+
+        heartbeats
+            .beat_while(
+                "validate bundler",
+                interval,
+                tokio::time::sleep(Duration::from_millis(1500)),
+            )
+            .await;
+
+        let (last, _) = heartbeats.tasks.lock().unwrap()["validate bundler"];
+        assert!(last >= started + interval);
+    }
+
+    #[actix_rt::test]
+    async fn hung_run_stops_beating() {
+        let heartbeats = Heartbeats::default();
+        let interval = Duration::from_secs(1);
+        let max_duration = Duration::from_millis(1500);
+        let started = Instant::now();
+
+        heartbeats
+            .beat_for(
+                "validate bundler",
+                interval,
+                max_duration,
+                tokio::time::sleep(Duration::from_millis(2500)),
+            )
+            .await;
+
+        let (last, _) = heartbeats.tasks.lock().unwrap()["validate bundler"];
+        assert!(last >= started + interval);
+        assert!(last < started + max_duration);
+    }
+
+    #[actix_rt::test]
+    async fn drain_waits_for_running_tasks() {
+        let heartbeats = Heartbeats::default();
+        assert!(heartbeats.start_run("validate bundler"));
+
+        let finisher = heartbeats.clone();
+        actix_rt::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            finisher.finish_run("validate bundler");
+        });
+        heartbeats.drain().await;
+
+        assert!(!heartbeats.start_run("validate bundler"));
+    }
+}
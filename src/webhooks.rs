@@ -0,0 +1,208 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, warn};
+use url::Url;
+
+use crate::{
+    config::RuntimeConfigAccess,
+    events::{Event, EventBusAccess},
+    http,
+};
+
+/// Shape of the JSON body posted to a webhook
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// Event serialized as is
+    #[default]
+    Json,
+    /// Slack incoming webhook message
+    Slack,
+    /// Discord webhook message
+    Discord,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: Url,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Also post `epoch_started` and `epoch_finished` events
+    #[serde(default)]
+    pub epochs: bool,
+}
+
+impl WebhookConfig {
+    /// Whether `event` is posted to this webhook
+    pub fn accepts(&self, event: &Event) -> bool {
+        event.is_critical()
+            || (self.epochs
+                && matches!(
+                    event,
+                    Event::EpochStarted { .. } | Event::EpochFinished { .. }
+                ))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum WebhookError {
+    #[error("failed to build webhook request: {0}")]
+    InvalidRequest(String),
+    #[error("webhook request failed: {0}")]
+    RequestFailed(String),
+}
+
+pub fn payload(format: WebhookFormat, event: &Event) -> Value {
+    match format {
+        WebhookFormat::Json => serde_json::to_value(event).expect("Event is serializable"),
+        WebhookFormat::Slack => json!({ "text": event.summary() }),
+        WebhookFormat::Discord => json!({ "content": event.summary() }),
+    }
+}
+
+pub async fn send_webhook<Context, HttpClient>(
+    ctx: &Context,
+    webhook: &WebhookConfig,
+    event: &Event,
+) -> Result<(), WebhookError>
+where
+    Context: http::ClientAccess<HttpClient>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let req = reqwest::Client::new()
+        .post(webhook.url.clone())
+        .json(&payload(webhook.format, event))
+        .build()
+        .map_err(|err| WebhookError::InvalidRequest(err.to_string()))?;
+
+    let res = ctx
+        .get_http_client()
+        .execute(req)
+        .await
+        .map_err(|err| WebhookError::RequestFailed(format!("{:?}", err)))?;
+    if !res.status().is_success() {
+        return Err(WebhookError::RequestFailed(format!(
+            "unexpected status {}",
+            res.status()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Deliver published events to the webhooks in runtime config accepting
+/// them, see `WebhookConfig::accepts`
+pub async fn run_webhooks<Context, HttpClient>(ctx: Context)
+where
+    Context: EventBusAccess + RuntimeConfigAccess + http::ClientAccess<HttpClient>,
+    HttpClient: http::Client<Request = reqwest::Request, Response = reqwest::Response>,
+{
+    let mut events = ctx.event_bus().subscribe();
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                for webhook in ctx.webhooks().iter().filter(|w| w.accepts(&event)) {
+                    if let Err(err) = send_webhook(&ctx, webhook, &event).await {
+                        error!(webhook = %webhook.url, "Failed to deliver event: {}", err);
+                    }
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(skipped, "Webhook delivery fell behind, events were dropped")
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use http::Method;
+    use reqwest::{Request, Response};
+    use url::Url;
+
+    use crate::{
+        context::test_utils::test_context_with_http_client, events::Event,
+        http::reqwest::mock::MockHttpClient, key_manager::test_utils::test_keys,
+        state::ValidatorRole,
+    };
+
+    use super::{payload, send_webhook, WebhookConfig, WebhookFormat};
+
+    fn invalid_bundle() -> Event {
+        Event::BundleInvalid {
+            bundle: "bundle-id".to_string(),
+            reason: "invalid signature".to_string(),
+        }
+    }
+
+    #[test]
+    fn epoch_events_are_only_posted_when_asked_for() {
+        let epoch_started = Event::EpochStarted {
+            epoch: 2,
+            previous: 1,
+            role: ValidatorRole::Idle,
+        };
+        let mut webhook = WebhookConfig {
+            url: Url::from_str("http://example.com/hook").unwrap(),
+            format: WebhookFormat::Json,
+            epochs: false,
+        };
+
+        assert!(webhook.accepts(&invalid_bundle()));
+        assert!(!webhook.accepts(&epoch_started));
+        webhook.epochs = true;
+        assert!(webhook.accepts(&epoch_started));
+    }
+
+    #[test]
+    fn chat_payloads_contain_summary() {
+        let event = invalid_bundle();
+
+        assert_eq!(
+            payload(WebhookFormat::Slack, &event)["text"],
+            "Bundle bundle-id is invalid: invalid signature"
+        );
+        assert_eq!(
+            payload(WebhookFormat::Discord, &event)["content"],
+            "Bundle bundle-id is invalid: invalid signature"
+        );
+    }
+
+    #[test]
+    fn json_payload_contains_event() {
+        let value = payload(WebhookFormat::Json, &invalid_bundle());
+
+        assert_eq!(value["event"], "bundle_invalid");
+        assert_eq!(value["bundle"], "bundle-id");
+    }
+
+    #[actix_rt::test]
+    async fn send_webhook_posts_payload() {
+        let client = MockHttpClient::new(|a: &Request, b: &Request| a.url() == b.url())
+            .when(|req: &Request| {
+                req.method() == Method::POST && &req.url().to_string() == "http://example.com/hook"
+            })
+            .then(|_: &Request| {
+                let response = http::response::Builder::new().status(200).body("").unwrap();
+                Response::from(response)
+            });
+
+        let (key_manager, _) = test_keys();
+        let ctx = test_context_with_http_client(key_manager, client);
+        let webhook = WebhookConfig {
+            url: Url::from_str("http://example.com/hook").unwrap(),
+            format: WebhookFormat::Slack,
+            epochs: false,
+        };
+
+        send_webhook(&ctx, &webhook, &invalid_bundle())
+            .await
+            .unwrap();
+    }
+}